@@ -0,0 +1,165 @@
+/// Decoded audio: mono samples from -1.0 to 1.0.
+pub struct Audio {
+    pub rate: u32,
+    pub samples: Vec<f32>,
+}
+
+/// Decode a WAV file of integer (8 to 32 bit) or 32-bit float PCM, mixing
+/// all channels down to mono. Returns `None` on malformed or unsupported input.
+pub fn decode_wav(data: &[u8]) -> Option<Audio> {
+    if data.len() < 12 || &data[0..4] != b"RIFF" || &data[8..12] != b"WAVE" {
+        return None;
+    }
+    let mut pos = 12;
+    let mut format = None;
+    let mut samples = None;
+    while pos + 8 <= data.len() {
+        let kind = &data[pos..pos + 4];
+        let len = u32::from_le_bytes(data[pos + 4..pos + 8].try_into().unwrap()) as usize;
+        let body = data.get(pos + 8..(pos + 8 + len).min(data.len()))?;
+        match kind {
+            b"fmt " if body.len() >= 16 => {
+                let mut tag = u16::from_le_bytes([body[0], body[1]]);
+                // WAVE_FORMAT_EXTENSIBLE keeps the real tag in its sub-format GUID.
+                if tag == 0xFFFE && body.len() >= 26 {
+                    tag = u16::from_le_bytes([body[24], body[25]]);
+                }
+                let channels = u16::from_le_bytes([body[2], body[3]]) as usize;
+                let rate = u32::from_le_bytes(body[4..8].try_into().unwrap());
+                let bits = u16::from_le_bytes([body[14], body[15]]) as usize;
+                format = Some((tag, channels, rate, bits));
+            }
+            b"data" => samples = Some(body),
+            _ => {}
+        }
+        pos += 8 + len + (len & 1);
+    }
+    let (tag, channels, rate, bits) = format?;
+    let samples = samples?;
+    let width = bits.div_ceil(8);
+    if rate == 0 || channels == 0 || width == 0 || !matches!((tag, bits), (1, 8..=32) | (3, 32)) {
+        return None;
+    }
+    let decode = |bytes: &[u8]| -> f32 {
+        match (tag, width) {
+            (3, _) => f32::from_le_bytes(bytes.try_into().unwrap()),
+            // 8-bit WAV is unsigned; wider samples are signed.
+            (_, 1) => (bytes[0] as f32 - 128.0) / 128.0,
+            _ => {
+                let mut word = [0u8; 4];
+                word[4 - width..].copy_from_slice(bytes);
+                i32::from_le_bytes(word) as f32 / 2147483648.0
+            }
+        }
+    };
+    let samples = samples.chunks_exact(width * channels)
+        .map(|frame| frame.chunks_exact(width).map(decode).sum::<f32>() / channels as f32)
+        .collect();
+    Some(Audio { rate, samples })
+}
+
+/// Resample to `rate` by linear interpolation, first averaging over each
+/// output period when rate goes down so high frequencies don't alias.
+/// Nothing comes out at a rate of 0.
+pub fn resample(audio: &Audio, rate: u32) -> Vec<f32> {
+    let ratio = audio.rate as f64 / rate as f64;
+    let source = &audio.samples;
+    if source.is_empty() || audio.rate == 0 || rate == 0 {
+        return Vec::new();
+    }
+    let window = ratio.floor().max(1.0) as usize;
+    let filtered: Vec<f32> = match window {
+        1 => source.clone(),
+        _ => {
+            let mut sum = 0.0;
+            let mut out = Vec::with_capacity(source.len());
+            for i in 0..source.len() {
+                sum += source[i];
+                if i >= window {
+                    sum -= source[i - window];
+                }
+                out.push(sum / window.min(i + 1) as f32);
+            }
+            out
+        }
+    };
+    let len = (source.len() as f64 / ratio).floor() as usize;
+    (0..len).map(|i| {
+        let position = i as f64 * ratio;
+        let index = position as usize;
+        let fraction = (position - index as f64) as f32;
+        let next = filtered.get(index + 1).copied().unwrap_or(filtered[index]);
+        filtered[index] * (1.0 - fraction) + next * fraction
+    }).collect()
+}
+
+/// Samples as 16-bit signed little-endian PCM.
+pub fn encode_pcm16(samples: &[f32]) -> Vec<u8> {
+    samples.iter().flat_map(|&sample| ((sample.clamp(-1.0, 1.0) * 32767.0).round() as i16).to_le_bytes()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A WAV file of PCM `data` as `channels` channels of `bits` bits.
+    fn wav(rate: u32, channels: u16, bits: u16, data: &[u8]) -> Vec<u8> {
+        let mut out = b"RIFF".to_vec();
+        out.extend((36 + data.len() as u32).to_le_bytes());
+        out.extend(b"WAVEfmt ");
+        out.extend(16u32.to_le_bytes());
+        out.extend(1u16.to_le_bytes());
+        out.extend(channels.to_le_bytes());
+        out.extend(rate.to_le_bytes());
+        out.extend((rate * channels as u32 * bits as u32 / 8).to_le_bytes());
+        out.extend((channels * bits / 8).to_le_bytes());
+        out.extend(bits.to_le_bytes());
+        out.extend(b"data");
+        out.extend((data.len() as u32).to_le_bytes());
+        out.extend(data);
+        out
+    }
+
+    #[test]
+    fn eight_bit_is_unsigned() {
+        let audio = decode_wav(&wav(8000, 1, 8, &[0, 128, 192])).unwrap();
+        assert_eq!(audio.rate, 8000);
+        assert_eq!(audio.samples, [-1.0, 0.0, 0.5]);
+    }
+
+    #[test]
+    fn sixteen_bit_is_signed() {
+        let data: Vec<u8> = [i16::MIN, 0, 16384].iter().flat_map(|sample| sample.to_le_bytes()).collect();
+        assert_eq!(decode_wav(&wav(16000, 1, 16, &data)).unwrap().samples, [-1.0, 0.0, 0.5]);
+    }
+
+    #[test]
+    fn stereo_is_mixed_down() {
+        let data: Vec<u8> = [16384i16, -16384, 16384, 16384].iter().flat_map(|sample| sample.to_le_bytes()).collect();
+        assert_eq!(decode_wav(&wav(44100, 2, 16, &data)).unwrap().samples, [0.0, 0.5]);
+    }
+
+    #[test]
+    fn zero_fields_are_refused() {
+        assert!(decode_wav(&wav(0, 1, 16, &[0; 4])).is_none());
+        assert!(decode_wav(&wav(8000, 0, 16, &[0; 4])).is_none());
+        assert!(decode_wav(&wav(8000, 1, 0, &[0; 4])).is_none());
+    }
+
+    #[test]
+    fn resampled_length_follows_the_rate() {
+        let audio = Audio { rate: 48000, samples: vec![0.25; 48000] };
+        assert_eq!(resample(&audio, 16000).len(), 16000);
+        assert_eq!(resample(&Audio { rate: 8000, samples: vec![0.25; 8000] }, 16000).len(), 16000);
+        assert!(resample(&audio, 0).is_empty());
+        assert!(resample(&Audio { rate: 0, samples: vec![0.25; 10] }, 16000).is_empty());
+    }
+
+    #[test]
+    fn downsampling_filters_out_high_frequencies() {
+        // Full scale at the source's Nyquist frequency, far above the new one.
+        let samples = (0..4800).map(|i| if i % 2 == 0 { 1.0 } else { -1.0 }).collect();
+        let out = resample(&Audio { rate: 48000, samples }, 16000);
+        assert!(out[1..].iter().all(|sample| sample.abs() <= 0.34), "{:?}", &out[..8]);
+    }
+}
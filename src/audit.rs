@@ -0,0 +1,22 @@
+//! A log of everything piecer has changed on each device.
+
+use crate::date;
+use crate::dirs;
+use crate::Piece;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+
+/// Append a device-modifying `operation` to the audit log in the state
+/// directory, before it is carried out. Each line is
+/// `<local time> <device serial> <operation> <details>`.
+///
+/// If the log can't be written, the operation must not go ahead. Nothing is
+/// logged in a dry run, which doesn't change the device.
+pub fn record(piece: &Piece, operation: &str, details: &str) -> io::Result<()> {
+    if piece.options.dry_run {
+        return Ok(());
+    }
+    fs::create_dir_all(dirs::state_dir())?;
+    let mut log = OpenOptions::new().create(true).append(true).open(dirs::state_dir().join("audit.log"))?;
+    writeln!(log, "{} {} {} {}", date::format(date::now_local()), piece.serial.as_deref().unwrap_or("-"), operation, details)
+}
@@ -0,0 +1,522 @@
+use crate::crypto;
+use crate::date;
+use crate::deflate;
+use crate::json::{self, Value};
+use crate::kernel;
+use crate::names;
+use piecer::pffs::{self, DirEnt};
+use crate::progress;
+use crate::resume;
+use crate::sha256;
+use crate::tar;
+use crate::zip;
+use crate::{Options, Piece, PieceError, Result};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+
+/// Back up every file into a directory named after today's date, recording
+/// the outcome in `log_path` instead of the terminal. Returns the exit code.
+///
+/// A missing or busy device is a clean skip (exit 0) so cron stays quiet.
+pub fn unattended(log_path: &Path, encoding: &Encoding, options: &Options) -> Result<i32> {
+    let mut log = OpenOptions::new().create(true).append(true).open(log_path)
+        .map_err(PieceError::host_io(format!("Could not open log file {}", log_path.display())))?;
+    let mut record = |status: &str, message: &str| {
+        writeln!(log, "{} {} {}", date::format(date::now_local()), status, message).unwrap();
+    };
+    let mut piece = match crate::shell::connect(options) {
+        Ok(piece) => piece,
+        Err(PieceError::DeviceNotFound { .. }) => {
+            record("SKIP", "no device attached");
+            return Ok(0);
+        }
+        Err(PieceError::Locked { .. }) => {
+            record("SKIP", "device is in use by another piecer process");
+            return Ok(0);
+        }
+        Err(error) => {
+            record("FAIL", &error.to_string());
+            return Ok(1);
+        }
+    };
+    let dir = PathBuf::from(date::format_date(date::now_local()));
+    // Failures go to the log rather than the terminal.
+    let hook = panic::take_hook();
+    panic::set_hook(Box::new(|_| {}));
+    let result = panic::catch_unwind(AssertUnwindSafe(|| -> Result<usize> {
+        fs::create_dir_all(&dir).map_err(PieceError::host_io(format!("Could not create backup directory {}", dir.display())))?;
+        let directory = piece.ls()?;
+        let fat = piece.read_fat()?;
+        let mut entries = Vec::new();
+        let hosts = names::hosts(directory.iter().map(|dirent| dirent.name.as_str()));
+        for (dirent, host) in directory.iter().zip(hosts) {
+            let data = piece.read_entry(dirent, &fat)?;
+            entries.push(manifest_entry(dirent, &fat, &data));
+            save(data, &dir.join(host), encoding)?;
+        }
+        write_manifest(&dir, manifest(&piece, date::now_local(), &entries))?;
+        Ok(directory.len())
+    }));
+    panic::set_hook(hook);
+    let result = result.map_err(|payload| crate::panic_message(payload.as_ref()))
+        .and_then(|result| result.map_err(|error| error.to_string()));
+    match result {
+        Ok(count) => {
+            progress::end();
+            record("OK", &format!("{} files backed up to {}", count, dir.display()));
+            Ok(0)
+        }
+        Err(message) => {
+            progress::error(&message);
+            record("FAIL", &message);
+            Ok(1)
+        }
+    }
+}
+
+/// Back up the files in `directory` into `dir`, stored as `encoding` says,
+/// with a [`MANIFEST`]. With `resume`, files saved before an interrupted
+/// backup into the same directory aren't read again.
+pub fn to_dir(piece: &mut Piece, directory: Vec<DirEnt>, dir: &Path, encoding: &Encoding, resume: bool) -> Result<()> {
+    if resume && encoding.passphrase.is_none() && fs::read_dir(dir).into_iter().flatten().flatten()
+        .any(|entry| saved_name(&entry.file_name().to_string_lossy()).1.ends_with(".enc")) {
+        return Err(PieceError::EncryptedResume);
+    }
+    let mut state = resume::State::open(&dir.join(".piecer-backup.state"), resume);
+    let fat = piece.read_fat()?;
+    let mut entries = Vec::new();
+    let hosts = names::hosts(directory.iter().map(|dirent| dirent.name.as_str()));
+    for (dirent, host) in directory.into_iter().zip(hosts) {
+        let step = format!("{}\t{}", host, dirent.len);
+        // The manifest needs the checksums of files saved before the interruption too.
+        let saved = state.is_done(&step).then(|| find_host(dir, &host, || Ok(encoding.passphrase.clone().unwrap_or_default())))
+            .transpose()?.flatten();
+        if let Some(data) = saved {
+            entries.push(manifest_entry(&dirent, &fat, &data));
+            continue;
+        }
+        println!("{}", dirent.name);
+        let data = piece.read_entry(&dirent, &fat)?;
+        entries.push(manifest_entry(&dirent, &fat, &data));
+        save(data, &dir.join(&host), encoding)?;
+        state.mark_done(&step);
+    }
+    write_manifest(dir, manifest(piece, date::now_local(), &entries))?;
+    state.finish();
+    Ok(())
+}
+
+/// How files are stored in a backup directory.
+pub struct Encoding {
+    pub passphrase: Option<String>,
+    pub compress: bool,
+}
+
+/// Save a downloaded file to `path`, appending `.gz` if it was compressed and
+/// `.enc` if it was encrypted.
+pub fn save(mut data: Vec<u8>, path: &Path, encoding: &Encoding) -> Result<()> {
+    let mut path = path.as_os_str().to_owned();
+    if encoding.compress && !deflate::is_compressed(&data) {
+        let compressed = deflate::gzip(&data);
+        if compressed.len() < data.len() {
+            data = compressed;
+            path.push(".gz");
+        }
+    }
+    if let Some(passphrase) = &encoding.passphrase {
+        data = crypto::encrypt(passphrase, &data)?;
+        path.push(".enc");
+    }
+    let what = format!("Could not write backup file {}", Path::new(&path).display());
+    fs::write(path, data).map_err(PieceError::host_io(what))
+}
+
+/// Name of the manifest a backup keeps beside the files it holds.
+pub const MANIFEST: &str = "manifest.json";
+
+fn write_manifest(dir: &Path, manifest: String) -> Result<()> {
+    let path = dir.join(MANIFEST);
+    fs::write(&path, manifest).map_err(PieceError::host_io(format!("Could not write manifest {}", path.display())))
+}
+
+/// The manifest line for `dirent`, whose contents are `data`.
+pub fn manifest_entry(dirent: &DirEnt, fat: &[u16], data: &[u8]) -> String {
+    let clusters: Vec<String> = pffs::chain(fat, dirent.cluster).iter().map(u16::to_string).collect();
+    format!("    {{\"name\": {}, \"len\": {}, \"sha256\": {}, \"clusters\": [{}]}}",
+            json::string(&dirent.name), dirent.len, json::string(&sha256::hex(data)), clusters.join(", "))
+}
+
+/// A manifest recording the device a backup taken at `created` came from,
+/// and the `entries` from [`manifest_entry`] for the files in it.
+pub fn manifest(piece: &Piece, created: i64, entries: &[String]) -> String {
+    format!("{{\n  \"format\": \"piecer-backup\",\n  \"version\": 2,\n  \"created\": {},\n  \"kernel\": {},\n  \"serial\": {},\n  \"pffs_top\": {},\n  \"files\": [\n{}\n  ]\n}}\n",
+            json::string(&date::format(created)),
+            json::string(&kernel::version_string(piece.kernel_version)),
+            piece.serial.as_deref().map_or("null".to_string(), json::string),
+            piece.pffs_top, entries.join(",\n"))
+}
+
+/// What a backup's manifest says about the device and files it came from.
+struct Manifest {
+    kernel: String,
+    pffs_top: u64,
+    /// Device name, length and SHA-256 of each file. Manifests from before
+    /// version 2 have no checksums.
+    files: Vec<(String, u64, Option<String>)>,
+}
+
+impl Manifest {
+    /// Parse a manifest, or return `None` if `text` isn't one.
+    fn parse(text: &str) -> Option<Manifest> {
+        let manifest = json::parse(text)?;
+        if manifest.get("format").and_then(Value::as_str) != Some("piecer-backup") {
+            return None;
+        }
+        let files = manifest.get("files").and_then(Value::as_array)?.iter().map(|file| {
+            let name = file.get("name").and_then(Value::as_str)?.to_string();
+            let len = file.get("len").and_then(Value::as_u64)?;
+            Some((name, len, file.get("sha256").and_then(Value::as_str).map(str::to_string)))
+        }).collect::<Option<_>>()?;
+        Some(Manifest {
+            kernel: manifest.get("kernel").and_then(Value::as_str).unwrap_or_default().to_string(),
+            pffs_top: manifest.get("pffs_top").and_then(Value::as_u64).unwrap_or_default(),
+            files,
+        })
+    }
+
+    /// Refuse to restore onto a device with another kernel or filesystem
+    /// layout, unless `any_device`.
+    fn check_device(&self, piece: &Piece, any_device: bool) -> Result<()> {
+        let kernel = kernel::version_string(piece.kernel_version);
+        if any_device || (self.kernel == kernel && self.pffs_top == piece.pffs_top as u64) {
+            return Ok(());
+        }
+        Err(PieceError::Usage(format!("Backup is from kernel {} with the filesystem at {:#x}, but the device has kernel {} \
+                                       with it at {:#x}; pass --any-device to restore anyway",
+                                      self.kernel, self.pffs_top, kernel, piece.pffs_top)))
+    }
+
+    /// Why `data`, the backed-up copy of `name`, isn't what was saved, if
+    /// it isn't.
+    fn damage(&self, name: &str, data: &[u8]) -> Option<String> {
+        let (_, len, sha256) = self.files.iter().find(|(file, _, _)| file == name)?;
+        if *len != data.len() as u64 {
+            return Some(format!("backup copy is {} bytes, but {} were saved", data.len(), len));
+        }
+        sha256.as_ref().filter(|&sha256| *sha256 != sha256::hex(data))
+            .map(|_| "backup copy doesn't match its checksum; it may have been corrupted".to_string())
+    }
+}
+
+/// `data` as saved by [`save`] under a name ending in `suffix`, decoded.
+fn decode(mut data: Vec<u8>, suffix: &str, passphrase: impl FnOnce() -> Result<String>) -> Result<Vec<u8>> {
+    if suffix.ends_with(".enc") {
+        data = crypto::decrypt_or_fail(&passphrase()?, &data)?;
+    }
+    if suffix.starts_with(".gz") {
+        data = deflate::gunzip(&data).ok_or_else(|| PieceError::BadInput("Corrupt compressed file".to_string()))?;
+    }
+    Ok(data)
+}
+
+/// Read a file saved by [`save`] from a backup directory, whatever its encoding.
+pub fn load(dir: &Path, filename: &str) -> Result<Vec<u8>> {
+    find(dir, filename, crypto::passphrase)?.ok_or_else(|| PieceError::NotFound(format!("Could not find {} in backup", filename)))
+}
+
+/// [`load`], asking `passphrase` for the passphrase if the file is
+/// encrypted. Returns `None` if the backup doesn't have the file.
+pub fn find(dir: &Path, filename: &str, passphrase: impl FnOnce() -> Result<String>) -> Result<Option<Vec<u8>>> {
+    find_host(dir, &names::host(filename), passphrase)
+}
+
+/// [`find`] by the name the file was saved under.
+pub fn find_host(dir: &Path, host: &str, passphrase: impl FnOnce() -> Result<String>) -> Result<Option<Vec<u8>>> {
+    ["", ".gz", ".enc", ".gz.enc"].into_iter().find_map(|suffix| {
+        let data = fs::read(dir.join(format!("{}{}", host, suffix))).ok()?;
+        Some((data, suffix))
+    }).map(|(data, suffix)| decode(data, suffix, passphrase)).transpose()
+}
+
+/// The name a file in a backup directory was saved under, and the suffix
+/// [`save`] added to it.
+pub fn saved_name(file_name: &str) -> (&str, &str) {
+    let suffix = [".gz.enc", ".enc", ".gz"].into_iter().find(|suffix| file_name.ends_with(suffix)).unwrap_or("");
+    (&file_name[..file_name.len() - suffix.len()], suffix)
+}
+
+/// Upload every file in the backup directory `dir`, skipping those the device
+/// already has with the same contents. Returns the exit code: 1 if any file
+/// couldn't be written.
+pub fn restore_dir(piece: &mut Piece, dir: &Path, force: bool, any_device: bool) -> Result<i32> {
+    let manifest = fs::read_to_string(dir.join(MANIFEST)).ok().and_then(|text| Manifest::parse(&text));
+    let unreadable = || PieceError::host_io(format!("Could not read backup directory {}", dir.display()));
+    let mut paths: Vec<PathBuf> = fs::read_dir(dir).map_err(unreadable())?
+        .map(|entry| entry.map(|entry| entry.path())).collect::<std::io::Result<Vec<_>>>().map_err(unreadable())?;
+    // A device file that happens to be called manifest.json is restored.
+    paths.retain(|path| path.is_file() && !path.file_name().unwrap().to_string_lossy().starts_with('.')
+        && (manifest.is_none() || path.file_name().unwrap() != MANIFEST));
+    paths.sort();
+    // Ask once, not for every encrypted file.
+    let passphrase = paths.iter().any(|path| path.extension().is_some_and(|extension| extension == "enc"))
+        .then(crypto::passphrase).transpose()?;
+    let files = paths.iter().map(|path| {
+        let file_name = path.file_name().unwrap().to_string_lossy().into_owned();
+        let (name, suffix) = saved_name(&file_name);
+        let data = fs::read(path).map_err(PieceError::host_io(format!("Could not read backup file {}", path.display())))?;
+        Ok((name.to_string(), decode(data, suffix, || Ok(passphrase.clone().unwrap_or_default()))?))
+    }).collect::<Result<_>>()?;
+    restore(piece, files, manifest, force, any_device)
+}
+
+/// The kinds of archive `backup --archive` writes, told apart by extension.
+#[derive(Clone, Copy)]
+enum Archive {
+    Zip,
+    Tar,
+    TarGz,
+}
+
+impl Archive {
+    fn of(path: &Path) -> Result<Archive> {
+        Archive::kind(path)
+            .ok_or_else(|| PieceError::Usage(format!("Archive name must end in .zip, .tar or .tar.gz: {}", path.display())))
+    }
+    fn kind(path: &Path) -> Option<Archive> {
+        let name = path.to_string_lossy().to_lowercase();
+        if name.ends_with(".zip") {
+            Some(Archive::Zip)
+        } else if name.ends_with(".tar") {
+            Some(Archive::Tar)
+        } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Some(Archive::TarGz)
+        } else {
+            None
+        }
+    }
+}
+
+/// Whether `path` is named as an archive from [`to_archive`] would be.
+pub fn is_archive(path: &Path) -> bool {
+    Archive::kind(path).is_some()
+}
+
+/// Directory in an archive holding the device files, under their device names.
+const FILES_DIR: &str = "files/";
+
+/// Back up every file into a single zip or tar archive, with a `manifest.json`
+/// recording the device and each file's size, checksum and clusters.
+pub fn to_archive(piece: &mut Piece, path: &Path) -> Result<()> {
+    let kind = Archive::of(path)?;
+    let now = date::now_local();
+    let directory = piece.ls()?;
+    let fat = piece.read_fat()?;
+    let mut members = Vec::new();
+    let mut entries = Vec::new();
+    for dirent in &directory {
+        println!("{}", dirent.name);
+        let data = piece.read_entry(dirent, &fat)?;
+        entries.push(manifest_entry(dirent, &fat, &data));
+        members.push((format!("{}{}", FILES_DIR, dirent.name), data));
+    }
+    members.insert(0, (MANIFEST.to_string(), manifest(piece, now, &entries).into_bytes()));
+    let data = match kind {
+        Archive::Zip => {
+            let mut archive = zip::Writer::new(Vec::new(), now);
+            for (name, data) in &members {
+                archive.append(name, data).unwrap();
+            }
+            archive.finish().unwrap()
+        }
+        Archive::Tar | Archive::TarGz => {
+            let mut archive = tar::Writer::new(Vec::new(), now as u64);
+            for (name, data) in &members {
+                archive.append(name, data).unwrap();
+            }
+            let data = archive.finish().unwrap();
+            match kind {
+                Archive::TarGz => deflate::gzip(&data),
+                _ => data,
+            }
+        }
+    };
+    fs::write(path, data).map_err(PieceError::host_io(format!("Could not write backup archive {}", path.display())))
+}
+
+/// Files as (device name, contents).
+type Files = Vec<(String, Vec<u8>)>;
+
+/// The files in an archive written by [`to_archive`] and its manifest.
+fn read_archive(path: &Path) -> Result<(Files, Option<Manifest>)> {
+    let data = fs::read(path).map_err(PieceError::host_io(format!("Could not read backup archive {}", path.display())))?;
+    let members = match Archive::of(path)? {
+        Archive::Zip => zip::read(&data),
+        Archive::Tar => tar::read(&data),
+        Archive::TarGz => deflate::gunzip(&data).and_then(|data| tar::read(&data)),
+    }.ok_or_else(|| PieceError::BadInput(format!("Corrupt backup archive {}", path.display())))?;
+    let manifest = members.iter().find(|(name, _)| name == MANIFEST)
+        .and_then(|(_, data)| Manifest::parse(&String::from_utf8_lossy(data)));
+    let files = members.into_iter()
+        .filter_map(|(name, data)| Some((name.strip_prefix(FILES_DIR)?.to_string(), data)))
+        .filter(|(name, _)| !name.is_empty())
+        .collect();
+    Ok((files, manifest))
+}
+
+/// The contents of device file `name` in an archive written by
+/// [`to_archive`], if it has one.
+pub fn load_archive(path: &Path, name: &str) -> Result<Option<Vec<u8>>> {
+    Ok(read_archive(path)?.0.into_iter().find(|(file, _)| file == name).map(|(_, data)| data))
+}
+
+/// Upload every file in an archive written by [`to_archive`], as
+/// [`restore_dir`] does for a directory.
+pub fn restore_archive(piece: &mut Piece, path: &Path, force: bool, any_device: bool) -> Result<i32> {
+    let (files, manifest) = read_archive(path)?;
+    restore(piece, files, manifest, force, any_device)
+}
+
+/// Upload `files`, given as (device name, contents), that the device lacks
+/// or has with other contents, as from a backup without a manifest.
+pub fn restore_files(piece: &mut Piece, files: Vec<(String, Vec<u8>)>, force: bool) -> Result<i32> {
+    restore(piece, files, None, force, false)
+}
+
+/// Upload `files`, given as (device name, contents), skipping those the
+/// device already has with the same contents. With a `manifest`, the device
+/// has to match the one backed up, and files that were damaged or lost
+/// since count as failures. Returns the exit code: 1 if any file couldn't
+/// be written.
+fn restore(piece: &mut Piece, files: Vec<(String, Vec<u8>)>, manifest: Option<Manifest>, force: bool,
+           any_device: bool) -> Result<i32> {
+    let directory = piece.ls()?;
+    let (mut uploaded, mut unchanged, mut failed) = (0, 0, 0);
+    if let Some(manifest) = &manifest {
+        manifest.check_device(piece, any_device)?;
+        for (name, _, _) in manifest.files.iter().filter(|(name, _, _)| files.iter().all(|(file, _)| file != name)) {
+            eprintln!("{}: missing from the backup", name);
+            failed += 1;
+        }
+    }
+    for (name, data) in files {
+        if let Some(damage) = manifest.as_ref().and_then(|manifest| manifest.damage(&name, &data)) {
+            eprintln!("{}: {}", name, damage);
+            failed += 1;
+            continue;
+        }
+        if let Some(existing) = directory.iter().find(|dirent| dirent.name == name && dirent.len as usize == data.len()) {
+            if piece.read_file(&existing.name)? == data {
+                unchanged += 1;
+                continue;
+            }
+        }
+        match piece.upload(&name, &data, force) {
+            Ok(()) => {
+                println!("{}", name);
+                uploaded += 1;
+            }
+            Err(error @ (PieceError::NameTooLong(_) | PieceError::UnencodableName(_) | PieceError::DirectoryFull | PieceError::NoSpace | PieceError::LowSpace(_))) => {
+                eprintln!("{}: {}", name, error);
+                failed += 1;
+            }
+            Err(error) => return Err(error),
+        }
+    }
+    println!("{} uploaded, {} already on the device, {} failed", uploaded, unchanged, failed);
+    Ok((failed > 0) as i32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use piecer::transport::FakeDevice;
+    use std::sync::Once;
+
+    fn scratch(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("piecer-backup-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Keeps the audit log out of the user's state directory.
+    fn connect(device: &FakeDevice, options: &Options) -> Piece {
+        static STATE: Once = Once::new();
+        STATE.call_once(|| std::env::set_var("XDG_STATE_HOME", scratch("state")));
+        Piece::with_transport(device.clone(), options).unwrap()
+    }
+
+    fn device() -> FakeDevice {
+        FakeDevice::with_files(&[("GAME.PEX", &[7; 9000]), ("SAVE.DAT", b"level 3")]).unwrap()
+    }
+
+    const PLAIN: Encoding = Encoding { passphrase: None, compress: false };
+
+    #[test]
+    fn directory_round_trip() {
+        let backup = scratch("round-trip");
+        let mut piece = connect(&device(), &Options::default());
+        let directory = piece.ls().unwrap();
+        to_dir(&mut piece, directory, &backup, &PLAIN, false).unwrap();
+        assert_eq!(fs::read(backup.join("SAVE.DAT")).unwrap(), b"level 3");
+        assert!(fs::read_to_string(backup.join(MANIFEST)).unwrap().contains("\"format\": \"piecer-backup\""));
+        assert!(!backup.join(".piecer-backup.state").exists());
+
+        let empty = FakeDevice::with_files(&[]).unwrap();
+        let mut target = connect(&empty, &Options::default());
+        assert_eq!(restore_dir(&mut target, &backup, false, false).unwrap(), 0);
+        assert_eq!(target.read_file("GAME.PEX").unwrap(), [7; 9000]);
+        assert_eq!(target.read_file("SAVE.DAT").unwrap(), b"level 3");
+        // Nothing to do the second time.
+        assert_eq!(restore_dir(&mut target, &backup, false, false).unwrap(), 0);
+    }
+
+    #[test]
+    fn damaged_file_fails_restore() {
+        let backup = scratch("damaged");
+        let mut piece = connect(&device(), &Options::default());
+        let directory = piece.ls().unwrap();
+        to_dir(&mut piece, directory, &backup, &Encoding { passphrase: None, compress: true }, false).unwrap();
+        fs::write(backup.join("SAVE.DAT"), b"level 99").unwrap();
+        let empty = FakeDevice::with_files(&[]).unwrap();
+        let mut target = connect(&empty, &Options::default());
+        assert_eq!(restore_dir(&mut target, &backup, false, false).unwrap(), 1);
+        assert!(target.ls().unwrap().iter().all(|dirent| dirent.name != "SAVE.DAT"));
+        assert_eq!(target.read_file("GAME.PEX").unwrap(), [7; 9000]);
+    }
+
+    #[test]
+    fn encrypted_resume_needs_the_passphrase() {
+        let backup = scratch("encrypted-resume");
+        let mut piece = connect(&device(), &Options::default());
+        let encrypted = Encoding { passphrase: Some("secret".to_string()), compress: false };
+        let directory = piece.ls().unwrap();
+        to_dir(&mut piece, directory, &backup, &encrypted, false).unwrap();
+        let directory = piece.ls().unwrap();
+        assert!(matches!(to_dir(&mut piece, directory, &backup, &PLAIN, true), Err(PieceError::EncryptedResume)));
+        let directory = piece.ls().unwrap();
+        to_dir(&mut piece, directory, &backup, &encrypted, true).unwrap();
+    }
+
+    #[test]
+    fn archive_round_trip() {
+        let archive = scratch("archive").join("backup.tar.gz");
+        to_archive(&mut connect(&device(), &Options::default()), &archive).unwrap();
+        let empty = FakeDevice::with_files(&[]).unwrap();
+        let mut target = connect(&empty, &Options::default());
+        assert_eq!(restore_archive(&mut target, &archive, false, false).unwrap(), 0);
+        assert_eq!(target.read_file("SAVE.DAT").unwrap(), b"level 3");
+    }
+
+    #[test]
+    fn one_file_from_a_zip() {
+        let archive = scratch("zip").join("backup.zip");
+        to_archive(&mut connect(&device(), &Options::default()), &archive).unwrap();
+        assert!(is_archive(&archive));
+        assert_eq!(load_archive(&archive, "SAVE.DAT").unwrap().unwrap(), b"level 3");
+        assert_eq!(load_archive(&archive, "GAME.PEX").unwrap().unwrap(), [7; 9000]);
+        assert!(load_archive(&archive, "NONE.DAT").unwrap().is_none());
+    }
+}
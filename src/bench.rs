@@ -0,0 +1,56 @@
+use crate::{Piece, Result};
+use piecer::flash::FLASH_BASE;
+use std::time::{Duration, Instant};
+
+/// Block sizes sustained reads are timed at.
+const BLOCKS: [u32; 8] = [32, 64, 128, 256, 512, 1024, 2048, 4096];
+
+fn ms(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+/// Time `rounds` command round-trips, then read `bytes` of flash at each
+/// block size and with overlapped reads, and print the latency and
+/// throughput. Reads never change the
+/// device, so this is safe with an app running, though the app slows it down.
+pub fn run(piece: &mut Piece, rounds: u32, bytes: u32) -> Result<()> {
+    let mut times = Vec::new();
+    for _ in 0..rounds {
+        let start = Instant::now();
+        piece.system_info()?;
+        times.push(start.elapsed());
+    }
+    times.sort();
+    let mean = times.iter().sum::<Duration>() / rounds;
+    println!("round trip  {:.2} ms mean, {:.2} median, {:.2} min, {:.2} max over {} commands",
+             ms(mean), ms(times[times.len() / 2]), ms(times[0]), ms(times[times.len() - 1]), rounds);
+    println!("{:>6}  {:>9}  {:>9}", "block", "KB/s", "ms/block");
+    let (original, overlap) = (piece.read_block(), piece.overlap());
+    piece.set_overlap(false);
+    let mut data = vec![0; bytes as usize];
+    for block in BLOCKS {
+        piece.set_read_block(block);
+        let start = Instant::now();
+        piece.get_memory(FLASH_BASE, bytes, &mut data)?;
+        let elapsed = start.elapsed();
+        let blocks = bytes.div_ceil(block);
+        let note = match piece.read_block() < block {
+            true => format!("  (kernel only answered {} byte blocks)", piece.read_block()),
+            false => String::new(),
+        };
+        println!("{:>6}  {:>9.1}  {:>9.2}{}", block, bytes as f64 / 1024.0 / elapsed.as_secs_f64(),
+                 ms(elapsed) / blocks as f64, note);
+    }
+    // The same again at the configured block, with each request sent while
+    // the last block arrives.
+    piece.set_read_block(original);
+    piece.set_overlap(true);
+    let start = Instant::now();
+    piece.get_memory(FLASH_BASE, bytes, &mut data)?;
+    let elapsed = start.elapsed();
+    piece.set_overlap(overlap);
+    println!("{:>6}  {:>9.1}  {:>9.2}  (overlapped, see --overlap)", piece.read_block(),
+             bytes as f64 / 1024.0 / elapsed.as_secs_f64(), ms(elapsed) / bytes.div_ceil(piece.read_block()) as f64);
+    piece.set_read_block(original);
+    Ok(())
+}
@@ -0,0 +1,33 @@
+/// Encode 8-bit grayscale `pixels`, row by row, as an uncompressed BMP with
+/// a gray palette.
+pub fn encode_gray(width: u32, height: u32, pixels: &[u8]) -> Vec<u8> {
+    let stride = (width as usize).next_multiple_of(4);
+    let offset = 14 + 40 + 256 * 4;
+    let size = offset + stride * height as usize;
+    let mut out = Vec::with_capacity(size);
+    out.extend(b"BM");
+    out.extend((size as u32).to_le_bytes());
+    out.extend([0; 4]);
+    out.extend((offset as u32).to_le_bytes());
+    out.extend(40u32.to_le_bytes());
+    out.extend(width.to_le_bytes());
+    out.extend(height.to_le_bytes());
+    out.extend(1u16.to_le_bytes());
+    out.extend(8u16.to_le_bytes());
+    out.extend([0; 4]);
+    out.extend(((stride * height as usize) as u32).to_le_bytes());
+    // 72 dpi, and a full palette.
+    out.extend(2835u32.to_le_bytes());
+    out.extend(2835u32.to_le_bytes());
+    out.extend(256u32.to_le_bytes());
+    out.extend([0; 4]);
+    for level in 0..=255u8 {
+        out.extend([level, level, level, 0]);
+    }
+    // Rows are stored bottom-up, each padded to four bytes.
+    for row in pixels.chunks(width as usize).rev() {
+        out.extend_from_slice(row);
+        out.resize(out.len() + stride - row.len(), 0);
+    }
+    out
+}
@@ -0,0 +1,40 @@
+use crate::flash::{FLASH_BASE, FLASH_SIZE};
+use crate::{Piece, Result};
+
+/// Bring up a unit from a kernel image: either run it from RAM (at
+/// `load_addr`, or the start of SRAM) or, with `flash`, program it into the
+/// kernel area at the start of flash. Every write is read back and compared.
+///
+/// A blank or recovery-mode unit is expected to still answer the handshake,
+/// as the boot loader does.
+pub fn run(piece: &mut Piece, image: &[u8], load_addr: Option<u32>, flash: bool) -> Result<()> {
+    if flash {
+        let pffs_in_flash = piece.pffs_top > FLASH_BASE && piece.pffs_top < FLASH_BASE + FLASH_SIZE;
+        let limit = if pffs_in_flash { piece.pffs_top - FLASH_BASE } else { FLASH_SIZE };
+        assert!(image.len() as u32 <= limit, "Kernel image is larger than the {} byte kernel area", limit);
+        for (i, chunk) in image.chunks(4096).enumerate() {
+            let addr = FLASH_BASE + i as u32 * 4096;
+            let mut sector = [0xFF; 4096];
+            sector[..chunk.len()].copy_from_slice(chunk);
+            piece.write_flash_sector(addr, &sector)?;
+            verify(piece, addr, &sector)?;
+            println!("Programmed {:#x}", addr);
+        }
+        println!("Kernel written to flash; power-cycle the device to boot it");
+    } else {
+        let addr = load_addr.unwrap_or(piece.sram_top);
+        piece.set_memory(addr, image)?;
+        verify(piece, addr, image)?;
+        println!("Starting kernel at {:#x}", addr);
+        piece.exec(addr)?;
+    }
+    Ok(())
+}
+
+/// Read back `expected.len()` bytes at `addr` and panic if they differ.
+pub fn verify(piece: &mut Piece, addr: u32, expected: &[u8]) -> Result<()> {
+    let mut readback = vec![0; expected.len()];
+    piece.get_memory(addr, expected.len() as u32, &mut readback)?;
+    assert!(readback == expected, "Verification failed at {:#x}", addr);
+    Ok(())
+}
@@ -0,0 +1,60 @@
+//! Stopping long operations cleanly on Ctrl-C.
+//!
+//! Once [`install`]ed, SIGINT and SIGTERM only raise a flag. Transfers
+//! check it between blocks and fail with [`PieceError::Cancelled`], so the
+//! error unwinds as any other would: downloads keep their `.part` file, a
+//! paused application is resumed and the interface is released as the
+//! [`Piece`](crate::Piece) is dropped. A second signal exits at once.
+//!
+//! Programs using the library can [`cancel`] from another thread instead.
+
+use crate::error::{PieceError, Result};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static CANCELLED: AtomicBool = AtomicBool::new(false);
+
+#[cfg(unix)]
+extern "C" fn interrupt(_signal: libc::c_int) {
+    if CANCELLED.swap(true, Ordering::Relaxed) {
+        unsafe { libc::_exit(130) };
+    }
+}
+
+/// Cancel on Ctrl-C or SIGTERM instead of exiting.
+#[cfg(unix)]
+pub fn install() {
+    unsafe {
+        // With SA_RESTART, so reads of the terminal carry on meanwhile.
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = interrupt as *const () as libc::sighandler_t;
+        action.sa_flags = libc::SA_RESTART;
+        libc::sigaction(libc::SIGINT, &action, std::ptr::null_mut());
+        libc::sigaction(libc::SIGTERM, &action, std::ptr::null_mut());
+    }
+}
+
+#[cfg(not(unix))]
+pub fn install() {}
+
+/// Ask whatever is running to stop at the next block.
+pub fn cancel() {
+    CANCELLED.store(true, Ordering::Relaxed);
+}
+
+/// Let operations run again, e.g. before the next command in a shell.
+pub fn reset() {
+    CANCELLED.store(false, Ordering::Relaxed);
+}
+
+/// Whether [`cancel`] has been called since the last [`reset`].
+pub fn cancelled() -> bool {
+    CANCELLED.load(Ordering::Relaxed)
+}
+
+/// `Err(Cancelled)` once [`cancel`] has been called.
+pub fn check() -> Result<()> {
+    match cancelled() {
+        true => Err(PieceError::Cancelled),
+        false => Ok(()),
+    }
+}
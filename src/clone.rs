@@ -0,0 +1,34 @@
+use crate::flash::{self, SECTOR_SIZE};
+use crate::progress;
+use crate::{Piece, Result};
+
+/// Copy `source`'s files onto `target`, one at a time, replacing same-named
+/// files there. With `whole`, copy the entire PFFS region instead, so the
+/// target ends up with exactly the source's filesystem.
+pub fn run(source: &mut Piece, target: &mut Piece, whole: bool) -> Result<()> {
+    if whole {
+        let geometry = flash::geometry(source)?;
+        assert!(target.pffs_top == source.pffs_top && flash::geometry(target)?.pffs_end == geometry.pffs_end,
+                "The devices' filesystems are at different addresses; copy files instead");
+        let total = geometry.pffs_end - geometry.kernel_end;
+        let mut sector = [0; SECTOR_SIZE as usize];
+        for addr in (geometry.kernel_end..geometry.pffs_end).step_by(SECTOR_SIZE as usize) {
+            source.read_stable(addr, SECTOR_SIZE, &mut sector)?;
+            target.write_flash_sector(addr, &sector)?;
+            progress::update(None, (addr + SECTOR_SIZE - geometry.kernel_end) as u64, total as u64);
+        }
+        println!("Copied {} bytes of filesystem", total);
+        return Ok(());
+    }
+    let directory = source.ls()?;
+    for dirent in &directory {
+        if let Some(problem) = dirent.problem {
+            eprintln!("warning: skipping {:?}: {}", dirent.name, problem);
+            continue;
+        }
+        let data = source.read_file(&dirent.name)?;
+        target.upload(&dirent.name, &data, false)?;
+        println!("{}\t{}", dirent.name, data.len());
+    }
+    Ok(())
+}
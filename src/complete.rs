@@ -0,0 +1,75 @@
+use crate::{Cli, Options, Piece};
+use clap::{CommandFactory, ValueEnum};
+use std::panic::{self, AssertUnwindSafe};
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+/// Subcommands whose positional argument is a file on the device.
+const DEVICE_FILE_COMMANDS: [&str; 6] = ["download", "rm", "patch", "info", "run", "verify"];
+
+/// A completion script for `shell` that asks `piecer __complete` for
+/// candidates, so device file names can be offered.
+pub fn script(shell: Shell) -> &'static str {
+    match shell {
+        Shell::Bash => r#"_piecer() {
+    local IFS=$'\n'
+    COMPREPLY=($(piecer __complete "${COMP_WORDS[@]:1:COMP_CWORD}" 2>/dev/null))
+}
+complete -o default -F _piecer piecer
+"#,
+        Shell::Zsh => r#"#compdef piecer
+_piecer() {
+    local -a candidates
+    candidates=("${(@f)$(piecer __complete "${words[@]:1:$((CURRENT-1))}" 2>/dev/null)}")
+    compadd -a candidates
+}
+compdef _piecer piecer
+"#,
+        Shell::Fish => r#"complete -c piecer -f -a '(piecer __complete (commandline -opc)[2..-1] (commandline -ct) 2>/dev/null)'
+"#,
+    }
+}
+
+/// Print the completions for the last of `words`, the arguments typed so far.
+pub fn candidates(words: &[String], options: &Options) {
+    let (current, before) = match words.split_last() {
+        Some((current, before)) => (current.as_str(), before),
+        None => ("", &[][..]),
+    };
+    let mut command = Cli::command();
+    command.build();
+    let subcommand = before.iter().find(|word| !word.starts_with('-'))
+        .and_then(|name| command.find_subcommand(name).cloned());
+    let mut found: Vec<String> = Vec::new();
+    if current.starts_with('-') {
+        let target = subcommand.as_ref().unwrap_or(&command);
+        found.extend(target.get_arguments().filter_map(|arg| arg.get_long()).map(|long| format!("--{}", long)));
+    } else {
+        match &subcommand {
+            None => found.extend(command.get_subcommands().filter(|sub| !sub.is_hide_set())
+                .map(|sub| sub.get_name().to_string())),
+            Some(sub) if DEVICE_FILE_COMMANDS.contains(&sub.get_name()) => found.extend(device_files(options)),
+            Some(_) => {}
+        }
+    }
+    for candidate in found.iter().filter(|candidate| candidate.starts_with(current)) {
+        println!("{}", candidate);
+    }
+}
+
+/// Names of the files on the attached device, or nothing if it can't be read.
+fn device_files(options: &Options) -> Vec<String> {
+    panic::set_hook(Box::new(|_| {}));
+    let names = panic::catch_unwind(AssertUnwindSafe(|| {
+        Piece::new(options).and_then(|mut piece| piece.ls()).map(|directory| {
+            directory.into_iter().map(|dirent| dirent.name).collect()
+        })
+    }));
+    let _ = panic::take_hook();
+    names.ok().and_then(Result::ok).unwrap_or_default()
+}
@@ -0,0 +1,74 @@
+//! The user's settings file, `piecer.toml`.
+
+use crate::dirs;
+use crate::error::{PieceError, Result};
+use std::collections::HashMap;
+use std::fs;
+
+/// Settings from `piecer.toml` in the config directory.
+///
+/// Only the subset of TOML piecer needs is understood: `[section]` headers
+/// and `key = value` lines, where values are basic strings or bare words.
+/// Keys are looked up as `section.key`.
+#[derive(Default)]
+pub struct Config {
+    values: HashMap<String, String>,
+}
+
+impl Config {
+    /// The value of `key`, written `section.key`, if the file sets it.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.values.get(key).map(String::as_str)
+    }
+}
+
+/// Read the config file, or the defaults if there is none. Fails if the
+/// file is there but isn't valid.
+pub fn load() -> Result<Config> {
+    match fs::read_to_string(dirs::config_dir().join("piecer.toml")) {
+        Ok(text) => parse(&text),
+        Err(_) => Ok(Config::default()),
+    }
+}
+
+fn parse(text: &str) -> Result<Config> {
+    let mut values = HashMap::new();
+    let mut section = String::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            section = name.trim().to_string();
+            continue;
+        }
+        let (key, value) = line.split_once('=')
+            .ok_or_else(|| PieceError::BadInput(format!("piecer.toml line {}: expected key = value", number + 1)))?;
+        let key = key.trim().trim_matches('"');
+        let value = parse_value(value.trim())
+            .ok_or_else(|| PieceError::BadInput(format!("piecer.toml line {}: malformed value", number + 1)))?;
+        let key = if section.is_empty() { key.to_string() } else { format!("{}.{}", section, key) };
+        values.insert(key, value);
+    }
+    Ok(Config { values })
+}
+
+fn parse_value(value: &str) -> Option<String> {
+    let Some(rest) = value.strip_prefix('"') else {
+        return Some(value.split('#').next()?.trim().to_string());
+    };
+    let mut out = String::new();
+    let mut chars = rest.chars();
+    loop {
+        match chars.next()? {
+            '"' => return Some(out),
+            '\\' => out.push(match chars.next()? {
+                'n' => '\n',
+                't' => '\t',
+                c => c,
+            }),
+            c => out.push(c),
+        }
+    }
+}
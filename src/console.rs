@@ -0,0 +1,106 @@
+use crate::{Piece, Result};
+use piecer::memmap::SRAM_BASE;
+use std::io::{self, BufRead, Write};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+// The kernel has no console channel, so apps that want one keep a pair of
+// ring buffers in SRAM for piecer to poll. The block starts with a header of
+// little-endian words:
+//
+//   0x00  "pCon"
+//   0x04  output ring size
+//   0x08  output head: bytes ever written by the app
+//   0x0c  output tail: bytes ever read by piecer
+//   0x10  input ring size
+//   0x14  input head: bytes ever written by piecer
+//   0x18  input tail: bytes ever read by the app
+//
+// followed by the output ring and then the input ring. A byte's position is
+// its count modulo the ring size.
+const MAGIC: &[u8] = b"pCon";
+const HEADER_LEN: u32 = 0x1c;
+const POLL: Duration = Duration::from_millis(50);
+
+/// Search SRAM for a console block, a chunk at a time.
+fn find(piece: &mut Piece) -> Result<Option<u32>> {
+    let sram_end = piece.system_info()?.sram_end;
+    let mut chunk = vec![0; 0x1000];
+    for base in (SRAM_BASE..sram_end).step_by(chunk.len()) {
+        let len = (chunk.len() as u32).min(sram_end - base);
+        piece.get_memory(base, len, &mut chunk[..len as usize])?;
+        if let Some(offset) = chunk[..len as usize].chunks_exact(4).position(|word| word == MAGIC) {
+            return Ok(Some(base + offset as u32 * 4));
+        }
+    }
+    Ok(None)
+}
+
+fn word(header: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(header[offset..offset + 4].try_into().unwrap())
+}
+
+/// Stream the app's console output to stdout, and send lines typed on stdin
+/// to it, until interrupted. The block is at `addr`, or searched for in SRAM.
+pub fn run(piece: &mut Piece, addr: Option<u32>) -> Result<()> {
+    let addr = match addr {
+        Some(addr) => addr,
+        None => find(piece)?.expect("No console found in SRAM; is the app using one? Pass --addr to give its address"),
+    };
+    let mut header = [0; HEADER_LEN as usize];
+    piece.get_memory(addr, HEADER_LEN, &mut header)?;
+    assert!(header.starts_with(MAGIC), "No console at {:#x}", addr);
+    let (out_size, in_size) = (word(&header, 0x04), word(&header, 0x10));
+    assert!(out_size > 0, "Console at {:#x} has an empty output ring", addr);
+    let out_ring = addr + HEADER_LEN;
+    let in_ring = out_ring + out_size;
+    eprintln!("Console at {:#x}; Ctrl-C to quit", addr);
+    let (sender, input) = mpsc::channel::<Vec<u8>>();
+    thread::spawn(move || {
+        for line in io::stdin().lock().lines().map_while(|line| line.ok()) {
+            if sender.send((line + "\n").into_bytes()).is_err() {
+                break;
+            }
+        }
+    });
+    // Bytes typed but not yet accepted by a full input ring.
+    let mut pending = Vec::new();
+    let mut stdout = io::stdout();
+    loop {
+        piece.get_memory(addr, HEADER_LEN, &mut header)?;
+        let (head, tail) = (word(&header, 0x08), word(&header, 0x0c));
+        // A writer that got more than a ring ahead overwrote the oldest bytes.
+        let available = head.wrapping_sub(tail).min(out_size);
+        let start = head.wrapping_sub(available);
+        let mut data = vec![0; available as usize];
+        let mut done = 0;
+        while done < available {
+            let position = start.wrapping_add(done) % out_size;
+            let len = (available - done).min(out_size - position);
+            piece.get_memory(out_ring + position, len, &mut data[done as usize..(done + len) as usize])?;
+            done += len;
+        }
+        if available > 0 {
+            stdout.write_all(&data).unwrap();
+            stdout.flush().unwrap();
+            piece.set_memory(addr + 0x0c, &head.to_le_bytes())?;
+        }
+        pending.extend(input.try_iter().flatten());
+        if in_size > 0 && !pending.is_empty() {
+            let (in_head, in_tail) = (word(&header, 0x14), word(&header, 0x18));
+            let room = in_size - in_head.wrapping_sub(in_tail).min(in_size);
+            let count = (room as usize).min(pending.len());
+            let mut done = 0;
+            while done < count {
+                let position = in_head.wrapping_add(done as u32) % in_size;
+                let len = (count - done).min((in_size - position) as usize);
+                piece.set_memory(in_ring + position, &pending[done..done + len])?;
+                done += len;
+            }
+            pending.drain(..count);
+            piece.set_memory(addr + 0x14, &in_head.wrapping_add(count as u32).to_le_bytes())?;
+        }
+        piece.idle(POLL)?;
+    }
+}
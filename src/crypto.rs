@@ -0,0 +1,228 @@
+//! Passphrase-based encryption for backups and dumps: PBKDF2-HMAC-SHA256 key
+//! derivation, ChaCha20 encryption and an HMAC-SHA256 tag over the ciphertext.
+//!
+//! Layout: MAGIC | salt (16) | nonce (12) | ciphertext | tag (32)
+
+use crate::i18n;
+use crate::sha256;
+use crate::{PieceError, Result};
+use std::env;
+use std::fs::File;
+use std::io::{self, BufRead, Read, Write};
+
+const MAGIC: &[u8; 8] = b"PIECENC1";
+const ITERATIONS: u32 = 100_000;
+
+fn hmac(key: &[u8], message: &[u8]) -> [u8; 32] {
+    let mut block = [0; 64];
+    if key.len() > 64 {
+        block[..32].copy_from_slice(&sha256::digest(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+    let mut inner: Vec<u8> = block.iter().map(|b| b ^ 0x36).collect();
+    inner.extend_from_slice(message);
+    let mut outer: Vec<u8> = block.iter().map(|b| b ^ 0x5c).collect();
+    outer.extend_from_slice(&sha256::digest(&inner));
+    sha256::digest(&outer)
+}
+
+fn pbkdf2(passphrase: &[u8], salt: &[u8], iterations: u32) -> [u8; 64] {
+    let mut key = [0; 64];
+    for (i, out) in key.chunks_mut(32).enumerate() {
+        let mut message = salt.to_vec();
+        message.extend((i as u32 + 1).to_be_bytes());
+        let mut u = hmac(passphrase, &message);
+        let mut t = u;
+        for _ in 1..iterations {
+            u = hmac(passphrase, &u);
+            t.iter_mut().zip(u).for_each(|(t, u)| *t ^= u);
+        }
+        out.copy_from_slice(&t);
+    }
+    key
+}
+
+fn quarter_round(s: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize) {
+    s[a] = s[a].wrapping_add(s[b]); s[d] = (s[d] ^ s[a]).rotate_left(16);
+    s[c] = s[c].wrapping_add(s[d]); s[b] = (s[b] ^ s[c]).rotate_left(12);
+    s[a] = s[a].wrapping_add(s[b]); s[d] = (s[d] ^ s[a]).rotate_left(8);
+    s[c] = s[c].wrapping_add(s[d]); s[b] = (s[b] ^ s[c]).rotate_left(7);
+}
+
+fn chacha20(key: &[u8], nonce: &[u8], data: &mut [u8]) {
+    let word = |b: &[u8], i: usize| u32::from_le_bytes(b[i * 4..i * 4 + 4].try_into().unwrap());
+    for (counter, block) in data.chunks_mut(64).enumerate() {
+        let mut state = [0x61707865, 0x3320646e, 0x79622d32, 0x6b206574,
+                         word(key, 0), word(key, 1), word(key, 2), word(key, 3),
+                         word(key, 4), word(key, 5), word(key, 6), word(key, 7),
+                         counter as u32 + 1, word(nonce, 0), word(nonce, 1), word(nonce, 2)];
+        let initial = state;
+        for _ in 0..10 {
+            quarter_round(&mut state, 0, 4, 8, 12);
+            quarter_round(&mut state, 1, 5, 9, 13);
+            quarter_round(&mut state, 2, 6, 10, 14);
+            quarter_round(&mut state, 3, 7, 11, 15);
+            quarter_round(&mut state, 0, 5, 10, 15);
+            quarter_round(&mut state, 1, 6, 11, 12);
+            quarter_round(&mut state, 2, 7, 8, 13);
+            quarter_round(&mut state, 3, 4, 9, 14);
+        }
+        let keystream: Vec<u8> = state.iter().zip(initial).flat_map(|(s, i)| s.wrapping_add(i).to_le_bytes()).collect();
+        block.iter_mut().zip(keystream).for_each(|(b, k)| *b ^= k);
+    }
+}
+
+#[cfg(unix)]
+fn random_bytes(len: usize) -> Result<Vec<u8>> {
+    let mut bytes = vec![0; len];
+    File::open("/dev/urandom").and_then(|mut f| f.read_exact(&mut bytes))
+        .map_err(PieceError::host_io("Could not read random bytes from /dev/urandom"))?;
+    Ok(bytes)
+}
+
+// Salts and nonces come from /dev/urandom, which only Unix-like systems have.
+#[cfg(not(unix))]
+fn random_bytes(_len: usize) -> Result<Vec<u8>> {
+    Err(PieceError::Usage("Encryption needs /dev/urandom, so it is only available on Unix-like systems".to_string()))
+}
+
+pub fn encrypt(passphrase: &str, data: &[u8]) -> Result<Vec<u8>> {
+    let salt = random_bytes(16)?;
+    let nonce = random_bytes(12)?;
+    let key = pbkdf2(passphrase.as_bytes(), &salt, ITERATIONS);
+    let mut out = MAGIC.to_vec();
+    out.extend(&salt);
+    out.extend(&nonce);
+    let mut ciphertext = data.to_vec();
+    chacha20(&key[..32], &nonce, &mut ciphertext);
+    out.extend(ciphertext);
+    let tag = hmac(&key[32..], &out);
+    out.extend(tag);
+    Ok(out)
+}
+
+/// Returns `None` if the data is not an encrypted file or the passphrase is wrong.
+pub fn decrypt(passphrase: &str, data: &[u8]) -> Option<Vec<u8>> {
+    if data.len() < MAGIC.len() + 16 + 12 + 32 || !data.starts_with(MAGIC) {
+        return None;
+    }
+    let (body, tag) = data.split_at(data.len() - 32);
+    let salt = &body[8..24];
+    let nonce = &body[24..36];
+    let key = pbkdf2(passphrase.as_bytes(), salt, ITERATIONS);
+    let expected = hmac(&key[32..], body);
+    if expected.iter().zip(tag).fold(0, |acc, (a, b)| acc | (a ^ b)) != 0 {
+        return None;
+    }
+    let mut plaintext = body[36..].to_vec();
+    chacha20(&key[..32], nonce, &mut plaintext);
+    Some(plaintext)
+}
+
+/// [`decrypt`], failing with a message if the passphrase is wrong.
+pub fn decrypt_or_fail(passphrase: &str, data: &[u8]) -> Result<Vec<u8>> {
+    decrypt(passphrase, data).ok_or_else(|| PieceError::BadInput(i18n::tr("Wrong passphrase or corrupt file").to_string()))
+}
+
+/// Passphrase from `PIECER_PASSPHRASE`, or prompted for on the terminal.
+pub fn passphrase() -> Result<String> {
+    if let Ok(passphrase) = env::var("PIECER_PASSPHRASE") {
+        return Ok(passphrase);
+    }
+    eprint!("Passphrase: ");
+    io::stderr().flush().unwrap();
+    let _echo = EchoOff::new();
+    let mut line = String::new();
+    io::stdin().lock().read_line(&mut line).map_err(PieceError::host_io("Could not read passphrase"))?;
+    eprintln!();
+    let passphrase = line.trim_end_matches(['\r', '\n']).to_string();
+    if passphrase.is_empty() {
+        return Err(PieceError::Usage("Empty passphrase".to_string()));
+    }
+    Ok(passphrase)
+}
+
+#[cfg(unix)]
+struct EchoOff(Option<libc::termios>);
+
+#[cfg(unix)]
+impl EchoOff {
+    fn new() -> EchoOff {
+        unsafe {
+            let mut termios: libc::termios = std::mem::zeroed();
+            if libc::tcgetattr(0, &mut termios) != 0 {
+                return EchoOff(None);
+            }
+            let saved = termios;
+            termios.c_lflag &= !libc::ECHO;
+            libc::tcsetattr(0, libc::TCSANOW, &termios);
+            EchoOff(Some(saved))
+        }
+    }
+}
+
+#[cfg(unix)]
+impl Drop for EchoOff {
+    fn drop(&mut self) {
+        if let Some(saved) = self.0 {
+            unsafe { libc::tcsetattr(0, libc::TCSANOW, &saved) };
+        }
+    }
+}
+
+#[cfg(not(unix))]
+struct EchoOff;
+
+#[cfg(not(unix))]
+impl EchoOff {
+    fn new() -> EchoOff {
+        EchoOff
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    #[test]
+    fn hmac_matches_rfc_4231() {
+        assert_eq!(hex(&hmac(&[0x0b; 20], b"Hi There")),
+                   "b0344c61d8db38535ca8afceaf0bf12b881dc200c9833da726e9376c2e32cff7");
+        // A key longer than the block is hashed first.
+        assert_eq!(hex(&hmac(&[0xaa; 131], b"Test Using Larger Than Block-Size Key - Hash Key First")),
+                   "60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54");
+    }
+
+    #[test]
+    fn pbkdf2_matches_rfc_7914() {
+        assert_eq!(hex(&pbkdf2(b"passwd", b"salt", 1)),
+                   "55ac046e56e3089fec1691c22544b605f94185216dde0465e68b9d57c20dacbc\
+                    49ca9cccf179b645991664b39d77ef317c71b845b1e30bd509112041d3a19783");
+    }
+
+    #[test]
+    fn chacha20_matches_rfc_8439() {
+        let key: Vec<u8> = (0..32).collect();
+        let nonce = [0, 0, 0, 0, 0, 0, 0, 0x4a, 0, 0, 0, 0];
+        let mut data = b"Ladies and Gentlemen of the class of '99: If I could offer you only one tip for the future, sunscreen would be it.".to_vec();
+        chacha20(&key, &nonce, &mut data);
+        assert_eq!(hex(&data),
+                   "6e2e359a2568f98041ba0728dd0d6981e97e7aec1d4360c20a27afccfd9fae0b\
+                    f91b65c5524733ab8f593dabcd62b3571639d624e65152ab8f530c359f0861d8\
+                    07ca0dbf500d6a6156a38e088a22b65e52bc514d16ccf806818ce91ab7793736\
+                    5af90bbf74a35be6b40b8eedf2785e42874d");
+    }
+
+    #[test]
+    fn round_trip() {
+        let sealed = encrypt("secret", b"level 3").unwrap();
+        assert_eq!(decrypt("secret", &sealed).unwrap(), b"level 3");
+        assert!(decrypt("wrong", &sealed).is_none());
+        assert!(decrypt("secret", b"level 3").is_none());
+    }
+}
@@ -0,0 +1,134 @@
+//! Dates without a time zone library: the proleptic Gregorian calendar over
+//! seconds since the Unix epoch, in whatever zone the caller works in.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Host wall-clock time, in seconds since the Unix epoch.
+pub fn now_local() -> i64 {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64;
+    now + utc_offset(now)
+}
+
+#[cfg(unix)]
+fn utc_offset(time: i64) -> i64 {
+    let time = time as libc::time_t;
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    unsafe { libc::localtime_r(&time, &mut tm) };
+    tm.tm_gmtoff as i64
+}
+
+#[cfg(not(unix))]
+fn utc_offset(_time: i64) -> i64 {
+    0
+}
+
+/// Split a timestamp into (year, month, day, hour, minute, second).
+pub fn civil(time: i64) -> (i64, u32, u32, u32, u32, u32) {
+    let days = time.div_euclid(86400);
+    let secs = time.rem_euclid(86400) as u32;
+    // Howard Hinnant's civil_from_days
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day, secs / 3600, secs / 60 % 60, secs % 60)
+}
+
+/// The timestamp of a local time such as `2024-05-01 12:30:00`, with a `T`
+/// instead of the space also accepted and the seconds optional.
+pub fn parse(s: &str) -> Option<i64> {
+    let (date, time) = s.split_once([' ', 'T'])?;
+    let mut date = date.splitn(3, '-').map(str::parse::<i64>);
+    let (year, month, day) = (date.next()?.ok()?, date.next()?.ok()?, date.next()?.ok()?);
+    let mut time = time.splitn(3, ':').map(str::parse::<i64>);
+    let (hour, minute) = (time.next()?.ok()?, time.next()?.ok()?);
+    let second = time.next().unwrap_or(Ok(0)).ok()?;
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23 || minute > 59 || second > 59 {
+        return None;
+    }
+    // Howard Hinnant's days_from_civil
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y.rem_euclid(400);
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146097 + doe - 719468;
+    Some(days * 86400 + hour * 3600 + minute * 60 + second)
+}
+
+/// `time`, in seconds since the Unix epoch, as `YYYY-MM-DD`.
+pub fn format_date(time: i64) -> String {
+    let (y, m, d, ..) = civil(time);
+    format!("{:04}-{:02}-{:02}", y, m, d)
+}
+
+/// `time`, in seconds since the Unix epoch, as `YYYY-MM-DD hh:mm:ss`.
+pub fn format(time: i64) -> String {
+    let (y, m, d, hh, mm, ss) = civil(time);
+    format!("{:04}-{:02}-{:02} {:02}:{:02}:{:02}", y, m, d, hh, mm, ss)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(s: &str) -> i64 {
+        parse(s).unwrap()
+    }
+
+    #[test]
+    fn days_from_civil() {
+        assert_eq!(at("1970-01-01 00:00"), 0);
+        assert_eq!(at("1970-01-02 00:00"), 86400);
+        assert_eq!(at("1969-12-31 23:59:59"), -1);
+        assert_eq!(at("2000-01-01 00:00"), 10957 * 86400);
+        assert_eq!(at("2024-05-01T12:30:15"), 1714566615);
+    }
+
+    #[test]
+    fn civil_from_days() {
+        assert_eq!(civil(0), (1970, 1, 1, 0, 0, 0));
+        assert_eq!(civil(-1), (1969, 12, 31, 23, 59, 59));
+        assert_eq!(civil(1714566615), (2024, 5, 1, 12, 30, 15));
+    }
+
+    #[test]
+    fn leap_years() {
+        // Every fourth year, but not centuries unless divisible by 400.
+        assert_eq!(at("2024-03-01 00:00") - at("2024-02-28 00:00"), 2 * 86400);
+        assert_eq!(at("2023-03-01 00:00") - at("2023-02-28 00:00"), 86400);
+        assert_eq!(at("2000-03-01 00:00") - at("2000-02-28 00:00"), 2 * 86400);
+        assert_eq!(at("2100-03-01 00:00") - at("2100-02-28 00:00"), 86400);
+        assert_eq!(format_date(at("2000-02-28 00:00") + 86400), "2000-02-29");
+        assert_eq!(format_date(at("2100-02-28 00:00") + 86400), "2100-03-01");
+    }
+
+    #[test]
+    fn century_edges() {
+        assert_eq!(format(at("1999-12-31 23:59:59") + 1), "2000-01-01 00:00:00");
+        assert_eq!(format(at("2099-12-31 23:59:59") + 1), "2100-01-01 00:00:00");
+        assert_eq!(at("2101-01-01 00:00") - at("2100-01-01 00:00"), 365 * 86400);
+        assert_eq!(at("2001-01-01 00:00") - at("2000-01-01 00:00"), 366 * 86400);
+    }
+
+    #[test]
+    fn round_trip() {
+        for time in [0, 951782400, 4107542400, 6000000000, -86400 * 366] {
+            assert_eq!(parse(&format(time)), Some(time));
+        }
+    }
+
+    #[test]
+    fn malformed_dates_are_refused() {
+        for s in ["2024-05-01", "2024-13-01 00:00", "2024-05-32 00:00", "2024-05-01 24:00", "2024-05-01 12:60",
+                  "2024-05-01 12:00:60", "2024/05/01 00:00", "x-05-01 00:00"] {
+            assert_eq!(parse(s), None, "{}", s);
+        }
+    }
+}
@@ -0,0 +1,400 @@
+use crate::crc32::crc32;
+
+// Raw DEFLATE (RFC 1951): an LZ77 compressor emitting fixed Huffman blocks,
+// and a full decompressor. gzip framing (RFC 1952) on top.
+
+const LENGTH_BASE: [u16; 29] = [3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31,
+                                35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258];
+const LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2,
+                                3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+const DIST_BASE: [u16; 30] = [1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193,
+                              257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577];
+const DIST_EXTRA: [u8; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6,
+                              7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+const WINDOW: usize = 32768;
+const MAX_CHAIN: usize = 128;
+
+struct BitWriter {
+    out: Vec<u8>,
+    bits: u64,
+    count: u32,
+}
+
+impl BitWriter {
+    fn put(&mut self, value: u32, len: u32) {
+        self.bits |= (value as u64) << self.count;
+        self.count += len;
+        while self.count >= 8 {
+            self.out.push(self.bits as u8);
+            self.bits >>= 8;
+            self.count -= 8;
+        }
+    }
+    // Huffman codes are packed starting from their most significant bit.
+    fn put_code(&mut self, code: u32, len: u32) {
+        self.put(code.reverse_bits() >> (32 - len), len);
+    }
+    fn put_symbol(&mut self, symbol: u16) {
+        match symbol {
+            0..=143 => self.put_code(0x30 + symbol as u32, 8),
+            144..=255 => self.put_code(0x190 + symbol as u32 - 144, 9),
+            256..=279 => self.put_code(symbol as u32 - 256, 7),
+            _ => self.put_code(0xC0 + symbol as u32 - 280, 8),
+        }
+    }
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.out.push(self.bits as u8);
+        }
+        self.out
+    }
+}
+
+fn hash(data: &[u8], i: usize) -> usize {
+    ((data[i] as usize) << 10 ^ (data[i + 1] as usize) << 5 ^ data[i + 2] as usize) & 0x7FFF
+}
+
+fn insert(data: &[u8], i: usize, head: &mut [usize], prev: &mut [usize]) {
+    if i + 2 < data.len() {
+        let h = hash(data, i);
+        prev[i % WINDOW] = head[h];
+        head[h] = i;
+    }
+}
+
+pub fn compress(data: &[u8]) -> Vec<u8> {
+    let mut w = BitWriter { out: Vec::new(), bits: 0, count: 0 };
+    w.put(1, 1);
+    w.put(1, 2);
+    let mut head = vec![usize::MAX; 1 << 15];
+    let mut prev = vec![usize::MAX; WINDOW];
+    let mut i = 0;
+    while i < data.len() {
+        let (mut best_len, mut best_dist) = (0, 0);
+        if i + 2 < data.len() {
+            let mut candidate = head[hash(data, i)];
+            let mut chain = 0;
+            while candidate != usize::MAX && i - candidate <= WINDOW && chain < MAX_CHAIN {
+                let len = data[candidate..].iter().zip(&data[i..]).take(258).take_while(|(a, b)| a == b).count();
+                if len > best_len {
+                    best_len = len;
+                    best_dist = i - candidate;
+                }
+                let next = prev[candidate % WINDOW];
+                if next == usize::MAX || next >= candidate {
+                    break;
+                }
+                candidate = next;
+                chain += 1;
+            }
+        }
+        if best_len >= 3 {
+            let code = LENGTH_BASE.iter().rposition(|&base| base as usize <= best_len).unwrap();
+            w.put_symbol(257 + code as u16);
+            w.put((best_len - LENGTH_BASE[code] as usize) as u32, LENGTH_EXTRA[code] as u32);
+            let code = DIST_BASE.iter().rposition(|&base| base as usize <= best_dist).unwrap();
+            w.put_code(code as u32, 5);
+            w.put((best_dist - DIST_BASE[code] as usize) as u32, DIST_EXTRA[code] as u32);
+            for j in i..i + best_len {
+                insert(data, j, &mut head, &mut prev);
+            }
+            i += best_len;
+        } else {
+            w.put_symbol(data[i] as u16);
+            insert(data, i, &mut head, &mut prev);
+            i += 1;
+        }
+    }
+    w.put_symbol(256);
+    w.finish()
+}
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    bit: u32,
+}
+
+impl BitReader<'_> {
+    fn bits(&mut self, len: u32) -> Option<u32> {
+        let mut value = 0;
+        for i in 0..len {
+            let byte = *self.data.get(self.pos)?;
+            value |= (((byte >> self.bit) & 1) as u32) << i;
+            self.bit += 1;
+            if self.bit == 8 {
+                self.bit = 0;
+                self.pos += 1;
+            }
+        }
+        Some(value)
+    }
+    fn align(&mut self) {
+        if self.bit != 0 {
+            self.bit = 0;
+            self.pos += 1;
+        }
+    }
+}
+
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Huffman {
+        let mut counts = [0; 16];
+        for &len in lengths {
+            counts[len as usize] += 1;
+        }
+        counts[0] = 0;
+        let mut symbols = Vec::with_capacity(lengths.len());
+        for len in 1..16 {
+            for (symbol, _) in lengths.iter().enumerate().filter(|(_, &l)| l == len) {
+                symbols.push(symbol as u16);
+            }
+        }
+        Huffman { counts, symbols }
+    }
+    fn decode(&self, r: &mut BitReader) -> Option<u16> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for len in 1..16 {
+            code |= r.bits(1)? as i32;
+            let count = self.counts[len] as i32;
+            if code - count < first {
+                return self.symbols.get((index + code - first) as usize).copied();
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        None
+    }
+}
+
+/// Returns `None` on malformed input.
+pub fn decompress(data: &[u8]) -> Option<Vec<u8>> {
+    let mut r = BitReader { data, pos: 0, bit: 0 };
+    let mut out = Vec::new();
+    loop {
+        let last = r.bits(1)?;
+        match r.bits(2)? {
+            0 => {
+                r.align();
+                let header = data.get(r.pos..r.pos + 4)?;
+                let len = u16::from_le_bytes([header[0], header[1]]) as usize;
+                out.extend_from_slice(data.get(r.pos + 4..r.pos + 4 + len)?);
+                r.pos += 4 + len;
+            }
+            kind @ (1 | 2) => {
+                let (lit, dist) = if kind == 1 {
+                    let mut lengths = [8; 288];
+                    lengths[144..256].fill(9);
+                    lengths[256..280].fill(7);
+                    (Huffman::new(&lengths), Huffman::new(&[5; 30]))
+                } else {
+                    dynamic_tables(&mut r)?
+                };
+                loop {
+                    let symbol = lit.decode(&mut r)? as usize;
+                    if symbol < 256 {
+                        out.push(symbol as u8);
+                    } else if symbol == 256 {
+                        break;
+                    } else {
+                        let code = symbol - 257;
+                        let len = *LENGTH_BASE.get(code)? as usize + r.bits(*LENGTH_EXTRA.get(code)? as u32)? as usize;
+                        let code = dist.decode(&mut r)? as usize;
+                        let distance = *DIST_BASE.get(code)? as usize + r.bits(*DIST_EXTRA.get(code)? as u32)? as usize;
+                        if distance > out.len() {
+                            return None;
+                        }
+                        for _ in 0..len {
+                            out.push(out[out.len() - distance]);
+                        }
+                    }
+                }
+            }
+            _ => return None,
+        }
+        if last == 1 {
+            return Some(out);
+        }
+    }
+}
+
+fn dynamic_tables(r: &mut BitReader) -> Option<(Huffman, Huffman)> {
+    const ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+    let nlen = r.bits(5)? as usize + 257;
+    let ndist = r.bits(5)? as usize + 1;
+    let ncode = r.bits(4)? as usize + 4;
+    let mut code_lengths = [0; 19];
+    for &i in &ORDER[..ncode] {
+        code_lengths[i] = r.bits(3)? as u8;
+    }
+    let code = Huffman::new(&code_lengths);
+    let mut lengths = Vec::with_capacity(nlen + ndist);
+    while lengths.len() < nlen + ndist {
+        match code.decode(r)? {
+            symbol @ 0..=15 => lengths.push(symbol as u8),
+            16 => {
+                let previous = *lengths.last()?;
+                for _ in 0..3 + r.bits(2)? {
+                    lengths.push(previous);
+                }
+            }
+            17 => lengths.extend(std::iter::repeat_n(0, 3 + r.bits(3)? as usize)),
+            _ => lengths.extend(std::iter::repeat_n(0, 11 + r.bits(7)? as usize)),
+        }
+    }
+    if lengths.len() != nlen + ndist {
+        return None;
+    }
+    Some((Huffman::new(&lengths[..nlen]), Huffman::new(&lengths[nlen..])))
+}
+
+pub fn gzip(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 0xff];
+    out.extend(compress(data));
+    out.extend(crc32(data).to_le_bytes());
+    out.extend((data.len() as u32).to_le_bytes());
+    out
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for chunk in data.chunks(5552) {
+        for &byte in chunk {
+            a += byte as u32;
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+    b << 16 | a
+}
+
+/// Wrap `compress` output in a zlib stream, as PNG uses.
+pub fn zlib(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x78, 0x01];
+    out.extend(compress(data));
+    out.extend(adler32(data).to_be_bytes());
+    out
+}
+
+/// Decode a zlib stream. Returns `None` on malformed input, a preset
+/// dictionary, or checksum mismatch.
+pub fn unzlib(data: &[u8]) -> Option<Vec<u8>> {
+    if data.len() < 6 || data[0] & 0x0f != 8 || !u16::from_be_bytes([data[0], data[1]]).is_multiple_of(31) || data[1] & 0x20 != 0 {
+        return None;
+    }
+    let out = decompress(&data[2..])?;
+    // Assumes nothing follows the stream, as in PNG.
+    if adler32(&out).to_be_bytes() != data[data.len() - 4..] {
+        return None;
+    }
+    Some(out)
+}
+
+/// Decode a single-member gzip file without optional header fields beyond
+/// a file name. Returns `None` on malformed input or checksum mismatch.
+pub fn gunzip(data: &[u8]) -> Option<Vec<u8>> {
+    if data.len() < 18 || data[0..3] != [0x1f, 0x8b, 8] {
+        return None;
+    }
+    let flags = data[3];
+    let mut start = 10;
+    if flags & 0x04 != 0 {
+        start += 2 + u16::from_le_bytes([*data.get(10)?, *data.get(11)?]) as usize;
+    }
+    for flag in [0x08, 0x10] {
+        if flags & flag != 0 {
+            start += data.get(start..)?.iter().position(|&b| b == 0)? + 1;
+        }
+    }
+    if flags & 0x02 != 0 {
+        start += 2;
+    }
+    let out = decompress(data.get(start..data.len() - 8)?)?;
+    let trailer = &data[data.len() - 8..];
+    if crc32(&out).to_le_bytes() != trailer[0..4] {
+        return None;
+    }
+    Some(out)
+}
+
+/// Whether `data` looks like it is already compressed, judging by common
+/// container signatures.
+pub fn is_compressed(data: &[u8]) -> bool {
+    const SIGNATURES: [&[u8]; 6] = [b"\x1f\x8b", b"PK\x03\x04", b"\x89PNG", b"BZh", b"\xfd7zXZ", b"7z\xbc\xaf"];
+    SIGNATURES.iter().any(|signature| data.starts_with(signature))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Contents with runs, repeats at every distance and bytes of all values.
+    fn sample(len: usize) -> Vec<u8> {
+        (0..len).map(|i| match i % 1000 {
+            0..=99 => b'a',
+            100..=499 => (i / 7 % 251) as u8,
+            _ => b"the quick brown fox "[i % 20],
+        }).collect()
+    }
+
+    #[test]
+    fn round_trip() {
+        for len in [0, 1, 2, 3, 258, 1000, 40_000, 100_000] {
+            let data = sample(len);
+            assert_eq!(decompress(&compress(&data)).unwrap(), data, "{} bytes", len);
+            assert_eq!(gunzip(&gzip(&data)).unwrap(), data);
+            assert_eq!(unzlib(&zlib(&data)).unwrap(), data);
+        }
+        assert!(compress(&sample(100_000)).len() < 40_000);
+    }
+
+    #[test]
+    fn stored_block() {
+        assert_eq!(decompress(&[0x01, 0x05, 0x00, 0xfa, 0xff, b'h', b'e', b'l', b'l', b'o']).unwrap(), b"hello");
+        // Shorter than its header says.
+        assert!(decompress(&[0x01, 0x05, 0x00, 0xfa, 0xff, b'h', b'e']).is_none());
+    }
+
+    #[test]
+    fn fixed_block() {
+        // zlib's raw deflate of "hello hello hello".
+        let data = [0xcb, 0x48, 0xcd, 0xc9, 0xc9, 0x57, 0xc8, 0x40, 0x90, 0x00];
+        assert_eq!(decompress(&data).unwrap(), b"hello hello hello");
+    }
+
+    #[test]
+    fn dynamic_block() {
+        // zlib's raw deflate, Huffman codes only, of 62 'z' then "ab" ten times.
+        let data = [0x05, 0xc1, 0x01, 0x01, 0x00, 0x00, 0x00, 0x82, 0xa0, 0xad, 0x36, 0x83, 0xf5, 0x01, 0x00,
+                    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xd0, 0x5a, 0x6b, 0xad, 0xb5, 0xd6, 0xda, 0x01];
+        let mut expected = vec![b'z'; 62];
+        expected.extend(b"ab".repeat(10));
+        assert_eq!(decompress(&data).unwrap(), expected);
+    }
+
+    #[test]
+    fn malformed_input() {
+        // Block type 3 is reserved.
+        assert!(decompress(&[0x07]).is_none());
+        assert!(decompress(&[]).is_none());
+        let mut data = gzip(b"level 3");
+        data[12] ^= 0xff;
+        assert!(gunzip(&data).is_none());
+    }
+
+    #[test]
+    fn compressed_data_is_recognised() {
+        assert!(is_compressed(&gzip(b"level 3")));
+        assert!(is_compressed(b"PK\x03\x04rest of a zip"));
+        assert!(is_compressed(b"\x89PNG\r\n\x1a\n"));
+        assert!(!is_compressed(b"level 3"));
+        assert!(!is_compressed(&[]));
+    }
+}
@@ -0,0 +1,1129 @@
+//! The USB protocol spoken by the P/ECE kernel: memory access, starting
+//! code, pausing applications and reading the screen.
+
+use crate::audit;
+use crate::cancel;
+use crate::config;
+use crate::date;
+use crate::dirs;
+use crate::error::{PieceError, Result};
+use crate::flash;
+use crate::kernel::{self, Feature};
+use crate::lock::{self, DeviceLock};
+use crate::pffs::PffsGeometry;
+use crate::power;
+use crate::trace;
+use crate::transport::Transport;
+use crate::wire;
+use rusb::{open_device_with_vid_pid, DeviceHandle, GlobalContext, Hotplug, HotplugBuilder, UsbContext};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// USB vendor and product ID of the P/ECE.
+pub const VID: u16 = 0x0e19;
+/// The P/ECE's USB product ID.
+pub const PID: u16 = 0x1000;
+/// Size of the LCD in pixels.
+pub const LCD_WIDTH: usize = 128;
+/// Height of the LCD in pixels.
+pub const LCD_HEIGHT: usize = 88;
+/// Largest block requested per read round-trip, unless tuned otherwise.
+/// Blocks the kernel won't answer in full are halved, down to
+/// `MIN_READ_BLOCK`.
+const MAX_READ_BLOCK: u32 = 4096;
+/// Read size every kernel version answers.
+pub const MIN_READ_BLOCK: u32 = 32;
+/// Attempts per transfer, or per `MIN_READ_BLOCK` chunk of a read, before it
+/// is given up on, unless tuned otherwise.
+const RETRIES: u32 = 4;
+/// Wait before the first retry, doubled for each one after.
+const RETRY_BACKOFF: Duration = Duration::from_millis(100);
+/// How long `reconnect` waits for a device that went away to reappear.
+const RECONNECT_WAIT: Duration = Duration::from_secs(10);
+/// Longest the link is left idle during waits before a keep-alive handshake.
+const KEEPALIVE: Duration = Duration::from_secs(2);
+/// How often `wait` looks for the device without hotplug events.
+const WAIT_POLL: Duration = Duration::from_secs(1);
+/// Reads of a region in paranoid mode before giving up on two agreeing.
+const PARANOID_READS: u32 = 5;
+
+/// What a bulk transfer does, which decides how long the device may take.
+#[derive(Clone, Copy)]
+enum Transfer {
+    /// Commands the kernel acts on immediately, like pause and resume.
+    Control,
+    /// Memory reads and writes.
+    Data,
+    /// Sector writes, which include a flash erase.
+    Flash,
+}
+
+/// How transfers are timed, sized and retried.
+#[derive(Clone, Copy)]
+pub struct Tuning {
+    /// Base latency allowed for every transfer, instead of the defaults for
+    /// each kind. Sector writes still get the 2 s an erase can take.
+    pub timeout: Option<Duration>,
+    /// Largest read block, at least `MIN_READ_BLOCK`.
+    pub chunk_size: u32,
+    /// Attempts per transfer.
+    pub retries: u32,
+    /// Send each read request while the block before it is still arriving,
+    /// hiding a round trip per block.
+    pub overlap: bool,
+}
+
+impl Default for Tuning {
+    fn default() -> Tuning {
+        Tuning { timeout: None, chunk_size: MAX_READ_BLOCK, retries: RETRIES, overlap: false }
+    }
+}
+
+impl Tuning {
+    /// USB timeout for a transfer of `kind` moving `bytes`. Each kind gets a
+    /// base latency, plus time for the payload at a slow hub's worst-case
+    /// 50 KB/s.
+    fn timeout(&self, kind: Transfer, bytes: usize) -> Duration {
+        let base = match (kind, self.timeout) {
+            (Transfer::Flash, Some(timeout)) => timeout.max(Duration::from_secs(2)),
+            (_, Some(timeout)) => timeout,
+            (Transfer::Control, None) => Duration::from_millis(250),
+            (Transfer::Data, None) => Duration::from_millis(500),
+            (Transfer::Flash, None) => Duration::from_secs(2),
+        };
+        base + Duration::from_micros(bytes as u64 * 20)
+    }
+}
+
+/// Settings that apply to every device connection.
+#[derive(Clone, Default)]
+pub struct Options {
+    /// Transfer rate limit in bytes per second.
+    pub throttle: Option<u32>,
+    /// When a write leaves the device short of room.
+    pub low_space: LowSpace,
+    /// Refuse every command that could modify the device.
+    pub read_only: bool,
+    /// Read file and dump data twice, retrying until two reads agree.
+    pub paranoid: bool,
+    /// Open the device with this USB serial number, not the first one found.
+    pub serial: Option<String>,
+    /// Open the device at this USB bus number and address.
+    pub bus_address: Option<(u8, u8)>,
+    /// When a transfer fails because the device went away, look for it
+    /// again (by serial number, if it has one) before retrying.
+    pub reconnect: bool,
+    /// If no device is attached yet, wait for one instead of failing.
+    pub wait: bool,
+    /// If a kernel driver has the interface, fail instead of detaching it.
+    pub no_detach: bool,
+    /// If another piecer is using the device, wait for it to finish instead
+    /// of failing.
+    pub queue: bool,
+    /// Block sizes, timeouts and retries for the transfers.
+    pub tuning: Tuning,
+    /// The filesystem layout, instead of detecting it.
+    pub pffs: Option<PffsGeometry>,
+    /// Print the flash sectors and memory that would be written, and the
+    /// code that would be started, instead of doing it.
+    pub dry_run: bool,
+}
+
+/// How much room a write must leave on the device before piecer warns,
+/// from the `[space]` section of the config file.
+#[derive(Clone)]
+pub struct LowSpace {
+    /// Free clusters a write must leave.
+    pub min_free_clusters: usize,
+    /// Free directory slots a write must leave.
+    pub min_free_slots: usize,
+    /// Refuse low-space writes unless forced, instead of only warning.
+    pub refuse: bool,
+}
+
+impl Default for LowSpace {
+    fn default() -> LowSpace {
+        LowSpace { min_free_clusters: 8, min_free_slots: 4, refuse: false }
+    }
+}
+
+impl LowSpace {
+    /// The `[space]` settings in `config`, or the defaults for those it
+    /// leaves out.
+    pub fn from_config(config: &config::Config) -> LowSpace {
+        let default = LowSpace::default();
+        let number = |key, default| config.get(key).map_or(default, |value: &str| {
+            value.parse().unwrap_or_else(|_| panic!("{} in config must be a number", key))
+        });
+        LowSpace {
+            min_free_clusters: number("space.min-free-clusters", default.min_free_clusters),
+            min_free_slots: number("space.min-free-slots", default.min_free_slots),
+            refuse: config.get("space.refuse") == Some("true"),
+        }
+    }
+}
+
+/// A claimed connection to one device.
+///
+/// Every method returns a [`PieceError`] if the device goes away or refuses
+/// the request.
+pub struct Piece {
+    transport: Box<dyn Transport>,
+    /// Kernel version in BCD, e.g. 0x0120 for 1.20.
+    pub kernel_version: u16,
+    /// Start of SRAM available to applications.
+    pub sram_top: u32,
+    /// Flash address of the PFFS metadata.
+    pub pffs_top: u32,
+    /// How PFFS is laid out.
+    pub pffs: PffsGeometry,
+    /// USB serial number, if the device reports one.
+    pub serial: Option<String>,
+    pub(crate) options: Options,
+    /// Read block size that has worked so far, shrunk on failures.
+    read_block: u32,
+    paced_bytes: u64,
+    paced_since: Instant,
+    last_transfer: Instant,
+    _no_suspend: Option<power::NoSuspend>,
+    /// Keeps other piecer processes off the device while connected. Links
+    /// other than USB aren't shared, so don't need one.
+    _lock: Option<DeviceLock>,
+    /// Sectors a dry run would have written, by address. Reads see these
+    /// instead of flash, so later steps act on what would be there.
+    unwritten: BTreeMap<u32, Vec<u8>>,
+    /// Whether to keep the directory and cluster table between reads; see
+    /// [`Piece::keep_meta`].
+    pub(crate) keep_meta: bool,
+    pub(crate) cached_meta: Option<Vec<u8>>,
+    /// Whether the application is paused by this connection, so that it can
+    /// be resumed if the command stops before it does.
+    paused: bool,
+}
+
+/// An attached P/ECE, as listed by [`Piece::list`].
+pub struct Attached {
+    /// USB bus number.
+    pub bus: u8,
+    /// Address on the bus.
+    pub address: u8,
+    /// USB serial number, if the device has one and could be opened.
+    pub serial: Option<String>,
+    /// Kernel version in BCD, or None if the device is in use by another
+    /// program or didn't answer the handshake.
+    pub kernel_version: Option<u16>,
+}
+
+/// The kernel's SYSTEMINFO block.
+pub struct DeviceInfo {
+    /// Hardware revision in BCD.
+    pub hardware_version: u16,
+    /// Kernel version in BCD, e.g. 0x0120 for 1.20.
+    pub kernel_version: u16,
+    /// Kernel build date as (year, month, day).
+    pub kernel_date: (u16, u8, u8),
+    /// CPU clock in Hz.
+    pub clock_hz: u32,
+    /// Supply voltage in millivolts, which tracks the battery.
+    pub vdde_mv: u16,
+    /// SRAM available to applications.
+    pub sram_top: u32,
+    /// End of the SRAM available to applications.
+    pub sram_end: u32,
+    /// Flash taken by PFFS, starting with its metadata sector.
+    pub pffs_top: u32,
+    /// End of the flash taken by PFFS.
+    pub pffs_end: u32,
+    /// The block as the kernel sent it.
+    pub raw: [u8; 32],
+}
+
+impl DeviceInfo {
+    /// Decode the block `raw` as the kernel sends it.
+    pub fn parse(raw: [u8; 32]) -> DeviceInfo {
+        let u16_at = |offset: usize| u16::from_le_bytes(raw[offset..offset + 2].try_into().unwrap());
+        let u32_at = |offset: usize| u32::from_le_bytes(raw[offset..offset + 4].try_into().unwrap());
+        // Packed as YY(7):MM(4):DD(5), years counted from 2000.
+        let date = u16_at(6);
+        DeviceInfo {
+            hardware_version: u16_at(2),
+            kernel_version: u16_at(4),
+            kernel_date: (2000 + (date >> 9), (date >> 5 & 0xf) as u8, (date & 0x1f) as u8),
+            clock_hz: u32_at(8),
+            vdde_mv: u16_at(12),
+            sram_top: u32_at(16),
+            sram_end: u32_at(20),
+            pffs_top: u32_at(24),
+            pffs_end: u32_at(28),
+            raw,
+        }
+    }
+}
+
+/// How the device looks on the bus.
+pub struct UsbInfo {
+    /// USB bus number.
+    pub bus: u8,
+    /// Address on the bus.
+    pub address: u8,
+    /// Vendor ID from the device descriptor.
+    pub vendor_id: u16,
+    /// Product ID from the device descriptor.
+    pub product_id: u16,
+    /// Manufacturer string, if the device has one.
+    pub manufacturer: Option<String>,
+    /// Product string, if the device has one.
+    pub product: Option<String>,
+    /// Serial number string, if the device has one.
+    pub serial: Option<String>,
+    /// USB spec version, as "major.minor".
+    pub usb_version: String,
+    /// Device release, as "major.minor".
+    pub device_version: String,
+    /// The speed the device is running at.
+    pub speed: rusb::Speed,
+}
+
+fn serial_number(device_handle: &DeviceHandle<GlobalContext>) -> Option<String> {
+    device_handle.device().device_descriptor().ok()
+        .and_then(|descriptor| device_handle.read_serial_number_string_ascii(&descriptor).ok())
+}
+
+/// What the lock for the device behind `device_handle` is called: its
+/// serial number, or else the port it is plugged into.
+fn lock_key(device_handle: &DeviceHandle<GlobalContext>) -> String {
+    let device = device_handle.device();
+    match serial_number(device_handle) {
+        Some(serial) => format!("serial-{}", serial),
+        None => {
+            let ports: Vec<String> = device.port_numbers().unwrap_or_default().iter().map(u8::to_string).collect();
+            format!("usb-{}-{}", device.bus_number(), ports.join("."))
+        }
+    }
+}
+
+/// Lock the device behind `device_handle` for this process, then claim it.
+fn take(device_handle: &DeviceHandle<GlobalContext>, options: &Options) -> Result<DeviceLock> {
+    let lock = lock::acquire(&lock_key(device_handle), options.queue)?;
+    claim(device_handle, options.no_detach)?;
+    Ok(lock)
+}
+
+/// Claim interface 0. If a kernel driver holds it, it is detached unless
+/// `no_detach`, and otherwise the error says who has it.
+fn claim(device_handle: &DeviceHandle<GlobalContext>, no_detach: bool) -> Result<()> {
+    match device_handle.claim_interface(0) {
+        Err(rusb::Error::Busy) => {
+            if !device_handle.kernel_driver_active(0).unwrap_or(false) {
+                return Err(PieceError::InterfaceBusy { driver: None });
+            }
+            let driver = driver_name(&device_handle.device()).unwrap_or_else(|| "unknown".to_string());
+            if no_detach {
+                return Err(PieceError::InterfaceBusy { driver: Some(driver) });
+            }
+            device_handle.detach_kernel_driver(0)
+                .map_err(|error| PieceError::DetachFailed { driver: driver.clone(), reason: error.to_string() })?;
+            match device_handle.claim_interface(0) {
+                Err(rusb::Error::Busy) => Err(PieceError::InterfaceBusy { driver: None }),
+                result => Ok(result?),
+            }
+        }
+        result => Ok(result?),
+    }
+}
+
+/// The kernel driver bound to interface 0 of `device`, from sysfs.
+#[cfg(target_os = "linux")]
+fn driver_name(device: &rusb::Device<GlobalContext>) -> Option<String> {
+    let ports: Vec<String> = device.port_numbers().ok()?.iter().map(u8::to_string).collect();
+    let prefix = format!("{}-{}:", device.bus_number(), ports.join("."));
+    let interface = fs::read_dir("/sys/bus/usb/devices").ok()?.filter_map(|entry| entry.ok())
+        .find(|entry| entry.file_name().to_str().is_some_and(|name| name.starts_with(&prefix) && name.ends_with(".0")))?;
+    let driver = fs::read_link(interface.path().join("driver")).ok()?;
+    Some(driver.file_name()?.to_string_lossy().into_owned())
+}
+
+/// Elsewhere the driver goes unnamed.
+#[cfg(not(target_os = "linux"))]
+fn driver_name(_device: &rusb::Device<GlobalContext>) -> Option<String> {
+    None
+}
+
+fn is_piece(device: &rusb::Device<GlobalContext>) -> bool {
+    has_ids(device, (VID, PID))
+}
+
+fn has_ids(device: &rusb::Device<GlobalContext>, (vid, pid): (u16, u16)) -> bool {
+    device.device_descriptor().is_ok_and(|d| d.vendor_id() == vid && d.product_id() == pid)
+}
+
+/// Whether a transfer that failed with `error` might work if tried again.
+fn is_transient(error: rusb::Error) -> bool {
+    matches!(error, rusb::Error::Timeout | rusb::Error::Pipe | rusb::Error::Io | rusb::Error::NoDevice
+                    | rusb::Error::Overflow | rusb::Error::Busy | rusb::Error::Interrupted | rusb::Error::Other)
+}
+
+/// Notes that a P/ECE was plugged in.
+struct Arrival(Arc<AtomicBool>);
+
+impl Hotplug<GlobalContext> for Arrival {
+    fn device_arrived(&mut self, _device: rusb::Device<GlobalContext>) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+    fn device_left(&mut self, _device: rusb::Device<GlobalContext>) {}
+}
+
+/// Block until a P/ECE enumerates, or one already attached is reported
+/// again. Where libusb has no hotplug support this just waits a while
+/// before the bus is searched again.
+fn wait_for_arrival() -> Result<()> {
+    if !rusb::has_hotplug() {
+        thread::sleep(WAIT_POLL);
+        return Ok(());
+    }
+    let arrived = Arc::new(AtomicBool::new(false));
+    let _registration = HotplugBuilder::new().vendor_id(VID).product_id(PID).enumerate(true)
+        .register(GlobalContext::default(), Box::new(Arrival(arrived.clone())))?;
+    while !arrived.load(Ordering::Relaxed) {
+        GlobalContext::default().handle_events(Some(WAIT_POLL))?;
+    }
+    // The kernel needs a moment after enumerating before it answers.
+    thread::sleep(Duration::from_millis(500));
+    Ok(())
+}
+
+/// The kernel's 32-byte SYSTEMINFO block, which the handshake returns.
+fn handshake(transport: &dyn Transport, tuning: &Tuning) -> rusb::Result<[u8; 32]> {
+    wire::write(transport, &[0, 32], tuning.timeout(Transfer::Data, 2))?;
+    let mut info = [0; 32];
+    wire::read(transport, &mut info, tuning.timeout(Transfer::Data, 32))?;
+    Ok(info)
+}
+
+impl Piece {
+    /// The device `options` selects by serial number or bus address, or
+    /// else the first attached device. With `wait`, blocks until it is
+    /// plugged in.
+    pub fn new(options: &Options) -> Result<Piece> {
+        let mut waiting = false;
+        loop {
+            match Piece::open(options) {
+                Err(PieceError::DeviceNotFound { .. }) if options.wait => {
+                    if !waiting {
+                        eprintln!("Waiting for a P/ECE to be plugged in...");
+                        waiting = true;
+                    }
+                    wait_for_arrival()?;
+                }
+                result => return result,
+            }
+        }
+    }
+    fn open(options: &Options) -> Result<Piece> {
+        if let Some((bus, address)) = options.bus_address {
+            let wanted = format!("bus {} address {}", bus, address);
+            let device = rusb::devices()?.iter()
+                .find(|device| device.bus_number() == bus && device.address() == address)
+                .ok_or(PieceError::DeviceNotFound { wanted: Some(wanted), found: Vec::new() })?;
+            let device_handle = device.open()?;
+            let lock = take(&device_handle, options)?;
+            let piece = Piece::attach(device_handle, lock, options)?;
+            if let Some(serial) = options.serial.as_deref().filter(|&serial| piece.serial.as_deref() != Some(serial)) {
+                return Err(PieceError::DeviceNotFound {
+                    wanted: Some(format!("serial {} at bus {} address {}", serial, bus, address)),
+                    found: piece.serial.clone().into_iter().collect(),
+                });
+            }
+            return Ok(piece);
+        }
+        if let Some(serial) = &options.serial {
+            return Piece::open_serial(options, serial);
+        }
+        let device_handle = open_device_with_vid_pid(VID, PID)
+            .ok_or(PieceError::DeviceNotFound { wanted: None, found: Vec::new() })?;
+        let lock = take(&device_handle, options)?;
+        Piece::attach(device_handle, lock, options)
+    }
+    /// Every attached device, whether or not it can be opened.
+    pub fn list() -> Result<Vec<Attached>> {
+        let devices = rusb::devices()?;
+        Ok(devices.iter().filter(is_piece).map(|device| {
+            let handle = device.open().ok();
+            let serial = handle.as_ref().and_then(serial_number);
+            // Devices another piecer is using are left alone, not handshaken with mid-command.
+            let lock = handle.as_ref().and_then(|handle| lock::acquire(&lock_key(handle), false).ok());
+            let kernel_version = handle.filter(|handle| lock.is_some() && handle.claim_interface(0).is_ok())
+                .and_then(|handle| handshake(&handle, &Tuning::default()).ok())
+                .map(|info| DeviceInfo::parse(info).kernel_version);
+            Attached { bus: device.bus_number(), address: device.address(), serial, kernel_version }
+        }).collect())
+    }
+    /// Every attached device that isn't in use by another program and
+    /// answers the handshake.
+    pub fn open_all(options: &Options) -> Result<Vec<Piece>> {
+        let devices = rusb::devices()?;
+        Ok(devices.iter()
+            .filter(is_piece)
+            .filter_map(|device| device.open().ok())
+            .filter_map(|handle| take(&handle, options).ok().map(|lock| (handle, lock)))
+            .filter_map(|(handle, lock)| Piece::attach(handle, lock, options).ok())
+            .collect())
+    }
+    /// The attached device with USB serial number `serial`.
+    pub fn open_serial(options: &Options, serial: &str) -> Result<Piece> {
+        let mut pieces = Piece::open_all(options)?;
+        match pieces.iter().position(|piece| piece.serial.as_deref() == Some(serial)) {
+            Some(i) => Ok(pieces.swap_remove(i)),
+            None => Err(PieceError::DeviceNotFound {
+                wanted: Some(format!("serial {}", serial)),
+                found: pieces.iter().map(|piece| piece.serial.clone().unwrap_or_else(|| "(none)".to_string())).collect(),
+            }),
+        }
+    }
+    fn attach(device_handle: DeviceHandle<GlobalContext>, lock: DeviceLock, options: &Options) -> Result<Piece> {
+        let serial = serial_number(&device_handle);
+        let no_suspend = power::prevent_suspend(&device_handle.device());
+        Piece::connect(Box::new(device_handle), Some(lock), serial, no_suspend, options)
+    }
+    /// Handshake over `transport`, which isn't USB, such as a
+    /// [`FakeDevice`](crate::transport::FakeDevice) in tests.
+    pub fn with_transport(transport: impl Transport + 'static, options: &Options) -> Result<Piece> {
+        Piece::connect(Box::new(transport), None, None, None, options)
+    }
+    fn connect(transport: Box<dyn Transport>, lock: Option<DeviceLock>, serial: Option<String>,
+               _no_suspend: Option<power::NoSuspend>, options: &Options) -> Result<Piece> {
+        let _span = trace::span("handshake");
+        let info = DeviceInfo::parse(handshake(&*transport, &options.tuning)?);
+        let mut piece = Piece { transport, kernel_version: info.kernel_version, sram_top: info.sram_top, pffs_top: info.pffs_top,
+                                pffs: options.pffs.unwrap_or(PffsGeometry::STOCK), serial, options: options.clone(),
+                                read_block: options.tuning.chunk_size.max(MIN_READ_BLOCK), paced_bytes: 0, paced_since: Instant::now(), last_transfer: Instant::now(), _no_suspend,
+                                _lock: lock, unwritten: BTreeMap::new(),
+                                keep_meta: false, cached_meta: None, paused: false };
+        if options.pffs.is_none() {
+            piece.pffs = piece.detect_pffs(info.pffs_end)?;
+        }
+        Ok(piece)
+    }
+    /// The kernel's SYSTEMINFO block, fetched again by a fresh handshake.
+    pub fn system_info(&mut self) -> Result<DeviceInfo> {
+        let tuning = self.options.tuning;
+        Ok(DeviceInfo::parse(self.retry(|handle| handshake(handle, &tuning))?))
+    }
+    /// Supply voltage in millivolts, which tracks the battery.
+    pub fn battery_mv(&mut self) -> Result<u16> {
+        Ok(self.system_info()?.vdde_mv)
+    }
+    /// The largest block reads currently ask for, initially the tuned chunk
+    /// size and halved each time the kernel can't answer in full.
+    pub fn read_block(&self) -> u32 {
+        self.read_block
+    }
+    /// Ask for reads of up to `bytes` from now on, at least `MIN_READ_BLOCK`.
+    pub fn set_read_block(&mut self, bytes: u32) {
+        self.read_block = bytes.max(MIN_READ_BLOCK);
+    }
+    /// Whether writes are only printed; see [`Options::dry_run`].
+    pub fn dry_run(&self) -> bool {
+        self.options.dry_run
+    }
+    /// Whether reads overlap; see [`Tuning::overlap`].
+    pub fn overlap(&self) -> bool {
+        self.options.tuning.overlap
+    }
+    /// Turn overlapping reads on or off for this connection.
+    pub fn set_overlap(&mut self, overlap: bool) {
+        self.options.tuning.overlap = overlap;
+    }
+    /// The device's USB descriptor and where it is attached.
+    pub fn usb_info(&self) -> Result<UsbInfo> {
+        let handle = self.transport.usb().ok_or(PieceError::Usb(rusb::Error::NotSupported))?;
+        let device = handle.device();
+        let descriptor = device.device_descriptor()?;
+        let string = |index: Option<u8>| index.and_then(|index| handle.read_string_descriptor_ascii(index).ok());
+        let version = |version: rusb::Version| format!("{}.{}{}", version.major(), version.minor(), version.sub_minor());
+        Ok(UsbInfo {
+            bus: device.bus_number(),
+            address: device.address(),
+            vendor_id: descriptor.vendor_id(),
+            product_id: descriptor.product_id(),
+            manufacturer: string(descriptor.manufacturer_string_index()),
+            product: string(descriptor.product_string_index()),
+            serial: self.serial.clone(),
+            usb_version: version(descriptor.usb_version()),
+            device_version: version(descriptor.device_version()),
+            speed: device.speed(),
+        })
+    }
+    pub(crate) fn require_writable(&self, operation: &'static str) -> Result<()> {
+        match self.options.read_only {
+            true => Err(PieceError::ReadOnly(operation)),
+            false => Ok(()),
+        }
+    }
+    /// Wait for `duration` while keeping the link alive, so neither the host
+    /// nor the device power-saves in the middle of a long-running command.
+    pub fn idle(&mut self, duration: Duration) -> Result<()> {
+        let until = Instant::now() + duration;
+        while let Some(left) = until.checked_duration_since(Instant::now()).filter(|left| !left.is_zero()) {
+            cancel::check()?;
+            let due = KEEPALIVE.saturating_sub(self.last_transfer.elapsed());
+            if due.is_zero() {
+                let tuning = self.options.tuning;
+                self.retry(|handle| handshake(handle, &tuning))?;
+                self.last_transfer = Instant::now();
+                continue;
+            }
+            thread::sleep(due.min(left));
+        }
+        Ok(())
+    }
+    /// Read whole blocks of `len` bytes at `addr` into `data`, with the
+    /// request for each block sent from a second thread while the one before
+    /// it is read, so the kernel can start on it as soon as it has sent the
+    /// last. Stops at the first failure, which `get_memory` then retries a
+    /// block at a time, and returns how many bytes were read.
+    fn read_overlapped(&mut self, addr: u32, len: u32, data: &mut [u8]) -> u32 {
+        let block = self.read_block;
+        let tuning = self.options.tuning;
+        let command = |offset: u32| {
+            let mut command: Vec<u8> = vec![2];
+            command.extend((addr + offset).to_le_bytes());
+            command.extend((len - offset).min(block).to_le_bytes());
+            command
+        };
+        let first = command(0);
+        if wire::write(&*self.transport, &first, tuning.timeout(Transfer::Data, first.len())).is_err() {
+            self.drain();
+            return 0;
+        }
+        let (requests, pending) = mpsc::sync_channel::<Vec<u8>>(0);
+        let (results, written) = mpsc::channel();
+        let mut read = 0;
+        let handle = &*self.transport;
+        thread::scope(|scope| {
+            scope.spawn(move || {
+                for command in pending {
+                    let ok = wire::write(handle, &command, tuning.timeout(Transfer::Data, command.len())).is_ok();
+                    if results.send(ok).is_err() {
+                        break;
+                    }
+                }
+            });
+            while read < len && !cancel::cancelled() {
+                let bytes = (len - read).min(block);
+                let next = read + bytes;
+                let queued = next < len && requests.send(command(next)).is_ok();
+                let chunk = &mut data[read as usize..next as usize];
+                let answered = wire::read(handle, chunk, tuning.timeout(Transfer::Data, bytes as usize))
+                    .is_ok_and(|n| n == bytes as usize);
+                let sent = queued && written.recv() == Ok(true);
+                if !answered {
+                    break;
+                }
+                read = next;
+                if next < len && !sent {
+                    break;
+                }
+            }
+            drop(requests);
+        });
+        self.pace(read as usize);
+        if read < len {
+            // A request may have gone out whose answer nobody read.
+            self.drain();
+        }
+        read
+    }
+    /// Run the transfers in `transfer`, which must be safe to repeat, again
+    /// after errors that might be transient, with `recover` in between.
+    fn retry<T>(&mut self, mut transfer: impl FnMut(&dyn Transport) -> rusb::Result<T>) -> Result<T> {
+        let mut attempt = 0;
+        loop {
+            match transfer(&*self.transport) {
+                Ok(value) => return Ok(value),
+                Err(error) if is_transient(error) && attempt + 1 < self.options.tuning.retries => {
+                    attempt += 1;
+                    self.recover(attempt, error);
+                }
+                Err(error) => return Err(error.into()),
+            }
+        }
+    }
+    /// Get the link going again after `error`, before retry number `attempt`:
+    /// back off, clear stalled endpoints, and with `reconnect` open the
+    /// device afresh if it went away.
+    fn recover(&mut self, attempt: u32, error: rusb::Error) {
+        eprintln!("warning: USB transfer failed ({}), retrying", error);
+        thread::sleep(RETRY_BACKOFF * (1 << (attempt - 1)));
+        if error == rusb::Error::NoDevice || error == rusb::Error::Io {
+            if self.options.reconnect {
+                match self.reopen() {
+                    Ok(()) => eprintln!("Reconnected"),
+                    Err(error) => eprintln!("warning: could not reconnect: {}", error),
+                }
+            }
+            return;
+        }
+        let _ = self.transport.clear_halt();
+        self.drain();
+    }
+    /// Reset the USB port of the device `options` select, without the
+    /// handshake that a hung kernel wouldn't answer, and give it a moment to
+    /// come back.
+    pub fn reset_port(options: &Options) -> Result<()> {
+        let devices = rusb::devices()?;
+        let device = devices.iter()
+            .filter(|device| match options.bus_address {
+                Some((bus, address)) => device.bus_number() == bus && device.address() == address,
+                None => is_piece(device),
+            })
+            .find(|device| options.serial.as_ref().is_none_or(|serial| {
+                device.open().ok().and_then(|handle| serial_number(&handle)).as_ref() == Some(serial)
+            }))
+            .ok_or(PieceError::DeviceNotFound { wanted: None, found: Vec::new() })?;
+        let device_handle = device.open()?;
+        let _lock = lock::acquire(&lock_key(&device_handle), options.queue)?;
+        match device_handle.reset() {
+            Ok(()) | Err(rusb::Error::NotFound | rusb::Error::NoDevice) => {}
+            Err(error) => return Err(error.into()),
+        }
+        thread::sleep(Duration::from_secs(1));
+        Ok(())
+    }
+    /// Get a stuck link going again and handshake. Without `hard` the USB
+    /// port is reset, which helps when the host side is confused; with it
+    /// the kernel is restarted through its reset vector, which also stops the
+    /// running application, but needs the kernel to still answer commands.
+    pub fn reset(&mut self, hard: bool) -> Result<()> {
+        let _span = trace::span("reset").arg("hard", hard);
+        if hard {
+            self.require_writable("restart the kernel")?;
+            let mut vector = [0; 4];
+            self.get_memory(flash::FLASH_BASE, 4, &mut vector)?;
+            self.exec(u32::from_le_bytes(vector))?;
+            if self.options.dry_run {
+                return Ok(());
+            }
+            // The restarted kernel runs whatever it starts.
+            self.forget_halt()?;
+            // Give it time to drop off the bus before looking for it again.
+            thread::sleep(Duration::from_secs(1));
+            return self.reopen();
+        }
+        match self.transport.reset() {
+            Ok(()) => {}
+            // The device enumerated afresh, so the handle is stale.
+            Err(rusb::Error::NotFound | rusb::Error::NoDevice) => return self.reopen(),
+            Err(error) => return Err(error.into()),
+        }
+        self.drain();
+        self.system_info()?;
+        Ok(())
+    }
+    /// Find the device again after it was unplugged or reset, and claim it,
+    /// waiting up to `RECONNECT_WAIT` for it to come back.
+    fn reopen(&mut self) -> Result<()> {
+        let Some(handle) = self.transport.usb() else {
+            return Ok(());
+        };
+        let until = Instant::now() + RECONNECT_WAIT;
+        // Whatever IDs it had, in case it was opened by bus and address.
+        let ids = handle.device().device_descriptor()
+            .map_or((VID, PID), |descriptor| (descriptor.vendor_id(), descriptor.product_id()));
+        loop {
+            for device in rusb::devices()?.iter().filter(|device| has_ids(device, ids)) {
+                let Ok(handle) = device.open() else {
+                    continue;
+                };
+                if self.serial.is_some() && serial_number(&handle) != self.serial {
+                    continue;
+                }
+                if claim(&handle, self.options.no_detach).is_ok() && handshake(&handle, &self.options.tuning).is_ok() {
+                    self.transport = Box::new(handle);
+                    return Ok(());
+                }
+            }
+            if Instant::now() >= until {
+                let wanted = self.serial.as_ref().map(|serial| format!("serial {}", serial));
+                return Err(PieceError::DeviceNotFound { wanted, found: Vec::new() });
+            }
+            thread::sleep(Duration::from_millis(500));
+        }
+    }
+    /// Sleep as needed to keep transfers under the throttle rate.
+    fn pace(&mut self, bytes: usize) {
+        self.last_transfer = Instant::now();
+        let Some(rate) = self.options.throttle else {
+            return;
+        };
+        self.paced_bytes += bytes as u64;
+        let due = Duration::from_secs_f64(self.paced_bytes as f64 / rate as f64);
+        let elapsed = self.paced_since.elapsed();
+        if elapsed < due {
+            thread::sleep(due - elapsed);
+        } else {
+            // Idle time doesn't earn a burst allowance.
+            self.paced_bytes = 0;
+            self.paced_since = Instant::now();
+        }
+    }
+    /// Discard whatever is left of a reply the read gave up on, so it isn't
+    /// taken for the answer to the next command.
+    fn drain(&mut self) {
+        let mut scratch = [0; 64];
+        while wire::read(&*self.transport, &mut scratch, Duration::from_millis(50)).is_ok() {}
+    }
+    /// Read `len` bytes at `addr` into the first `len` bytes of `data`, in
+    /// blocks of up to the tuned chunk size. When a block comes back short,
+    /// the bytes that did arrive are kept, the rest is asked for at half
+    /// the size, and the smaller size is kept for later reads. A chunk that
+    /// still fails at `MIN_READ_BLOCK` after the tuned number of attempts
+    /// gives a `ShortRead` saying how much of `data` is valid.
+    ///
+    /// Panics if `data` is shorter than `len` or the range runs past the
+    /// end of the address space.
+    pub fn get_memory(&mut self, addr: u32, len: u32, data: &mut [u8]) -> Result<()> {
+        let _span = trace::span("get_memory").arg("addr", format!("{:#x}", addr)).arg("len", len);
+        if data.len() < len as usize {
+            return Err(PieceError::BadRequest(format!("Buffer of {} bytes for a read of {}", data.len(), len)));
+        }
+        if addr.checked_add(len).is_none() {
+            return Err(PieceError::BadRequest(format!("Read of {} bytes at {:#x} wraps around", len, addr)));
+        }
+        let mut read = match self.options.tuning.overlap && len > self.read_block {
+            true => self.read_overlapped(addr, len, data),
+            false => 0,
+        };
+        let mut attempt = 0;
+        while read < len {
+            cancel::check()?;
+            let bytes_to_read = (len - read).min(self.read_block);
+            let chunk = &mut data[read as usize..(read + bytes_to_read) as usize];
+            let mut command: Vec<u8> = vec![2];
+            command.extend((addr + read).to_le_bytes());
+            command.extend(bytes_to_read.to_le_bytes());
+            let tuning = &self.options.tuning;
+            let result = wire::write(&*self.transport, &command, tuning.timeout(Transfer::Data, command.len()))
+                .and_then(|_| wire::read(&*self.transport, chunk, tuning.timeout(Transfer::Data, bytes_to_read as usize)));
+            // What arrived of a short answer is good, and needn't be asked for again.
+            let arrived = result.map_or(0, |n| n.min(bytes_to_read as usize)) as u32;
+            self.pace(arrived as usize);
+            read += arrived;
+            match result {
+                Ok(n) if n == bytes_to_read as usize => {
+                    attempt = 0;
+                    continue;
+                }
+                // A kernel that can't send the whole block either stops short
+                // or never answers.
+                Ok(_) | Err(rusb::Error::Timeout) if self.read_block > MIN_READ_BLOCK => {
+                    self.read_block /= 2;
+                    self.drain();
+                }
+                Err(error) if is_transient(error) && attempt + 1 < self.options.tuning.retries => {
+                    attempt += 1;
+                    self.recover(attempt, error);
+                }
+                Ok(_) if attempt + 1 < self.options.tuning.retries => {
+                    attempt = match arrived {
+                        0 => attempt + 1,
+                        _ => 0,
+                    };
+                    self.drain();
+                }
+                _ => return Err(PieceError::ShortRead { addr, read, len }),
+            }
+        }
+        let end = addr as u64 + len as u64;
+        for (&sector, contents) in self.unwritten.range(addr.saturating_sub(flash::SECTOR_SIZE - 1)..) {
+            if sector as u64 >= end {
+                break;
+            }
+            let (from, to) = (sector.max(addr), (sector as u64 + contents.len() as u64).min(end) as u32);
+            data[(from - addr) as usize..(to - addr) as usize]
+                .copy_from_slice(&contents[(from - sector) as usize..(to - sector) as usize]);
+        }
+        Ok(())
+    }
+    /// `len` bytes at `addr`; see [`Piece::get_memory`].
+    pub fn read_memory(&mut self, addr: u32, len: u32) -> Result<Vec<u8>> {
+        let mut data = vec![0; len as usize];
+        self.get_memory(addr, len, &mut data)?;
+        Ok(data)
+    }
+    /// `get_memory`, but with --paranoid the region is read again until two
+    /// consecutive reads agree. Only for memory that isn't expected to
+    /// change, like flash.
+    pub fn read_stable(&mut self, addr: u32, len: u32, data: &mut [u8]) -> Result<()> {
+        self.get_memory(addr, len, data)?;
+        if !self.options.paranoid {
+            return Ok(());
+        }
+        let mut again = vec![0; len as usize];
+        for _ in 1..PARANOID_READS {
+            self.get_memory(addr, len, &mut again)?;
+            if again[..] == data[..len as usize] {
+                return Ok(());
+            }
+            eprintln!("warning: reads of {:#x}+{:#x} disagree, reading again", addr, len);
+            data[..len as usize].copy_from_slice(&again);
+        }
+        Err(PieceError::Unstable { addr, len })
+    }
+    /// Write `data` at `addr`. RAM is written as it is; parts in flash are
+    /// merged into their sectors, and each sector that changes is erased,
+    /// programmed and read back to check it.
+    pub fn set_memory(&mut self, addr: u32, data: &[u8]) -> Result<()> {
+        let _span = trace::span("set_memory").arg("addr", format!("{:#x}", addr)).arg("len", data.len());
+        self.require_writable("write memory")?;
+        for (range, in_flash) in flash::split(addr, data.len()) {
+            let at = addr + range.start as u32;
+            match in_flash {
+                true => self.set_flash(at, &data[range])?,
+                false => self.set_ram(at, &data[range])?,
+            }
+        }
+        Ok(())
+    }
+    fn set_flash(&mut self, addr: u32, data: &[u8]) -> Result<()> {
+        let writes = flash::sector_writes(addr, data, |sector, contents| self.get_memory(sector, flash::SECTOR_SIZE, contents))?;
+        let mut written = vec![0; flash::SECTOR_SIZE as usize];
+        for write in writes {
+            self.write_flash_sector(write.addr, &write.data)?;
+            self.get_memory(write.addr, flash::SECTOR_SIZE, &mut written)?;
+            if written != write.data {
+                return Err(PieceError::WriteMismatch { addr: write.addr });
+            }
+        }
+        Ok(())
+    }
+    fn set_ram(&mut self, addr: u32, data: &[u8]) -> Result<()> {
+        kernel::require(self.kernel_version, Feature::MemoryWrite)?;
+        if self.options.dry_run {
+            println!("would write {} bytes of memory at {:#x}", data.len(), addr);
+            return Ok(());
+        }
+        audit::record(self, "write-memory", &format!("addr={:#x} len={}", addr, data.len()))?;
+        let tuning = self.options.tuning;
+        for (i, chunk) in data.chunks(32).enumerate() {
+            cancel::check()?;
+            let mut command: Vec<u8> = vec![3];
+            command.extend((addr + i as u32 * 32).to_le_bytes());
+            command.extend((chunk.len() as u32).to_le_bytes());
+            self.retry(|handle| {
+                wire::write(handle, &command, tuning.timeout(Transfer::Data, command.len()))?;
+                wire::write(handle, chunk, tuning.timeout(Transfer::Data, chunk.len()))
+            })?;
+            self.pace(chunk.len());
+        }
+        Ok(())
+    }
+    /// Jump to code at `addr`.
+    pub fn exec(&mut self, addr: u32) -> Result<()> {
+        let _span = trace::span("exec").arg("addr", format!("{:#x}", addr));
+        kernel::require(self.kernel_version, Feature::Exec)?;
+        self.require_writable("start code")?;
+        if self.options.dry_run {
+            println!("would start code at {:#x}", addr);
+            return Ok(());
+        }
+        audit::record(self, "exec", &format!("addr={:#x}", addr))?;
+        // What starts running may write files.
+        self.cached_meta = None;
+        let mut command: Vec<u8> = vec![4];
+        command.extend(addr.to_le_bytes());
+        let tuning = self.options.tuning;
+        // Not retried: if the command got through, the code is already running.
+        wire::write(&*self.transport, &command, tuning.timeout(Transfer::Control, command.len()))?;
+        self.paused = false;
+        Ok(())
+    }
+    /// Stop the running application until `resume`.
+    pub fn pause(&mut self) -> Result<()> {
+        let _span = trace::span("pause");
+        kernel::require(self.kernel_version, Feature::AppControl)?;
+        let tuning = self.options.tuning;
+        self.retry(|handle| wire::write(handle, &[16, 1], tuning.timeout(Transfer::Control, 2)))?;
+        self.paused = true;
+        Ok(())
+    }
+    /// Let the application stopped by [`pause`](Piece::pause) run again.
+    pub fn resume(&mut self) -> Result<()> {
+        let _span = trace::span("resume");
+        kernel::require(self.kernel_version, Feature::AppControl)?;
+        let tuning = self.options.tuning;
+        self.retry(|handle| wire::write(handle, &[16, 0], tuning.timeout(Transfer::Control, 2)))?;
+        self.paused = false;
+        Ok(())
+    }
+    /// Where [`Piece::halt`] notes that it stopped this device, since the
+    /// kernel can't be asked.
+    fn halt_record(&self) -> PathBuf {
+        dirs::state_dir().join("halted").join(self.serial.as_deref().unwrap_or("unknown"))
+    }
+    /// Stop the running application until [`Piece::unhalt`], even after
+    /// this connection is closed. Unlike a [`Piece::pause`], commands that
+    /// pause the device for a moment leave it stopped afterwards.
+    pub fn halt(&mut self) -> Result<()> {
+        self.pause()?;
+        fs::create_dir_all(dirs::state_dir().join("halted"))?;
+        fs::write(self.halt_record(), date::format(date::now_local()))?;
+        // Meant to outlast the connection.
+        self.paused = false;
+        Ok(())
+    }
+    /// Let the application stopped by [`Piece::halt`] run again.
+    pub fn unhalt(&mut self) -> Result<()> {
+        self.resume()?;
+        self.forget_halt()
+    }
+    fn forget_halt(&self) -> Result<()> {
+        match fs::remove_file(self.halt_record()) {
+            Err(error) if error.kind() != io::ErrorKind::NotFound => Err(error.into()),
+            _ => Ok(()),
+        }
+    }
+    /// When [`Piece::halt`] stopped the device, if piecer hasn't let it run
+    /// since. Unplugging it also lets it run, which piecer can't notice.
+    pub fn halted_since(&self) -> Option<String> {
+        fs::read_to_string(self.halt_record()).ok()
+    }
+    /// [`Piece::resume`], unless the device was halted before it was paused.
+    pub fn resume_unless_halted(&mut self) -> Result<()> {
+        match self.halted_since() {
+            Some(_) => Ok(()),
+            None => self.resume(),
+        }
+    }
+    /// Where the kernel currently displays from. Apps that double-buffer
+    /// change this every frame.
+    pub fn framebuffer_addr(&mut self) -> Result<u32> {
+        kernel::require(self.kernel_version, Feature::LcdInfo)?;
+        let mut lcd_data = [0; 12];
+        let tuning = self.options.tuning;
+        self.retry(|handle| {
+            wire::write(handle, &[17], tuning.timeout(Transfer::Control, 1))?;
+            wire::read(handle, &mut lcd_data, tuning.timeout(Transfer::Control, 12))
+        })?;
+        let lcd_width = lcd_data[2];
+        let lcd_height = lcd_data[4];
+        if lcd_width as usize != LCD_WIDTH || lcd_height as usize != LCD_HEIGHT {
+            return Err(PieceError::Protocol(format!("LCD is {}x{}", lcd_width, lcd_height)));
+        }
+        Ok(u32::from_le_bytes(lcd_data[8..12].try_into().unwrap()))
+    }
+    /// Hold down the keys in `mask` (see `input::Key`), overriding the real
+    /// pad until called again. A mask of 0 hands control back.
+    pub fn set_keys(&mut self, mask: u8) -> Result<()> {
+        let _span = trace::span("set_keys").arg("mask", format!("{:#04x}", mask));
+        kernel::require(self.kernel_version, Feature::KeyInject)?;
+        let tuning = self.options.tuning;
+        self.retry(|handle| wire::write(handle, &[18, mask], tuning.timeout(Transfer::Control, 2)))?;
+        Ok(())
+    }
+    /// Send `command`, and then `payload` if there is one, exactly as given,
+    /// and read up to `len` bytes of answer, for working out commands piecer
+    /// doesn't know. Nothing is retried, since an unknown command may not be
+    /// safe to repeat. Answers left over from earlier commands are read off
+    /// first, and whatever comes beyond `len` afterwards, so that the next
+    /// command starts clean. Returns what arrived, which is short if the
+    /// kernel stopped answering.
+    pub fn raw(&mut self, command: &[u8], payload: Option<&[u8]>, len: usize) -> Result<Vec<u8>> {
+        let hex: String = command.iter().map(|byte| format!("{:02x}", byte)).collect();
+        let _span = trace::span("raw").arg("cmd", &hex).arg("len", len);
+        // Unknown commands may well write flash.
+        self.require_writable("send raw commands")?;
+        if self.options.dry_run {
+            println!("would send command {} and read {} bytes", hex, len);
+            return Ok(Vec::new());
+        }
+        audit::record(self, "raw", &format!("cmd={} payload={} len={}", hex, payload.map_or(0, <[u8]>::len), len))?;
+        self.cached_meta = None;
+        self.drain();
+        let tuning = self.options.tuning;
+        wire::write(&*self.transport, command, tuning.timeout(Transfer::Control, command.len()))?;
+        if let Some(payload) = payload {
+            wire::write(&*self.transport, payload, tuning.timeout(Transfer::Data, payload.len()))?;
+        }
+        let mut answer = Vec::new();
+        while answer.len() < len {
+            // Whole packets, so an answer longer than asked for can't overflow.
+            let mut buf = vec![0; (len - answer.len()).next_multiple_of(64)];
+            let timeout = tuning.timeout(Transfer::Data, buf.len());
+            match wire::read(&*self.transport, &mut buf, timeout) {
+                Ok(0) | Err(rusb::Error::Timeout) => break,
+                Ok(n) => answer.extend(&buf[..n]),
+                Err(error) => return Err(error.into()),
+            }
+        }
+        answer.truncate(len);
+        self.drain();
+        self.last_transfer = Instant::now();
+        Ok(answer)
+    }
+    /// Read the framebuffer, one byte per pixel from 0 (black) to 3 (white).
+    pub fn capture(&mut self) -> Result<Vec<u8>> {
+        let _span = trace::span("screenshot");
+        kernel::require(self.kernel_version, Feature::LcdInfo)?;
+        self.pause()?;
+        let lcd_addr = self.framebuffer_addr()?;
+        let mut frame = vec![0; LCD_WIDTH * LCD_HEIGHT];
+        self.get_memory(lcd_addr, frame.len() as u32, &mut frame)?;
+        self.resume_unless_halted()?;
+        Ok(frame)
+    }
+    /// What the flash sector at `addr` holds, for dry-run output.
+    fn sector_use(&self, addr: u32) -> String {
+        if addr < self.pffs_top {
+            return "kernel area".to_string();
+        }
+        match ((addr - self.pffs_top) / self.pffs.cluster_size) as usize {
+            cluster if cluster < self.pffs.first_cluster() => "filesystem metadata".to_string(),
+            cluster => format!("cluster {}", cluster),
+        }
+    }
+    /// Erase the flash sector at `addr` and program it with `data`, which
+    /// must be exactly one sector.
+    pub fn write_flash_sector(&mut self, addr: u32, data: &[u8]) -> Result<()> {
+        let _span = trace::span("write_flash_sector").arg("addr", format!("{:#x}", addr));
+        kernel::require(self.kernel_version, Feature::FlashWrite)?;
+        self.require_writable("write flash")?;
+        if data.len() as u32 != flash::SECTOR_SIZE {
+            return Err(PieceError::BadRequest(format!("{} bytes for a flash sector of {}", data.len(), flash::SECTOR_SIZE)));
+        }
+        self.cached_meta = None;
+        if self.options.dry_run {
+            println!("would write flash sector {:#x} ({})", addr, self.sector_use(addr));
+            self.unwritten.insert(addr, data.to_vec());
+            return Ok(());
+        }
+        audit::record(self, "write-flash", &format!("addr={:#x} len={}", addr, data.len()))?;
+        let mut command: Vec<u8> = vec![5];
+        command.extend(addr.to_le_bytes());
+        command.extend((data.len() as u32).to_le_bytes());
+        let tuning = self.options.tuning;
+        // Safe to repeat, since the kernel erases the sector first.
+        self.retry(|handle| {
+            wire::write(handle, &command, tuning.timeout(Transfer::Data, command.len()))?;
+            wire::write(handle, data, tuning.timeout(Transfer::Flash, data.len()))
+        })?;
+        self.pace(data.len());
+        Ok(())
+    }
+}
+
+impl Drop for Piece {
+    /// Let an application paused by a command that failed or was cancelled
+    /// run again. Not retried, since the link may be why it failed.
+    fn drop(&mut self) {
+        if self.paused && self.halted_since().is_none() {
+            let _ = wire::write(&*self.transport, &[16, 0], self.options.tuning.timeout(Transfer::Control, 2));
+        }
+    }
+}
@@ -0,0 +1,21 @@
+//! The XDG base directories piecer reads and writes its own files in.
+
+use std::env;
+use std::path::PathBuf;
+
+/// Where piecer keeps its audit log, locks and other state:
+/// `$XDG_STATE_HOME/piecer`, or `~/.local/state/piecer`.
+pub fn state_dir() -> PathBuf {
+    match env::var_os("XDG_STATE_HOME") {
+        Some(dir) => PathBuf::from(dir).join("piecer"),
+        None => PathBuf::from(env::var_os("HOME").unwrap_or_default()).join(".local/state/piecer"),
+    }
+}
+
+/// Where config files go: `$XDG_CONFIG_HOME`, or `~/.config`.
+pub fn config_dir() -> PathBuf {
+    match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(env::var_os("HOME").unwrap_or_default()).join(".config"),
+    }
+}
@@ -0,0 +1,62 @@
+use crate::{chain, json, Piece, Result, FAT_FREE};
+
+/// Print each file's logical size next to the flash it occupies, counting
+/// whole clusters, then totals for the filesystem.
+pub fn report(piece: &mut Piece) -> Result<()> {
+    let fat = piece.read_fat()?;
+    let directory = piece.ls()?;
+    let geometry = piece.pffs;
+    let cluster_size = geometry.cluster_size as u64;
+    let (mut total_size, mut total_used) = (0u64, 0u64);
+    println!("FILE\tSIZE\tCLUSTERS\tUSED\tSLACK");
+    for dirent in &directory {
+        let used = chain(&fat, dirent.cluster).len() as u64 * cluster_size;
+        let size = dirent.len as u64;
+        println!("{}\t{}\t{}\t{}\t{}", dirent.name, size, used / cluster_size, used, used.saturating_sub(size));
+        total_size += size;
+        total_used += used;
+    }
+    let free = fat[geometry.first_cluster()..].iter().filter(|&&entry| entry == FAT_FREE).count() as u64 * cluster_size;
+    let capacity = geometry.data_clusters() as u64 * cluster_size;
+    println!("total\t{}\t{}\t{}\t{}", total_size, total_used / cluster_size, total_used, total_used.saturating_sub(total_size));
+    println!("{} of {} bytes used, {} free, {} lost to slack", total_used, capacity, free, total_used.saturating_sub(total_size));
+    let unaccounted = (capacity - free).saturating_sub(total_used);
+    if unaccounted > 0 {
+        println!("{} bytes in allocated clusters no file refers to", unaccounted);
+    }
+    Ok(())
+}
+
+/// Print filesystem-wide totals: space in clusters, like `df`, and directory
+/// slots, which run out too.
+pub fn df(piece: &mut Piece, as_json: bool) -> Result<()> {
+    let fat = piece.read_fat()?;
+    let files = piece.ls()?.len();
+    let geometry = piece.pffs;
+    let (cluster_size, slots) = (geometry.cluster_size as usize, geometry.file_slots());
+    let total = geometry.data_clusters();
+    let free = fat[geometry.first_cluster()..].iter().filter(|&&entry| entry == FAT_FREE).count();
+    let used = total - free;
+    if as_json {
+        let largest = if files < slots { free * cluster_size } else { 0 };
+        println!("{}", json::object(&[
+            ("cluster_size", cluster_size.to_string()),
+            ("total_clusters", total.to_string()),
+            ("used_clusters", used.to_string()),
+            ("free_clusters", free.to_string()),
+            ("files", files.to_string()),
+            ("directory_slots", slots.to_string()),
+            ("largest_file", largest.to_string()),
+        ]));
+        return Ok(());
+    }
+    println!("Size\tUsed\tFree\tUse%");
+    let kib = cluster_size / 1024;
+    println!("{}K\t{}K\t{}K\t{}%", total * kib, used * kib, free * kib, (used * 100).div_ceil(total.max(1)));
+    println!("{} of {} clusters free, {} of {} directory slots used", free, total, files, slots);
+    match files < slots {
+        true => println!("A file of up to {} bytes fits", free * cluster_size),
+        false => println!("The directory is full"),
+    }
+    Ok(())
+}
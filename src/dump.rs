@@ -0,0 +1,153 @@
+use crate::flash::{self, FLASH_BASE, FLASH_SIZE, SECTOR_SIZE};
+use crate::progress;
+use crate::resume;
+use crate::{Piece, PieceError, Result};
+use piecer::memmap::{IRAM_BASE, IRAM_SIZE, SRAM_BASE};
+use clap::ValueEnum;
+use std::fs::OpenOptions;
+use std::io::{Seek, SeekFrom, Write};
+use std::path::Path;
+
+const CHUNK_SIZE: u32 = 0x10000;
+
+/// Memory areas that can be dumped by name.
+#[derive(Clone, Copy, Default, ValueEnum)]
+pub enum Region {
+    /// The whole flash chip, kernel and filesystem
+    #[default]
+    Flash,
+    /// The CPU's internal RAM
+    Ram,
+    /// External SRAM, where applications run
+    Sram,
+}
+
+impl Region {
+    /// Start address and length of the region on this device.
+    pub fn bounds(self, piece: &mut Piece) -> Result<(u32, u32)> {
+        Ok(match self {
+            Region::Flash => (FLASH_BASE, flash::geometry(piece)?.size),
+            Region::Ram => (IRAM_BASE, IRAM_SIZE),
+            Region::Sram => {
+                let sram_end = piece.system_info()?.sram_end;
+                assert!(sram_end > SRAM_BASE, "SYSTEMINFO reports an unexpected SRAM end of {:#x}", sram_end);
+                (SRAM_BASE, sram_end - SRAM_BASE)
+            }
+        })
+    }
+    /// Where a dump of the region goes by default.
+    pub fn file_name(self) -> &'static str {
+        match self {
+            Region::Flash => "dump.img",
+            Region::Ram => "ram.bin",
+            Region::Sram => "sram.bin",
+        }
+    }
+}
+
+/// Read flash with `read_stable`, since it shouldn't change under us; RAM
+/// that a running app is using can.
+fn read(piece: &mut Piece, addr: u32, data: &mut [u8]) -> Result<()> {
+    match (FLASH_BASE..FLASH_BASE + FLASH_SIZE).contains(&addr) {
+        true => piece.read_stable(addr, data.len() as u32, data),
+        false => piece.get_memory(addr, data.len() as u32, data),
+    }
+}
+
+/// Stream `len` bytes of memory from `base` to `out` in chunks as they arrive.
+pub fn to_writer(piece: &mut Piece, base: u32, len: u32, out: &mut dyn Write) -> Result<()> {
+    let mut chunk = vec![0; CHUNK_SIZE as usize];
+    for start in (0..len).step_by(CHUNK_SIZE as usize) {
+        let chunk = &mut chunk[..CHUNK_SIZE.min(len - start) as usize];
+        read(piece, base + start, chunk)?;
+        out.write_all(chunk).map_err(PieceError::host_io("Could not write dump"))?;
+        progress::update(None, (start + chunk.len() as u32) as u64, len as u64);
+    }
+    out.flush().map_err(PieceError::host_io("Could not write dump"))
+}
+
+/// Dump `len` bytes of memory from `base` to `path` in chunks, recording
+/// progress in `path.state` so an interrupted dump can be continued with
+/// `resume`.
+pub fn to_file(piece: &mut Piece, base: u32, len: u32, path: &Path, resume: bool) -> Result<()> {
+    let mut state_path = path.as_os_str().to_owned();
+    state_path.push(".state");
+    let mut state = resume::State::open(Path::new(&state_path), resume);
+    let mut file = OpenOptions::new().create(true).write(true).truncate(!resume).open(path)
+        .map_err(PieceError::host_io(format!("Could not create dump file {}", path.display())))?;
+    let mut chunk = vec![0; CHUNK_SIZE as usize];
+    for start in (0..len).step_by(CHUNK_SIZE as usize) {
+        let size = CHUNK_SIZE.min(len - start);
+        let step = format!("{:#x}+{:#x}", start, size);
+        if state.is_done(&step) {
+            continue;
+        }
+        // A chunk that failed partway through resumes after the bytes it got.
+        let offset = partial(&state, start);
+        let left = size - offset;
+        let result = read(piece, base + start + offset, &mut chunk[..left as usize]);
+        let read = match result {
+            Ok(()) => left,
+            Err(PieceError::ShortRead { read, .. }) => read,
+            Err(error) => return Err(error),
+        };
+        file.seek(SeekFrom::Start((start + offset) as u64))
+            .and_then(|_| file.write_all(&chunk[..read as usize]))
+            .and_then(|()| file.sync_data())
+            .map_err(PieceError::host_io(format!("Could not write dump file {}", path.display())))?;
+        if let Err(error) = result {
+            state.mark_done(&format!("{:#x}+{:#x}", start, offset + read));
+            eprintln!("Rerun with --resume to continue from {:#x}", base + start + offset + read);
+            return Err(error);
+        }
+        state.mark_done(&step);
+        progress::update(None, (start + size) as u64, len as u64);
+    }
+    state.finish();
+    Ok(())
+}
+
+/// How much of the chunk at `start` an earlier, failed run already saved.
+fn partial(state: &resume::State, start: u32) -> u32 {
+    let prefix = format!("{:#x}+", start);
+    state.done()
+        .filter_map(|step| step.strip_prefix(&prefix))
+        .filter_map(|len| u32::from_str_radix(len.trim_start_matches("0x"), 16).ok())
+        .max()
+        .unwrap_or(0)
+}
+
+/// Write a flash image made by `dump` back to the device, sector by sector.
+///
+/// Only the filesystem is written unless `kernel`: the kernel is running from
+/// flash while we rewrite it, so a bad image there needs `bootstrap` to undo.
+/// Sectors that already match are skipped, and the PFFS metadata sector goes
+/// last so an interrupted restore never points at clusters it hasn't written.
+pub fn restore(piece: &mut Piece, image: &[u8], kernel: bool) -> Result<()> {
+    if image.len() != FLASH_SIZE as usize {
+        return Err(PieceError::BadInput(format!("Image is {} bytes, expected a {} byte flash dump", image.len(), FLASH_SIZE)));
+    }
+    let first = match kernel {
+        true => FLASH_BASE,
+        false => piece.pffs_top,
+    };
+    let mut sectors: Vec<u32> = (first..FLASH_BASE + FLASH_SIZE).step_by(SECTOR_SIZE as usize)
+        .filter(|&addr| addr != piece.pffs_top)
+        .collect();
+    sectors.push(piece.pffs_top);
+    let total = (sectors.len() as u32 * SECTOR_SIZE) as u64;
+    let mut current = vec![0; SECTOR_SIZE as usize];
+    let mut written = 0;
+    for (i, &addr) in sectors.iter().enumerate() {
+        let offset = (addr - FLASH_BASE) as usize;
+        let wanted = &image[offset..offset + SECTOR_SIZE as usize];
+        piece.read_stable(addr, SECTOR_SIZE, &mut current)?;
+        if current != wanted {
+            piece.write_flash_sector(addr, wanted)?;
+            written += 1;
+        }
+        progress::update(None, (i as u32 + 1) as u64 * SECTOR_SIZE as u64, total);
+    }
+    println!("{} of {} sectors rewritten", written, sectors.len());
+    Ok(())
+}
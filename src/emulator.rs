@@ -0,0 +1,76 @@
+//! `piecer emulator`: moving files between the device and P/ECE emulators.
+//!
+//! Emulators that run the real kernel boot from a dump of all of flash,
+//! and the others load applications and their data from a directory, so an
+//! export holds both:
+//!
+//! - `flash.bin`: flash from `FLASH_BASE`, kernel included, as `dump` writes it
+//! - `files/`: every file in the filesystem, under its host name
+
+use crate::backup;
+use crate::dump;
+use crate::names;
+use crate::offline;
+use crate::progress;
+use crate::{Piece, PieceError, Result};
+use piecer::flash::{FLASH_BASE, FLASH_SIZE};
+use piecer::pffs::{Image, PffsGeometry};
+use std::fs;
+use std::path::Path;
+
+/// The flash image in an export.
+const FLASH: &str = "flash.bin";
+/// The directory of files in an export.
+const FILES: &str = "files";
+
+/// Write `flash`, a dump from `FLASH_BASE`, and the files of its filesystem
+/// at `pffs_top` (found if not given) into `dir` as an emulator takes them.
+pub fn export(flash: Vec<u8>, pffs_top: Option<u32>, geometry: Option<PffsGeometry>, dir: &Path) -> Result<()> {
+    let files = dir.join(FILES);
+    fs::create_dir_all(&files).map_err(PieceError::host_io(format!("Could not create export directory {}", files.display())))?;
+    fs::write(dir.join(FLASH), &flash).map_err(PieceError::host_io("Could not write flash image"))?;
+    let image = Image::new(flash, pffs_top, geometry)
+        .ok_or_else(|| PieceError::BadInput("Could not find PFFS in the flash image; pass --pffs-top".to_string()))?;
+    let directory: Vec<_> = image.ls().into_iter().filter(|dirent| match dirent.problem {
+        Some(problem) => {
+            eprintln!("warning: skipping entry {} {:?}: {}", dirent.index, dirent.name, problem);
+            false
+        }
+        None => true,
+    }).collect();
+    for (dirent, host) in directory.iter().zip(names::hosts(directory.iter().map(|dirent| dirent.name.as_str()))) {
+        let data = image.read_file(&dirent.name)?;
+        fs::write(files.join(&host), &data).map_err(PieceError::host_io(format!("Could not write exported file {}", host)))?;
+        println!("{}\t{}", dirent.name, data.len());
+    }
+    println!("Exported {} and {} files to {}", FLASH, directory.len(), dir.display());
+    Ok(())
+}
+
+/// [`export`] the device's flash.
+pub fn export_device(piece: &mut Piece, dir: &Path) -> Result<()> {
+    let mut flash = Vec::with_capacity(FLASH_SIZE as usize);
+    dump::to_writer(piece, FLASH_BASE, FLASH_SIZE, &mut flash)?;
+    progress::end();
+    export(flash, Some(piece.pffs_top), Some(piece.pffs), dir)
+}
+
+/// Upload the files from `source` that the device lacks or has with other
+/// contents: an emulator's flash image, an export from [`export`], whose
+/// flash image keeps the device names, or a plain directory of files.
+/// Returns the exit code: 1 if any file couldn't be written.
+pub fn import(piece: &mut Piece, source: &Path, geometry: Option<PffsGeometry>, force: bool) -> Result<i32> {
+    let image = match source.is_dir() {
+        true => source.join(FLASH),
+        false => source.to_path_buf(),
+    };
+    if !image.is_file() {
+        let files = source.join(FILES);
+        return backup::restore_dir(piece, if files.is_dir() { &files } else { source }, force, false);
+    }
+    let image = offline::open(&image, None, geometry)?;
+    let files = image.ls().into_iter().filter(|dirent| dirent.problem.is_none())
+        .map(|dirent| Ok((dirent.name.clone(), image.read_file(&dirent.name)?)))
+        .collect::<Result<Vec<_>>>()?;
+    backup::restore_files(piece, files, force)
+}
@@ -0,0 +1,253 @@
+//! [`PieceError`], every way a piecer operation can fail, and the exit
+//! codes and messages the CLI gives for them.
+
+use crate::date;
+use crate::i18n;
+use crate::kernel::{self, Feature};
+use std::fmt;
+use std::io;
+
+/// What every fallible piecer call returns.
+pub type Result<T> = std::result::Result<T, PieceError>;
+
+/// Why talking to the device failed.
+#[derive(Debug)]
+pub enum PieceError {
+    /// A USB transfer or the device enumeration failed.
+    Usb(rusb::Error),
+    /// Writing the audit log or a downloaded file failed.
+    Io(io::Error),
+    /// No device is attached, or none matches `wanted`, e.g. "serial 1234"
+    /// or "bus 1 address 5". `found` lists the serial numbers of the others.
+    DeviceNotFound {
+        /// Which device was asked for, if one was.
+        wanted: Option<String>,
+        /// Serial numbers of the devices that are attached.
+        found: Vec<String>,
+    },
+    /// No file of that name is on the device.
+    FileNotFound(String),
+    /// A file of that name is already on the device.
+    FileExists(String),
+    /// The directory entry in `index` can't be trusted.
+    CorruptEntry {
+        /// Slot in the directory table.
+        index: usize,
+        /// What is wrong with it.
+        problem: &'static str,
+    },
+    /// Reading `len` bytes at `addr` failed after `read` of them, retries
+    /// included.
+    ShortRead {
+        /// Where the read started.
+        addr: u32,
+        /// Bytes that arrived.
+        read: u32,
+        /// Bytes asked for.
+        len: u32,
+    },
+    /// With --paranoid, reads of a region kept coming back different.
+    Unstable {
+        /// Where the region starts.
+        addr: u32,
+        /// Its length in bytes.
+        len: u32,
+    },
+    /// The flash sector at `addr` didn't read back as it was programmed.
+    WriteMismatch {
+        /// Start of the sector.
+        addr: u32,
+    },
+    /// Another piecer process, `pid` if it's known, is using the device.
+    Locked {
+        /// Process ID from the lock file.
+        pid: Option<u32>,
+    },
+    /// The kernel driver `driver` has the interface, or another program if
+    /// there is none.
+    InterfaceBusy {
+        /// Name of the kernel driver.
+        driver: Option<String>,
+    },
+    /// The kernel driver `driver` has the interface and couldn't be detached.
+    DetachFailed {
+        /// Name of the kernel driver.
+        driver: String,
+        /// Why detaching failed, as the system said.
+        reason: String,
+    },
+    /// The device replied with something the protocol doesn't allow.
+    Protocol(String),
+    /// The running kernel is too old for `feature`.
+    Unsupported {
+        /// The running kernel's version, in BCD.
+        version: u16,
+        /// What the operation needs.
+        feature: Feature,
+    },
+    /// The operation would modify the device in read-only mode.
+    ReadOnly(&'static str),
+    /// The name takes more than the directory's 24 bytes.
+    NameTooLong(String),
+    /// The name has characters the device's name encoding lacks.
+    UnencodableName(String),
+    /// Every directory slot is taken.
+    DirectoryFull,
+    /// Too few free clusters for the file.
+    NoSpace,
+    /// A cluster number past the last of the filesystem's `clusters`.
+    NoSuchCluster {
+        /// The cluster asked for.
+        cluster: u32,
+        /// How many the filesystem has.
+        clusters: usize,
+    },
+    /// Resuming a backup that has encrypted files, without the passphrase.
+    EncryptedResume,
+    /// A time, in seconds since the Unix epoch, the device clock can't hold.
+    ClockRange(i64),
+    /// The write would leave too little room, and the config asks to refuse.
+    LowSpace(String),
+    /// Stopped by [`cancel`](crate::cancel), e.g. on Ctrl-C.
+    Cancelled,
+    /// The filesystem has the problems [`check`](crate::pffs::check) lists,
+    /// and the operation won't go ahead until fsck has repaired them.
+    NeedsRepair(Vec<String>),
+    /// A file on the host couldn't be read or written; `what` says which,
+    /// e.g. "Could not read image dump.img".
+    HostIo {
+        /// What was being done.
+        what: String,
+        /// Why it failed.
+        error: io::Error,
+    },
+    /// A file named on the command line isn't there, e.g. in a backup.
+    NotFound(String),
+    /// A file given to piecer isn't usable: a corrupt archive or config, a
+    /// wrong passphrase, or a firmware image that fails its checks.
+    BadInput(String),
+    /// The command line asks for something that can't be done.
+    Usage(String),
+    /// A call was given arguments that don't fit together: a buffer shorter
+    /// than the read, a range that wraps around the address space, or data
+    /// that isn't a whole sector or cluster.
+    BadRequest(String),
+}
+
+impl PieceError {
+    /// Process exit code for the CLI: 2 when there is no device to talk to,
+    /// 3 when a file is missing, 130 as for SIGINT when cancelled, and 1 for
+    /// everything else.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            PieceError::DeviceNotFound { .. } => 2,
+            PieceError::FileNotFound(_) | PieceError::NotFound(_) => 3,
+            PieceError::Cancelled => 130,
+            _ => 1,
+        }
+    }
+    /// Make an I/O error a [`PieceError::HostIo`] saying `what` failed, for
+    /// `map_err`.
+    pub fn host_io(what: impl Into<String>) -> impl FnOnce(io::Error) -> PieceError {
+        let what = what.into();
+        move |error| PieceError::HostIo { what, error }
+    }
+}
+
+impl fmt::Display for PieceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let message = match self {
+            PieceError::Usb(error) => i18n::trf("USB error: {}", &[error]),
+            PieceError::Io(error) => error.to_string(),
+            PieceError::DeviceNotFound { wanted: None, .. } => i18n::tr("Could not open PIECE device").to_string(),
+            PieceError::DeviceNotFound { wanted: Some(wanted), found } => {
+                let found = match found.is_empty() {
+                    true => "none".to_string(),
+                    false => found.join(", "),
+                };
+                format!("No device with {} (found: {})", wanted, found)
+            }
+            PieceError::FileNotFound(name) => i18n::trf("Could not find {} on device", &[name]),
+            PieceError::FileExists(name) => i18n::trf("{} already exists on device", &[name]),
+            PieceError::CorruptEntry { index, problem } => {
+                i18n::trf("Directory entry {} is corrupt: {}", &[index, &i18n::tr(problem)])
+            }
+            PieceError::ShortRead { addr, read, len } => {
+                i18n::trf("Read of {} failed after {} of {} bytes", &[&format!("{:#x}", addr), read, len])
+            }
+            PieceError::Unstable { addr, len } => {
+                format!("Reads of {:#x}+{:#x} kept disagreeing", addr, len)
+            }
+            PieceError::WriteMismatch { addr } => {
+                format!("Flash sector at {:#x} did not read back as written", addr)
+            }
+            PieceError::Locked { pid: Some(pid) } => {
+                i18n::trf("The device is in use by piecer process {}; pass --queue to wait for it", &[pid])
+            }
+            PieceError::Locked { pid: None } => {
+                i18n::tr("The device is in use by another piecer; pass --queue to wait for it").to_string()
+            }
+            PieceError::InterfaceBusy { driver: Some(driver) } => {
+                i18n::trf("The device is claimed by the {} kernel driver; leave out --no-detach to take it over", &[driver])
+            }
+            PieceError::InterfaceBusy { driver: None } => {
+                i18n::tr("The device is in use by another program; close it and try again").to_string()
+            }
+            PieceError::DetachFailed { driver, reason } => {
+                i18n::trf("Could not detach the {} kernel driver from the device ({}); run as root or give yourself the device with a udev rule",
+                          &[driver, reason])
+            }
+            PieceError::Protocol(message) => format!("Unexpected reply from device: {}", message),
+            PieceError::Unsupported { version, feature } => {
+                i18n::trf("Your kernel {} doesn't support {}, update to {} or later",
+                          &[&kernel::version_string(*version), &i18n::tr(feature.description()),
+                            &kernel::version_string(feature.min_version())])
+            }
+            PieceError::ReadOnly(operation) => {
+                i18n::trf("Refusing to {}: piecer is in read-only mode", &[&i18n::tr(operation)])
+            }
+            PieceError::NameTooLong(_) => i18n::tr("File name is longer than 24 bytes").to_string(),
+            PieceError::UnencodableName(name) => i18n::trf("{} can't be written in the device's name encoding", &[name]),
+            PieceError::DirectoryFull => i18n::tr("Directory is full").to_string(),
+            PieceError::NoSpace => i18n::tr("Not enough free space on device").to_string(),
+            PieceError::NoSuchCluster { cluster, clusters } => {
+                i18n::trf("There is no cluster {}; the filesystem has {}", &[cluster, clusters])
+            }
+            PieceError::EncryptedResume => i18n::tr("Resume of an encrypted backup needs --encrypt and its passphrase").to_string(),
+            PieceError::ClockRange(time) => {
+                i18n::trf("The device clock can't be set to {}; it runs from 2000-01-01 to 2179-06-06", &[&date::format(*time)])
+            }
+            PieceError::LowSpace(message) => i18n::trf("Refusing: {} (use --force to write anyway)", &[message]),
+            PieceError::Cancelled => i18n::tr("Interrupted").to_string(),
+            PieceError::NeedsRepair(problems) => {
+                i18n::trf("The filesystem has problems; repair it with fsck first: {}", &[&problems.join("; ")])
+            }
+            PieceError::HostIo { what, error } => format!("{}: {}", what, error),
+            PieceError::NotFound(message) | PieceError::BadInput(message) | PieceError::Usage(message)
+                | PieceError::BadRequest(message) => message.clone(),
+        };
+        f.write_str(&message)
+    }
+}
+
+impl std::error::Error for PieceError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            PieceError::Usb(error) => Some(error),
+            PieceError::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<rusb::Error> for PieceError {
+    fn from(error: rusb::Error) -> PieceError {
+        PieceError::Usb(error)
+    }
+}
+
+impl From<io::Error> for PieceError {
+    fn from(error: io::Error) -> PieceError {
+        PieceError::Io(error)
+    }
+}
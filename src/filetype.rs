@@ -0,0 +1,60 @@
+//! Telling what a device file holds from its first bytes.
+
+/// Signature at the start of every P/ECE executable.
+pub const PEX_MAGIC: &[u8] = b"pCeX";
+
+/// Bytes of a file's first cluster needed to tell its type.
+pub const HEAD_LEN: u32 = 16;
+
+/// What a file holds, judged from its first bytes rather than its name.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    /// A P/ECE executable.
+    Pex,
+    /// A PNG image.
+    Png,
+    /// Gzip-compressed data.
+    Gzip,
+    /// A zip archive.
+    Zip,
+    /// A WAV sound.
+    Wav,
+    /// Anything else.
+    Data,
+    /// A file of no bytes.
+    Empty,
+}
+
+impl Kind {
+    /// The kind of a file starting with `head`, its first [`HEAD_LEN`] bytes
+    /// or all of it if shorter.
+    pub fn detect(head: &[u8]) -> Kind {
+        if head.is_empty() {
+            Kind::Empty
+        } else if head.starts_with(PEX_MAGIC) {
+            Kind::Pex
+        } else if head.starts_with(b"\x89PNG\r\n\x1a\n") {
+            Kind::Png
+        } else if head.starts_with(&[0x1f, 0x8b]) {
+            Kind::Gzip
+        } else if head.starts_with(b"PK\x03\x04") {
+            Kind::Zip
+        } else if head.starts_with(b"RIFF") && head.get(8..12) == Some(b"WAVE") {
+            Kind::Wav
+        } else {
+            Kind::Data
+        }
+    }
+    /// Short lowercase name, as `ls` shows it.
+    pub fn label(self) -> &'static str {
+        match self {
+            Kind::Pex => "pex",
+            Kind::Png => "png",
+            Kind::Gzip => "gzip",
+            Kind::Zip => "zip",
+            Kind::Wav => "wav",
+            Kind::Data => "data",
+            Kind::Empty => "empty",
+        }
+    }
+}
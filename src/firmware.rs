@@ -0,0 +1,59 @@
+use crate::bootstrap;
+use crate::crc32::crc32;
+use crate::{Piece, Result};
+use piecer::flash::{FLASH_BASE, FLASH_SIZE, SECTOR_SIZE};
+use piecer::progress;
+
+/// Size of the kernel area at the start of flash, which ends where PFFS
+/// starts.
+pub fn kernel_area(piece: &Piece) -> u32 {
+    assert!(piece.pffs_top > FLASH_BASE && piece.pffs_top < FLASH_BASE + FLASH_SIZE,
+            "The device doesn't report where its kernel area ends");
+    piece.pffs_top - FLASH_BASE
+}
+
+/// Why `image` can't be a firmware image for a kernel area of `area`
+/// bytes: it has to fill the area exactly, not be blank, and start with a
+/// reset vector into the area, and with `crc` have that CRC-32.
+pub fn validate(image: &[u8], area: u32, crc: Option<u32>) -> std::result::Result<(), String> {
+    if image.len() as u32 != area {
+        return Err(format!("Image is {} bytes, but the kernel area is {} bytes", image.len(), area));
+    }
+    if image.iter().all(|&byte| byte == 0xff) || image.iter().all(|&byte| byte == 0) {
+        return Err("Image is blank".to_string());
+    }
+    let reset = u32::from_le_bytes(image[..4].try_into().unwrap());
+    if !(FLASH_BASE..FLASH_BASE + area).contains(&reset) {
+        return Err(format!("Reset vector {:#x} is outside the kernel area; this doesn't look like a kernel image", reset));
+    }
+    if let Some(crc) = crc.filter(|&crc| crc != crc32(image)) {
+        return Err(format!("Image CRC-32 is {:08x}, expected {:08x}", crc32(image), crc));
+    }
+    Ok(())
+}
+
+/// Program a validated `image` over the kernel area, reading each sector
+/// back. Sectors that already match are skipped, and the first sector,
+/// which holds the vectors, goes last so an interrupted update still boots
+/// into the old reset handler rather than into a half-written kernel.
+pub fn program(piece: &mut Piece, image: &[u8]) -> Result<()> {
+    let mut sectors: Vec<u32> = (SECTOR_SIZE..image.len() as u32).step_by(SECTOR_SIZE as usize).collect();
+    sectors.push(0);
+    let total = image.len() as u64;
+    let mut current = vec![0; SECTOR_SIZE as usize];
+    let mut written = 0;
+    for (i, &offset) in sectors.iter().enumerate() {
+        let addr = FLASH_BASE + offset;
+        let wanted = &image[offset as usize..(offset + SECTOR_SIZE) as usize];
+        piece.read_stable(addr, SECTOR_SIZE, &mut current)?;
+        if current != wanted {
+            piece.write_flash_sector(addr, wanted)?;
+            bootstrap::verify(piece, addr, wanted)?;
+            written += 1;
+        }
+        progress::update(None, (i as u64 + 1) * SECTOR_SIZE as u64, total);
+    }
+    println!("{} of {} sectors rewritten and verified; power-cycle the device to boot the new kernel",
+             written, sectors.len());
+    Ok(())
+}
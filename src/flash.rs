@@ -0,0 +1,176 @@
+//! The flash chip the kernel and PFFS live in.
+
+use crate::{Piece, Result};
+use std::ops::Range;
+
+/// Where flash is mapped, and its size on a stock P/ECE.
+pub const FLASH_BASE: u32 = 0xc00000;
+/// Size of the stock P/ECE's flash, 2 MiB.
+pub const FLASH_SIZE: u32 = 0x200000;
+
+/// Sector size of the SST39VF160 the P/ECE ships with; the erase unit, and so
+/// also what `write_flash_sector` takes.
+pub const SECTOR_SIZE: u32 = 4096;
+
+/// How flash is laid out, as far as the kernel tells us.
+pub struct Geometry {
+    /// Where flash is mapped.
+    pub base: u32,
+    /// Size of the chip.
+    pub size: u32,
+    /// End of the kernel area, where PFFS starts.
+    pub kernel_end: u32,
+    /// End of PFFS, and of the area piecer writes.
+    pub pffs_end: u32,
+}
+
+/// Work out the layout from the device's SYSTEMINFO.
+pub fn geometry(piece: &mut Piece) -> Result<Geometry> {
+    let pffs_end = piece.system_info()?.pffs_end;
+    // PFFS runs to the end of the chip, so its end gives the chip size.
+    let size = match pffs_end > FLASH_BASE {
+        true => (pffs_end - FLASH_BASE).next_power_of_two(),
+        false => FLASH_SIZE,
+    };
+    Ok(Geometry { base: FLASH_BASE, size, kernel_end: piece.pffs_top, pffs_end })
+}
+
+/// Print the flash layout and its write constraints.
+///
+/// The chip ID isn't queried: that needs the chip in ID mode, which would
+/// crash the kernel that is running from it.
+pub fn info(piece: &mut Piece) -> Result<()> {
+    let geometry = geometry(piece)?;
+    println!("flash       {:#x}-{:#x} ({} KiB)", geometry.base, geometry.base + geometry.size, geometry.size / 1024);
+    println!("sectors     {} of {} bytes", geometry.size / SECTOR_SIZE, SECTOR_SIZE);
+    println!("kernel      {:#x}-{:#x} ({} sectors)", geometry.base, geometry.kernel_end,
+             (geometry.kernel_end - geometry.base) / SECTOR_SIZE);
+    println!("filesystem  {:#x}-{:#x} ({} sectors)", geometry.kernel_end, geometry.pffs_end,
+             (geometry.pffs_end - geometry.kernel_end) / SECTOR_SIZE);
+    match geometry.size {
+        0x200000 => println!("chip        SST39VF160 (inferred from size; not probed)"),
+        _ => println!("chip        unknown"),
+    }
+    println!("writes      whole {} byte sectors only, erased to 0xFF first", SECTOR_SIZE);
+    Ok(())
+}
+
+/// Whether `addr` is in the flash window.
+pub fn contains(addr: u32) -> bool {
+    (FLASH_BASE..FLASH_BASE + FLASH_SIZE).contains(&addr)
+}
+
+/// `len` bytes from `addr` split where they cross into or out of flash, as
+/// ranges of offsets from `addr`, each marked with whether it is in flash.
+pub fn split(addr: u32, len: usize) -> Vec<(Range<usize>, bool)> {
+    let end = addr as u64 + len as u64;
+    let mut edges = vec![addr as u64];
+    edges.extend([FLASH_BASE as u64, (FLASH_BASE + FLASH_SIZE) as u64].into_iter().filter(|&edge| edge > addr as u64 && edge < end));
+    edges.push(end);
+    edges.windows(2).filter(|pair| pair[0] < pair[1])
+        .map(|pair| ((pair[0] - addr as u64) as usize..(pair[1] - addr as u64) as usize, contains(pair[0] as u32)))
+        .collect()
+}
+
+/// A whole sector to program at `addr`.
+#[derive(Debug, PartialEq)]
+pub struct SectorWrite {
+    /// Start of the sector.
+    pub addr: u32,
+    /// All of the sector's bytes.
+    pub data: Vec<u8>,
+}
+
+/// The sectors to program so flash from `addr` holds `data`. Sectors only
+/// partly covered are read with `read` and merged, and sectors that already
+/// hold the right bytes are left out.
+pub fn sector_writes(addr: u32, data: &[u8], mut read: impl FnMut(u32, &mut [u8]) -> Result<()>) -> Result<Vec<SectorWrite>> {
+    let end = addr + data.len() as u32;
+    let mut writes = Vec::new();
+    let mut sector = addr - addr % SECTOR_SIZE;
+    while sector < end {
+        let (from, to) = (addr.max(sector), end.min(sector + SECTOR_SIZE));
+        let wanted = &data[(from - addr) as usize..(to - addr) as usize];
+        let mut contents = vec![0; SECTOR_SIZE as usize];
+        read(sector, &mut contents)?;
+        let part = &mut contents[(from - sector) as usize..(to - sector) as usize];
+        if part != wanted {
+            part.copy_from_slice(wanted);
+            writes.push(SectorWrite { addr: sector, data: contents });
+        }
+        sector += SECTOR_SIZE;
+    }
+    Ok(writes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A flash chip to merge against, and the sectors that were read.
+    struct Chip {
+        memory: Vec<u8>,
+        reads: Vec<u32>,
+    }
+
+    impl Chip {
+        fn new() -> Chip {
+            let memory = (0..FLASH_SIZE).map(|i| (i / SECTOR_SIZE) as u8).collect();
+            Chip { memory, reads: Vec::new() }
+        }
+
+        fn read(&mut self, addr: u32, data: &mut [u8]) -> Result<()> {
+            self.reads.push(addr);
+            let at = (addr - FLASH_BASE) as usize;
+            data.copy_from_slice(&self.memory[at..at + data.len()]);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn partial_write_keeps_the_rest_of_the_sector() {
+        let mut chip = Chip::new();
+        let addr = FLASH_BASE + SECTOR_SIZE * 3 + 10;
+        let writes = sector_writes(addr, &[0xaa; 4], |addr, data| chip.read(addr, data)).unwrap();
+        assert_eq!(writes.len(), 1);
+        assert_eq!(writes[0].addr, FLASH_BASE + SECTOR_SIZE * 3);
+        assert_eq!(&writes[0].data[..10], &[3; 10]);
+        assert_eq!(&writes[0].data[10..14], &[0xaa; 4]);
+        assert!(writes[0].data[14..].iter().all(|&byte| byte == 3));
+        assert_eq!(chip.reads, [FLASH_BASE + SECTOR_SIZE * 3]);
+    }
+
+    #[test]
+    fn write_across_a_boundary_touches_both_sectors() {
+        let mut chip = Chip::new();
+        let addr = FLASH_BASE + SECTOR_SIZE - 2;
+        let writes = sector_writes(addr, &[0x55; 4], |addr, data| chip.read(addr, data)).unwrap();
+        let addrs: Vec<u32> = writes.iter().map(|write| write.addr).collect();
+        assert_eq!(addrs, [FLASH_BASE, FLASH_BASE + SECTOR_SIZE]);
+        assert_eq!(&writes[0].data[SECTOR_SIZE as usize - 2..], &[0x55; 2]);
+        assert_eq!(&writes[1].data[..3], &[0x55, 0x55, 1]);
+    }
+
+    #[test]
+    fn unchanged_sectors_are_skipped() {
+        let mut chip = Chip::new();
+        let mut data = vec![0; SECTOR_SIZE as usize];
+        data.extend([1; 8]);
+        data[5] = 0x42;
+        let writes = sector_writes(FLASH_BASE, &data, |addr, data| chip.read(addr, data)).unwrap();
+        assert_eq!(writes.len(), 1);
+        assert_eq!(writes[0].addr, FLASH_BASE);
+        assert_eq!(writes[0].data[5], 0x42);
+        let again = sector_writes(FLASH_BASE + SECTOR_SIZE, &[1; 16], |addr, data| chip.read(addr, data)).unwrap();
+        assert!(again.is_empty());
+    }
+
+    #[test]
+    fn split_separates_ram_from_flash() {
+        assert_eq!(split(0x100000, 16), [(0..16, false)]);
+        assert_eq!(split(FLASH_BASE + 16, 16), [(0..16, true)]);
+        assert_eq!(split(FLASH_BASE - 4, 8), [(0..4, false), (4..8, true)]);
+        assert_eq!(split(FLASH_BASE + FLASH_SIZE - 4, 8), [(0..4, true), (4..8, false)]);
+        assert!(split(FLASH_BASE, 0).is_empty());
+    }
+}
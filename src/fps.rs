@@ -0,0 +1,64 @@
+use crate::crc32::crc32;
+use crate::{Piece, Result, LCD_HEIGHT, LCD_WIDTH};
+use std::time::{Duration, Instant};
+
+/// Sample the app's frame progress for `duration` and print frame rate and
+/// frame-time statistics. `counter` is the address of a frame counter; without
+/// one, any change of framebuffer address or contents counts as a frame.
+pub fn measure(piece: &mut Piece, duration: Duration, counter: Option<u32>) -> Result<()> {
+    let sample = |piece: &mut Piece| -> Result<u32> {
+        match counter {
+            Some(addr) => {
+                let mut value = [0; 4];
+                piece.get_memory(addr, 4, &mut value)?;
+                Ok(u32::from_le_bytes(value))
+            }
+            None => {
+                let addr = piece.framebuffer_addr()?;
+                let mut frame = vec![0; LCD_WIDTH * LCD_HEIGHT];
+                piece.get_memory(addr, frame.len() as u32, &mut frame)?;
+                Ok(crc32(&frame) ^ addr)
+            }
+        }
+    };
+    let start = Instant::now();
+    let mut previous = sample(piece)?;
+    let mut samples = 1;
+    // When a new frame was seen, and how many frames it stood for.
+    let mut changes: Vec<(Instant, u32)> = Vec::new();
+    while start.elapsed() < duration {
+        let value = sample(piece)?;
+        samples += 1;
+        if value != previous {
+            let frames = match counter {
+                Some(_) => value.wrapping_sub(previous),
+                None => 1,
+            };
+            changes.push((Instant::now(), frames));
+            previous = value;
+        }
+    }
+    let elapsed = start.elapsed().as_secs_f64();
+    println!("{} samples in {:.2}s ({:.1} ms apart)", samples, elapsed, elapsed * 1000.0 / samples as f64);
+    if changes.len() < 2 {
+        println!("fewer than two frame changes seen; the app may be idle");
+        return Ok(());
+    }
+    let mut frame_times = Vec::new();
+    for pair in changes.windows(2) {
+        let (earlier, (later, frames)) = (pair[0].0, pair[1]);
+        let each = (later - earlier).as_secs_f64() * 1000.0 / frames as f64;
+        frame_times.extend(std::iter::repeat_n(each, frames as usize));
+    }
+    let span = (changes[changes.len() - 1].0 - changes[0].0).as_secs_f64();
+    let mean = frame_times.iter().sum::<f64>() / frame_times.len() as f64;
+    let jitter = (frame_times.iter().map(|t| (t - mean).powi(2)).sum::<f64>() / frame_times.len() as f64).sqrt();
+    let min = frame_times.iter().copied().fold(f64::INFINITY, f64::min);
+    let max = frame_times.iter().copied().fold(0.0, f64::max);
+    println!("{} frames, {:.1} fps", frame_times.len(), frame_times.len() as f64 / span);
+    println!("frame time {:.1} ms mean, {:.1} min, {:.1} max, {:.1} jitter (std dev)", mean, min, max, jitter);
+    if counter.is_none() {
+        println!("frames shorter than the sample interval are merged; use --counter for exact numbers");
+    }
+    Ok(())
+}
@@ -0,0 +1,30 @@
+use crate::{chain, Piece, Result, FAT_FREE};
+
+pub fn report(piece: &mut Piece) -> Result<()> {
+    let fat = piece.read_fat()?;
+    let directory = piece.ls()?;
+    let mut fragmented = 0;
+    println!("FILE\tCLUSTERS\tFRAGMENTS");
+    for dirent in &directory {
+        let clusters = chain(&fat, dirent.cluster);
+        let fragments = 1 + clusters.windows(2).filter(|pair| pair[1] != pair[0] + 1).count();
+        if fragments > 1 {
+            fragmented += 1;
+        }
+        println!("{}\t{}\t{}", dirent.name, clusters.len(), fragments);
+    }
+    let percent = if directory.is_empty() { 0.0 } else { fragmented as f64 * 100.0 / directory.len() as f64 };
+    println!("{} of {} files fragmented ({:.1}%)", fragmented, directory.len(), percent);
+    let (mut free, mut run, mut largest_run) = (0, 0, 0);
+    for &entry in &fat[piece.pffs.first_cluster()..] {
+        if entry == FAT_FREE {
+            free += 1;
+            run += 1;
+            largest_run = largest_run.max(run);
+        } else {
+            run = 0;
+        }
+    }
+    println!("{} clusters free, largest contiguous run {} clusters ({} bytes)", free, largest_run, largest_run * piece.pffs.cluster_size);
+    Ok(())
+}
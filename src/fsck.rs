@@ -0,0 +1,37 @@
+use piecer::pffs::Problem;
+use crate::{Piece, Result};
+
+/// Print `problems` found in a filesystem of `files` files. Returns the exit
+/// code: 1 if there were any.
+pub fn report(problems: &[Problem], files: usize) -> i32 {
+    for problem in problems {
+        println!("{}", problem);
+    }
+    match problems.is_empty() {
+        true => {
+            println!("{} files, no problems", files);
+            0
+        }
+        false => 1,
+    }
+}
+
+/// Check the device's filesystem, and with `repair` fix what can be fixed.
+pub fn run(piece: &mut Piece, repair: bool) -> Result<i32> {
+    let problems = piece.check()?;
+    if !repair || problems.is_empty() {
+        return Ok(report(&problems, piece.ls()?.len()));
+    }
+    for problem in &problems {
+        println!("{}", problem);
+    }
+    let left = piece.repair()?;
+    println!("Repaired {} problems", problems.len().saturating_sub(left.len()));
+    match left.is_empty() {
+        true => Ok(0),
+        false => {
+            println!("Left for you to sort out, e.g. by downloading and re-uploading the files:");
+            Ok(report(&left, piece.ls()?.len()))
+        }
+    }
+}
@@ -0,0 +1,104 @@
+use crate::date;
+use crate::json::{self, Value};
+use crate::{chain, Piece, Result, FAT_FREE};
+use std::fs;
+use std::path::Path;
+
+/// A file as recorded in a snapshot.
+struct Entry {
+    name: String,
+    len: u64,
+    clusters: Vec<u64>,
+    /// The directory entry looked corrupt.
+    suspicious: bool,
+}
+
+fn current(piece: &mut Piece) -> Result<(Vec<Entry>, usize)> {
+    let fat = piece.read_fat()?;
+    let entries = piece.ls()?.into_iter().map(|dirent| Entry {
+        clusters: chain(&fat, dirent.cluster).into_iter().map(u64::from).collect(),
+        suspicious: dirent.problem.is_some(),
+        name: dirent.name,
+        len: dirent.len as u64,
+    }).collect();
+    let free = fat[1..].iter().filter(|&&entry| entry == FAT_FREE).count();
+    Ok((entries, free))
+}
+
+/// Record the directory and each file's cluster chain to `path` as JSON.
+pub fn save(piece: &mut Piece, path: &Path) -> Result<()> {
+    let (entries, free) = current(piece)?;
+    let files: Vec<String> = entries.iter().map(|entry| {
+        let clusters: Vec<String> = entry.clusters.iter().map(u64::to_string).collect();
+        format!("    {{\"name\": {}, \"len\": {}, \"clusters\": [{}], \"suspicious\": {}}}",
+                json::string(&entry.name), entry.len, clusters.join(", "), entry.suspicious)
+    }).collect();
+    let text = format!("{{\n  \"saved\": {},\n  \"free_clusters\": {},\n  \"files\": [\n{}\n  ]\n}}\n",
+                       json::string(&date::format(date::now_local())), free, files.join(",\n"));
+    fs::write(path, text).expect("Could not write snapshot");
+    println!("Recorded {} files", entries.len());
+    Ok(())
+}
+
+fn load(path: &Path) -> (Vec<Entry>, u64) {
+    let text = fs::read_to_string(path).expect("Could not read snapshot");
+    let snapshot = json::parse(&text).expect("Snapshot is not valid JSON");
+    let files = snapshot.get("files").and_then(Value::as_array).expect("Snapshot has no file list");
+    let entries = files.iter().map(|file| Entry {
+        name: file.get("name").and_then(Value::as_str).expect("Snapshot entry has no name").to_string(),
+        len: file.get("len").and_then(Value::as_u64).expect("Snapshot entry has no length"),
+        clusters: file.get("clusters").and_then(Value::as_array).unwrap_or_default()
+            .iter().filter_map(Value::as_u64).collect(),
+        suspicious: file.get("suspicious").and_then(Value::as_bool).unwrap_or(false),
+    }).collect();
+    (entries, snapshot.get("free_clusters").and_then(Value::as_u64).unwrap_or(0))
+}
+
+/// Compare the device against the snapshot at `path`, printing files that
+/// were added (`+`), removed (`-`), resized (`~`), moved to other clusters
+/// (`>`) or whose directory entry became corrupt (`!`).
+pub fn diff(piece: &mut Piece, path: &Path) -> Result<()> {
+    let (before, free_before) = load(path);
+    let (after, free_after) = current(piece)?;
+    let find = |entries: &'_ [Entry], name: &str| entries.iter().position(|entry| entry.name == name);
+    let mut changes = 0;
+    for entry in &after {
+        match find(&before, &entry.name).map(|i| &before[i]) {
+            None => println!("+ {}\t{} bytes", entry.name, entry.len),
+            Some(old) if entry.suspicious && !old.suspicious => println!("! {}\tdirectory entry now looks corrupt", entry.name),
+            Some(old) if old.len != entry.len => println!("~ {}\t{} -> {} bytes", entry.name, old.len, entry.len),
+            Some(old) if old.clusters != entry.clusters => {
+                println!("> {}\tclusters {} -> {}", entry.name, describe(&old.clusters), describe(&entry.clusters));
+            }
+            Some(_) => continue,
+        }
+        changes += 1;
+    }
+    for entry in &before {
+        if find(&after, &entry.name).is_none() {
+            println!("- {}\t{} bytes", entry.name, entry.len);
+            changes += 1;
+        }
+    }
+    println!("{} changed, free clusters {} -> {}", changes, free_before, free_after);
+    Ok(())
+}
+
+/// A chain of clusters as runs, e.g. `12-15,40`.
+fn describe(clusters: &[u64]) -> String {
+    let mut runs: Vec<(u64, u64)> = Vec::new();
+    for &cluster in clusters {
+        match runs.last_mut() {
+            Some((_, end)) if *end + 1 == cluster => *end = cluster,
+            _ => runs.push((cluster, cluster)),
+        }
+    }
+    let runs: Vec<String> = runs.iter().map(|&(start, end)| match start == end {
+        true => start.to_string(),
+        false => format!("{}-{}", start, end),
+    }).collect();
+    match runs.is_empty() {
+        true => "none".to_string(),
+        false => runs.join(","),
+    }
+}
@@ -0,0 +1,205 @@
+use crate::{Piece, Result};
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::time::Duration;
+
+/// The S1C33's registers: r0-r15, then pc, psr, sp, alr and ahr. The kernel
+/// can't read them over USB, so every one is reported as unavailable.
+const REGISTERS: usize = 21;
+
+/// Serve the GDB remote protocol on `port`, one client at a time. The app is
+/// paused while a client is attached and stopped, and resumed on continue or
+/// detach. Memory can be read and written; registers, breakpoints and
+/// stepping aren't available.
+pub fn serve(piece: &mut Piece, port: u16) -> Result<()> {
+    let listener = TcpListener::bind(("127.0.0.1", port)).expect("Could not listen for GDB");
+    println!("Waiting for GDB on localhost:{} (target remote :{})", port, port);
+    for stream in listener.incoming() {
+        let stream = stream.expect("Could not accept GDB connection");
+        println!("GDB connected from {}", stream.peer_addr().map_or("?".to_string(), |addr| addr.to_string()));
+        let result = Session { piece: &mut *piece, stream, ack: true }.run();
+        match result {
+            Ok(()) => println!("GDB disconnected"),
+            Err(Disconnect::Io(error)) => println!("GDB connection lost: {}", error),
+            Err(Disconnect::Device(error)) => return Err(error),
+        }
+    }
+    Ok(())
+}
+
+enum Disconnect {
+    Io(io::Error),
+    Device(piecer::PieceError),
+}
+
+impl From<io::Error> for Disconnect {
+    fn from(error: io::Error) -> Disconnect {
+        Disconnect::Io(error)
+    }
+}
+
+impl From<piecer::PieceError> for Disconnect {
+    fn from(error: piecer::PieceError) -> Disconnect {
+        Disconnect::Device(error)
+    }
+}
+
+/// What the client sent outside a reply.
+enum Incoming {
+    Packet(String),
+    Interrupt,
+    Closed,
+    /// Nothing arrived before the timeout.
+    Idle,
+}
+
+struct Session<'a> {
+    piece: &'a mut Piece,
+    stream: TcpStream,
+    /// Whether packets are still acknowledged, until QStartNoAckMode.
+    ack: bool,
+}
+
+fn hex(data: &[u8]) -> String {
+    data.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn unhex(digits: &str) -> Option<Vec<u8>> {
+    (0..digits.len()).step_by(2)
+        .map(|i| digits.get(i..i + 2).and_then(|pair| u8::from_str_radix(pair, 16).ok()))
+        .collect()
+}
+
+/// `addr,len` from an m or M packet.
+fn range(args: &str) -> Option<(u32, u32)> {
+    let (addr, len) = args.split_once(',')?;
+    Some((u32::from_str_radix(addr, 16).ok()?, u32::from_str_radix(len, 16).ok()?))
+}
+
+impl Session<'_> {
+    fn run(&mut self) -> std::result::Result<(), Disconnect> {
+        self.piece.pause()?;
+        loop {
+            let packet = match self.receive(Duration::from_secs(1))? {
+                Incoming::Packet(packet) => packet,
+                Incoming::Interrupt => {
+                    self.send("S02")?;
+                    continue;
+                }
+                Incoming::Closed => break,
+                Incoming::Idle => continue,
+            };
+            let reply = match packet.as_bytes().first() {
+                Some(b'?') => "S05".to_string(),
+                Some(b'g') => "xxxxxxxx".repeat(REGISTERS),
+                Some(b'p') => "xxxxxxxx".to_string(),
+                Some(b'm') => match range(&packet[1..]) {
+                    Some((addr, len)) => {
+                        let mut data = vec![0; len as usize];
+                        match self.piece.get_memory(addr, len, &mut data) {
+                            Ok(()) => hex(&data),
+                            Err(_) => "E01".to_string(),
+                        }
+                    }
+                    None => "E02".to_string(),
+                },
+                Some(b'M') => {
+                    let parsed = packet[1..].split_once(':')
+                        .and_then(|(args, digits)| Some((range(args)?, unhex(digits)?)));
+                    match parsed {
+                        Some(((addr, _), data)) => match self.piece.set_memory(addr, &data) {
+                            Ok(()) => "OK".to_string(),
+                            Err(_) => "E01".to_string(),
+                        },
+                        None => "E02".to_string(),
+                    }
+                }
+                Some(b'c') => {
+                    self.piece.resume()?;
+                    let stopped = self.wait_for_interrupt()?;
+                    self.piece.pause()?;
+                    if !stopped {
+                        break;
+                    }
+                    "S02".to_string()
+                }
+                Some(b'D') => {
+                    self.send("OK")?;
+                    break;
+                }
+                Some(b'k') => break,
+                _ if packet.starts_with("qSupported") => "PacketSize=4000;QStartNoAckMode+".to_string(),
+                _ if packet == "QStartNoAckMode" => {
+                    self.send("OK")?;
+                    self.ack = false;
+                    continue;
+                }
+                _ if packet == "qAttached" => "1".to_string(),
+                // Anything else, like breakpoints and stepping, isn't supported.
+                _ => String::new(),
+            };
+            self.send(&reply)?;
+        }
+        self.piece.resume_unless_halted()?;
+        Ok(())
+    }
+
+    /// While the app runs, keep the link alive until the client interrupts.
+    /// Returns false if it disconnected instead.
+    fn wait_for_interrupt(&mut self) -> std::result::Result<bool, Disconnect> {
+        loop {
+            match self.receive(Duration::from_millis(200))? {
+                Incoming::Interrupt => return Ok(true),
+                Incoming::Closed => return Ok(false),
+                // Only an interrupt is expected while running; drop the rest.
+                Incoming::Packet(_) | Incoming::Idle => {}
+            }
+        }
+    }
+
+    /// The next packet or interrupt, or `Incoming::Idle` after `timeout`
+    /// without one, having kept the device link alive.
+    fn receive(&mut self, timeout: Duration) -> std::result::Result<Incoming, Disconnect> {
+        self.stream.set_read_timeout(Some(timeout))?;
+        let mut packet = Vec::new();
+        let mut in_packet = false;
+        let mut byte = [0];
+        loop {
+            match self.stream.read(&mut byte) {
+                Ok(0) => return Ok(Incoming::Closed),
+                Ok(_) => {}
+                Err(error) if matches!(error.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) && !in_packet => {
+                    self.piece.idle(Duration::from_millis(1))?;
+                    return Ok(Incoming::Idle);
+                }
+                Err(error) => return Err(error.into()),
+            }
+            match byte[0] {
+                0x03 if !in_packet => return Ok(Incoming::Interrupt),
+                b'$' => {
+                    in_packet = true;
+                    packet.clear();
+                    // A packet has started, so wait for the rest of it.
+                    self.stream.set_read_timeout(None)?;
+                }
+                b'#' if in_packet => {
+                    let mut checksum = [0; 2];
+                    self.stream.read_exact(&mut checksum)?;
+                    if self.ack {
+                        self.stream.write_all(b"+")?;
+                    }
+                    return Ok(Incoming::Packet(String::from_utf8_lossy(&packet).into_owned()));
+                }
+                other if in_packet => packet.push(other),
+                // Acks from the client.
+                _ => {}
+            }
+        }
+    }
+
+    fn send(&mut self, reply: &str) -> io::Result<()> {
+        let checksum = reply.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte));
+        self.stream.write_all(format!("${}#{:02x}", reply, checksum).as_bytes())?;
+        self.stream.flush()
+    }
+}
@@ -0,0 +1,227 @@
+/// Builds a looping animated GIF from 2-bit frames, one byte per pixel.
+pub struct Animation {
+    out: Vec<u8>,
+    width: u16,
+    height: u16,
+}
+
+impl Animation {
+    /// Start an animation with a four-colour `palette`, indexed by pixel value.
+    pub fn new(width: u16, height: u16, palette: &[[u8; 3]; 4]) -> Animation {
+        let mut out = b"GIF89a".to_vec();
+        out.extend(width.to_le_bytes());
+        out.extend(height.to_le_bytes());
+        // Global colour table of 2^(1+1) entries, 2 bits per primary.
+        out.extend([0x91, 0, 0]);
+        for colour in palette {
+            out.extend(colour);
+        }
+        out.extend(b"\x21\xff\x0bNETSCAPE2.0\x03\x01\x00\x00\x00");
+        Animation { out, width, height }
+    }
+
+    /// Append a frame shown for `delay` hundredths of a second.
+    pub fn frame(&mut self, pixels: &[u8], delay: u16) {
+        assert_eq!(pixels.len(), self.width as usize * self.height as usize);
+        self.out.extend(b"\x21\xf9\x04\x00");
+        self.out.extend(delay.to_le_bytes());
+        self.out.extend([0, 0]);
+        self.out.push(0x2c);
+        self.out.extend([0, 0, 0, 0]);
+        self.out.extend(self.width.to_le_bytes());
+        self.out.extend(self.height.to_le_bytes());
+        self.out.push(0);
+        self.out.push(MIN_CODE_SIZE);
+        for block in lzw(pixels).chunks(255) {
+            self.out.push(block.len() as u8);
+            self.out.extend(block);
+        }
+        self.out.push(0);
+    }
+
+    pub fn finish(mut self) -> Vec<u8> {
+        self.out.push(0x3b);
+        self.out
+    }
+}
+
+const MIN_CODE_SIZE: u8 = 2;
+
+struct Bits {
+    out: Vec<u8>,
+    buffer: u32,
+    count: u32,
+}
+
+impl Bits {
+    fn write(&mut self, code: u16, size: u32) {
+        self.buffer |= (code as u32) << self.count;
+        self.count += size;
+        while self.count >= 8 {
+            self.out.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.count -= 8;
+        }
+    }
+}
+
+/// GIF's variable-width LZW, clearing the table whenever it fills.
+fn lzw(pixels: &[u8]) -> Vec<u8> {
+    let clear: u16 = 1 << MIN_CODE_SIZE;
+    let end = clear + 1;
+    let mut bits = Bits { out: Vec::new(), buffer: 0, count: 0 };
+    // Codes above `end` by prefix code and next pixel.
+    let mut table = vec![[0u16; 4]; 4096];
+    let mut next = end + 1;
+    let mut size = MIN_CODE_SIZE as u32 + 1;
+    bits.write(clear, size);
+    let Some((&first, rest)) = pixels.split_first() else {
+        bits.write(end, size);
+        bits.out.push(bits.buffer as u8);
+        return bits.out;
+    };
+    let mut current = (first & 3) as u16;
+    for &pixel in rest {
+        let pixel = (pixel & 3) as usize;
+        let known = table[current as usize][pixel];
+        if known != 0 {
+            current = known;
+            continue;
+        }
+        bits.write(current, size);
+        table[current as usize][pixel] = next;
+        if next == 1 << size && size < 12 {
+            size += 1;
+        }
+        next += 1;
+        if next == 4096 {
+            bits.write(clear, size);
+            table.iter_mut().for_each(|entry| *entry = [0; 4]);
+            next = end + 1;
+            size = MIN_CODE_SIZE as u32 + 1;
+        }
+        current = pixel as u16;
+    }
+    bits.write(current, size);
+    bits.write(end, size);
+    if bits.count > 0 {
+        bits.out.push(bits.buffer as u8);
+    }
+    bits.out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Decode GIF LZW data, returning the pixels and how many clear codes
+    /// it held.
+    fn unlzw(data: &[u8]) -> (Vec<u8>, usize) {
+        let clear = 1u16 << MIN_CODE_SIZE;
+        let end = clear + 1;
+        let (mut buffer, mut count, mut bytes) = (0u32, 0u32, data.iter());
+        let mut table: Vec<Vec<u8>> = Vec::new();
+        let mut size = MIN_CODE_SIZE as u32 + 1;
+        let mut previous: Option<Vec<u8>> = None;
+        let (mut out, mut clears) = (Vec::new(), 0);
+        loop {
+            while count < size {
+                buffer |= (*bytes.next().expect("no end code") as u32) << count;
+                count += 8;
+            }
+            let code = (buffer & ((1 << size) - 1)) as u16;
+            buffer >>= size;
+            count -= size;
+            if code == clear {
+                table = (0..clear).map(|i| vec![i as u8]).chain([vec![], vec![]]).collect();
+                size = MIN_CODE_SIZE as u32 + 1;
+                previous = None;
+                clears += 1;
+                continue;
+            }
+            if code == end {
+                return (out, clears);
+            }
+            let entry = match (table.get(code as usize), &previous) {
+                (Some(entry), _) => entry.clone(),
+                (None, Some(previous)) if code as usize == table.len() => [&previous[..], &previous[..1]].concat(),
+                _ => panic!("code {} before it was defined", code),
+            };
+            out.extend(&entry);
+            if let Some(previous) = previous {
+                table.push([&previous[..], &entry[..1]].concat());
+                if table.len() == 1 << size && size < 12 {
+                    size += 1;
+                }
+            }
+            previous = Some(entry);
+        }
+    }
+
+    #[test]
+    fn lzw_round_trip() {
+        for pixels in [vec![], vec![3], vec![1; 1000], (0..500).map(|i| (i % 4) as u8).collect()] {
+            let (out, clears) = unlzw(&lzw(&pixels));
+            assert_eq!(out, pixels);
+            assert_eq!(clears, 1);
+        }
+    }
+
+    #[test]
+    fn full_table_is_cleared() {
+        // Noise, so nearly every code adds a new string.
+        let mut state = 1u32;
+        let pixels: Vec<u8> = (0..60000).map(|_| {
+            state = state.wrapping_mul(1103515245).wrapping_add(12345);
+            (state >> 16) as u8 & 3
+        }).collect();
+        let (out, clears) = unlzw(&lzw(&pixels));
+        assert!(clears > 1, "the table never filled");
+        assert_eq!(out, pixels);
+    }
+
+    #[test]
+    fn file_layout() {
+        let palette = [[0, 0, 0], [85, 85, 85], [170, 170, 170], [255, 255, 255]];
+        let mut animation = Animation::new(3, 2, &palette);
+        animation.frame(&[0, 1, 2, 3, 2, 1], 5);
+        animation.frame(&[3; 6], 10);
+        let gif = animation.finish();
+        assert!(gif.starts_with(b"GIF89a\x03\x00\x02\x00\x91\x00\x00"));
+        assert_eq!(gif[13..25], palette.concat()[..]);
+        assert_eq!(&gif[25..44], b"\x21\xff\x0bNETSCAPE2.0\x03\x01\x00\x00\x00");
+        let mut pos = 44;
+        let mut frames = Vec::new();
+        while gif[pos] == 0x21 {
+            assert_eq!(gif[pos..pos + 4], [0x21, 0xf9, 0x04, 0x00]);
+            let delay = u16::from_le_bytes([gif[pos + 4], gif[pos + 5]]);
+            pos += 8;
+            assert_eq!(gif[pos..pos + 10], [0x2c, 0, 0, 0, 0, 3, 0, 2, 0, 0]);
+            assert_eq!(gif[pos + 10], MIN_CODE_SIZE);
+            pos += 11;
+            let mut data = Vec::new();
+            while gif[pos] != 0 {
+                let len = gif[pos] as usize;
+                data.extend(&gif[pos + 1..pos + 1 + len]);
+                pos += 1 + len;
+            }
+            pos += 1;
+            frames.push((delay, unlzw(&data).0));
+        }
+        assert_eq!(frames, [(5, vec![0, 1, 2, 3, 2, 1]), (10, vec![3; 6])]);
+        assert_eq!(&gif[pos..], [0x3b]);
+    }
+
+    #[test]
+    fn long_frames_split_into_sub_blocks() {
+        let mut animation = Animation::new(128, 88, &[[0; 3]; 4]);
+        let mut state = 7u32;
+        animation.frame(&(0..128 * 88).map(|_| {
+            state = state.wrapping_mul(1103515245).wrapping_add(12345);
+            (state >> 16) as u8 & 3
+        }).collect::<Vec<u8>>(), 1);
+        let gif = animation.finish();
+        // The first sub-block after the header, extensions and descriptor is full.
+        assert_eq!(gif[44 + 8 + 11], 255);
+    }
+}
@@ -0,0 +1,124 @@
+/// Shell-style wildcard matching for device file names: `*` matches any run
+/// of characters, `?` any single character, and `[abc]`/`[a-z]`/`[!abc]` a
+/// character class. There is no backslash escape: a wildcard character in a
+/// class, like `[*]`, matches itself. Case matters.
+pub fn matches(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    matches_from(&pattern, &name)
+}
+
+fn matches_from(pattern: &[char], name: &[char]) -> bool {
+    let (mut p, mut n) = (0, 0);
+    // Where to retry after the last `*`: pattern position and name position.
+    let mut backtrack: Option<(usize, usize)> = None;
+    while n < name.len() {
+        let step = match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, n));
+                p += 1;
+                continue;
+            }
+            Some('?') => Some(1),
+            Some('[') => class(&pattern[p..], name[n]),
+            Some(&c) if c == name[n] => Some(1),
+            _ => None,
+        };
+        match (step, backtrack) {
+            (Some(len), _) => {
+                p += len;
+                n += 1;
+            }
+            (None, Some((star, from))) => {
+                backtrack = Some((star, from + 1));
+                p = star + 1;
+                n = from + 1;
+            }
+            (None, None) => return false,
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// If the class at the start of `pattern` matches `c`, the class's length.
+/// An unterminated `[` is treated as a literal.
+fn class(pattern: &[char], c: char) -> Option<usize> {
+    let Some(end) = pattern.iter().skip(2).position(|&c| c == ']').map(|i| i + 2) else {
+        return (c == '[').then_some(1);
+    };
+    let (negate, body) = match pattern[1] {
+        '!' | '^' => (true, &pattern[2..end]),
+        _ => (false, &pattern[1..end]),
+    };
+    let mut found = false;
+    let mut i = 0;
+    while i < body.len() {
+        if i + 2 < body.len() && body[i + 1] == '-' {
+            found |= (body[i]..=body[i + 2]).contains(&c);
+            i += 3;
+        } else {
+            found |= body[i] == c;
+            i += 1;
+        }
+    }
+    (found != negate).then_some(end + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn star_matches_any_run() {
+        assert!(matches("*", ""));
+        assert!(matches("*.pex", "GAME.pex"));
+        assert!(matches("*.pex", ".pex"));
+        assert!(matches("a*b*c", "aXXbYbc"));
+        assert!(!matches("*.pex", "GAME.pex.bak"));
+        assert!(!matches("a*b", "ac"));
+    }
+
+    #[test]
+    fn question_mark_matches_one_character() {
+        assert!(matches("SAVE?.DAT", "SAVE1.DAT"));
+        assert!(!matches("SAVE?.DAT", "SAVE.DAT"));
+        assert!(!matches("SAVE?.DAT", "SAVE12.DAT"));
+        // A whole character, not a byte.
+        assert!(matches("?.DAT", "セ.DAT"));
+    }
+
+    #[test]
+    fn classes_match_sets_and_ranges() {
+        assert!(matches("SAVE[123].DAT", "SAVE2.DAT"));
+        assert!(!matches("SAVE[123].DAT", "SAVE4.DAT"));
+        assert!(matches("[a-c]x", "bx"));
+        assert!(!matches("[a-c]x", "dx"));
+        assert!(matches("[!a-c]x", "dx"));
+        assert!(matches("[^a-c]x", "dx"));
+        assert!(!matches("[!a-c]x", "ax"));
+        // A trailing `-` is literal.
+        assert!(matches("[a-]", "-"));
+    }
+
+    #[test]
+    fn wildcards_in_a_class_are_literal() {
+        assert!(matches("a[*]", "a*"));
+        assert!(!matches("a[*]", "ab"));
+        assert!(matches("[?]", "?"));
+        assert!(matches("[]]", "]"));
+        assert!(matches("[[]", "["));
+        // An unterminated `[` matches itself.
+        assert!(matches("a[b", "a[b"));
+        assert!(!matches("a[b", "ab"));
+        // Backslash is an ordinary character.
+        assert!(matches("\\*", "\\x"));
+        assert!(!matches("\\*", "*"));
+    }
+
+    #[test]
+    fn case_matters() {
+        assert!(!matches("*.PEX", "game.pex"));
+        assert!(matches("*.pex", "game.pex"));
+        assert!(!matches("[A-Z]", "a"));
+    }
+}
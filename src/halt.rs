@@ -0,0 +1,58 @@
+use crate::json;
+use crate::{Piece, Result};
+use piecer::pex::{Header, HEADER_LEN};
+
+/// Bytes of an executable's image compared against memory to tell whether
+/// it is the one loaded.
+const COMPARED: u32 = 64;
+
+/// The executable on the device whose image is loaded in memory, with its
+/// header. The kernel doesn't say what is running, so this is the file
+/// whose first bytes of code are where it loads.
+pub fn running_app(piece: &mut Piece) -> Result<Option<(String, Header)>> {
+    let directory = piece.ls()?;
+    for dirent in &directory {
+        let Some(header) = piece.pex_header(dirent)? else {
+            continue;
+        };
+        let len = COMPARED.min(header.image_len).min(dirent.len - HEADER_LEN)
+            .min(piece.pffs.cluster_size - HEADER_LEN);
+        if len == 0 {
+            continue;
+        }
+        let image = piece.read_memory(piece.cluster_addr(dirent.cluster) + HEADER_LEN, len)?;
+        if image == piece.read_memory(header.load_addr, len)? {
+            return Ok(Some((dirent.name.clone(), header)));
+        }
+    }
+    Ok(None)
+}
+
+/// Print whether the device is halted and what it is running.
+pub fn status(piece: &mut Piece, as_json: bool) -> Result<()> {
+    let halted = piece.halted_since();
+    let app = running_app(piece)?;
+    if as_json {
+        let app = app.as_ref().map_or("null".to_string(), |(name, header)| json::object(&[
+            ("name", json::string(name)),
+            ("title", json::string(&header.title)),
+            ("version", json::string(&header.version_string())),
+            ("entry", header.entry.to_string()),
+        ]));
+        println!("{}", json::object(&[
+            ("halted", halted.is_some().to_string()),
+            ("halted_since", halted.as_deref().map_or("null".to_string(), json::string)),
+            ("app", app),
+        ]));
+        return Ok(());
+    }
+    match &halted {
+        Some(since) => println!("state\thalted since {}", since),
+        None => println!("state\trunning"),
+    }
+    match &app {
+        Some((name, header)) => println!("app\t{} ({} {}) at {:#x}", name, header.title, header.version_string(), header.entry),
+        None => println!("app\tnone from the filesystem (the menu, or one loaded over USB)"),
+    }
+    Ok(())
+}
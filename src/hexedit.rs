@@ -0,0 +1,156 @@
+use crate::term::{Key, Screen};
+use crate::{Piece, Result};
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+const ROW: u32 = 16;
+const ROWS: u32 = 16;
+
+/// Full-screen hex viewer/editor over device memory, starting at `addr`.
+/// Edits are kept locally and highlighted until written with `w`.
+pub fn run(piece: &mut Piece, addr: u32) -> Result<()> {
+    let screen = Screen::new();
+    let mut top = addr & !(ROW - 1);
+    let mut cursor = addr;
+    let mut low_nibble = false;
+    let mut pending: BTreeMap<u32, u8> = BTreeMap::new();
+    let mut status = String::from("arrows move, hex digits edit, w write, u undo, r reread, g goto, q quit");
+    let mut goto: Option<String> = None;
+    let mut confirm_quit = false;
+    let mut data = read(piece, top)?;
+    loop {
+        if cursor < top {
+            top = cursor & !(ROW - 1);
+            data = read(piece, top)?;
+        } else if cursor >= top + ROW * ROWS {
+            top = (cursor & !(ROW - 1)) - ROW * (ROWS - 1);
+            data = read(piece, top)?;
+        }
+        screen.draw(&render(top, &data, &pending, cursor, low_nibble, goto.as_deref().map_or(status.as_str(), |g| g)));
+        let Some(key) = screen.key(Duration::from_secs(3600)) else {
+            continue;
+        };
+        if let Some(input) = &mut goto {
+            match key {
+                Key::Char(c) if c.is_ascii_hexdigit() && input.len() < 16 => input.push(c),
+                Key::Backspace => {
+                    input.pop();
+                }
+                Key::Enter => {
+                    let digits = input.trim_start_matches("goto ");
+                    match u32::from_str_radix(digits, 16) {
+                        Ok(target) => {
+                            cursor = target;
+                            top = target & !(ROW - 1);
+                            data = read(piece, top)?;
+                            low_nibble = false;
+                        }
+                        Err(_) => status = "invalid address".to_string(),
+                    }
+                    goto = None;
+                }
+                _ => goto = None,
+            }
+            continue;
+        }
+        let quitting = matches!(key, Key::Char('q') | Key::Esc);
+        match key {
+            Key::Left => {
+                cursor = cursor.saturating_sub(1);
+                low_nibble = false;
+            }
+            Key::Right => {
+                cursor = cursor.saturating_add(1);
+                low_nibble = false;
+            }
+            Key::Up => cursor = cursor.saturating_sub(ROW),
+            Key::Down => cursor = cursor.saturating_add(ROW),
+            Key::PageUp => cursor = cursor.saturating_sub(ROW * ROWS),
+            Key::PageDown => cursor = cursor.saturating_add(ROW * ROWS),
+            Key::Char(c) if c.is_ascii_hexdigit() => {
+                let digit = c.to_digit(16).unwrap() as u8;
+                let current = pending.get(&cursor).copied().unwrap_or_else(|| data[(cursor - top) as usize]);
+                let value = match low_nibble {
+                    false => (current & 0x0f) | digit << 4,
+                    true => (current & 0xf0) | digit,
+                };
+                if value == data[(cursor - top) as usize] {
+                    pending.remove(&cursor);
+                } else {
+                    pending.insert(cursor, value);
+                }
+                if low_nibble {
+                    cursor = cursor.saturating_add(1);
+                }
+                low_nibble = !low_nibble;
+            }
+            Key::Char('w') => {
+                let count = pending.len();
+                for (addr, bytes) in runs(&pending) {
+                    piece.set_memory(addr, &bytes)?;
+                }
+                pending.clear();
+                data = read(piece, top)?;
+                status = format!("wrote {} bytes", count);
+            }
+            Key::Char('u') => {
+                pending.clear();
+                status = "edits discarded".to_string();
+            }
+            Key::Char('r') => {
+                data = read(piece, top)?;
+                status = "reread".to_string();
+            }
+            Key::Char('g') => goto = Some("goto ".to_string()),
+            _ if quitting && (pending.is_empty() || confirm_quit) => return Ok(()),
+            _ if quitting => status = format!("{} unwritten edits; q again to discard them", pending.len()),
+            _ => {}
+        }
+        confirm_quit = quitting;
+    }
+}
+
+fn read(piece: &mut Piece, top: u32) -> Result<Vec<u8>> {
+    let mut data = vec![0; (ROW * ROWS) as usize];
+    piece.get_memory(top, ROW * ROWS, &mut data)?;
+    Ok(data)
+}
+
+/// Pending edits as contiguous runs, to write each with one command.
+fn runs(pending: &BTreeMap<u32, u8>) -> Vec<(u32, Vec<u8>)> {
+    let mut runs: Vec<(u32, Vec<u8>)> = Vec::new();
+    for (&addr, &value) in pending {
+        match runs.last_mut() {
+            Some((start, bytes)) if *start + bytes.len() as u32 == addr => bytes.push(value),
+            _ => runs.push((addr, vec![value])),
+        }
+    }
+    runs
+}
+
+fn render(top: u32, data: &[u8], pending: &BTreeMap<u32, u8>, cursor: u32, low_nibble: bool, status: &str) -> String {
+    let mut out = String::new();
+    for (row, bytes) in data.chunks(ROW as usize).enumerate() {
+        let row_addr = top + row as u32 * ROW;
+        out += &format!("{:08x}  ", row_addr);
+        let mut text = String::new();
+        for (i, &original) in bytes.iter().enumerate() {
+            let addr = row_addr + i as u32;
+            let value = pending.get(&addr).copied().unwrap_or(original);
+            let hex = format!("{:02x}", value);
+            let cell = match (addr == cursor, pending.contains_key(&addr)) {
+                // The nibble being edited is underlined.
+                (true, _) if low_nibble => format!("\x1b[7m{}\x1b[4m{}\x1b[0m", &hex[..1], &hex[1..]),
+                (true, _) => format!("\x1b[7m\x1b[4m{}\x1b[24m{}\x1b[0m", &hex[..1], &hex[1..]),
+                (false, true) => format!("\x1b[33m{}\x1b[0m", hex),
+                (false, false) => hex,
+            };
+            out += &cell;
+            out += if i == 7 { "  " } else { " " };
+            text.push(if value.is_ascii_graphic() || value == b' ' { value as char } else { '.' });
+        }
+        out += &format!(" {}\n", text);
+    }
+    out += &format!("\n{} edits pending\n{}", pending.len(), status);
+    out
+}
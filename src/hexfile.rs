@@ -0,0 +1,201 @@
+use clap::ValueEnum;
+use std::io::{self, Write};
+
+/// How memory read from the device is written out.
+#[derive(Clone, Copy, Default, PartialEq, ValueEnum)]
+pub enum Format {
+    /// Raw bytes
+    #[default]
+    Bin,
+    /// Intel HEX, with extended linear address records
+    Ihex,
+    /// Motorola S-records: S2 for 24-bit addresses, S3 beyond
+    Srec,
+}
+
+impl Format {
+    /// What a file in this format is usually called, given the raw name.
+    pub fn file_name(self, raw: &str) -> String {
+        let stem = raw.rsplit_once('.').map_or(raw, |(stem, _)| stem);
+        match self {
+            Format::Bin => raw.to_string(),
+            Format::Ihex => format!("{}.hex", stem),
+            Format::Srec => format!("{}.srec", stem),
+        }
+    }
+}
+
+/// Data bytes per record, which every reader accepts.
+const RECORD_LEN: usize = 16;
+
+/// Encodes bytes written to it as records addressed from `addr`, passed on
+/// to `out` a line at a time. `finish` writes the end record.
+pub struct Encoder<W: Write> {
+    format: Format,
+    out: W,
+    /// Address of the first byte in `pending`.
+    addr: u32,
+    pending: Vec<u8>,
+    /// Upper address half of the last Intel HEX extended address record.
+    upper: Option<u16>,
+    /// Whether S-record addresses need 32 bits.
+    wide: bool,
+}
+
+impl<W: Write> Encoder<W> {
+    /// An encoder for `len` bytes at `addr`, which decides the S-record
+    /// address width.
+    pub fn new(format: Format, addr: u32, len: u32, mut out: W) -> io::Result<Encoder<W>> {
+        let wide = addr as u64 + len as u64 > 0x1000000;
+        if format == Format::Srec {
+            writeln!(out, "{}", srec(0, 2, 0, b"piecer"))?;
+        }
+        Ok(Encoder { format, out, addr, pending: Vec::new(), upper: None, wide })
+    }
+
+    /// Write the record or records for `data` at `addr`.
+    fn record(&mut self, mut addr: u32, mut data: &[u8]) -> io::Result<()> {
+        match self.format {
+            Format::Bin => self.out.write_all(data),
+            Format::Ihex => {
+                while !data.is_empty() {
+                    let upper = (addr >> 16) as u16;
+                    if self.upper != Some(upper) {
+                        writeln!(self.out, "{}", ihex(0, 4, &upper.to_be_bytes()))?;
+                        self.upper = Some(upper);
+                    }
+                    // A record can't run past a 64 KiB boundary.
+                    let room = 0x10000 - (addr & 0xffff) as usize;
+                    let (now, rest) = data.split_at(data.len().min(room));
+                    writeln!(self.out, "{}", ihex(addr as u16, 0, now))?;
+                    addr += now.len() as u32;
+                    data = rest;
+                }
+                Ok(())
+            }
+            Format::Srec => match self.wide {
+                true => writeln!(self.out, "{}", srec(3, 4, addr, data)),
+                false => writeln!(self.out, "{}", srec(2, 3, addr, data)),
+            },
+        }
+    }
+
+    /// Write what is left and the end record, and hand back the output.
+    pub fn finish(mut self) -> io::Result<W> {
+        let pending = std::mem::take(&mut self.pending);
+        if !pending.is_empty() {
+            self.record(self.addr, &pending)?;
+        }
+        match self.format {
+            Format::Bin => {}
+            Format::Ihex => writeln!(self.out, "{}", ihex(0, 1, &[]))?,
+            Format::Srec => match self.wide {
+                true => writeln!(self.out, "{}", srec(7, 4, 0, &[]))?,
+                false => writeln!(self.out, "{}", srec(8, 3, 0, &[]))?,
+            },
+        }
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+impl<W: Write> Write for Encoder<W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        if self.format == Format::Bin {
+            return self.out.write(data);
+        }
+        self.pending.extend_from_slice(data);
+        let full = self.pending.len() - self.pending.len() % RECORD_LEN;
+        let lines: Vec<u8> = self.pending.drain(..full).collect();
+        for line in lines.chunks(RECORD_LEN) {
+            self.record(self.addr, line)?;
+            self.addr += line.len() as u32;
+        }
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+/// An Intel HEX record of `kind` at the 16-bit `offset`.
+fn ihex(offset: u16, kind: u8, data: &[u8]) -> String {
+    let mut bytes = vec![data.len() as u8];
+    bytes.extend(offset.to_be_bytes());
+    bytes.push(kind);
+    bytes.extend(data);
+    let checksum = bytes.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte)).wrapping_neg();
+    bytes.push(checksum);
+    format!(":{}", hex(&bytes))
+}
+
+/// An S-record of `kind` with an `addr_len`-byte address.
+fn srec(kind: u8, addr_len: usize, addr: u32, data: &[u8]) -> String {
+    let mut bytes = vec![(addr_len + data.len() + 1) as u8];
+    bytes.extend(&addr.to_be_bytes()[4 - addr_len..]);
+    bytes.extend(data);
+    let checksum = !bytes.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte));
+    bytes.push(checksum);
+    format!("S{}{}", kind, hex(&bytes))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02X}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `data` at `addr` as `format`, written in two pieces.
+    fn encode(format: Format, addr: u32, data: &[u8]) -> Vec<String> {
+        let mut encoder = Encoder::new(format, addr, data.len() as u32, Vec::new()).unwrap();
+        encoder.write_all(&data[..5]).unwrap();
+        encoder.write_all(&data[5..]).unwrap();
+        String::from_utf8(encoder.finish().unwrap()).unwrap().lines().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn ihex_splits_at_64k() {
+        let data: Vec<u8> = (0..16).collect();
+        assert_eq!(encode(Format::Ihex, 0xfff8, &data), [
+            ":020000040000FA",
+            ":08FFF8000001020304050607E5",
+            ":020000040001F9",
+            ":0800000008090A0B0C0D0E0F9C",
+            ":00000001FF",
+        ]);
+        assert_eq!(encode(Format::Ihex, 0x0c0f_fff8, &data), [
+            ":020000040C0FDF",
+            ":08FFF8000001020304050607E5",
+            ":020000040C10DE",
+            ":0800000008090A0B0C0D0E0F9C",
+            ":00000001FF",
+        ]);
+    }
+
+    #[test]
+    fn srec_address_width() {
+        let data: Vec<u8> = (0..16).collect();
+        assert_eq!(encode(Format::Srec, 0xfff8, &data), [
+            "S00900007069656365727E",
+            "S21400FFF8000102030405060708090A0B0C0D0E0F7C",
+            "S804000000FB",
+        ]);
+        assert_eq!(encode(Format::Srec, 0x0c0f_fff8, &data), [
+            "S00900007069656365727E",
+            "S3150C0FFFF8000102030405060708090A0B0C0D0E0F60",
+            "S70500000000FA",
+        ]);
+    }
+
+    #[test]
+    fn bin_passes_through() {
+        let mut encoder = Encoder::new(Format::Bin, 0xfff8, 3, Vec::new()).unwrap();
+        encoder.write_all(b"abc").unwrap();
+        assert_eq!(encoder.finish().unwrap(), b"abc");
+        assert_eq!(Format::Ihex.file_name("flash.bin"), "flash.hex");
+        assert_eq!(Format::Srec.file_name("sram"), "sram.srec");
+    }
+}
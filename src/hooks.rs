@@ -0,0 +1,35 @@
+use crate::config::Config;
+use std::process::Command;
+
+/// Run the `[hooks]` command configured for the outcome of `operation`, e.g.
+/// `backup-success` or `dump-failure`.
+///
+/// The command runs through the shell with PIECER_OPERATION, PIECER_STATUS,
+/// PIECER_EXIT_CODE and, when piecer failed with an error, PIECER_ERROR set.
+pub fn run(config: &Config, operation: &str, exit_code: i32, error: Option<&str>) {
+    let status = if exit_code == 0 { "success" } else { "failure" };
+    let Some(hook) = config.get(&format!("hooks.{}-{}", operation, status)) else {
+        return;
+    };
+    let mut command = if cfg!(windows) {
+        let mut command = Command::new("cmd");
+        command.arg("/C");
+        command
+    } else {
+        let mut command = Command::new("sh");
+        command.arg("-c");
+        command
+    };
+    command.arg(hook)
+        .env("PIECER_OPERATION", operation)
+        .env("PIECER_STATUS", status)
+        .env("PIECER_EXIT_CODE", exit_code.to_string());
+    if let Some(error) = error {
+        command.env("PIECER_ERROR", error);
+    }
+    match command.status() {
+        Ok(result) if !result.success() => eprintln!("Hook {}-{} exited with {}", operation, status, result),
+        Ok(_) => {}
+        Err(e) => eprintln!("Could not run hook: {}", e),
+    }
+}
@@ -0,0 +1,119 @@
+//! Translations of user-facing messages.
+
+use std::env;
+use std::fmt::Display;
+use std::sync::OnceLock;
+
+/// Language for user-facing messages, from `PIECER_LANG` or the usual locale
+/// variables. Messages are looked up by their English text, gettext-style,
+/// so an untranslated message simply stays in English.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Lang {
+    En,
+    Ja,
+}
+
+fn lang() -> Lang {
+    static LANG: OnceLock<Lang> = OnceLock::new();
+    *LANG.get_or_init(|| {
+        let locale = ["PIECER_LANG", "LC_ALL", "LC_MESSAGES", "LANG"].iter()
+            .filter_map(|name| env::var(name).ok())
+            .find(|value| !value.is_empty())
+            .unwrap_or_default();
+        match locale.starts_with("ja") {
+            true => Lang::Ja,
+            false => Lang::En,
+        }
+    })
+}
+
+const JA: &[(&str, &str)] = &[
+    ("Could not open PIECE device", "P/ECE に接続できませんでした"),
+    ("Could not find {} on device", "{} はデバイス上に見つかりません"),
+    ("Could not find {} on device. Did you mean: {}?", "{} はデバイス上に見つかりません。もしかして: {}?"),
+    ("error: {}", "エラー: {}"),
+    ("No file in that directory slot", "そのディレクトリスロットにファイルはありません"),
+    ("warning: entry {} {}: {}", "警告: エントリ {} {}: {}"),
+    ("name is not valid in the configured encoding", "名前が設定された文字コードで正しくありません"),
+    ("name contains control characters", "名前に制御文字が含まれています"),
+    ("start cluster is out of range", "開始クラスタが範囲外です"),
+    ("length is larger than the filesystem", "サイズがファイルシステムより大きいです"),
+    ("warning: {}: broken cluster chain, only {} of {} bytes read",
+     "警告: {}: クラスタチェーンが壊れています。{} / {} バイトのみ読み込みました"),
+    ("No files match {}", "{} に一致するファイルがありません"),
+    ("--stdout takes a single file", "--stdout には 1 つのファイルしか指定できません"),
+    ("{} already exists; use --force to overwrite it", "{} は既に存在します。上書きするには --force を指定してください"),
+    ("File name is longer than 24 bytes", "ファイル名が 24 バイトを超えています"),
+    ("{} can't be written in the device's name encoding", "{} はデバイスのファイル名の文字コードで表せません"),
+    ("Directory is full", "ディレクトリが満杯です"),
+    ("{} already exists on device", "{} はすでにデバイス上にあります"),
+    ("The device is claimed by the {} kernel driver; leave out --no-detach to take it over",
+     "デバイスはカーネルドライバ {} に使用されています。--no-detach を付けなければ切り離せます"),
+    ("The device is in use by another program; close it and try again",
+     "デバイスは他のプログラムが使用中です。そのプログラムを終了してから再試行してください"),
+    ("Could not detach the {} kernel driver from the device ({}); run as root or give yourself the device with a udev rule",
+     "カーネルドライバ {} をデバイスから切り離せませんでした ({})。root で実行するか、udev ルールでデバイスへのアクセスを許可してください"),
+    ("The device is in use by piecer process {}; pass --queue to wait for it",
+     "デバイスは piecer (プロセス {}) が使用中です。--queue で待機できます"),
+    ("The device is in use by another piecer; pass --queue to wait for it",
+     "デバイスは他の piecer が使用中です。--queue で待機できます"),
+    ("Not enough free space on device", "デバイスの空き容量が足りません"),
+    ("Resume of an encrypted backup needs --encrypt and its passphrase",
+     "暗号化されたバックアップの再開には --encrypt とそのパスフレーズが必要です"),
+    ("The device clock can't be set to {}; it runs from 2000-01-01 to 2179-06-06",
+     "デバイスの時計は {} に設定できません（範囲は 2000-01-01 から 2179-06-06）"),
+    ("There is no cluster {}; the filesystem has {}", "クラスタ {} はありません（ファイルシステムのクラスタ数は {}）"),
+    ("writing {} leaves only {} free clusters and {} free directory slots",
+     "{} を書き込むと空きクラスタが {} 個、空きディレクトリスロットが {} 個しか残りません"),
+    ("warning: {}", "警告: {}"),
+    ("Refusing: {} (use --force to write anyway)", "中止しました: {}(それでも書き込むには --force を指定してください)"),
+    ("Refusing to {}: piecer is in read-only mode", "{} を拒否しました: piecer は読み取り専用モードです"),
+    ("Interrupted", "中断しました"),
+    ("The filesystem has problems; repair it with fsck first: {}",
+     "ファイルシステムに問題があります。先に fsck で修復してください: {}"),
+    ("Read of {} failed after {} of {} bytes", "{} の読み込みが {} / {} バイトで失敗しました"),
+    ("Your kernel {} doesn't support {}, update to {} or later",
+     "カーネル {} は{}に対応していません。{} 以降に更新してください"),
+    ("memory writes", "メモリ書き込み"),
+    ("starting code", "コードの実行"),
+    ("pausing applications", "アプリケーションの一時停止"),
+    ("screen capture", "画面キャプチャ"),
+    ("flash writes (upload, restore)", "フラッシュ書き込み(upload、restore)"),
+    ("key injection", "キー入力の注入"),
+    ("write memory", "メモリ書き込み"),
+    ("start code", "コードの実行"),
+    ("write flash", "フラッシュ書き込み"),
+    ("upload", "アップロード"),
+    ("delete files", "ファイルの削除"),
+    ("repair the filesystem", "ファイルシステムの修復"),
+    ("format the filesystem", "ファイルシステムのフォーマット"),
+    ("USB error: {}", "USB エラー: {}"),
+    ("Directory entry {} is corrupt: {}", "ディレクトリエントリ {} が壊れています: {}"),
+    ("File not found in snapshot", "スナップショットにファイルがありません"),
+    ("File not found in backup", "バックアップにファイルがありません"),
+    ("Wrong passphrase or corrupt file", "パスフレーズが違うか、ファイルが壊れています"),
+];
+
+/// `en` translated to the user's language.
+pub fn tr(en: &'static str) -> &'static str {
+    match lang() {
+        Lang::En => en,
+        Lang::Ja => JA.iter().find(|&&(key, _)| key == en).map_or(en, |&(_, ja)| ja),
+    }
+}
+
+/// Translate the template `en`, then fill its `{}` placeholders with `args`
+/// in order.
+pub fn trf(en: &'static str, args: &[&dyn Display]) -> String {
+    let mut out = String::new();
+    let mut args = args.iter();
+    let mut parts = tr(en).split("{}");
+    out.push_str(parts.next().unwrap_or_default());
+    for part in parts {
+        if let Some(arg) = args.next() {
+            out.push_str(&arg.to_string());
+        }
+        out.push_str(part);
+    }
+    out
+}
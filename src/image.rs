@@ -0,0 +1,86 @@
+use crate::png::Gray;
+
+/// Signature of piecer's 2-bit bitmap files.
+pub const MAGIC: &[u8; 4] = b"PBMP";
+
+/// Scale `image` to fit within `max_width` x `max_height`, keeping its aspect
+/// ratio. Shrinking averages the covered source pixels; images that already
+/// fit are left alone.
+pub fn fit(image: &Gray, max_width: u32, max_height: u32) -> Gray {
+    let scale = (max_width as f64 / image.width as f64).min(max_height as f64 / image.height as f64);
+    if scale >= 1.0 {
+        return Gray { width: image.width, height: image.height, pixels: image.pixels.clone() };
+    }
+    let width = ((image.width as f64 * scale).round() as u32).max(1);
+    let height = ((image.height as f64 * scale).round() as u32).max(1);
+    let mut pixels = Vec::with_capacity((width * height) as usize);
+    for y in 0..height {
+        let y0 = (y * image.height / height) as usize;
+        let y1 = (((y + 1) * image.height).div_ceil(height) as usize).max(y0 + 1);
+        for x in 0..width {
+            let x0 = (x * image.width / width) as usize;
+            let x1 = (((x + 1) * image.width).div_ceil(width) as usize).max(x0 + 1);
+            let mut sum = 0u32;
+            for row in y0..y1 {
+                let start = row * image.width as usize;
+                sum += image.pixels[start + x0..start + x1].iter().map(|&p| p as u32).sum::<u32>();
+            }
+            pixels.push((sum / ((y1 - y0) * (x1 - x0)) as u32) as u8);
+        }
+    }
+    Gray { width, height, pixels }
+}
+
+/// Reduce 8-bit grayscale to the LCD's four levels, 0 black to 3 white,
+/// optionally with Floyd-Steinberg error diffusion.
+pub fn quantize(image: &Gray, dither: bool) -> Vec<u8> {
+    let width = image.width as usize;
+    let mut values: Vec<f32> = image.pixels.iter().map(|&p| p as f32).collect();
+    let mut levels = Vec::with_capacity(values.len());
+    for i in 0..values.len() {
+        let level = (values[i] / 85.0).round().clamp(0.0, 3.0);
+        levels.push(level as u8);
+        if !dither {
+            continue;
+        }
+        let error = values[i] - level * 85.0;
+        let x = i % width;
+        let mut spread = |index: usize, weight: f32| {
+            if let Some(value) = values.get_mut(index) {
+                *value += error * weight;
+            }
+        };
+        if x + 1 < width {
+            spread(i + 1, 7.0 / 16.0);
+            spread(i + width + 1, 1.0 / 16.0);
+        }
+        if x > 0 {
+            spread(i + width - 1, 3.0 / 16.0);
+        }
+        spread(i + width, 5.0 / 16.0);
+    }
+    levels
+}
+
+/// Encode `levels` as a bitmap file: a 16-byte header (magic, file size, bits
+/// per pixel, a zero mask flag, width, height, pixel data size, all little
+/// endian) followed by the rows packed four pixels to a byte, leftmost pixel
+/// in the high bits. Rows are padded to a whole byte.
+pub fn encode(width: u32, height: u32, levels: &[u8]) -> Vec<u8> {
+    let stride = (width as usize).div_ceil(4);
+    let mut data = vec![0u8; stride * height as usize];
+    assert!(data.len() <= u16::MAX as usize, "Image is too large for the bitmap format");
+    for (y, row) in levels.chunks(width as usize).enumerate() {
+        for (x, &level) in row.iter().enumerate() {
+            data[y * stride + x / 4] |= (level & 3) << (6 - 2 * (x % 4));
+        }
+    }
+    let mut out = MAGIC.to_vec();
+    out.extend((16 + data.len() as u32).to_le_bytes());
+    out.extend([2, 0]);
+    out.extend((width as u16).to_le_bytes());
+    out.extend((height as u16).to_le_bytes());
+    out.extend((data.len() as u16).to_le_bytes());
+    out.extend(data);
+    out
+}
@@ -0,0 +1,180 @@
+use crate::screen::Renderer;
+use crate::term::{self, Screen};
+use crate::{Piece, Result, LCD_HEIGHT, LCD_WIDTH};
+use clap::ValueEnum;
+use std::time::{Duration, Instant};
+
+/// A button on the pad, with its bit in the kernel's pad state.
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Key {
+    Right,
+    Left,
+    Down,
+    Up,
+    B,
+    A,
+    Select,
+    Start,
+}
+
+impl Key {
+    pub fn mask(self) -> u8 {
+        match self {
+            Key::Right => 0x01,
+            Key::Left => 0x02,
+            Key::Down => 0x04,
+            Key::Up => 0x08,
+            Key::B => 0x10,
+            Key::A => 0x20,
+            Key::Select => 0x40,
+            Key::Start => 0x80,
+        }
+    }
+}
+
+/// Buttons to press one after another, each given as the pad mask of the
+/// buttons held together.
+#[derive(Clone)]
+pub struct Sequence(Vec<u8>);
+
+/// Parse a comma-separated list of buttons such as `A,UP,UP,START`, with
+/// `+` joining buttons pressed together, as in `A+B`.
+pub fn parse_sequence(s: &str) -> std::result::Result<Sequence, String> {
+    let press = |step: &str| step.split('+').try_fold(0, |mask, name| {
+        Key::from_str(name.trim(), true).map(|key| mask | key.mask())
+            .map_err(|_| format!("unknown button {:?}", name.trim()))
+    });
+    Ok(Sequence(s.split(',').map(press).collect::<std::result::Result<_, _>>()?))
+}
+
+/// Press each step of `sequence` for `hold`, releasing for `gap` between.
+pub fn play(piece: &mut Piece, sequence: &Sequence, hold: Duration, gap: Duration) -> Result<()> {
+    for (i, &mask) in sequence.0.iter().enumerate() {
+        if i > 0 {
+            piece.idle(gap)?;
+        }
+        piece.set_keys(mask)?;
+        piece.idle(hold)?;
+        piece.set_keys(0)?;
+    }
+    Ok(())
+}
+
+/// The button a key on the host keyboard stands for.
+fn button(key: term::Key) -> Option<Key> {
+    Some(match key {
+        term::Key::Up | term::Key::Char('w') => Key::Up,
+        term::Key::Down | term::Key::Char('s') => Key::Down,
+        term::Key::Left | term::Key::Char('a') => Key::Left,
+        term::Key::Right | term::Key::Char('d') => Key::Right,
+        term::Key::Char('z' | 'j') => Key::A,
+        term::Key::Char('x' | 'k') => Key::B,
+        term::Key::Enter => Key::Start,
+        term::Key::Char(' ') | term::Key::Tab => Key::Select,
+        _ => return None,
+    })
+}
+
+/// Drive the device from the keyboard while mirroring its display, until q
+/// is pressed. Terminals only report key presses, not releases, so every
+/// key taps its button for `hold`.
+pub fn interactive(piece: &mut Piece, hold: Duration, renderer: &dyn Renderer) -> Result<()> {
+    const HELP: &str = "arrows/wasd pad, z/j A, x/k B, enter START, space SELECT, q quit";
+    let screen = Screen::new();
+    let mut frame = vec![0; LCD_WIDTH * LCD_HEIGHT];
+    loop {
+        let addr = piece.framebuffer_addr()?;
+        piece.get_memory(addr, frame.len() as u32, &mut frame)?;
+        screen.draw(&(renderer.render(&frame) + HELP));
+        match screen.key(Duration::from_millis(100)) {
+            Some(term::Key::Char('q') | term::Key::Esc) => break,
+            Some(key) => {
+                if let Some(button) = button(key) {
+                    piece.set_keys(button.mask())?;
+                    piece.idle(hold)?;
+                    piece.set_keys(0)?;
+                }
+            }
+            None => {}
+        }
+    }
+    Ok(())
+}
+
+/// A rectangle of the display, in pixels.
+#[derive(Clone, Copy)]
+pub struct Region {
+    x: usize,
+    y: usize,
+    width: usize,
+    height: usize,
+}
+
+impl Default for Region {
+    fn default() -> Region {
+        Region { x: 0, y: 0, width: LCD_WIDTH, height: LCD_HEIGHT }
+    }
+}
+
+/// Parse `x,y,width,height`, checking it lies on the display.
+pub fn parse_region(s: &str) -> std::result::Result<Region, String> {
+    let numbers: Vec<usize> = s.split(',').map(|n| n.trim().parse().map_err(|_| format!("invalid region {:?}", s)))
+        .collect::<std::result::Result<_, _>>()?;
+    let [x, y, width, height] = numbers[..] else {
+        return Err("region must be x,y,width,height".to_string());
+    };
+    if width == 0 || height == 0 || x + width > LCD_WIDTH || y + height > LCD_HEIGHT {
+        return Err(format!("region must lie within the {}x{} display", LCD_WIDTH, LCD_HEIGHT));
+    }
+    Ok(Region { x, y, width, height })
+}
+
+/// Read `region` of the live framebuffer without pausing the app.
+fn read_region(piece: &mut Piece, region: Region) -> Result<Vec<u8>> {
+    let addr = piece.framebuffer_addr()?;
+    let mut pixels = vec![0; region.width * region.height];
+    for (row, line) in pixels.chunks_mut(region.width).enumerate() {
+        let offset = (region.y + row) * LCD_WIDTH + region.x;
+        piece.get_memory(addr + offset as u32, region.width as u32, line)?;
+    }
+    Ok(pixels)
+}
+
+/// Press `key` `count` times, timing how long `region` takes to change after
+/// each press, and print the results alongside the cost of one poll.
+pub fn latency(piece: &mut Piece, key: Key, region: Region, count: u32) -> Result<()> {
+    let poll_start = Instant::now();
+    read_region(piece, region)?;
+    let poll = poll_start.elapsed();
+    let mut latencies = Vec::new();
+    for _ in 0..count {
+        let before = read_region(piece, region)?;
+        let pressed = Instant::now();
+        piece.set_keys(key.mask())?;
+        let changed = loop {
+            if read_region(piece, region)? != before {
+                break Some(pressed.elapsed());
+            }
+            if pressed.elapsed() > Duration::from_secs(5) {
+                break None;
+            }
+        };
+        piece.set_keys(0)?;
+        match changed {
+            Some(latency) => {
+                println!("{:.1} ms", latency.as_secs_f64() * 1000.0);
+                latencies.push(latency);
+            }
+            None => println!("no change within 5s"),
+        }
+        // Let the app settle before the next press.
+        piece.idle(Duration::from_millis(500))?;
+    }
+    println!("one poll of the region takes {:.1} ms over USB", poll.as_secs_f64() * 1000.0);
+    if let (Some(min), Some(max)) = (latencies.iter().min(), latencies.iter().max()) {
+        let mean = latencies.iter().sum::<Duration>() / latencies.len() as u32;
+        println!("latency {:.1} ms mean, {:.1} min, {:.1} max over {} presses",
+                 mean.as_secs_f64() * 1000.0, min.as_secs_f64() * 1000.0, max.as_secs_f64() * 1000.0, latencies.len());
+    }
+    Ok(())
+}
@@ -0,0 +1,58 @@
+//! Kernel versions and the protocol features each one has.
+
+use crate::error::{PieceError, Result};
+
+/// Protocol features that only some kernel versions implement.
+#[derive(Clone, Copy, Debug)]
+pub enum Feature {
+    /// Writing to RAM.
+    MemoryWrite,
+    /// Calling code at an address.
+    Exec,
+    /// Pausing and resuming the running application.
+    AppControl,
+    /// Reading the screen.
+    LcdInfo,
+    /// Programming flash sectors.
+    FlashWrite,
+    /// Pressing buttons from the host.
+    KeyInject,
+}
+
+impl Feature {
+    /// First kernel version (BCD, as reported in the handshake) that
+    /// implements the feature.
+    pub(crate) fn min_version(self) -> u16 {
+        match self {
+            Feature::MemoryWrite => 0x0100,
+            Feature::Exec => 0x0100,
+            Feature::AppControl => 0x0100,
+            Feature::LcdInfo => 0x0110,
+            Feature::FlashWrite => 0x0120,
+            Feature::KeyInject => 0x0130,
+        }
+    }
+    pub(crate) fn description(self) -> &'static str {
+        match self {
+            Feature::MemoryWrite => "memory writes",
+            Feature::Exec => "starting code",
+            Feature::AppControl => "pausing applications",
+            Feature::LcdInfo => "screen capture",
+            Feature::FlashWrite => "flash writes (upload, restore)",
+            Feature::KeyInject => "key injection",
+        }
+    }
+}
+
+/// A BCD kernel version as it's usually written, e.g. `1.20`.
+pub fn version_string(version: u16) -> String {
+    format!("{:x}.{:02x}", version >> 8, version & 0xff)
+}
+
+/// Fail with an upgrade hint if `version` does not implement `feature`.
+pub fn require(version: u16, feature: Feature) -> Result<()> {
+    match version < feature.min_version() {
+        true => Err(PieceError::Unsupported { version, feature }),
+        false => Ok(()),
+    }
+}
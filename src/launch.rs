@@ -0,0 +1,20 @@
+use crate::bootstrap;
+use crate::{Piece, Result};
+use piecer::pex::{Header, HEADER_LEN};
+
+/// Start the executable `name` from the device's filesystem: its image is
+/// loaded at the header's load address, checked, and entered.
+pub fn run(piece: &mut Piece, name: &str) -> Result<()> {
+    let data = piece.read_file(name)?;
+    let header = Header::parse(&data).unwrap_or_else(|| panic!("{} is not a P/ECE executable", name));
+    let image = &data[HEADER_LEN as usize..];
+    let image = &image[..(header.image_len as usize).min(image.len())];
+    assert!(header.load_addr >= piece.sram_top,
+            "{} loads at {:#x}, below application memory at {:#x}", name, header.load_addr, piece.sram_top);
+    // Keep the running app from drawing over the image as it goes in.
+    piece.pause()?;
+    piece.set_memory(header.load_addr, image)?;
+    bootstrap::verify(piece, header.load_addr, image)?;
+    println!("Starting {} ({} {}) at {:#x}", name, header.title, header.version_string(), header.entry);
+    piece.exec(header.entry)
+}
@@ -0,0 +1,56 @@
+//! Talk to an Aquaplus P/ECE handheld over its USB link.
+//!
+//! [`Piece`] is a connection to one device. Memory access, screen capture and
+//! application control are in [`device`]; reading and writing files on the
+//! flash filesystem is in [`pffs`]. Underneath, commands go over a
+//! [`transport::Transport`], which can be a
+//! [`FakeDevice`](transport::FakeDevice) for testing without hardware.
+//!
+//! ```no_run
+//! let mut piece = piecer::Piece::new(&piecer::Options::default())?;
+//! for dirent in piece.ls()? {
+//!     println!("{} {}", dirent.name, dirent.len);
+//! }
+//! # Ok::<(), piecer::PieceError>(())
+//! ```
+//!
+//! Failures are reported as [`PieceError`]s, and long operations can be
+//! stopped partway with [`cancel`].
+
+#![warn(missing_docs)]
+
+pub mod audit;
+pub mod cancel;
+pub mod config;
+pub mod date;
+pub mod device;
+pub mod dirs;
+pub mod error;
+pub mod filetype;
+pub mod flash;
+pub mod i18n;
+pub mod json;
+pub mod kernel;
+pub mod lock;
+pub mod memmap;
+pub mod names;
+pub mod pex;
+pub mod pffs;
+pub mod power;
+pub mod progress;
+pub mod trace;
+pub mod transport;
+pub mod wire;
+
+use std::any::Any;
+
+pub use device::{Attached, DeviceInfo, Options, Piece, UsbInfo};
+pub use error::{PieceError, Result};
+pub use pffs::DirEnt;
+
+/// The message a panic was raised with.
+pub fn panic_message(payload: &(dyn Any + Send)) -> String {
+    payload.downcast_ref::<&str>().map(|s| s.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown error".to_string())
+}
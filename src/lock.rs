@@ -0,0 +1,114 @@
+//! Advisory locks that keep two piecer processes from using one device at
+//! the same time, which would interleave their bulk commands.
+//!
+//! Each device has a lock file in the state directory, named after its
+//! serial number or where it is plugged in, holding the pid of the process
+//! that has it.
+
+use crate::dirs;
+use crate::error::{PieceError, Result};
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::process;
+
+/// Advisory lock serializing access to one device between piecer processes.
+/// Released when dropped.
+pub struct DeviceLock {
+    _file: File,
+}
+
+/// Lock the device called `key`. If another process has it, wait for it to
+/// finish with `queue`, or else fail with [`PieceError::Locked`].
+pub fn acquire(key: &str, queue: bool) -> Result<DeviceLock> {
+    acquire_in(&dirs::state_dir().join("locks"), key, queue)
+}
+
+/// [`acquire`], with the lock files in `dir`.
+pub fn acquire_in(dir: &Path, key: &str, queue: bool) -> Result<DeviceLock> {
+    fs::create_dir_all(dir)?;
+    let name: String = key.chars().map(|c| if c.is_ascii_alphanumeric() || "-_.".contains(c) { c } else { '_' }).collect();
+    let mut file = OpenOptions::new().read(true).write(true).create(true).truncate(false)
+        .open(dir.join(format!("{}.lock", name)))?;
+    if let Err(error) = file.try_lock() {
+        if let TryLockError::Error(error) = error {
+            return Err(error.into());
+        }
+        let mut holder = String::new();
+        file.read_to_string(&mut holder)?;
+        let pid = holder.trim().parse().ok();
+        if !queue {
+            return Err(PieceError::Locked { pid });
+        }
+        match pid {
+            Some(pid) => eprintln!("Waiting for piecer process {} to finish with the device...", pid),
+            None => eprintln!("Waiting for another piecer to finish with the device..."),
+        }
+        file.lock()?;
+    }
+    file.set_len(0)?;
+    file.seek(SeekFrom::Start(0))?;
+    write!(file, "{}", process::id())?;
+    Ok(DeviceLock { _file: file })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    // File locks belong to the open file, so two opens in one process
+    // conflict just as two piecer processes would.
+
+    fn dir(test: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("piecer-lock-{}-{}", process::id(), test));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn second_user_is_refused_with_the_holder() {
+        let dir = dir("refused");
+        let _first = acquire_in(&dir, "serial-1234", false).unwrap();
+        match acquire_in(&dir, "serial-1234", false) {
+            Err(PieceError::Locked { pid }) => assert_eq!(pid, Some(process::id())),
+            _ => panic!("second lock was not refused"),
+        }
+    }
+
+    #[test]
+    fn lock_is_released_on_drop() {
+        let dir = dir("released");
+        drop(acquire_in(&dir, "serial-1234", false).unwrap());
+        assert!(acquire_in(&dir, "serial-1234", false).is_ok());
+    }
+
+    #[test]
+    fn other_devices_are_not_locked() {
+        let dir = dir("others");
+        let _first = acquire_in(&dir, "serial-1234", false).unwrap();
+        assert!(acquire_in(&dir, "usb-1-2.3", false).is_ok());
+    }
+
+    #[test]
+    fn queued_user_waits_for_the_holder() {
+        let dir = dir("queued");
+        let first = acquire_in(&dir, "serial-1234", false).unwrap();
+        let released = Arc::new(AtomicBool::new(false));
+        let waiter = {
+            let (dir, released) = (dir.clone(), released.clone());
+            thread::spawn(move || {
+                let _second = acquire_in(&dir, "serial-1234", true).unwrap();
+                released.load(Ordering::SeqCst)
+            })
+        };
+        thread::sleep(Duration::from_millis(200));
+        released.store(true, Ordering::SeqCst);
+        drop(first);
+        assert!(waiter.join().unwrap(), "queued lock was taken while the first was held");
+    }
+}
@@ -1,169 +1,1954 @@
-use clap::{Parser, Subcommand};
-use rusb::*;
+use clap::{ArgGroup, CommandFactory, FromArgMatches, Parser, Subcommand};
+use std::thread;
 use std::time::Duration;
+use std::ffi::OsString;
+use std::panic::{self, AssertUnwindSafe};
+use std::process;
 use std::str;
-use std::fs::File;
-use std::io::Write;
+use std::fs;
+use std::io::{self, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use piecer::device::{self, Options, LowSpace, Piece, LCD_HEIGHT, LCD_WIDTH, VID, PID};
+use piecer::pffs::{chain, DirEnt, PffsGeometry, FAT_FREE};
+use piecer::{PieceError, Result};
+use piecer::{cancel, config, date, dirs, flash, i18n, json, kernel, memmap, names, panic_message, pex, progress, trace, wire};
 
-const TIMEOUT: Duration = Duration::from_secs(1);
+mod audio;
+mod backup;
+mod base64;
+mod bench;
+mod bmp;
+mod bootstrap;
+mod clone;
+mod complete;
+mod console;
+mod crc32;
+mod crypto;
+mod deflate;
+mod du;
+mod dump;
+mod emulator;
+mod firmware;
+mod fps;
+mod frag;
+mod fsck;
+mod fssnap;
+mod gdb;
+mod gif;
+mod glob;
+mod halt;
+mod hexedit;
+mod hexfile;
+mod hooks;
+mod image;
+mod input;
+mod launch;
+#[cfg(all(feature = "fuse", target_os = "linux"))]
+mod mount;
+mod offline;
+mod patch;
+mod peek;
+mod plugins;
+mod png;
+mod ramdiff;
+mod rawfs;
+mod recover;
+mod regs;
+mod repo;
+mod report;
+mod resume;
+mod rtc;
+mod saves;
+mod screen;
+mod scrub;
+mod sha256;
+mod shell;
+mod state;
+mod sync;
+mod sysinfo;
+mod tar;
+mod term;
+mod top;
+mod tui;
+mod verify;
+mod watch;
+mod zip;
 
-struct DirEnt {
-    name: String,
-    cluster: u16,
-    len: u32
+/// Parse a duration such as `10s`, `500ms` or `2m`; a bare number is seconds.
+fn parse_duration(s: &str) -> std::result::Result<Duration, String> {
+    let (number, scale) = if let Some(ms) = s.strip_suffix("ms") {
+        (ms, 0.001)
+    } else if let Some(seconds) = s.strip_suffix('s') {
+        (seconds, 1.0)
+    } else if let Some(minutes) = s.strip_suffix('m') {
+        (minutes, 60.0)
+    } else {
+        (s, 1.0)
+    };
+    let number: f64 = number.parse().map_err(|_| format!("invalid duration {:?}", s))?;
+    Duration::try_from_secs_f64(number * scale).map_err(|e| e.to_string())
 }
 
-struct Piece {
-    device_handle: DeviceHandle<GlobalContext>,
-    pffs_top: u32
+/// Parse a decimal or 0x-prefixed hexadecimal number.
+fn parse_number(s: &str) -> std::result::Result<u32, String> {
+    match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => s.parse(),
+    }.map_err(|e| e.to_string())
 }
 
+fn parse_time(s: &str) -> std::result::Result<i64, String> {
+    date::parse(s).ok_or_else(|| format!("expected a time like \"2024-05-01 12:30:00\", got {:?}", s))
+}
+
+/// Parse a USB location such as `1:5` into bus number and address.
+fn parse_bus_address(s: &str) -> std::result::Result<(u8, u8), String> {
+    let (bus, address) = s.split_once(':').ok_or_else(|| format!("expected BUS:ADDR, got {:?}", s))?;
+    Ok((bus.parse().map_err(|e| format!("bus: {}", e))?, address.parse().map_err(|e| format!("address: {}", e))?))
+}
+
+/// Ask `question` on stderr and whether the answer was yes.
+fn confirm(question: &str) -> bool {
+    eprint!("{} [y/N] ", question);
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer).expect("Could not read answer");
+    matches!(answer.trim(), "y" | "Y" | "yes")
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, &cb) in b.iter().enumerate() {
+            let above = row[j + 1];
+            row[j + 1] = (diagonal + (ca != cb) as usize).min(row[j] + 1).min(above + 1);
+            diagonal = above;
+        }
+    }
+    row[b.len()]
+}
+
+/// The on-device name `name` refers to, or an error suggesting close matches.
+fn resolve_name(directory: &[DirEnt], name: &str, ignore_case: bool) -> Result<String> {
+    let found = directory.iter().find(|dirent| dirent.name == name)
+        .or_else(|| directory.iter().find(|dirent| ignore_case && dirent.name.to_lowercase() == name.to_lowercase()));
+    if let Some(dirent) = found {
+        return Ok(dirent.name.clone());
+    }
+    let mut close: Vec<(usize, &str)> = directory.iter()
+        .map(|dirent| (edit_distance(&dirent.name.to_lowercase(), &name.to_lowercase()), dirent.name.as_str()))
+        .filter(|&(distance, _)| distance <= 3.max(name.len() / 3))
+        .collect();
+    close.sort();
+    Err(match close.is_empty() {
+        true => PieceError::FileNotFound(name.to_string()),
+        false => PieceError::NotFound(i18n::trf("Could not find {} on device. Did you mean: {}?",
+                                                &[&name, &close.iter().take(3).map(|&(_, name)| name).collect::<Vec<_>>().join(", ")])),
+    })
+}
+
+/// Write downloaded `data` to `path`, or to stdout, refusing to replace an
+/// existing file unless `force`.
+fn save_download(data: &[u8], path: &Path, stdout: bool, force: bool) -> Result<()> {
+    if stdout {
+        return io::stdout().lock().write_all(data).map_err(PieceError::host_io("Could not write to stdout"));
+    }
+    refuse_overwrite(path, force)?;
+    fs::write(path, data).map_err(PieceError::host_io(format!("Could not write {}", path.display())))
+}
+
+/// Refuse to replace `path` if it exists, unless `force`.
+fn refuse_overwrite(path: &Path, force: bool) -> Result<()> {
+    match path.exists() && !force {
+        true => Err(PieceError::Usage(i18n::trf("{} already exists; use --force to overwrite it", &[&path.display()]))),
+        false => Ok(()),
+    }
+}
+
+/// The members `ls --long --json` and `info --json` show for an executable.
+fn pex_json(header: &pex::Header) -> Vec<(&'static str, String)> {
+    vec![
+        ("title", json::string(&header.title)),
+        ("version", json::string(&header.version_string())),
+        ("load", header.load_addr.to_string()),
+        ("entry", header.entry.to_string()),
+    ]
+}
+
+/// What keeps each entry of `directory` from being saved on a host under
+/// its own name, with the name [`names::hosts`] gives it instead. Entries
+/// without problems are left out.
+fn name_problems(directory: &[DirEnt]) -> Vec<(&DirEnt, String, Vec<String>)> {
+    let hosts = names::hosts(directory.iter().map(|dirent| dirent.name.as_str()));
+    let mut found = Vec::new();
+    for (i, (dirent, host)) in directory.iter().zip(&hosts).enumerate() {
+        let mut problems = Vec::new();
+        if let Some(problem) = dirent.problem {
+            problems.push(i18n::tr(problem).to_string());
+        }
+        if let Some(first) = directory[..i].iter().find(|other| other.name == dirent.name) {
+            problems.push(format!("same name as entry {}, which is the one found by name", first.index));
+        }
+        if names::sanitize(&dirent.name) != dirent.name {
+            problems.push(format!("not a valid file name on every host; saved as {:?}", names::host(&dirent.name)));
+        }
+        let plain = names::host(&dirent.name).to_lowercase();
+        if let Some(other) = directory[..i].iter().zip(&hosts).find(|(_, other)| other.to_lowercase() == plain) {
+            problems.push(format!("clashes with entry {} on the host; saved as {:?}", other.0.index, host));
+        }
+        if !problems.is_empty() {
+            found.push((dirent, host.clone(), problems));
+        }
+    }
+    found
+}
+
+fn warn_suspicious(directory: &[DirEnt]) {
+    for dirent in directory {
+        if let Some(problem) = dirent.problem {
+            eprintln!("{}", i18n::trf("warning: entry {} {}: {}", &[&dirent.index, &format!("{:?}", dirent.name), &i18n::tr(problem)]));
+        }
+    }
+}
 
 #[derive(Parser)]
 #[command()]
 struct Cli {
     #[command(subcommand)]
     command: Commands,
+    /// Report transfer progress as machine-readable events on stderr
+    #[arg(long, global = true, value_enum)]
+    progress: Option<progress::Format>,
+    /// Write progress events to this file or pipe instead of stderr
+    #[arg(long, global = true, requires = "progress")]
+    progress_file: Option<PathBuf>,
+    /// Print ls, info, df, verify and diff results as JSON
+    #[arg(long, global = true)]
+    json: bool,
+    /// How device file names are mapped to host file names
+    #[arg(long, global = true, value_enum, default_value_t)]
+    host_names: names::HostNames,
+    /// Characters screenshot and watch draw black to white with in blocks mode, e.g. "#+. "
+    ///
+    /// Can also be set with `chars` in the [screen] section of the config,
+    /// as can `colors`, `invert` and `scale`.
+    #[arg(long, global = true, value_parser = screen::parse_chars)]
+    palette_chars: Option<[char; 4]>,
+    /// Colors screenshot and watch draw black to white with, e.g. #0f380f,#306230,#8bac0f,#9bbc0f
+    #[arg(long, global = true, value_parser = screen::parse_colors)]
+    palette_colors: Option<[[u8; 3]; 4]>,
+    /// Draw the display inverted, for terminals with a light background
+    #[arg(long, global = true)]
+    invert: bool,
+    /// Draw each pixel this many times across and down
+    #[arg(long, global = true, value_parser = clap::value_parser!(u8).range(1..=4))]
+    scale: Option<u8>,
+    /// How file names are stored on the device
+    ///
+    /// Can also be set with `name-encoding = utf8` in the [device] section of the config.
+    #[arg(long, global = true, value_enum)]
+    name_encoding: Option<names::NameEncoding>,
+    /// Limit transfers to this many KB/s, to avoid starving a running application
+    ///
+    /// Also helps on hubs that drop transfers when the link runs flat out.
+    #[arg(long, global = true, visible_alias = "limit-rate", value_name = "KB/S")]
+    throttle: Option<u32>,
+    /// Base USB timeout per transfer, e.g. 2s or 300ms
+    ///
+    /// Named so as not to clash with `assert-screen --timeout`. Can also be
+    /// set with `timeout` in the [usb] section of the config, as can
+    /// `chunk-size` and `retries`.
+    #[arg(long, global = true, value_parser = parse_duration)]
+    usb_timeout: Option<Duration>,
+    /// Largest read per USB round-trip, in bytes (default 4096)
+    #[arg(long, global = true, value_parser = clap::value_parser!(u32).range(device::MIN_READ_BLOCK as i64..))]
+    chunk_size: Option<u32>,
+    /// Attempts per transfer before giving up (default 4)
+    #[arg(long, global = true, value_parser = clap::value_parser!(u32).range(1..))]
+    retries: Option<u32>,
+    /// Request each read block while the last is still arriving, which
+    /// speeds up dumps and backups on kernels that allow it (see `bench`)
+    ///
+    /// Can also be set with `overlap = true` in the [usb] section of the config.
+    #[arg(long, global = true)]
+    overlap: bool,
+    /// Refuse any command that writes to or runs code on the device
+    ///
+    /// Can also be set with `read-only = true` in the [device] section of the config.
+    #[arg(long, global = true)]
+    read_only: bool,
+    /// Print which flash sectors, directory entries and clusters upload, rm,
+    /// format, restore, defrag and flash-firmware would change, without
+    /// writing anything
+    #[arg(long, global = true)]
+    dry_run: bool,
+    /// Read file and dump data twice and retry until the reads agree
+    #[arg(long, global = true)]
+    paranoid: bool,
+    /// Log every USB transfer to stderr; twice to add payload hex dumps,
+    /// three times to dump payloads in full
+    #[arg(short = 'v', long = "trace", global = true, action = clap::ArgAction::Count)]
+    verbose: u8,
+    /// Record device commands and filesystem operations as a Chrome trace
+    #[arg(long, global = true)]
+    trace_output: Option<PathBuf>,
+    /// Use the device with this USB serial number (see `devices`)
+    #[arg(long, global = true)]
+    serial: Option<String>,
+    /// Use the device at this USB bus and address, e.g. 1:5, whatever its
+    /// vendor and product IDs (see `devices`)
+    #[arg(long, global = true, value_name = "BUS:ADDR", value_parser = parse_bus_address)]
+    bus_address: Option<(u8, u8)>,
+    /// If the device disappears mid-command, wait for it to come back and carry on
+    #[arg(long, global = true)]
+    reconnect: bool,
+    /// If no device is plugged in yet, wait for one instead of failing
+    #[arg(long, global = true)]
+    wait: bool,
+    /// If a kernel driver has claimed the device, fail instead of detaching it
+    #[arg(long, global = true)]
+    no_detach: bool,
+    /// If another piecer is using the device, wait for it to finish
+    #[arg(long, global = true)]
+    queue: bool,
 }
 
 #[derive(Subcommand)]
 enum Commands {
+    /// List attached devices with their USB location, serial number and kernel
+    Devices,
+    /// Reset the USB connection to a device that stopped answering
+    Reset {
+        /// Restart the kernel instead, which also stops the running application
+        #[arg(long)]
+        hard: bool,
+    },
     /// List all files on device
-    Ls,
-    /// Display a screenshot in terminal
-    Screenshot,
-    /// Download a single file to current directory
+    Ls {
+        /// Only list files matching this wildcard pattern, e.g. 'SAVE*'
+        pattern: Option<String>,
+        /// Keep re-reading the directory and show entries as they change
+        #[arg(long)]
+        watch: bool,
+        /// Seconds between re-reads with --watch
+        #[arg(long, default_value_t = 2.0, requires = "watch")]
+        interval: f64,
+        /// Also show the unknown bytes 24..26 of each entry, and the title,
+        /// version and entry point of executables
+        #[arg(short, long, conflicts_with = "watch")]
+        long: bool,
+        /// Show each directory slot as stored, in hex, split into name,
+        /// unknown bytes, first cluster and length
+        #[arg(long, conflicts_with_all = ["watch", "long"])]
+        raw: bool,
+        /// Instead of listing, report entries that can't be saved on this
+        /// host under their own name: duplicates, names the host can't use
+        /// and corrupt entries. Exits 1 if there are any
+        #[arg(long, conflicts_with_all = ["watch", "long", "raw"])]
+        check: bool,
+    },
+    /// Show the device's kernel, memory and USB details, or with a file its
+    /// size, clusters and type, and the header of an executable
+    Info {
+        file: Option<String>,
+    },
+    /// Start an executable stored on the device
+    Run {
+        /// File on the device, or with --upload a local file
+        file: String,
+        /// Upload the local file first, replacing any with the same name
+        #[arg(long)]
+        upload: bool,
+    },
+    /// Freeze the running application, e.g. to inspect its memory, until `continue`
+    ///
+    /// Commands that pause the device for a moment, such as screenshot,
+    /// leave a halted device halted.
+    Halt,
+    /// Let an application frozen by `halt` run again
+    Continue,
+    /// Show whether the device is halted and which application it is running
+    Status,
+    /// Show the stopped CPU's registers, where readable, and a best-effort backtrace
+    ///
+    /// Without kernel support for reading registers, the backtrace comes from
+    /// scanning the stack for addresses in the app's or the kernel's code,
+    /// innermost first, and can include stale frames. Best run after `halt`.
+    Regs {
+        /// Most frames to show
+        #[arg(long, default_value_t = 16)]
+        depth: usize,
+    },
+    /// Let GDB attach to the device to read and write memory
+    Gdbserver {
+        #[arg(long, default_value_t = 3333)]
+        port: u16,
+    },
+    /// Show an app's console output and send it what you type
+    ///
+    /// The app has to keep a piecer console block in SRAM; see src/console.rs.
+    Console {
+        /// Address of the console block, if it can't be found
+        #[arg(long, value_parser = parse_number)]
+        addr: Option<u32>,
+    },
+    /// Display a screenshot in terminal, or print it as source code
+    Screenshot {
+        #[arg(long, value_enum, default_value_t)]
+        format: screen::Format,
+        /// How to draw it in the terminal
+        #[arg(long, value_enum, default_value_t)]
+        render: screen::Render,
+        /// Save to an image file instead (PNG, or BMP for a .bmp name)
+        #[arg(short, long, conflicts_with_all = ["format", "render"])]
+        output: Option<PathBuf>,
+        /// Keep watching the display and save each new screen as a numbered
+        /// PNG, until Ctrl-C
+        #[arg(long, conflicts_with_all = ["format", "render", "output"])]
+        on_change: bool,
+        /// Directory for --on-change shots
+        #[arg(long, default_value = "shots", requires = "on_change")]
+        out_dir: PathBuf,
+        /// How often --on-change looks at the display
+        #[arg(long, default_value = "100ms", value_parser = parse_duration, requires = "on_change")]
+        interval: Duration,
+        /// Stop after saving this many screens
+        #[arg(long, requires = "on_change")]
+        count: Option<usize>,
+    },
+    /// Upload a file to the device, replacing any file with the same name
+    #[command(visible_alias = "put")]
+    Upload {
+        file: PathBuf,
+        /// Name on the device; defaults to the file's name
+        #[arg(long)]
+        name: Option<String>,
+        /// Upload to every attached device at once
+        #[arg(long)]
+        all_devices: bool,
+        /// Write even if it leaves the device low on space
+        #[arg(long)]
+        force: bool,
+    },
+    /// Delete files from the device
+    Rm {
+        #[arg(required = true)]
+        files: Vec<String>,
+        /// Match file names regardless of case
+        #[arg(long)]
+        ignore_case: bool,
+    },
+    /// Rename a file on the device without rewriting its data
+    Mv {
+        old: String,
+        new: String,
+        /// Match the old name regardless of case
+        #[arg(long)]
+        ignore_case: bool,
+    },
+    /// Download files, by default to the current directory
+    #[command(group(ArgGroup::new("target").required(true).args(["files", "index", "cluster"])))]
     Download {
+        /// Save to this path instead of the device file name, or with several
+        /// files into this directory
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        /// Write the file to stdout instead
+        #[arg(long, conflicts_with = "output")]
+        stdout: bool,
+        /// Overwrite existing files
+        #[arg(long)]
+        force: bool,
+        /// Read each file a second time and fail if the reads differ
+        #[arg(long, requires = "files")]
+        verify: bool,
+        /// Continue downloads interrupted partway, kept as NAME.part
+        #[arg(long, requires = "files", conflicts_with = "stdout")]
+        resume: bool,
+        /// File names or wildcard patterns such as '*.pex'
+        files: Vec<String>,
+        /// Match file names regardless of case
+        #[arg(long, requires = "files")]
+        ignore_case: bool,
+        /// Download the file in this directory slot, for names that cannot be decoded
+        #[arg(long)]
+        index: Option<usize>,
+        /// Download the cluster chain starting here, whether or not a file refers to it
+        #[arg(long, value_parser = parse_number)]
+        cluster: Option<u32>,
+    },
+    /// Show the device's files as a read-only filesystem until Ctrl-C
+    #[cfg(all(feature = "fuse", target_os = "linux"))]
+    Mount {
+        mountpoint: PathBuf,
+    },
+    /// Compare the device's files with a directory of downloaded or backed-up copies
+    ///
+    /// Lists files only on the device, only in the directory, and those that
+    /// differ. Exits with status 1 if there are any.
+    Diff {
+        dir: PathBuf,
+        /// Compare sizes only, without reading the files from the device
+        #[arg(long)]
+        size_only: bool,
+    },
+    /// Print the CRC32 and SHA-256 of a device file, or compare it with a local copy
+    ///
+    /// Exits with status 1 if the local copy differs.
+    Verify {
         file: String,
+        local_file: Option<PathBuf>,
     },
-    /// Dump flash to dump.img
-    Dump,
-    /// Download all files to current directory
-    Backup,
-}
-
-impl Piece {
-    fn new() -> Piece {
-        let device_handle = open_device_with_vid_pid(0x0e19, 0x1000).expect("Could not open PIECE device");
-        device_handle.claim_interface(0).unwrap();
-        device_handle.write_bulk(0x02, &[0, 32], TIMEOUT).unwrap();
-        let mut version = [0; 32];
-        device_handle.read_bulk(0x82, &mut version, TIMEOUT).unwrap();
-        let pffs_top = u32::from_le_bytes(version[24..28].try_into().unwrap());
-        return Piece { device_handle, pffs_top }
-    }
-    fn get_memory(&mut self, addr: u32, len: u32, data: &mut [u8]) {
-        let mut bytes_left = len;
-        loop {
-            let bytes_to_read = bytes_left.min(32);
-            let mut command: Vec<u8> = vec![2];
-            command.extend((addr+len-bytes_left).to_le_bytes());
-            command.extend(bytes_to_read.to_le_bytes());
-            self.device_handle.write_bulk(0x02, &command, TIMEOUT).unwrap();
-            self.device_handle.read_bulk(0x82, &mut data[((len-bytes_left) as usize)..], TIMEOUT).unwrap();
-            bytes_left -= bytes_to_read;
-            if bytes_left == 0 {
-                break;
-            }
-        }
-    }
-    fn pause(&mut self) {
-        self.device_handle.write_bulk(0x02, &[16, 1], TIMEOUT).unwrap();
-    }
-    fn resume(&mut self) {
-        self.device_handle.write_bulk(0x02, &[16, 0], TIMEOUT).unwrap();
-    }
-    fn get_screenshot(&mut self) {
-        self.pause();
-        self.device_handle.write_bulk(0x02, &[17], TIMEOUT).unwrap();
-        let mut lcd_data = [0; 12];
-        self.device_handle.read_bulk(0x82, &mut lcd_data, TIMEOUT).unwrap();
-        println!("LCD data: {:?}", lcd_data);
-        let lcd_width = lcd_data[2];
-        let lcd_height = lcd_data[4];
-        assert_eq!(lcd_width, 128);
-        assert_eq!(lcd_height, 88);
-        let lcd_addr = u32::from_le_bytes(lcd_data[8..12].try_into().unwrap());
-        for y in 0..88 {
-            let mut line = [0; 128];
-            self.get_memory(lcd_addr + y * 128, 128, &mut line);
-            for p in line {
-                print!("{}", match p {
-                    3 => " ",
-                    2 => "░",
-                    1 => "▒",
-                    0 => "▓",
-                    _ => "X"
-                });
-            }
-            println!();
-        }
-        self.resume();
-    }
-    fn ls(&mut self) -> Vec<DirEnt> {
-        let mut directory = Vec::<DirEnt>::new();
-        for i in 1..96 {
-            let mut dirent_raw = [0; 32];
-            self.get_memory(self.pffs_top + i * 32, 32, &mut dirent_raw);
-            if dirent_raw[0] != 0x00 && dirent_raw[0] != 0xFF {
-                let dirent = DirEnt { name: str::from_utf8(&dirent_raw[0..24]).unwrap().trim_matches(char::from(0)).to_string(),
-                                      cluster: u16::from_le_bytes(dirent_raw[26..28].try_into().unwrap()),
-                                      len: u32::from_le_bytes(dirent_raw[28..32].try_into().unwrap()
-                )};
-                directory.push(dirent);
-            }
-        }
-        directory
+    /// Dump flash, or another memory region, to a file
+    Dump {
+        /// Named region to dump
+        #[arg(long, value_enum, default_value_t)]
+        region: dump::Region,
+        /// Start address, overriding the region's; may be symbolic, as in sram+0x100
+        #[arg(long, value_parser = memmap::parse)]
+        start: Option<memmap::Address>,
+        /// Bytes to dump; defaults to the rest of the region
+        #[arg(long, value_parser = parse_number)]
+        length: Option<u32>,
+        /// Output file (default: dump.img for flash, REGION.bin otherwise)
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        /// Encrypt the dump with a passphrase (written to OUTPUT.enc)
+        #[arg(long)]
+        encrypt: bool,
+        /// Continue an interrupted dump
+        #[arg(long, conflicts_with = "encrypt")]
+        resume: bool,
+        /// Write the dump to stdout instead of a file
+        #[arg(long, conflicts_with_all = ["encrypt", "resume", "output"])]
+        stdout: bool,
+        /// Raw bytes, or records addressed from the start address
+        #[arg(long, value_enum, conflicts_with_all = ["encrypt", "resume"])]
+        format: Option<hexfile::Format>,
+    },
+    /// Download all files to current directory, with a manifest.json of
+    /// the device's details and each file's size and checksum
+    Backup {
+        /// Run from cron: write into a dated directory and log to a file
+        #[arg(long)]
+        unattended: bool,
+        /// Log file for unattended mode
+        #[arg(long, default_value = "backup.log", requires = "unattended")]
+        log: PathBuf,
+        /// Store a deduplicated snapshot in this repository instead
+        #[arg(long, conflicts_with = "unattended")]
+        repo: Option<PathBuf>,
+        /// Encrypt each file with a passphrase (written as NAME.enc)
+        #[arg(long, conflicts_with = "repo")]
+        encrypt: bool,
+        /// Compress each file unless it is already compressed (written as NAME.gz)
+        #[arg(long)]
+        compress: bool,
+        /// Skip files already saved by an interrupted backup
+        #[arg(long, conflicts_with_all = ["unattended", "repo"])]
+        resume: bool,
+        /// Write everything into one .zip, .tar or .tar.gz archive instead,
+        /// with a manifest of sizes, clusters and device details
+        #[arg(long, conflicts_with_all = ["unattended", "repo", "encrypt", "compress", "resume"])]
+        archive: Option<PathBuf>,
+    },
+    /// Back up or restore just the games' save files
+    ///
+    /// Save files are those matching `patterns` in the [saves] section of
+    /// the config, a comma-separated list of wildcards, or by default
+    /// *.sav, *.sv, save* and *save.dat.
+    Saves {
+        #[command(subcommand)]
+        command: SavesCommands,
+    },
+    /// Read or adjust the device clock
+    Clock {
+        #[command(subcommand)]
+        command: ClockCommands,
+    },
+    /// Upload a single file from a backup, or write a flash dump back
+    ///
+    /// With --only, uploads that file from a backup directory or archive, or
+    /// a repository snapshot; encrypted and compressed copies in a backup
+    /// directory are decoded transparently. Without it, SOURCE is an image
+    /// written by `dump` (optionally encrypted), and the filesystem area is
+    /// rewritten from it.
+    Restore {
+        /// File to restore
+        #[arg(long)]
+        only: Option<String>,
+        /// Backup directory or archive, snapshot manifest, or flash image
+        source: PathBuf,
+        /// Write even if it leaves the device low on space
+        #[arg(long, requires = "only")]
+        force: bool,
+        /// Also rewrite the kernel area of a flash image
+        #[arg(long, conflicts_with = "only")]
+        kernel: bool,
+    },
+    /// Upload every file in a backup directory or archive, skipping ones already on the device
+    ///
+    /// If the backup has a manifest, the device has to have the kernel and
+    /// filesystem layout it records, and files whose size or checksum no
+    /// longer match it are reported instead of uploaded. Exits with status
+    /// 1 if any file couldn't be written.
+    RestoreFiles {
+        /// A directory written by `backup`, or an archive from `backup --archive`
+        dir: PathBuf,
+        /// Write even if it leaves the device low on space
+        #[arg(long)]
+        force: bool,
+        /// Restore even if the manifest records another kernel or filesystem layout
+        #[arg(long)]
+        any_device: bool,
+    },
+    /// Decrypt a file written with --encrypt
+    Decrypt {
+        input: PathBuf,
+        /// Defaults to the input name without .enc
+        output: Option<PathBuf>,
+    },
+    /// Load a kernel image onto a blank or recovery-mode unit
+    Bootstrap {
+        image: PathBuf,
+        /// Program the image into flash instead of running it from RAM
+        #[arg(long)]
+        flash: bool,
+        /// RAM address to load and start the image at (default: start of SRAM)
+        #[arg(long, value_parser = parse_number, conflicts_with = "flash")]
+        load_addr: Option<u32>,
+    },
+    /// Replace the kernel in flash with a firmware image such as pbios.img
+    ///
+    /// The image must be exactly the size of the device's kernel area and
+    /// start with a reset vector into it. Every sector is read back after
+    /// programming. If the update is interrupted or the image is bad, the
+    /// unit needs `bootstrap` to recover.
+    FlashFirmware {
+        image: PathBuf,
+        /// Refuse the image unless it has this CRC-32, e.g. 0x1234abcd
+        #[arg(long, value_parser = parse_number)]
+        crc32: Option<u32>,
+        /// Don't ask for confirmation
+        #[arg(long)]
+        yes: bool,
+    },
+    /// Report file fragmentation and the largest contiguous free space
+    Frag,
+    /// Move clusters so every file is contiguous, verifying each move
+    Defrag,
+    /// Show how much flash each file occupies, including cluster slack
+    Du,
+    /// Show total, used and free space and directory slots
+    Df,
+    /// Measure the running app's frame rate and frame-time jitter
+    ///
+    /// Without --counter, frames are detected by the framebuffer address or
+    /// contents changing, so the resolution is limited by how fast those can be
+    /// read over USB.
+    Fps {
+        /// How long to sample for
+        #[arg(long, default_value = "5s", value_parser = parse_duration)]
+        duration: Duration,
+        /// Address of a 32-bit frame counter the app increments, e.g. from its map file
+        #[arg(long, value_parser = parse_number)]
+        counter: Option<u32>,
+    },
+    /// Press buttons on the device, or without --keys, drive it from the
+    /// keyboard while watching its display
+    Input {
+        /// Buttons to press in turn, e.g. A,UP,UP,START; join buttons with +
+        /// to press them together, as in A+B
+        #[arg(long, value_parser = input::parse_sequence)]
+        keys: Option<input::Sequence>,
+        /// How long each press is held
+        #[arg(long, default_value = "100ms", value_parser = parse_duration)]
+        hold: Duration,
+        /// Time between presses with --keys
+        #[arg(long, default_value = "100ms", value_parser = parse_duration, requires = "keys")]
+        gap: Duration,
+        /// How to draw the display without --keys
+        #[arg(long, value_enum, default_value_t = screen::Render::HalfBlocks, conflicts_with = "keys")]
+        render: screen::Render,
+    },
+    /// Measure command latency and read throughput at each block size
+    Bench {
+        /// Command round-trips to time
+        #[arg(long, default_value_t = 100, value_parser = clap::value_parser!(u32).range(1..))]
+        rounds: u32,
+        /// Bytes of flash to read at each block size
+        #[arg(long, default_value = "0x10000", value_parser = parse_number)]
+        bytes: u32,
+    },
+    /// Measure how long a key press takes to change the display
+    Latency {
+        /// Key to press
+        #[arg(long, value_enum, default_value_t = input::Key::A)]
+        key: input::Key,
+        /// Screen region to watch, as x,y,width,height
+        #[arg(long, value_parser = input::parse_region)]
+        region: Option<input::Region>,
+        /// Number of presses to measure
+        #[arg(long, default_value_t = 5)]
+        count: u32,
+    },
+    /// Live view of battery, clock and flash usage
+    Top {
+        /// How often to refresh
+        #[arg(long, default_value = "1s", value_parser = parse_duration)]
+        interval: Duration,
+    },
+    /// Write a self-contained HTML page describing the device and its files
+    Report {
+        output: PathBuf,
+    },
+    /// Convert a PNG to the device's 2-bit bitmap format
+    ConvertImage {
+        input: PathBuf,
+        /// Output file, also the device file name with --upload
+        #[arg(long)]
+        out: PathBuf,
+        /// Largest width to scale to
+        #[arg(long, default_value_t = LCD_WIDTH as u32)]
+        width: u32,
+        /// Largest height to scale to
+        #[arg(long, default_value_t = LCD_HEIGHT as u32)]
+        height: u32,
+        /// Use error diffusion instead of plain rounding to four levels
+        #[arg(long)]
+        dither: bool,
+        /// Also upload the result to the device
+        #[arg(long)]
+        upload: bool,
+    },
+    /// Convert a WAV file to raw mono 16-bit PCM at the device's playback rate
+    ConvertAudio {
+        input: PathBuf,
+        /// Output file, also the device file name with --upload
+        #[arg(long)]
+        out: PathBuf,
+        /// Sample rate in Hz
+        #[arg(long, default_value_t = 16000, value_parser = clap::value_parser!(u32).range(1..))]
+        rate: u32,
+        /// Also upload the result to the device
+        #[arg(long)]
+        upload: bool,
+    },
+    /// Record the directory and cluster layout to a JSON file for fs-diff
+    FsSnapshot {
+        output: PathBuf,
+    },
+    /// Report files added, removed, resized or relocated since fs-snapshot
+    FsDiff {
+        snapshot: PathBuf,
+    },
+    /// Keep downloading files that appear or grow on the device into a directory
+    Autopull {
+        dir: PathBuf,
+        /// How often to check the device
+        #[arg(long, default_value = "2s", value_parser = parse_duration)]
+        interval: Duration,
+        /// Also download the files already on the device at startup
+        #[arg(long)]
+        all: bool,
+    },
+    /// Upload the files in a directory that differ from the device's
+    ///
+    /// With --watch, keeps running and uploads files again as they are
+    /// written, so it can be the deploy step of a build.
+    Sync {
+        dir: PathBuf,
+        /// Keep uploading files as they change
+        #[arg(long)]
+        watch: bool,
+        /// How often to check for changes where the directory can't be watched
+        #[arg(long, default_value = "1s", value_parser = parse_duration, requires = "watch")]
+        interval: Duration,
+        /// Start this executable on the device after each round of uploads
+        #[arg(long, value_name = "FILE")]
+        run: Option<String>,
+    },
+    /// Checksum every file and flag contents that changed since the last scrub
+    ///
+    /// Exits with status 1 if a file changed without its size changing, which
+    /// suggests flash corruption rather than an app rewriting it.
+    Scrub,
+    /// Show the flash layout and write constraints
+    FlashInfo,
+    /// Copy every file from one attached device to another
+    Clone {
+        /// Serial number of the device to copy from
+        #[arg(long)]
+        from: String,
+        /// Serial number of the device to copy to
+        #[arg(long)]
+        to: String,
+        /// Copy the whole filesystem region sector by sector instead,
+        /// replacing everything on the target
+        #[arg(long)]
+        whole: bool,
+    },
+    /// Browse device and local files side by side to copy and delete them
+    Tui,
+    /// Read commands such as `ls` or `peek flash 16` at a prompt
+    ///
+    /// Every command runs over the same connection, and the directory is
+    /// read once until a command writes or starts something. `refresh`
+    /// reconnects, e.g. after an app has saved files; `exit` leaves.
+    Shell,
+    /// Run the commands in a file, one per line, as `shell` would
+    ///
+    /// Stops at the first command that fails, exiting with its status.
+    Script {
+        file: PathBuf,
+        /// Carry on after a command fails
+        #[arg(long)]
+        keep_going: bool,
+    },
+    /// List the named regions of the address space, for use in addresses
+    /// such as flash+0x1000
+    Memmap,
+    /// Browse and edit device memory in a full-screen hex editor
+    Hexedit {
+        /// Address, or a region and offset such as flash+0x1000 (see memmap)
+        #[arg(value_parser = memmap::parse)]
+        addr: memmap::Address,
+    },
+    /// Read device memory, as a hex dump or raw to a file
+    Peek {
+        /// Address, or a region and offset such as flash+0x1000 (see memmap)
+        #[arg(value_parser = memmap::parse)]
+        addr: memmap::Address,
+        #[arg(value_parser = parse_number)]
+        len: u32,
+        /// Save the bytes here instead, raw unless --format says otherwise
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        /// Write raw bytes or records instead of a hex dump
+        #[arg(long, value_enum)]
+        format: Option<hexfile::Format>,
+    },
+    /// Print device memory as offsets, hex bytes and ASCII
+    Hexdump {
+        /// Address, or a region and offset such as flash+0x1000 (see memmap)
+        #[arg(value_parser = memmap::parse)]
+        addr: memmap::Address,
+        #[arg(value_parser = parse_number)]
+        len: u32,
+        /// Bytes per line
+        #[arg(long, default_value_t = 16, value_parser = clap::value_parser!(u16).range(1..=64))]
+        width: u16,
+        /// Count offsets from the start address instead of showing addresses
+        #[arg(long)]
+        relative: bool,
+    },
+    /// Write to device memory
+    Poke {
+        /// Address, or a region and offset such as flash+0x1000 (see memmap)
+        #[arg(value_parser = memmap::parse)]
+        addr: memmap::Address,
+        /// File to write, or hex bytes such as "12 34 ab"
+        data: String,
+    },
+    /// Send a command to the kernel byte for byte and show its answer
+    ///
+    /// For working out undocumented commands; add -vv to see every
+    /// transfer. Refused in read-only mode, since an unknown command may
+    /// write flash.
+    Raw {
+        /// Command bytes in hex, such as "10 01", or a file holding them
+        #[arg(long)]
+        cmd: String,
+        /// Data sent in a second transfer after the command, as for memory writes
+        #[arg(long)]
+        payload: Option<String>,
+        /// Bytes of answer to read
+        #[arg(long, default_value_t = 0)]
+        read: usize,
+    },
+    /// Compare memory against an earlier snapshot and print what changed
+    ///
+    /// The first run saves the snapshot. Run it again after something
+    /// happens on the device, such as a score going up, to find the bytes
+    /// that hold it.
+    Ramdiff {
+        /// Snapshot to compare against, saved here if it doesn't exist
+        #[arg(long)]
+        baseline: PathBuf,
+        /// Address, or a region and offset such as sram+0x1000 (see memmap)
+        #[arg(long, value_parser = memmap::parse, default_value = "sram")]
+        addr: memmap::Address,
+        /// Bytes to capture; defaults to the baseline's size or the rest of the region
+        #[arg(long, value_parser = parse_number)]
+        len: Option<u32>,
+        /// Replace the baseline with this capture after comparing
+        #[arg(long)]
+        update: bool,
+    },
+    /// Apply an IPS or BPS patch to a file on the device
+    Patch {
+        /// File on the device
+        file: String,
+        /// Patch on the host
+        patch: PathBuf,
+        /// Write the result to this device file instead of replacing FILE
+        #[arg(long)]
+        output: Option<String>,
+    },
+    /// Capture flash, RAM and display state into a tar bundle for emulators
+    ExportState {
+        output: PathBuf,
+    },
+    /// Mirror the display in the terminal until q is pressed
+    Watch {
+        /// Frames per second to aim for
+        #[arg(long, default_value_t = 5.0)]
+        fps: f64,
+        /// How to draw the display
+        #[arg(long, value_enum, default_value_t = screen::Render::HalfBlocks)]
+        render: screen::Render,
+    },
+    /// Record the display to an animated GIF
+    Record {
+        /// How long to record
+        #[arg(long, default_value = "10s", value_parser = parse_duration)]
+        duration: Duration,
+        #[arg(short, long)]
+        output: PathBuf,
+    },
+    /// Wait until the display matches a reference image
+    ///
+    /// Exits with status 1 if it doesn't match before the timeout.
+    AssertScreen {
+        /// 128x88 PNG to compare against
+        reference: PathBuf,
+        /// How long to keep capturing
+        #[arg(long, default_value = "10s", value_parser = parse_duration)]
+        timeout: Duration,
+        /// Percentage of pixels allowed to differ
+        #[arg(long, default_value_t = 0.0)]
+        tolerance: f64,
+    },
+    /// Check the filesystem for broken, looping or shared cluster chains
+    ///
+    /// Exits with status 1 if problems remain.
+    Fsck {
+        /// Fix what can be fixed without touching file data
+        #[arg(long)]
+        repair: bool,
+    },
+    /// Copy deleted files whose data is still in flash into a directory
+    ///
+    /// Directory entries that were only marked deleted give back the file
+    /// under its old name, with the first letter lost and shown as `_`.
+    /// With --scan, free clusters still holding data are gathered into
+    /// files too, each starting at a .pex, PNG, WAV, gzip or zip header.
+    /// Recovered files are a best guess; nothing on the device is changed.
+    Recover {
+        #[command(flatten)]
+        recover: RecoverArgs,
+    },
+    /// Read or write single clusters, or show a file's cluster chain
+    Fs {
+        #[command(subcommand)]
+        command: FsCommands,
+    },
+    /// Erase every file by writing an empty directory and cluster table
+    Format {
+        /// Don't ask for confirmation
+        #[arg(short, long)]
+        yes: bool,
+    },
+    /// Read a flash dump made by `dump`, without a device attached
+    Image {
+        #[command(subcommand)]
+        command: ImageCommands,
+    },
+    /// Move files between the device and P/ECE emulators
+    Emulator {
+        #[command(subcommand)]
+        command: EmulatorCommands,
+    },
+    /// Browse a backup repository created with `backup --repo`
+    Repo {
+        #[command(subcommand)]
+        command: RepoCommands,
+    },
+    /// List plugin subcommands (piecer-NAME programs) found on PATH
+    Plugins,
+    /// Print a shell completion script that also completes device file names
+    ///
+    /// For bash, add `source <(piecer completion bash)` to ~/.bashrc.
+    Completion {
+        #[arg(value_enum)]
+        shell: complete::Shell,
+    },
+    /// Completion candidates for the given words, used by the completion scripts
+    #[command(name = "__complete", hide = true)]
+    Complete {
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+        words: Vec<String>,
+    },
+    #[command(external_subcommand)]
+    External(Vec<OsString>),
+}
+
+#[derive(clap::Args)]
+struct RecoverArgs {
+    /// Also search free clusters for files with no entry left
+    #[arg(long)]
+    scan: bool,
+    /// Directory to write the files to
+    #[arg(short, long, default_value = "recovered")]
+    dest: PathBuf,
+    /// Only list what could be recovered
+    #[arg(long)]
+    list: bool,
+}
+
+#[derive(Subcommand)]
+enum EmulatorCommands {
+    /// Write a flash image, and the files in it, to a directory for an emulator
+    ///
+    /// The image is DIR/flash.bin, dumped from flash, and the files are in
+    /// DIR/files.
+    Export {
+        dir: PathBuf,
+        /// Take them from a flash dump instead of the device
+        #[arg(long, value_name = "IMAGE")]
+        from_dump: Option<PathBuf>,
+        /// Flash address of the PFFS metadata sector in the dump, if it can't be found
+        #[arg(long, value_parser = parse_number, requires = "from_dump")]
+        pffs_top: Option<u32>,
+    },
+    /// Upload the files from an emulator's flash image, or a directory of files
+    ///
+    /// Files the device already has with the same contents are skipped.
+    /// Exits with status 1 if any file couldn't be written.
+    Import {
+        /// A flash image, an export directory or a directory of files
+        source: PathBuf,
+        /// Write even if it leaves the device low on space
+        #[arg(long)]
+        force: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum RepoCommands {
+    /// List snapshots in a backup repository
+    List {
+        #[arg(long)]
+        repo: PathBuf,
+    },
+    /// Restore the files of a snapshot into a directory
+    Checkout {
+        snapshot: String,
+        dest: PathBuf,
+        #[arg(long)]
+        repo: PathBuf,
+    },
+}
+
+#[derive(Subcommand)]
+enum ImageCommands {
+    /// List the files in a dump
+    Ls {
+        image: PathBuf,
+        /// Flash address of the PFFS metadata sector, if it can't be found
+        #[arg(long, value_parser = parse_number)]
+        pffs_top: Option<u32>,
+    },
+    /// Copy files out of a dump, or all of them if none are named
+    Extract {
+        image: PathBuf,
+        files: Vec<String>,
+        /// Directory to write the files to
+        #[arg(short, long, default_value = ".")]
+        dest: PathBuf,
+        #[arg(long, value_parser = parse_number)]
+        pffs_top: Option<u32>,
+    },
+    /// Check a dump's directory and cluster table
+    ///
+    /// Exits with status 1 if there are problems.
+    Fsck {
+        image: PathBuf,
+        #[arg(long, value_parser = parse_number)]
+        pffs_top: Option<u32>,
+    },
+    /// Copy deleted files out of a dump; see `recover`
+    Recover {
+        image: PathBuf,
+        #[command(flatten)]
+        recover: RecoverArgs,
+        #[arg(long, value_parser = parse_number)]
+        pffs_top: Option<u32>,
+    },
+    /// Build a flash image holding the given files, for `restore` or an
+    /// emulator
+    ///
+    /// The PFFS position has to match the device the image is restored to.
+    Create {
+        output: PathBuf,
+        /// File to put in the image under its file name; repeat for more
+        #[arg(long = "add", required = true)]
+        files: Vec<PathBuf>,
+        /// Flash dump to take the kernel area and PFFS position from
+        #[arg(long)]
+        kernel: Option<PathBuf>,
+        /// Flash address of the PFFS metadata sector
+        #[arg(long, value_parser = parse_number, required_unless_present = "kernel")]
+        pffs_top: Option<u32>,
+    },
+}
+
+#[derive(Subcommand)]
+enum FsCommands {
+    /// Print a cluster as a hex dump, whether or not a file uses it
+    ReadCluster {
+        cluster: u16,
+        /// Save the raw cluster to this file instead
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Overwrite a cluster with the contents of a file, padded with 0xFF
+    ///
+    /// Only the data changes: the directory and cluster table are left as
+    /// they are, so this can break files.
+    WriteCluster {
+        cluster: u16,
+        file: PathBuf,
+        /// Don't ask for confirmation
+        #[arg(short, long)]
+        yes: bool,
+    },
+    /// List the clusters a file occupies, in order
+    Chain {
+        file: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum ClockCommands {
+    /// Show the device time and how far it is from the host's
+    Get,
+    /// Set the clock to a given local time, or the host's
+    Set {
+        /// Time such as "2024-05-01 12:30:00"
+        #[arg(required_unless_present = "from_host", value_parser = parse_time)]
+        time: Option<i64>,
+        #[arg(long, conflicts_with = "time")]
+        from_host: bool,
+    },
+    /// Set the clock from host time and report drift since the last sync
+    Sync,
+}
+
+#[derive(Subcommand)]
+enum SavesCommands {
+    /// Download the save files into a directory
+    Backup {
+        #[arg(default_value = "saves")]
+        dir: PathBuf,
+        /// Wildcard patterns to use instead of the configured ones
+        #[arg(long = "pattern")]
+        patterns: Vec<String>,
+    },
+    /// Upload saves from a directory, skipping ones the device already has
+    Restore {
+        #[arg(default_value = "saves")]
+        dir: PathBuf,
+        /// Write even if it leaves the device low on space
+        #[arg(long)]
+        force: bool,
+    },
+}
+
+/// Upload to every attached device concurrently, reporting each outcome.
+/// Returns the exit code: 1 if any device failed.
+fn put_all(options: &Options, name: &str, data: &[u8], force: bool) -> Result<i32> {
+    shell::disconnect();
+    let pieces = Piece::open_all(options)?;
+    if pieces.is_empty() {
+        return Err(PieceError::DeviceNotFound { wanted: None, found: Vec::new() });
     }
-    fn download(&mut self, filename: &str) {
-        let mut clusters_raw = [0; 496*2];
-        self.get_memory(self.pffs_top + 97 * 32, 496*2, &mut clusters_raw);
-        let directory = self.ls();
-        let dirent = directory.into_iter().find(|dirent| {
-            dirent.name == filename
-        }).expect("Could not find file to download");
-        let mut file = File::create(dirent.name).unwrap();
-        let mut cluster = dirent.cluster;
-        let mut data_left = dirent.len as usize;
-        loop {
-            let mut data = [0; 4096];
-            self.get_memory(self.pffs_top + 97 * 32 + 496 * 2 + (cluster as u32) * 4096 - 4096, 4096, &mut data);
-            file.write_all(&data[..data_left.min(4096)]).unwrap();
-            data_left -= data_left.min(4096);
-            cluster = u16::from_le_bytes(clusters_raw[(cluster as usize)*2..(cluster as usize)*2+2].try_into().unwrap());
-            if cluster > 0x8000 {
-                break;
+    let results: Vec<(String, std::result::Result<(), String>)> = thread::scope(|scope| {
+        let workers: Vec<_> = pieces.into_iter().map(|mut piece| scope.spawn(move || {
+            let serial = piece.serial.clone().unwrap_or_else(|| "(no serial)".to_string());
+            let result = panic::catch_unwind(AssertUnwindSafe(|| piece.upload(name, data, force)));
+            (serial, result.map_err(|payload| panic_message(payload.as_ref()))
+                .and_then(|result| result.map_err(|error| error.to_string())))
+        })).collect();
+        workers.into_iter().map(|worker| worker.join().unwrap()).collect()
+    });
+    progress::end();
+    let mut failed = 0;
+    for (serial, result) in &results {
+        match result {
+            Ok(()) => println!("{}: OK", serial),
+            Err(message) => {
+                println!("{}: FAILED: {}", serial, message);
+                failed += 1;
             }
         }
     }
+    println!("{} of {} devices updated", results.len() - failed, results.len());
+    Ok((failed > 0) as i32)
 }
 
-fn main() {
-    let cli = Cli::parse();
-    let mut piece = Piece::new();
-    match cli.command {
-        Commands::Ls => {
-            for dirent in piece.ls() {
-                println!("{}\t{}", dirent.name, dirent.len);
+fn run(command: Commands, options: &Options, as_json: bool) -> Result<i32> {
+    match command {
+        Commands::Devices => {
+            shell::disconnect();
+            let devices = Piece::list()?;
+            if devices.is_empty() {
+                return Err(PieceError::DeviceNotFound { wanted: None, found: Vec::new() });
+            }
+            println!("BUS:ADDR\tSERIAL\tKERNEL");
+            for device in devices {
+                println!("{}:{}\t{}\t{}", device.bus, device.address, device.serial.as_deref().unwrap_or("-"),
+                         device.kernel_version.map_or("(in use)".to_string(), kernel::version_string));
             }
         }
-        Commands::Screenshot => {
-            piece.get_screenshot();
+        Commands::Ls { pattern, watch: true, interval, .. } => {
+            watch::directory(&mut *shell::connect(options)?, pattern.as_deref(), Duration::from_secs_f64(interval))?;
         }
-        Commands::Download {file} => {
-            piece.download(file.as_str());
+        Commands::Ls { pattern, check: true, .. } => {
+            let directory = shell::connect(options)?.ls()?;
+            let problems: Vec<_> = name_problems(&directory).into_iter()
+                .filter(|(dirent, _, _)| pattern.as_ref().is_none_or(|pattern| glob::matches(pattern, &dirent.name)))
+                .collect();
+            if as_json {
+                let items: Vec<String> = problems.iter().map(|(dirent, host, messages)| json::object(&[
+                    ("index", dirent.index.to_string()),
+                    ("name", json::string(&dirent.name)),
+                    ("host", json::string(host)),
+                    ("problems", json::array(&messages.iter().map(|message| json::string(message)).collect::<Vec<_>>())),
+                ])).collect();
+                println!("{}", json::array(&items));
+            } else if problems.is_empty() {
+                println!("No problems with the names of {} files", directory.len());
+            } else {
+                for (dirent, _, messages) in &problems {
+                    for message in messages {
+                        println!("{}\t{:?}\t{}", dirent.index, dirent.name, message);
+                    }
+                }
+            }
+            return Ok(match problems.is_empty() {
+                true => 0,
+                false => 1,
+            });
         }
-        Commands::Dump => {
-            let mut file = File::create("dump.img").expect("Could not create dump.img");
-            let mut dump = [0; 2097152];
-            piece.get_memory(0xc00000, 2097142, &mut dump);
-            file.write_all(&dump).unwrap();
+        Commands::Ls { pattern, long, raw, .. } => {
+            let mut piece = shell::connect(options)?;
+            let mut directory = piece.ls()?;
+            if let Some(pattern) = pattern {
+                directory.retain(|dirent| glob::matches(&pattern, &dirent.name));
+            }
+            if as_json {
+                let hosts = names::hosts(directory.iter().map(|dirent| dirent.name.as_str()));
+                let mut items = Vec::new();
+                for (i, dirent) in directory.iter().enumerate() {
+                    let mut members = vec![
+                        ("name", json::string(&dirent.name)),
+                        ("index", dirent.index.to_string()),
+                        ("len", dirent.len.to_string()),
+                        ("cluster", dirent.cluster.to_string()),
+                        ("attrs", dirent.attrs.to_string()),
+                        ("host", json::string(&hosts[i])),
+                        ("type", json::string(piece.file_kind(dirent)?.label())),
+                        ("problem", dirent.problem.map_or("null".to_string(), json::string)),
+                    ];
+                    if let Some(header) = piece.pex_header(dirent)?.filter(|_| long) {
+                        members.extend(pex_json(&header));
+                    }
+                    items.push(json::object(&members));
+                }
+                println!("{}", json::array(&items));
+                return Ok(0);
+            }
+            for dirent in directory.iter().filter(|_| raw) {
+                let hex = |bytes: &[u8]| bytes.iter().map(|byte| format!("{:02x}", byte)).collect::<String>();
+                let slot = &dirent.slot;
+                println!("{:3} {} | {} | {} | {}  {}", dirent.index, hex(&slot[..24]), hex(&slot[24..26]),
+                         hex(&slot[26..28]), hex(&slot[28..32]), dirent.name);
+            }
+            for dirent in directory.iter().filter(|_| !raw) {
+                let kind = piece.file_kind(dirent)?;
+                let attrs = match long {
+                    true => format!("\t{:04x}", dirent.attrs),
+                    false => String::new(),
+                };
+                match piece.pex_header(dirent)?.filter(|_| long) {
+                    Some(header) => println!("{}\t{}\t{}{}\t{}\t{}\t{:#x}", dirent.name, dirent.len, kind.label(), attrs,
+                                             header.title, header.version_string(), header.entry),
+                    None => println!("{}\t{}\t{}{}", dirent.name, dirent.len, kind.label(), attrs),
+                }
+            }
+            warn_suspicious(&directory);
         }
-        Commands::Backup => {
-            for dirent in piece.ls() {
-                println!("{}", dirent.name);
-                piece.download(&dirent.name);
+        Commands::Reset {hard} => {
+            let piece = match shell::connect(options) {
+                Ok(mut piece) => {
+                    piece.reset(hard)?;
+                    piece
+                }
+                Err(error @ (PieceError::DeviceNotFound { .. } | PieceError::InterfaceBusy { .. } | PieceError::Locked { .. })) => {
+                    return Err(error)
+                }
+                Err(error) => {
+                    eprintln!("Device doesn't answer ({}); resetting its USB port", error);
+                    Piece::reset_port(options)?;
+                    shell::connect(options)?
+                }
+            };
+            println!("Device answering again, kernel {}", kernel::version_string(piece.kernel_version));
+        }
+        Commands::Info {file: None} => sysinfo::show(&mut *shell::connect(options)?, as_json)?,
+        Commands::Info {file: Some(file)} => {
+            let mut piece = shell::connect(options)?;
+            let directory = piece.ls()?;
+            let name = resolve_name(&directory, &file, false)?;
+            let dirent = directory.iter().find(|dirent| dirent.name == name).unwrap();
+            let fat = piece.read_fat()?;
+            if as_json {
+                let clusters: Vec<String> = chain(&fat, dirent.cluster).iter().map(u16::to_string).collect();
+                let mut members = vec![
+                    ("name", json::string(&dirent.name)),
+                    ("len", dirent.len.to_string()),
+                    ("clusters", format!("[{}]", clusters.join(", "))),
+                    ("type", json::string(piece.file_kind(dirent)?.label())),
+                ];
+                if let Some(header) = piece.pex_header(dirent)? {
+                    members.extend(pex_json(&header));
+                    members.push(("image_len", header.image_len.to_string()));
+                    members.push(("resources", header.resources.to_string()));
+                }
+                println!("{}", json::object(&members));
+                return Ok(0);
+            }
+            println!("name        {}", dirent.name);
+            println!("size        {}", dirent.len);
+            println!("clusters    {:?}", chain(&fat, dirent.cluster));
+            println!("type        {}", piece.file_kind(dirent)?.label());
+            if let Some(header) = piece.pex_header(dirent)? {
+                println!("title       {}", header.title);
+                println!("version     {}", header.version_string());
+                println!("load        {:#x}", header.load_addr);
+                println!("entry       {:#x}", header.entry);
+                println!("image       {} bytes", header.image_len);
+                println!("resources   {}", header.resources);
             }
         }
+        Commands::Run {file, upload: true} => {
+            let path = PathBuf::from(&file);
+            let data = fs::read(&path).expect("Could not read file to upload");
+            let name = path.file_name().expect("Path has no file name").to_string_lossy().into_owned();
+            let mut piece = shell::connect(options)?;
+            progress::begin("upload");
+            piece.upload(&name, &data, false)?;
+            progress::end();
+            launch::run(&mut piece, &name)?;
+        }
+        Commands::Run {file, upload: false} => {
+            let mut piece = shell::connect(options)?;
+            let name = resolve_name(&piece.ls()?, &file, false)?;
+            launch::run(&mut piece, &name)?;
+        }
+        Commands::Halt => shell::connect(options)?.halt()?,
+        Commands::Continue => shell::connect(options)?.unhalt()?,
+        Commands::Status => halt::status(&mut *shell::connect(options)?, as_json)?,
+        Commands::Regs {depth} => regs::show(&mut *shell::connect(options)?, depth)?,
+        Commands::Gdbserver {port} => gdb::serve(&mut *shell::connect(options)?, port)?,
+        Commands::Console {addr} => console::run(&mut *shell::connect(options)?, addr)?,
+        Commands::Screenshot {on_change: true, out_dir, interval, count, ..} => {
+            screen::shots(&mut *shell::connect(options)?, &out_dir, interval, count)?;
+        }
+        Commands::Screenshot {format, render, output, ..} => {
+            let frame = shell::connect(options)?.capture()?;
+            match output {
+                Some(path) => screen::save(&frame, &path),
+                None => print!("{}", screen::render(&frame, format, render)),
+            }
+        }
+        Commands::Upload {file, name, all_devices, force} => {
+            let data = fs::read(&file).expect("Could not read file to upload");
+            let name = name.unwrap_or_else(|| file.file_name().expect("Path has no file name").to_string_lossy().into_owned());
+            progress::begin("upload");
+            if all_devices {
+                return put_all(options, &name, &data, force);
+            }
+            shell::connect(options)?.upload(&name, &data, force)?;
+            progress::end();
+        }
+        Commands::Mv {old, new, ignore_case} => {
+            let mut piece = shell::connect(options)?;
+            let old = resolve_name(&piece.ls()?, &old, ignore_case)?;
+            piece.rename(&old, &new)?;
+            println!("Renamed {} to {}", old, new);
+        }
+        Commands::Rm {files, ignore_case} => {
+            let mut piece = shell::connect(options)?;
+            let directory = piece.ls()?;
+            for file in files {
+                let name = resolve_name(&directory, &file, ignore_case)?;
+                piece.remove(&name)?;
+                println!("Removed {}", name);
+            }
+        }
+        Commands::Download {files, ignore_case, output, stdout, force, verify, resume, ..} if !files.is_empty() => {
+            progress::begin("download");
+            let mut piece = shell::connect(options)?;
+            let directory = piece.ls()?;
+            let mut wanted: Vec<&DirEnt> = Vec::new();
+            for file in &files {
+                let matched: Vec<&DirEnt> = match file.contains(['*', '?', '[']) {
+                    true => directory.iter().filter(|dirent| match ignore_case {
+                        true => glob::matches(&file.to_lowercase(), &dirent.name.to_lowercase()),
+                        false => glob::matches(file, &dirent.name),
+                    }).collect(),
+                    false => {
+                        let name = resolve_name(&directory, file, ignore_case)?;
+                        directory.iter().filter(|dirent| dirent.name == name).collect()
+                    }
+                };
+                if matched.is_empty() {
+                    return Err(PieceError::NotFound(i18n::trf("No files match {}", &[file])));
+                }
+                for dirent in matched {
+                    if !wanted.iter().any(|other| other.index == dirent.index) {
+                        wanted.push(dirent);
+                    }
+                }
+            }
+            // A single named file may be saved under another name; anything
+            // more goes into a directory.
+            let single = files.len() == 1 && wanted.len() == 1 && !files[0].contains(['*', '?', '[']);
+            if stdout && !single {
+                return Err(PieceError::Usage(i18n::tr("--stdout takes a single file").to_string()));
+            }
+            let fat = piece.read_fat()?;
+            let hosts = names::hosts(directory.iter().map(|dirent| dirent.name.as_str()));
+            for dirent in wanted {
+                let host = &hosts[directory.iter().position(|other| other.index == dirent.index).unwrap()];
+                if stdout {
+                    let data = piece.read_entry(dirent, &fat)?;
+                    if verify {
+                        verify::reread(&mut piece, dirent, &fat, &data)?;
+                    }
+                    io::stdout().lock().write_all(&data).map_err(PieceError::host_io("Could not write to stdout"))?;
+                    continue;
+                }
+                let path = match &output {
+                    Some(output) if single => output.clone(),
+                    Some(dir) => {
+                        fs::create_dir_all(dir).map_err(PieceError::host_io(format!("Could not create {}", dir.display())))?;
+                        dir.join(host)
+                    }
+                    None => PathBuf::from(host),
+                };
+                refuse_overwrite(&path, force)?;
+                piece.download_entry(dirent, &fat, &path, resume)?;
+                if verify {
+                    let data = fs::read(&path).map_err(PieceError::host_io(format!("Could not read back {}", path.display())))?;
+                    verify::reread(&mut piece, dirent, &fat, &data)?;
+                }
+                if !single {
+                    println!("{}", dirent.name);
+                }
+            }
+            progress::end();
+        }
+        Commands::Download {index, cluster, output, stdout, force, ..} => {
+            progress::begin("download");
+            let mut piece = shell::connect(options)?;
+            let directory = piece.ls()?;
+            let (dirent, start, fallback) = match (index, cluster) {
+                (Some(index), _) => {
+                    let dirent = directory.iter().find(|dirent| dirent.index == index).unwrap_or_else(|| panic!("{}", i18n::tr("No file in that directory slot")));
+                    (Some(dirent), dirent.cluster, format!("entry-{}.bin", index))
+                }
+                (_, Some(cluster)) => {
+                    let clusters = piece.pffs.clusters;
+                    let start = u16::try_from(cluster).ok().filter(|&start| (start as usize) < clusters)
+                        .ok_or(PieceError::NoSuchCluster { cluster, clusters })?;
+                    let dirent = directory.iter().find(|dirent| dirent.cluster == start);
+                    (dirent, start, format!("cluster-{:#06x}.bin", cluster))
+                }
+                _ => unreachable!(),
+            };
+            let path = dirent.filter(|dirent| dirent.problem.is_none()).map_or(fallback, |dirent| names::host(&dirent.name));
+            let label = dirent.map_or(path.clone(), |dirent| dirent.name.clone());
+            let data = piece.read_chain(&label, start, dirent.map(|dirent| dirent.len))?;
+            progress::end();
+            let path = output.unwrap_or_else(|| PathBuf::from(path));
+            save_download(&data, &path, stdout, force)?;
+            if !stdout {
+                println!("{}", path.display());
+            }
+        }
+        #[cfg(all(feature = "fuse", target_os = "linux"))]
+        Commands::Mount {mountpoint} => mount::run(&mut *shell::connect(options)?, &mountpoint)?,
+        Commands::Diff {dir, size_only} => {
+            return verify::dir(&mut *shell::connect(options)?, &dir, size_only, as_json);
+        }
+        Commands::Verify {file, local_file} => {
+            let mut piece = shell::connect(options)?;
+            let directory = piece.ls()?;
+            let name = resolve_name(&directory, &file, false)?;
+            let dirent = directory.iter().find(|dirent| dirent.name == name).unwrap();
+            return verify::run(&mut piece, dirent, local_file.as_deref(), as_json);
+        }
+        Commands::Dump {region, start, length, output, encrypt, resume, stdout, format} => {
+            progress::begin("dump");
+            let passphrase = encrypt.then(crypto::passphrase).transpose()?;
+            let mut piece = shell::connect(options)?;
+            let (base, size) = region.bounds(&mut piece)?;
+            let start = match start {
+                Some(start) => start.resolve(&mut piece)?,
+                None => base,
+            };
+            let length = match length {
+                Some(length) => length,
+                None => (base + size).checked_sub(start).ok_or_else(|| PieceError::Usage(
+                    format!("--start {:#x} is past the end of the region; give --length", start)))?,
+            };
+            let format = format.unwrap_or_default();
+            let output = output.unwrap_or_else(|| PathBuf::from(format.file_name(region.file_name())));
+            let not_written = || PieceError::host_io(format!("Could not write {}", output.display()));
+            match passphrase {
+                Some(passphrase) => {
+                    let mut dump = Vec::new();
+                    dump::to_writer(&mut piece, start, length, &mut dump)?;
+                    let mut path = output.into_os_string();
+                    path.push(".enc");
+                    fs::write(&path, crypto::encrypt(&passphrase, &dump)?)
+                        .map_err(PieceError::host_io(format!("Could not write {}", Path::new(&path).display())))?;
+                }
+                None if format != hexfile::Format::Bin => {
+                    let out: Box<dyn Write> = match stdout {
+                        true => Box::new(io::stdout().lock()),
+                        false => Box::new(io::BufWriter::new(fs::File::create(&output).map_err(not_written())?)),
+                    };
+                    let mut encoder = hexfile::Encoder::new(format, start, length, out).map_err(not_written())?;
+                    if let Err(error) = dump::to_writer(&mut piece, start, length, &mut encoder) {
+                        // Not resumable, so don't leave half of it around.
+                        if !stdout {
+                            let _ = fs::remove_file(&output);
+                        }
+                        return Err(error);
+                    }
+                    encoder.finish().map_err(not_written())?;
+                }
+                None if stdout => dump::to_writer(&mut piece, start, length, &mut io::stdout().lock())?,
+                None => dump::to_file(&mut piece, start, length, &output, resume)?,
+            }
+            progress::end();
+        }
+        Commands::Backup {unattended: true, log, encrypt, compress, ..} => {
+            progress::begin("backup");
+            let encoding = backup::Encoding { passphrase: encrypt.then(crypto::passphrase).transpose()?, compress };
+            return backup::unattended(&log, &encoding, options);
+        }
+        Commands::Backup {archive: Some(archive), ..} => {
+            progress::begin("backup");
+            backup::to_archive(&mut *shell::connect(options)?, &archive)?;
+            progress::end();
+        }
+        Commands::Backup {repo: Some(repo), compress, ..} => {
+            progress::begin("backup");
+            repo::backup(&mut *shell::connect(options)?, &repo, compress)?;
+            progress::end();
+        }
+        Commands::Backup {encrypt, compress, resume, ..} => {
+            progress::begin("backup");
+            let encoding = backup::Encoding { passphrase: encrypt.then(crypto::passphrase).transpose()?, compress };
+            let mut piece = shell::connect(options)?;
+            let directory = piece.ls()?;
+            warn_suspicious(&directory);
+            backup::to_dir(&mut piece, directory, Path::new("."), &encoding, resume)?;
+            progress::end();
+        }
+        Commands::Saves {command} => match command {
+            SavesCommands::Backup {dir, patterns} => {
+                progress::begin("saves-backup");
+                saves::backup(&mut *shell::connect(options)?, &dir, &patterns)?;
+                progress::end();
+            }
+            SavesCommands::Restore {dir, force} => {
+                progress::begin("saves-restore");
+                let code = saves::restore(&mut *shell::connect(options)?, &dir, force)?;
+                progress::end();
+                return Ok(code);
+            }
+        }
+        Commands::Clock {command} => match command {
+            ClockCommands::Get => rtc::show(&mut *shell::connect(options)?)?,
+            ClockCommands::Set {time, ..} => {
+                let time = time.unwrap_or_else(date::now_local);
+                rtc::set(&mut *shell::connect(options)?, time)?;
+                println!("Clock set to {}", date::format(time));
+            }
+            ClockCommands::Sync => rtc::sync(&mut *shell::connect(options)?)?,
+        }
+        Commands::Restore {only: None, source, kernel, ..} => {
+            let mut image = fs::read(&source).map_err(PieceError::host_io(format!("Could not read flash image {}", source.display())))?;
+            if source.extension().is_some_and(|extension| extension == "enc") {
+                image = crypto::decrypt_or_fail(&crypto::passphrase()?, &image)?;
+            }
+            progress::begin("restore");
+            let mut piece = shell::connect(options)?;
+            dump::restore(&mut piece, &image, kernel)?;
+            progress::end();
+        }
+        Commands::Restore {only: Some(only), source, force, ..} => {
+            progress::begin("restore");
+            let data = if source.is_dir() {
+                backup::load(&source, &only)?
+            } else if backup::is_archive(&source) {
+                backup::load_archive(&source, &only)?.ok_or_else(|| PieceError::NotFound(i18n::tr("File not found in backup").to_string()))?
+            } else {
+                repo::read_file(&source, &only).ok_or_else(|| PieceError::NotFound(i18n::tr("File not found in snapshot").to_string()))?
+            };
+            shell::connect(options)?.upload(&only, &data, force)?;
+            progress::end();
+        }
+        Commands::Sync {dir, watch, interval, run} => {
+            sync::run(&mut *shell::connect(options)?, &dir, watch, interval, run.as_deref())?;
+        }
+        Commands::RestoreFiles {dir, force, any_device} => {
+            progress::begin("restore");
+            let mut piece = shell::connect(options)?;
+            let code = match dir.is_dir() {
+                true => backup::restore_dir(&mut piece, &dir, force, any_device)?,
+                false => backup::restore_archive(&mut piece, &dir, force, any_device)?,
+            };
+            progress::end();
+            return Ok(code);
+        }
+        Commands::Decrypt {input, output} => {
+            let output = output.unwrap_or_else(|| input.with_extension(""));
+            let data = fs::read(&input).map_err(PieceError::host_io(format!("Could not read {}", input.display())))?;
+            let data = crypto::decrypt_or_fail(&crypto::passphrase()?, &data)?;
+            fs::write(&output, data).map_err(PieceError::host_io(format!("Could not write {}", output.display())))?;
+        }
+        Commands::Bootstrap {image, flash, load_addr} => {
+            let image = fs::read(&image).map_err(PieceError::host_io(format!("Could not read kernel image {}", image.display())))?;
+            bootstrap::run(&mut *shell::connect(options)?, &image, load_addr, flash)?;
+        }
+        Commands::FlashFirmware {image, crc32, yes} => {
+            let image = fs::read(&image).map_err(PieceError::host_io(format!("Could not read firmware image {}", image.display())))?;
+            let mut piece = shell::connect(options)?;
+            let area = firmware::kernel_area(&piece);
+            firmware::validate(&image, area, crc32).map_err(|problem| PieceError::BadInput(problem.to_string()))?;
+            println!("Running kernel {}; image CRC-32 {:08x}", kernel::version_string(piece.kernel_version), crc32::crc32(&image));
+            if !yes && !options.dry_run && !confirm(&format!("Overwrite the {} byte kernel area?", area)) {
+                eprintln!("Firmware not written");
+                return Ok(1);
+            }
+            progress::begin("flash-firmware");
+            firmware::program(&mut piece, &image)?;
+            progress::end();
+        }
+        Commands::Frag => frag::report(&mut *shell::connect(options)?)?,
+        Commands::Defrag => {
+            let mut piece = shell::connect(options)?;
+            progress::begin("defrag");
+            let moved = piece.defrag()?;
+            progress::end();
+            println!("Moved {} clusters; every file is now contiguous", moved);
+        }
+        Commands::Du => du::report(&mut *shell::connect(options)?)?,
+        Commands::Df => du::df(&mut *shell::connect(options)?, as_json)?,
+        Commands::Fps {duration, counter} => fps::measure(&mut *shell::connect(options)?, duration, counter)?,
+        Commands::Input {keys, hold, gap, render} => {
+            let mut piece = shell::connect(options)?;
+            match keys {
+                Some(keys) => input::play(&mut piece, &keys, hold, gap)?,
+                None => input::interactive(&mut piece, hold, render.renderer().as_ref())?,
+            }
+        }
+        Commands::Bench {rounds, bytes} => bench::run(&mut *shell::connect(options)?, rounds, bytes)?,
+        Commands::Latency {key, region, count} => {
+            input::latency(&mut *shell::connect(options)?, key, region.unwrap_or_default(), count)?;
+        }
+        Commands::Top {interval} => top::run(&mut *shell::connect(options)?, interval)?,
+        Commands::Report {output} => report::write(&mut *shell::connect(options)?, &output)?,
+        Commands::ConvertImage {input, out, width, height, dither, upload} => {
+            let data = fs::read(&input).expect("Could not read input image");
+            let source = png::decode_gray(&data).expect("Input is not a supported PNG");
+            let scaled = image::fit(&source, width, height);
+            let bitmap = image::encode(scaled.width, scaled.height, &image::quantize(&scaled, dither));
+            fs::write(&out, &bitmap).expect("Could not write output file");
+            println!("{}x{} -> {}x{}, {} bytes", source.width, source.height, scaled.width, scaled.height, bitmap.len());
+            if upload {
+                let name = out.file_name().expect("Output has no file name").to_string_lossy();
+                progress::begin("upload");
+                shell::connect(options)?.upload(&name, &bitmap, false)?;
+                progress::end();
+            }
+        }
+        Commands::ConvertAudio {input, out, rate, upload} => {
+            let data = fs::read(&input).map_err(PieceError::host_io(format!("Could not read {}", input.display())))?;
+            let source = audio::decode_wav(&data)
+                .ok_or_else(|| PieceError::BadInput(format!("{} is not a supported WAV file", input.display())))?;
+            let pcm = audio::encode_pcm16(&audio::resample(&source, rate));
+            fs::write(&out, &pcm).map_err(PieceError::host_io(format!("Could not write {}", out.display())))?;
+            println!("{} Hz -> {} Hz, {:.2}s, {} bytes", source.rate, rate, pcm.len() as f64 / 2.0 / rate as f64, pcm.len());
+            if upload {
+                let name = out.file_name().expect("Output has no file name").to_string_lossy();
+                progress::begin("upload");
+                shell::connect(options)?.upload(&name, &pcm, false)?;
+                progress::end();
+            }
+        }
+        Commands::FsSnapshot {output} => fssnap::save(&mut *shell::connect(options)?, &output)?,
+        Commands::FsDiff {snapshot} => fssnap::diff(&mut *shell::connect(options)?, &snapshot)?,
+        Commands::Autopull {dir, interval, all} => watch::autopull(&mut *shell::connect(options)?, &dir, interval, all)?,
+        Commands::Scrub => return scrub::run(&mut *shell::connect(options)?),
+        Commands::FlashInfo => flash::info(&mut *shell::connect(options)?)?,
+        Commands::Clone {from, to, whole} => {
+            if from == to {
+                return Err(PieceError::Usage("Source and target are the same device".to_string()));
+            }
+            shell::disconnect();
+            let mut source = Piece::open_serial(options, &from)?;
+            let mut target = Piece::open_serial(options, &to)?;
+            progress::begin("clone");
+            clone::run(&mut source, &mut target, whole)?;
+            progress::end();
+        }
+        Commands::Shell => return Ok(shell::interactive(options, as_json)),
+        Commands::Script {file, keep_going} => return Ok(shell::script(&file, options, as_json, keep_going)),
+        Commands::Tui => tui::run(&mut *shell::connect(options)?)?,
+        Commands::Memmap => {
+            for region in memmap::regions(&mut *shell::connect(options)?)? {
+                println!("{:<8}{:#010x}-{:#010x} {:>9}  {}", region.name, region.base, region.base as u64 + region.size as u64,
+                         region.size, region.description);
+            }
+        }
+        Commands::Hexedit {addr} => {
+            let mut piece = shell::connect(options)?;
+            let addr = addr.resolve(&mut piece)?;
+            hexedit::run(&mut piece, addr)?;
+        }
+        Commands::Peek {addr, len, output, format} => {
+            let mut piece = shell::connect(options)?;
+            let addr = addr.resolve(&mut piece)?;
+            peek::peek(&mut piece, addr, len, output.as_deref(), format)?;
+        }
+        Commands::Hexdump {addr, len, width, relative} => {
+            let mut piece = shell::connect(options)?;
+            let addr = addr.resolve(&mut piece)?;
+            peek::hexdump(&mut piece, addr, len, width as usize, relative)?;
+        }
+        Commands::Poke {addr, data} => {
+            let mut piece = shell::connect(options)?;
+            let addr = addr.resolve(&mut piece)?;
+            peek::poke(&mut piece, addr, &data)?;
+        }
+        Commands::Raw {cmd, payload, read} => {
+            peek::raw(&mut *shell::connect(options)?, &cmd, payload.as_deref(), read)?;
+        }
+        Commands::Ramdiff {baseline, addr, len, update} => {
+            ramdiff::run(&mut *shell::connect(options)?, &baseline, addr, len, update)?;
+        }
+        Commands::Patch {file, patch, output} => {
+            let patch = fs::read(&patch).expect("Could not read patch");
+            let mut piece = shell::connect(options)?;
+            let file = resolve_name(&piece.ls()?, &file, false)?;
+            progress::begin("patch");
+            let patched = patch::apply(&piece.read_file(&file)?, &patch).unwrap_or_else(|e| panic!("Could not apply patch: {}", e));
+            let output = output.unwrap_or(file);
+            piece.upload(&output, &patched, false)?;
+            progress::end();
+            println!("Wrote {} ({} bytes)", output, patched.len());
+        }
+        Commands::ExportState {output} => {
+            progress::begin("export-state");
+            state::export(&mut *shell::connect(options)?, &output)?;
+            progress::end();
+        }
+        Commands::Watch {fps, render} => screen::mirror(&mut *shell::connect(options)?, fps, render.renderer().as_ref())?,
+        Commands::Record {duration, output} => screen::record(&mut *shell::connect(options)?, duration, &output)?,
+        Commands::AssertScreen {reference, timeout, tolerance} => {
+            return screen::assert(&mut *shell::connect(options)?, &reference, timeout, tolerance);
+        }
+        Commands::Plugins => {
+            for name in plugins::list() {
+                println!("{}", name);
+            }
+        }
+        Commands::Completion {shell} => print!("{}", complete::script(shell)),
+        Commands::Complete {words} => complete::candidates(&words, options),
+        Commands::External(args) => return Ok(plugins::run(&args)),
+        Commands::Fsck {repair} => return fsck::run(&mut *shell::connect(options)?, repair),
+        Commands::Recover {recover} => {
+            let found = shell::connect(options)?.recover_deleted(recover.scan)?;
+            return Ok(recover::save(&found, &recover.dest, recover.list));
+        }
+        Commands::Fs {command} => match command {
+            FsCommands::ReadCluster {cluster, output} => {
+                rawfs::read_cluster(&mut *shell::connect(options)?, cluster, output.as_deref())?
+            }
+            FsCommands::WriteCluster {cluster, file, yes} => {
+                let mut piece = shell::connect(options)?;
+                let target = rawfs::describe(&mut piece, cluster)?;
+                if !yes && !options.dry_run && !confirm(&format!("Overwrite {}?", target)) {
+                    eprintln!("Not written");
+                    return Ok(1);
+                }
+                rawfs::write_cluster(&mut piece, cluster, &file)?;
+            }
+            FsCommands::Chain {file} => {
+                let mut piece = shell::connect(options)?;
+                let file = resolve_name(&piece.ls()?, &file, false)?;
+                rawfs::print_chain(&mut piece, &file)?;
+            }
+        },
+        Commands::Format {yes} => {
+            let mut piece = shell::connect(options)?;
+            let files = piece.ls()?.len();
+            if !yes && !options.dry_run && !confirm(&format!("Erase all {} files on the device?", files)) {
+                eprintln!("Not formatted");
+                return Ok(1);
+            }
+            piece.format()?;
+            println!("Formatted; {} files erased", files);
+        }
+        Commands::Image {command} => match command {
+            ImageCommands::Ls {image, pffs_top} => offline::ls(&offline::open(&image, pffs_top, options.pffs)?),
+            ImageCommands::Extract {image, files, dest, pffs_top} => {
+                offline::extract(&offline::open(&image, pffs_top, options.pffs)?, &files, &dest)?
+            }
+            ImageCommands::Fsck {image, pffs_top} => return Ok(offline::fsck(&offline::open(&image, pffs_top, options.pffs)?)),
+            ImageCommands::Recover {image, recover, pffs_top} => {
+                let found = offline::open(&image, pffs_top, options.pffs)?.recover_deleted(recover.scan)?;
+                return Ok(recover::save(&found, &recover.dest, recover.list));
+            }
+            ImageCommands::Create {output, files, kernel, pffs_top} => {
+                offline::create(&output, &files, kernel.as_deref(), pffs_top, options.pffs)?
+            }
+        }
+        Commands::Emulator {command} => match command {
+            EmulatorCommands::Export {dir, from_dump: Some(dump), pffs_top} => {
+                let image = offline::open(&dump, pffs_top, options.pffs)?;
+                let (pffs_top, geometry) = (image.pffs_top, image.geometry);
+                emulator::export(image.into_data(), Some(pffs_top), Some(geometry), &dir)?;
+            }
+            EmulatorCommands::Export {dir, ..} => {
+                progress::begin("emulator-export");
+                emulator::export_device(&mut *shell::connect(options)?, &dir)?;
+            }
+            EmulatorCommands::Import {source, force} => {
+                progress::begin("emulator-import");
+                let code = emulator::import(&mut *shell::connect(options)?, &source, options.pffs, force)?;
+                progress::end();
+                return Ok(code);
+            }
+        }
+        Commands::Repo {command} => match command {
+            RepoCommands::List {repo} => repo::list(&repo),
+            RepoCommands::Checkout {snapshot, dest, repo} => repo::checkout(&repo, &snapshot, &dest),
+        }
     }
+    Ok(0)
+}
+
+/// Screenshot palette from the command line, falling back to the config.
+fn palette(cli: &Cli, config: &config::Config) -> screen::Palette {
+    let default = screen::Palette::default();
+    screen::Palette {
+        chars: cli.palette_chars.unwrap_or_else(|| config.get("screen.chars").map_or(default.chars, |value| {
+            screen::parse_chars(value).unwrap_or_else(|e| panic!("screen.chars in config: {}", e))
+        })),
+        colors: cli.palette_colors.unwrap_or_else(|| config.get("screen.colors").map_or(default.colors, |value| {
+            screen::parse_colors(value).unwrap_or_else(|e| panic!("screen.colors in config: {}", e))
+        })),
+        invert: cli.invert || config.get("screen.invert") == Some("true"),
+        scale: cli.scale.map_or_else(|| config.get("screen.scale").map_or(default.scale, |value| {
+            value.parse().ok().filter(|scale| (1..=4).contains(scale))
+                .unwrap_or_else(|| panic!("screen.scale in config must be a number from 1 to 4"))
+        }), usize::from),
+    }
+}
+
+/// Transfer tuning from the command line, falling back to the config.
+fn tuning(cli: &Cli, config: &config::Config) -> device::Tuning {
+    let default = device::Tuning::default();
+    let number = |key, default: u32, min: u32| config.get(key).map_or(default, |value| {
+        value.parse().ok().filter(|&n| n >= min).unwrap_or_else(|| panic!("{} in config must be a number of at least {}", key, min))
+    });
+    device::Tuning {
+        timeout: cli.usb_timeout.or_else(|| config.get("usb.timeout").map(|value| {
+            parse_duration(value).unwrap_or_else(|e| panic!("usb.timeout in config: {}", e))
+        })),
+        chunk_size: cli.chunk_size.unwrap_or_else(|| number("usb.chunk-size", default.chunk_size, device::MIN_READ_BLOCK)),
+        retries: cli.retries.unwrap_or_else(|| number("usb.retries", default.retries, 1)),
+        overlap: cli.overlap || config.get("usb.overlap") == Some("true"),
+    }
+}
+
+fn main() {
+    let matches = Cli::command().get_matches();
+    let cli = Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    let operation = matches.subcommand_name().unwrap_or_default().to_string();
+    match cli.progress {
+        Some(format) => progress::init(format, cli.progress_file.as_deref()),
+        None if io::stderr().is_terminal() => progress::bar(),
+        None => {}
+    }
+    names::set_mode(cli.host_names);
+    wire::set_level(cli.verbose);
+    if let Some(path) = &cli.trace_output {
+        trace::init(path);
+    }
+    let config = config::load().unwrap_or_else(|error| {
+        eprintln!("{}", i18n::trf("error: {}", &[&error.to_string()]));
+        process::exit(error.exit_code());
+    });
+    screen::set_palette(palette(&cli, &config));
+    names::set_encoding(cli.name_encoding.unwrap_or(match config.get("device.name-encoding") {
+        Some("utf8" | "utf-8") => names::NameEncoding::Utf8,
+        _ => names::NameEncoding::ShiftJis,
+    }));
+    let options = Options {
+        throttle: cli.throttle.map(|kb| kb * 1024),
+        low_space: LowSpace::from_config(&config),
+        read_only: cli.read_only || config.get("device.read-only") == Some("true"),
+        paranoid: cli.paranoid,
+        serial: cli.serial.clone(),
+        bus_address: cli.bus_address,
+        reconnect: cli.reconnect,
+        no_detach: cli.no_detach,
+        queue: cli.queue,
+        wait: cli.wait,
+        tuning: tuning(&cli, &config),
+        pffs: PffsGeometry::from_config(&config),
+        dry_run: cli.dry_run,
+    };
+    cancel::install();
+    let result = panic::catch_unwind(AssertUnwindSafe(|| run(cli.command, &options, cli.json)));
+    trace::flush();
+    let (code, error) = match result {
+        Ok(Ok(code)) => {
+            if options.dry_run {
+                eprintln!("Dry run; nothing was written to the device");
+            }
+            (code, None)
+        }
+        Ok(Err(error)) => {
+            let message = error.to_string();
+            progress::error(&message);
+            eprintln!("{}", i18n::trf("error: {}", &[&message]));
+            (error.exit_code(), Some(message))
+        }
+        Err(payload) => (101, Some(panic_message(payload.as_ref()))),
+    };
+    hooks::run(&config, &operation, code, error.as_deref());
+    process::exit(code);
 }
@@ -0,0 +1,172 @@
+//! The P/ECE's address space: what lives where, and addresses written
+//! relative to a named region such as `flash+0x1000`.
+
+use crate::device::{LCD_HEIGHT, LCD_WIDTH};
+use crate::error::{PieceError, Result};
+use crate::flash::{FLASH_BASE, FLASH_SIZE};
+use crate::Piece;
+
+/// The S1C33209's internal RAM.
+pub const IRAM_BASE: u32 = 0x0;
+/// Size of the internal RAM.
+pub const IRAM_SIZE: u32 = 0x2000;
+/// The S1C33209's peripheral registers.
+pub const IO_BASE: u32 = 0x40000;
+/// Size of the peripheral register area.
+pub const IO_SIZE: u32 = 0x10000;
+/// External SRAM, which runs to `sram_end` from SYSTEMINFO.
+pub const SRAM_BASE: u32 = 0x100000;
+
+/// A named stretch of the address space.
+pub struct Region {
+    /// What addresses call it, e.g. `sram`.
+    pub name: &'static str,
+    /// First address.
+    pub base: u32,
+    /// Size in bytes.
+    pub size: u32,
+    /// What is there, for `piecer regions`.
+    pub description: &'static str,
+}
+
+/// Regions whose base is the same on every device.
+const FIXED: [(&str, u32); 5] = [
+    ("iram", IRAM_BASE), ("io", IO_BASE), ("sram", SRAM_BASE), ("flash", FLASH_BASE), ("kernel", FLASH_BASE),
+];
+/// Regions whose base the device has to be asked for.
+const ASKED: [&str; 2] = ["pffs", "lcd"];
+
+/// Every region on this device, in address order.
+pub fn regions(piece: &mut Piece) -> Result<Vec<Region>> {
+    let info = piece.system_info()?;
+    let mut regions = vec![
+        Region { name: "iram", base: IRAM_BASE, size: IRAM_SIZE, description: "CPU internal RAM" },
+        Region { name: "io", base: IO_BASE, size: IO_SIZE, description: "peripheral registers" },
+        Region { name: "sram", base: SRAM_BASE, size: info.sram_end.saturating_sub(SRAM_BASE),
+                 description: "external SRAM, where applications run" },
+        Region { name: "flash", base: FLASH_BASE, size: FLASH_SIZE, description: "flash chip" },
+        Region { name: "kernel", base: FLASH_BASE, size: info.pffs_top.saturating_sub(FLASH_BASE),
+                 description: "kernel area of flash" },
+        Region { name: "pffs", base: info.pffs_top, size: info.pffs_end.saturating_sub(info.pffs_top),
+                 description: "filesystem area of flash" },
+    ];
+    // Kernels without screen capture can't say where the framebuffer is.
+    match piece.framebuffer_addr() {
+        Ok(lcd) => regions.push(Region { name: "lcd", base: lcd, size: (LCD_WIDTH * LCD_HEIGHT) as u32,
+                                         description: "framebuffer being displayed, a byte per pixel" }),
+        Err(PieceError::Unsupported { .. }) => {}
+        Err(error) => return Err(error),
+    }
+    regions.sort_by_key(|region| region.base);
+    Ok(regions)
+}
+
+/// An address as given on the command line: a number, or a region name
+/// with an optional offset.
+#[derive(Clone, Copy, Debug)]
+pub enum Address {
+    /// A plain address.
+    Absolute(u32),
+    /// An offset from the start of a region, resolved once connected.
+    Symbolic {
+        /// The region's name.
+        region: &'static str,
+        /// Bytes past the region's base; negative counts back.
+        offset: i64,
+    },
+}
+
+fn number(s: &str) -> Option<u32> {
+    match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
+
+/// Parse `0x1000`, `4096`, `flash`, `flash+0x1000` or `lcd-16`.
+pub fn parse(s: &str) -> std::result::Result<Address, String> {
+    if let Some(addr) = number(s) {
+        return Ok(Address::Absolute(addr));
+    }
+    let split = s.find(['+', '-']).unwrap_or(s.len());
+    let (name, rest) = s.split_at(split);
+    let region = FIXED.iter().map(|&(name, _)| name).chain(ASKED)
+        .find(|region| region.eq_ignore_ascii_case(name.trim()))
+        .ok_or_else(|| format!("{:?} is neither a number nor a region (see `memmap`)", s))?;
+    let offset = match rest.split_at(rest.len().min(1)) {
+        ("", _) => 0,
+        (sign, offset) => {
+            let offset = number(offset.trim()).ok_or_else(|| format!("invalid offset in {:?}", s))? as i64;
+            if sign == "-" { -offset } else { offset }
+        }
+    };
+    Ok(Address::Symbolic { region, offset })
+}
+
+impl Address {
+    /// The address, asking the device where needed.
+    pub fn resolve(self, piece: &mut Piece) -> Result<u32> {
+        let (region, offset) = match self {
+            Address::Absolute(addr) => return Ok(addr),
+            Address::Symbolic { region, offset } => (region, offset),
+        };
+        let base = match FIXED.iter().find(|&&(name, _)| name == region) {
+            Some(&(_, base)) => base,
+            None if region == "pffs" => piece.pffs_top,
+            None => piece.framebuffer_addr()?,
+        };
+        let addr = base as i64 + offset;
+        if !(0..=u32::MAX as i64).contains(&addr) {
+            return Err(PieceError::Usage(format!("{}{:+} is outside the address space", region, offset)));
+        }
+        Ok(addr as u32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The region, if any, and the address or offset.
+    type Parsed = Option<(Option<&'static str>, i64)>;
+
+    /// Addresses as they'd be typed, and what they parse to, or `None` for
+    /// ones that must be refused.
+    const FIXTURE: &[(&str, Parsed)] = &[
+        ("0x1000", Some((None, 0x1000))),
+        ("0X1000", Some((None, 0x1000))),
+        ("4096", Some((None, 4096))),
+        ("0xffffffff", Some((None, 0xffff_ffff))),
+        ("flash", Some((Some("flash"), 0))),
+        ("FLASH", Some((Some("flash"), 0))),
+        ("flash+0x1000", Some((Some("flash"), 0x1000))),
+        ("flash + 16", Some((Some("flash"), 16))),
+        ("lcd-16", Some((Some("lcd"), -16))),
+        ("pffs+0", Some((Some("pffs"), 0))),
+        ("", None),
+        ("rom", None),
+        ("flash+", None),
+        ("flash+0xz", None),
+        ("flash*2", None),
+        ("0x100000000", None),
+        ("+16", Some((None, 16))),
+        ("-16", None),
+    ];
+
+    #[test]
+    fn fixture() {
+        for &(text, expected) in FIXTURE {
+            let parsed = parse(text).ok().map(|address| match address {
+                Address::Absolute(addr) => (None, addr as i64),
+                Address::Symbolic { region, offset } => (Some(region), offset),
+            });
+            assert_eq!(parsed, expected, "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn refusals_say_why() {
+        assert!(parse("rom").unwrap_err().contains("neither a number nor a region"));
+        assert!(parse("flash+0xz").unwrap_err().contains("invalid offset"));
+    }
+}
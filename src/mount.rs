@@ -0,0 +1,355 @@
+//! `piecer mount`: PFFS as a read-only FUSE filesystem.
+//!
+//! Speaks the kernel's FUSE protocol on /dev/fuse directly. Mounting is done
+//! with mount(2) when allowed and otherwise by the setuid fusermount helper,
+//! which passes the /dev/fuse descriptor back over a socket.
+
+use crate::{DirEnt, Piece, Result};
+use std::collections::hash_map::{Entry, HashMap};
+use std::ffi::CString;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::fd::{AsRawFd, FromRawFd};
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+// Request opcodes from <linux/fuse.h>.
+const LOOKUP: u32 = 1;
+const FORGET: u32 = 2;
+const GETATTR: u32 = 3;
+const OPEN: u32 = 14;
+const READ: u32 = 15;
+const STATFS: u32 = 17;
+const RELEASE: u32 = 18;
+const FLUSH: u32 = 25;
+const INIT: u32 = 26;
+const OPENDIR: u32 = 27;
+const READDIR: u32 = 28;
+const RELEASEDIR: u32 = 29;
+const ACCESS: u32 = 34;
+const INTERRUPT: u32 = 36;
+const DESTROY: u32 = 38;
+const BATCH_FORGET: u32 = 42;
+
+const ROOT: u64 = 1;
+/// Largest read the kernel is told to send.
+const MAX_READ: u32 = 64 * 1024;
+/// Seconds the kernel may cache names and attributes. Nothing changes
+/// underneath while mounted, since the device is only read.
+const TTL: u64 = 60;
+/// How often the device is pinged while no requests come in.
+const KEEPALIVE: Duration = Duration::from_secs(1);
+
+static STOP: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn stop(_signal: libc::c_int) {
+    STOP.store(true, Ordering::Relaxed);
+}
+
+/// Serve the device's files at `mountpoint` until Ctrl-C or `fusermount -u`.
+pub fn run(piece: &mut Piece, mountpoint: &Path) -> Result<()> {
+    let directory = piece.ls()?;
+    let fat = piece.read_fat()?;
+    let mut fs = Filesystem { directory, fat, cache: HashMap::new() };
+    let mut fuse = mount(mountpoint);
+    unsafe {
+        // Without SA_RESTART, so the wait for requests is cut short.
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = stop as *const () as libc::sighandler_t;
+        libc::sigaction(libc::SIGINT, &action, std::ptr::null_mut());
+        libc::sigaction(libc::SIGTERM, &action, std::ptr::null_mut());
+    }
+    eprintln!("Mounted {} files at {}; press Ctrl-C to unmount", fs.directory.len(), mountpoint.display());
+    let result = serve(piece, &mut fs, &mut fuse);
+    unmount(mountpoint);
+    result
+}
+
+fn serve(piece: &mut Piece, fs: &mut Filesystem, fuse: &mut File) -> Result<()> {
+    let mut buffer = vec![0; MAX_READ as usize + 4096];
+    while !STOP.load(Ordering::Relaxed) {
+        let mut poll = libc::pollfd { fd: fuse.as_raw_fd(), events: libc::POLLIN, revents: 0 };
+        match unsafe { libc::poll(&mut poll, 1, KEEPALIVE.as_millis() as libc::c_int) } {
+            0 => {
+                piece.idle(Duration::from_millis(1))?;
+                continue;
+            }
+            ready if ready < 0 => continue,
+            _ => {}
+        }
+        let len = match fuse.read(&mut buffer) {
+            Ok(len) => len,
+            Err(error) if matches!(error.raw_os_error(), Some(libc::EINTR | libc::EAGAIN | libc::ENOENT)) => continue,
+            // Unmounted from outside.
+            Err(error) if error.raw_os_error() == Some(libc::ENODEV) => break,
+            Err(error) => return Err(error.into()),
+        };
+        let request = &buffer[..len];
+        let opcode = u32_at(request, 4);
+        let unique = u64_at(request, 8);
+        let node = u64_at(request, 16);
+        let body = &request[40..];
+        let reply = match opcode {
+            FORGET | BATCH_FORGET | INTERRUPT => continue,
+            DESTROY => break,
+            _ => fs.handle(piece, opcode, node, body),
+        };
+        let (error, data) = match reply {
+            Ok(data) => (0, data),
+            Err(errno) => (-errno, Vec::new()),
+        };
+        let mut out = Vec::with_capacity(16 + data.len());
+        out.extend((16 + data.len() as u32).to_le_bytes());
+        out.extend(error.to_le_bytes());
+        out.extend(unique.to_le_bytes());
+        out.extend(data);
+        // Fails harmlessly when the request was interrupted meanwhile.
+        fuse.write_all(&out).ok();
+    }
+    Ok(())
+}
+
+/// Errors are errno values, as FUSE replies with.
+type Reply = std::result::Result<Vec<u8>, i32>;
+
+struct Filesystem {
+    directory: Vec<DirEnt>,
+    fat: Vec<u16>,
+    /// Clusters already read, by cluster number.
+    cache: HashMap<u16, Vec<u8>>,
+}
+
+impl Filesystem {
+    fn handle(&mut self, piece: &mut Piece, opcode: u32, node: u64, body: &[u8]) -> Reply {
+        match opcode {
+            INIT => Ok(init(body)),
+            LOOKUP => {
+                let name = &body[..body.iter().position(|&b| b == 0).unwrap_or(body.len())];
+                let dirent = self.directory.iter().find(|dirent| dirent.name.as_bytes() == name).ok_or(libc::ENOENT)?;
+                let mut out = Vec::new();
+                out.extend(inode(dirent).to_le_bytes());
+                out.extend(0u64.to_le_bytes());
+                out.extend(TTL.to_le_bytes());
+                out.extend(TTL.to_le_bytes());
+                out.extend([0; 8]);
+                out.extend(attr(Some(dirent)));
+                Ok(out)
+            }
+            GETATTR => {
+                let dirent = self.node(node)?;
+                let mut out = Vec::new();
+                out.extend(TTL.to_le_bytes());
+                out.extend([0; 8]);
+                out.extend(attr(dirent));
+                Ok(out)
+            }
+            OPEN => {
+                self.node(node)?;
+                if u32_at(body, 0) as libc::c_int & libc::O_ACCMODE != libc::O_RDONLY {
+                    return Err(libc::EROFS);
+                }
+                Ok(vec![0; 16])
+            }
+            OPENDIR => Ok(vec![0; 16]),
+            READ => {
+                let dirent = self.node(node)?.ok_or(libc::EISDIR)?;
+                let (offset, size) = (u64_at(body, 8), u32_at(body, 16));
+                self.read(piece, dirent.index, offset, size)
+            }
+            READDIR => Ok(self.readdir(u64_at(body, 8), u32_at(body, 16))),
+            STATFS => {
+                let geometry = piece.pffs;
+                let free = self.fat.iter().skip(geometry.first_cluster()).filter(|&&link| link == piecer::pffs::FAT_FREE).count() as u64;
+                let (total, slots) = (geometry.data_clusters() as u64, geometry.file_slots() as u64);
+                let mut out = Vec::new();
+                for value in [total, free, free, slots, slots - self.directory.len() as u64] {
+                    out.extend(value.to_le_bytes());
+                }
+                for value in [geometry.cluster_size, 24, geometry.cluster_size, 0] {
+                    out.extend(value.to_le_bytes());
+                }
+                out.extend([0; 24]);
+                Ok(out)
+            }
+            RELEASE | RELEASEDIR | FLUSH | ACCESS => Ok(Vec::new()),
+            _ => Err(libc::ENOSYS),
+        }
+    }
+
+    /// The file behind `node`, or None for the root directory.
+    fn node(&self, node: u64) -> std::result::Result<Option<&DirEnt>, i32> {
+        match node {
+            ROOT => Ok(None),
+            _ => self.directory.iter().find(|dirent| inode(dirent) == node).map(Some).ok_or(libc::ENOENT),
+        }
+    }
+
+    fn read(&mut self, piece: &mut Piece, index: usize, offset: u64, size: u32) -> Reply {
+        let dirent = self.directory.iter().find(|dirent| dirent.index == index).unwrap();
+        if dirent.problem.is_some() {
+            return Err(libc::EIO);
+        }
+        let end = (offset + size as u64).min(dirent.len as u64);
+        let clusters = piecer::pffs::chain(&self.fat, dirent.cluster);
+        let cluster_size = piece.pffs.cluster_size as u64;
+        let mut out = Vec::new();
+        let mut pos = offset;
+        while pos < end {
+            let &cluster = clusters.get((pos / cluster_size) as usize).ok_or(libc::EIO)?;
+            if let Entry::Vacant(entry) = self.cache.entry(cluster) {
+                let mut data = vec![0; cluster_size as usize];
+                piece.read_cluster(cluster, &mut data).map_err(|_| libc::EIO)?;
+                entry.insert(data);
+            }
+            let start = (pos % cluster_size) as usize;
+            let len = (cluster_size as usize - start).min((end - pos) as usize);
+            out.extend(&self.cache[&cluster][start..start + len]);
+            pos += len as u64;
+        }
+        Ok(out)
+    }
+
+    /// Directory entries from position `offset`, as many as fit in `size`.
+    fn readdir(&self, offset: u64, size: u32) -> Vec<u8> {
+        let entries = [(ROOT, "."), (ROOT, "..")].into_iter()
+            .chain(self.directory.iter().map(|dirent| (inode(dirent), dirent.name.as_str())));
+        let mut out = Vec::new();
+        for (position, (ino, name)) in entries.enumerate().skip(offset as usize) {
+            let mut entry = Vec::new();
+            entry.extend(ino.to_le_bytes());
+            entry.extend((position as u64 + 1).to_le_bytes());
+            entry.extend((name.len() as u32).to_le_bytes());
+            let kind = if ino == ROOT { libc::DT_DIR } else { libc::DT_REG };
+            entry.extend((kind as u32).to_le_bytes());
+            entry.extend(name.as_bytes());
+            entry.resize(entry.len().next_multiple_of(8), 0);
+            if out.len() + entry.len() > size as usize {
+                break;
+            }
+            out.extend(entry);
+        }
+        out
+    }
+}
+
+fn inode(dirent: &DirEnt) -> u64 {
+    ROOT + dirent.index as u64
+}
+
+/// A `fuse_attr` for a file, or for the root directory.
+fn attr(dirent: Option<&DirEnt>) -> Vec<u8> {
+    let (ino, size, mode, nlink) = match dirent {
+        Some(dirent) => (inode(dirent), dirent.len as u64, libc::S_IFREG | 0o444, 1),
+        None => (ROOT, 0, libc::S_IFDIR | 0o555, 2),
+    };
+    let mut out = Vec::new();
+    out.extend(ino.to_le_bytes());
+    out.extend(size.to_le_bytes());
+    out.extend(size.div_ceil(512).to_le_bytes());
+    // Times, which PFFS doesn't record.
+    out.extend([0; 36]);
+    out.extend(mode.to_le_bytes());
+    out.extend((nlink as u32).to_le_bytes());
+    out.extend(unsafe { libc::getuid() }.to_le_bytes());
+    out.extend(unsafe { libc::getgid() }.to_le_bytes());
+    out.extend(0u32.to_le_bytes());
+    out.extend(4096u32.to_le_bytes());
+    out.extend(0u32.to_le_bytes());
+    out
+}
+
+/// The `fuse_init_out` answering the kernel's `fuse_init_in` in `body`.
+fn init(body: &[u8]) -> Vec<u8> {
+    let minor = u32_at(body, 4).min(31);
+    let mut out = Vec::new();
+    out.extend(7u32.to_le_bytes());
+    out.extend(minor.to_le_bytes());
+    out.extend(MAX_READ.to_le_bytes());
+    // No optional features.
+    out.extend(0u32.to_le_bytes());
+    out.extend(1u16.to_le_bytes());
+    out.extend(1u16.to_le_bytes());
+    out.extend(MAX_READ.to_le_bytes());
+    out.extend(1u32.to_le_bytes());
+    out.extend([0; 36]);
+    out
+}
+
+fn u32_at(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+fn u64_at(data: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+}
+
+fn path_cstring(path: &Path) -> CString {
+    CString::new(path.as_os_str().as_bytes()).expect("Mount point contains a NUL byte")
+}
+
+/// Mount a new FUSE filesystem at `mountpoint` and return its channel.
+fn mount(mountpoint: &Path) -> File {
+    let fuse = OpenOptions::new().read(true).write(true).open("/dev/fuse")
+        .expect("Could not open /dev/fuse; is FUSE available?");
+    let options = format!("fd={},rootmode=40000,user_id={},group_id={}",
+                          fuse.as_raw_fd(), unsafe { libc::getuid() }, unsafe { libc::getgid() });
+    let options = CString::new(options).unwrap();
+    let target = path_cstring(mountpoint);
+    let flags = libc::MS_RDONLY | libc::MS_NOSUID | libc::MS_NODEV;
+    let mounted = unsafe {
+        libc::mount(c"piecer".as_ptr(), target.as_ptr(), c"fuse.piecer".as_ptr(), flags, options.as_ptr().cast())
+    } == 0;
+    match mounted {
+        true => fuse,
+        false => fusermount(mountpoint),
+    }
+}
+
+/// Mount through fusermount, for when we aren't allowed to call mount(2).
+fn fusermount(mountpoint: &Path) -> File {
+    let mut fds = [0; 2];
+    assert!(unsafe { libc::socketpair(libc::AF_UNIX, libc::SOCK_STREAM, 0, fds.as_mut_ptr()) } == 0,
+            "Could not create socket: {}", io::Error::last_os_error());
+    let mounted = ["fusermount3", "fusermount"].iter().any(|program| {
+        Command::new(program)
+            .args(["-o", "ro,nosuid,nodev,fsname=piecer,subtype=piecer", "--"])
+            .arg(mountpoint)
+            .env("_FUSE_COMMFD", fds[1].to_string())
+            .status()
+            .is_ok_and(|status| status.success())
+    });
+    unsafe { libc::close(fds[1]) };
+    assert!(mounted, "Could not mount {}: mount(2) was refused and fusermount failed", mountpoint.display());
+    unsafe {
+        let mut byte = 0u8;
+        let mut iov = libc::iovec { iov_base: (&mut byte as *mut u8).cast(), iov_len: 1 };
+        let mut control = [0u8; 64];
+        let mut message: libc::msghdr = std::mem::zeroed();
+        message.msg_iov = &mut iov;
+        message.msg_iovlen = 1;
+        message.msg_control = control.as_mut_ptr().cast();
+        message.msg_controllen = control.len() as _;
+        let received = libc::recvmsg(fds[0], &mut message, 0);
+        libc::close(fds[0]);
+        let header = libc::CMSG_FIRSTHDR(&message);
+        assert!(received > 0 && !header.is_null() && (*header).cmsg_type == libc::SCM_RIGHTS,
+                "fusermount didn't pass back the FUSE channel");
+        File::from_raw_fd(std::ptr::read_unaligned(libc::CMSG_DATA(header) as *const libc::c_int))
+    }
+}
+
+fn unmount(mountpoint: &Path) {
+    let target = path_cstring(mountpoint);
+    if unsafe { libc::umount2(target.as_ptr(), libc::MNT_DETACH) } == 0 {
+        return;
+    }
+    let unmounted = ["fusermount3", "fusermount"].iter().any(|program| {
+        Command::new(program).args(["-u", "-z", "--"]).arg(mountpoint).status().is_ok_and(|status| status.success())
+    });
+    if !unmounted {
+        eprintln!("Could not unmount {}; run fusermount -u {0}", mountpoint.display());
+    }
+}
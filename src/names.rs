@@ -0,0 +1,328 @@
+//! File names: between the bytes in the device's directory and text, and
+//! from device names to names that are safe on the host.
+
+use clap::ValueEnum;
+use std::collections::{HashMap, HashSet};
+use std::sync::OnceLock;
+
+/// How file names are stored in the device's directory.
+#[derive(Clone, Copy, Default, PartialEq, ValueEnum)]
+pub enum NameEncoding {
+    /// Shift-JIS (Windows code page 932), as the P/ECE's own tools write them
+    #[default]
+    ShiftJis,
+    /// UTF-8
+    Utf8,
+}
+
+static ENCODING: OnceLock<NameEncoding> = OnceLock::new();
+
+/// Use `encoding` for every name from now on. Only the first call counts.
+pub fn set_encoding(encoding: NameEncoding) {
+    ENCODING.set(encoding).ok();
+}
+
+fn encoding() -> NameEncoding {
+    ENCODING.get().copied().unwrap_or_default()
+}
+
+// Code page 932 double-byte characters as little-endian UTF-16, 0 where
+// unassigned: 189 trail bytes from 0x40 for each lead byte in 0x81-0x9F and
+// 0xE0-0xFC. Generated with Python's cp932 codec.
+static CP932: &[u8] = include_bytes!("cp932.bin");
+const TRAILS: usize = 0xFD - 0x40;
+
+fn cp932_index(lead: u8, trail: u8) -> Option<usize> {
+    let row = match lead {
+        0x81..=0x9F => lead - 0x81,
+        0xE0..=0xFC => lead - 0xE0 + 0x1F,
+        _ => return None,
+    };
+    (0x40..=0xFC).contains(&trail).then(|| row as usize * TRAILS + (trail - 0x40) as usize)
+}
+
+fn cp932(lead: u8, trail: u8) -> Option<char> {
+    let index = cp932_index(lead, trail)?;
+    let code = u16::from_le_bytes([CP932[index * 2], CP932[index * 2 + 1]]);
+    (code != 0).then(|| char::from_u32(code as u32)).flatten()
+}
+
+fn decode_shift_jis(raw: &[u8]) -> (String, bool) {
+    let mut out = String::new();
+    let mut valid = true;
+    let mut i = 0;
+    while i < raw.len() {
+        let byte = raw[i];
+        i += 1;
+        let c = match byte {
+            0x00..=0x7F => Some(byte as char),
+            0xA1..=0xDF => char::from_u32(0xFF61 + (byte - 0xA1) as u32),
+            _ => match raw.get(i).and_then(|&trail| cp932(byte, trail)) {
+                Some(c) => {
+                    i += 1;
+                    Some(c)
+                }
+                None => None,
+            },
+        };
+        valid &= c.is_some();
+        out.push(c.unwrap_or('\u{FFFD}'));
+    }
+    (out, valid)
+}
+
+/// A device file name as text, and whether the bytes were valid in the
+/// configured encoding. Invalid bytes become U+FFFD.
+pub fn decode(raw: &[u8]) -> (String, bool) {
+    match encoding() {
+        NameEncoding::ShiftJis => decode_shift_jis(raw),
+        NameEncoding::Utf8 => (String::from_utf8_lossy(raw).into_owned(), std::str::from_utf8(raw).is_ok()),
+    }
+}
+
+/// The bytes stored on the device for `name`, or None if the configured
+/// encoding can't represent it.
+pub fn encode(name: &str) -> Option<Vec<u8>> {
+    if encoding() == NameEncoding::Utf8 {
+        return Some(name.as_bytes().to_vec());
+    }
+    static REVERSE: OnceLock<HashMap<char, [u8; 2]>> = OnceLock::new();
+    let reverse = REVERSE.get_or_init(|| {
+        let mut reverse = HashMap::new();
+        for lead in (0x81..=0x9F).chain(0xE0..=0xFC) {
+            for trail in 0x40..=0xFC {
+                if let Some(c) = cp932(lead, trail) {
+                    // The first of duplicated characters is the standard one.
+                    reverse.entry(c).or_insert([lead, trail]);
+                }
+            }
+        }
+        reverse
+    });
+    let mut out = Vec::new();
+    for c in name.chars() {
+        match c as u32 {
+            0x00..=0x7F => out.push(c as u8),
+            code @ 0xFF61..=0xFF9F => out.push((code - 0xFF61) as u8 + 0xA1),
+            _ => out.extend(reverse.get(&c)?),
+        }
+    }
+    Some(out)
+}
+
+/// How device file names are turned into host file names.
+#[derive(Clone, Copy, Default, ValueEnum)]
+pub enum HostNames {
+    /// Use names exactly as decoded
+    Raw,
+    /// Compose combining marks (Unicode NFC) so names match across macOS and Linux
+    #[default]
+    Nfc,
+    /// Transliterate to plain ASCII
+    Ascii,
+}
+
+static MODE: OnceLock<HostNames> = OnceLock::new();
+
+/// Use `mode` for every host name from now on. Only the first call counts.
+pub fn set_mode(mode: HostNames) {
+    MODE.set(mode).ok();
+}
+
+/// Host file name for the device file `name`.
+pub fn host(name: &str) -> String {
+    let name = match MODE.get().copied().unwrap_or_default() {
+        HostNames::Raw => name.to_string(),
+        HostNames::Nfc => nfc(name),
+        HostNames::Ascii => ascii(&nfc(name)),
+    };
+    sanitize(&name)
+}
+
+/// Names Windows keeps for devices, whatever the extension.
+const RESERVED: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8", "COM9",
+    "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// `name` with anything that can't be in a file name on Windows or Unix, or
+/// would make it a path, replaced with `_`. Trailing dots and spaces, which
+/// Windows drops, become `_` too, and a `_` is added to reserved names like
+/// `CON` or `AUX.TXT`.
+pub fn sanitize(name: &str) -> String {
+    let mut name: String = name.chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' | '\u{FFFD}' => '_',
+            _ if c.is_control() => '_',
+            _ => c,
+        })
+        .collect();
+    if matches!(name.as_str(), "" | "." | "..") {
+        return "_".repeat(name.len().max(1));
+    }
+    let kept = name.trim_end_matches(['.', ' ']).len();
+    let trailing = name.len() - kept;
+    name.truncate(kept);
+    name.push_str(&"_".repeat(trailing));
+    let stem = name.split('.').next().unwrap().len();
+    if RESERVED.iter().any(|reserved| reserved.eq_ignore_ascii_case(&name[..stem])) {
+        name.insert(stem, '_');
+    }
+    name
+}
+
+/// Host file names for a whole directory of device files `names`, in
+/// order: each as [`host`] makes it, with `~2`, `~3` and so on before the
+/// extension of any that would clash with an earlier one. Names that differ
+/// only in case clash, as they do on Windows and macOS.
+pub fn hosts<'a>(names: impl IntoIterator<Item = &'a str>) -> Vec<String> {
+    let mut taken = HashSet::new();
+    names.into_iter().map(|name| {
+        let host = host(name);
+        let (stem, extension) = match host.rfind('.') {
+            Some(dot) if dot > 0 => host.split_at(dot),
+            _ => (host.as_str(), ""),
+        };
+        let unique = (1..).map(|n| match n {
+            1 => host.clone(),
+            n => format!("{}~{}{}", stem, n, extension),
+        }).find(|candidate| !taken.contains(&candidate.to_lowercase())).unwrap();
+        taken.insert(unique.to_lowercase());
+        unique
+    }).collect()
+}
+
+const VOICED: char = '\u{3099}';
+const SEMI_VOICED: char = '\u{309A}';
+
+// Precomposed Latin-1 letters, per combining mark, as (base, composed) pairs.
+const LATIN: [(char, &str); 7] = [
+    ('\u{300}', "AÀEÈIÌOÒUÙaàeèiìoòuù"),
+    ('\u{301}', "AÁEÉIÍOÓUÚYÝaáeéiíoóuúyý"),
+    ('\u{302}', "AÂEÊIÎOÔUÛaâeêiîoôuû"),
+    ('\u{303}', "AÃNÑOÕaãnñoõ"),
+    ('\u{308}', "AÄEËIÏOÖUÜaäeëiïoöuüyÿ"),
+    ('\u{30A}', "AÅaå"),
+    ('\u{327}', "CÇcç"),
+];
+
+fn compose(base: char, mark: char) -> Option<char> {
+    let c = base as u32;
+    // Katakana mirror hiragana 0x60 code points higher.
+    let kana = if (0x30A1..=0x30F6).contains(&c) { c - 0x60 } else { c };
+    let kana_offset = c - kana;
+    let composed = match (kana, mark) {
+        (0x3046, VOICED) => Some(0x3094),
+        (0x304B..=0x3062, VOICED) if kana % 2 == 1 => Some(kana + 1),
+        (0x3064 | 0x3066 | 0x3068, VOICED) => Some(kana + 1),
+        (0x306F | 0x3072 | 0x3075 | 0x3078 | 0x307B, VOICED) => Some(kana + 1),
+        (0x306F | 0x3072 | 0x3075 | 0x3078 | 0x307B, SEMI_VOICED) => Some(kana + 2),
+        _ => None,
+    };
+    if let Some(composed) = composed.filter(|_| (0x3041..=0x3096).contains(&kana)) {
+        return char::from_u32(composed + kana_offset);
+    }
+    let (_, pairs) = LATIN.iter().find(|(m, _)| *m == mark)?;
+    let pairs: Vec<char> = pairs.chars().collect();
+    pairs.chunks(2).find(|pair| pair[0] == base).map(|pair| pair[1])
+}
+
+/// Canonical composition for the scripts device names use in practice: kana
+/// with (semi-)voiced sound marks, and Latin letters with Latin-1 accents.
+pub fn nfc(name: &str) -> String {
+    let mut out = String::with_capacity(name.len());
+    for c in name.chars() {
+        match out.pop() {
+            Some(base) => match compose(base, c) {
+                Some(composed) => out.push(composed),
+                None => {
+                    out.push(base);
+                    out.push(c);
+                }
+            },
+            None => out.push(c),
+        }
+    }
+    out
+}
+
+// Romaji for hiragana U+3041 to U+3096 (and katakana U+30A1 to U+30F6). Small
+// kana start with '~' and modify the preceding syllable.
+const ROMAJI: &str = "~a a ~i i ~u u ~e e ~o o ka ga ki gi ku gu ke ge ko go sa za shi ji su zu se ze so zo \
+                      ta da chi ji ~tsu tsu zu te de to do na ni nu ne no ha ba pa hi bi pi fu bu pu he be pe \
+                      ho bo po ma mi mu me mo ~ya ya ~yu yu ~yo yo ra ri ru re ro ~wa wa wi we wo n vu ka ke";
+
+/// Transliterate to ASCII, replacing anything without a reasonable spelling
+/// with `_`.
+pub fn ascii(name: &str) -> String {
+    let romaji: Vec<&str> = ROMAJI.split_whitespace().collect();
+    let mut out = String::new();
+    let mut double_next = false;
+    for c in name.chars() {
+        let code = c as u32;
+        let kana = if (0x30A1..=0x30F6).contains(&code) { code - 0x60 } else { code };
+        let spelling = match c {
+            _ if c.is_ascii() && !c.is_ascii_control() => c.to_string(),
+            '\u{FF01}'..='\u{FF5E}' => char::from_u32(code - 0xFEE0).unwrap().to_string(),
+            '\u{3000}' => " ".to_string(),
+            'ー' => "-".to_string(),
+            'ß' => "ss".to_string(),
+            'Æ' => "AE".to_string(),
+            'æ' => "ae".to_string(),
+            'Ø' => "O".to_string(),
+            'ø' => "o".to_string(),
+            _ if (0x3041..=0x3096).contains(&kana) => {
+                let syllable = romaji[(kana - 0x3041) as usize];
+                match syllable.strip_prefix('~') {
+                    Some("tsu") => {
+                        double_next = true;
+                        continue;
+                    }
+                    Some(small @ ("ya" | "yu" | "yo")) if out.ends_with('i') && out.len() >= 2 => {
+                        out.pop();
+                        if out.ends_with("sh") || out.ends_with("ch") || out.ends_with('j') {
+                            small[1..].to_string()
+                        } else {
+                            small.to_string()
+                        }
+                    }
+                    Some(small) => small.to_string(),
+                    None => syllable.to_string(),
+                }
+            }
+            _ => LATIN.iter().flat_map(|(_, pairs)| {
+                let pairs: Vec<char> = pairs.chars().collect();
+                pairs.chunks(2).find(|pair| pair[1] == c).map(|pair| pair[0])
+            }).next().unwrap_or('_').to_string(),
+        };
+        if double_next {
+            if let Some(first) = spelling.chars().next().filter(|c| c.is_ascii_alphabetic()) {
+                out.push(if spelling.starts_with("ch") { 't' } else { first });
+            }
+            double_next = false;
+        }
+        out.push_str(&spelling);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sanitize_makes_names_safe_everywhere() {
+        assert_eq!(sanitize("A/B:C"), "A_B_C");
+        assert_eq!(sanitize(".."), "__");
+        assert_eq!(sanitize("SAVE. "), "SAVE__");
+        assert_eq!(sanitize("con"), "con_");
+        assert_eq!(sanitize("AUX.TXT"), "AUX_.TXT");
+        assert_eq!(sanitize("CONFIG.SYS"), "CONFIG.SYS");
+    }
+
+    #[test]
+    fn hosts_are_unique() {
+        let names = ["SAVE.DAT", "save.dat", "SAVE.DAT", "SAVE~2.DAT", "A/B", "A_B", ".X"];
+        assert_eq!(hosts(names), ["SAVE.DAT", "save~2.dat", "SAVE~3.DAT", "SAVE~2~2.DAT", "A_B", "A_B~2", ".X"]);
+    }
+}
@@ -0,0 +1,85 @@
+use crate::fsck;
+use crate::names;
+use crate::{PieceError, Result};
+use piecer::flash::{FLASH_BASE, FLASH_SIZE};
+use piecer::pffs::{self, Image, PffsGeometry};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The flash dump at `path`, with PFFS at `pffs_top` or wherever it's found,
+/// laid out as `geometry` or as detected.
+pub fn open(path: &Path, pffs_top: Option<u32>, geometry: Option<PffsGeometry>) -> Result<Image> {
+    let data = fs::read(path).map_err(PieceError::host_io(format!("Could not read image {}", path.display())))?;
+    Image::new(data, pffs_top, geometry).ok_or_else(|| PieceError::BadInput(match pffs_top {
+        Some(pffs_top) => format!("PFFS at {:#x} is outside the image", pffs_top),
+        None => "Could not find PFFS in the image; pass --pffs-top".to_string(),
+    }))
+}
+
+pub fn ls(image: &Image) {
+    for dirent in image.ls() {
+        println!("{}\t{}\t{}", dirent.name, dirent.len, image.file_kind(&dirent).label());
+    }
+}
+
+/// Copy `files`, or every file, out of `image` into `dest` under their host
+/// names.
+pub fn extract(image: &Image, files: &[String], dest: &Path) -> Result<()> {
+    fs::create_dir_all(dest).map_err(PieceError::host_io(format!("Could not create {}", dest.display())))?;
+    let files = match files.is_empty() {
+        true => image.ls().into_iter().map(|dirent| dirent.name).collect(),
+        false => files.to_vec(),
+    };
+    for (name, host) in files.iter().zip(names::hosts(files.iter().map(String::as_str))) {
+        let data = image.read_file(name)?;
+        fs::write(dest.join(&host), &data).map_err(PieceError::host_io(format!("Could not write {}", host)))?;
+        println!("{}\t{}", name, data.len());
+    }
+    Ok(())
+}
+
+/// Write a flash image to `output` whose filesystem holds `files`. The rest
+/// of flash comes from the dump `kernel`, which also says where PFFS is
+/// unless `pffs_top` does, or is left erased.
+pub fn create(output: &Path, files: &[PathBuf], kernel: Option<&Path>, pffs_top: Option<u32>,
+              geometry: Option<PffsGeometry>) -> Result<()> {
+    let (mut flash, pffs_top, geometry) = match kernel {
+        Some(kernel) => {
+            let image = open(kernel, pffs_top, geometry)?;
+            let (pffs_top, geometry) = (image.pffs_top, image.geometry);
+            (image.into_data(), pffs_top, geometry)
+        }
+        None => (vec![0xFF; FLASH_SIZE as usize], pffs_top.unwrap(), geometry.unwrap_or(PffsGeometry::STOCK)),
+    };
+    if flash.len() != FLASH_SIZE as usize {
+        return Err(PieceError::BadInput(format!("{} is not a {} byte flash dump", kernel.unwrap().display(), FLASH_SIZE)));
+    }
+    if pffs_top <= FLASH_BASE || (pffs_top - FLASH_BASE) as usize + geometry.meta_len() > flash.len() {
+        return Err(PieceError::Usage(format!("PFFS at {:#x} is outside flash", pffs_top)));
+    }
+    // Without a dump there's nothing to say how to mark clusters that don't exist.
+    let end = pffs_top as u64 + geometry.clusters as u64 * geometry.cluster_size as u64;
+    if kernel.is_none() && end > (FLASH_BASE + FLASH_SIZE) as u64 {
+        return Err(PieceError::Usage(format!("PFFS at {:#x} would run past the end of flash; pass --kernel with a dump of the device",
+                                             pffs_top)));
+    }
+    let files: Vec<(String, Vec<u8>)> = files.iter().map(|path| {
+        let name = path.file_name().ok_or_else(|| PieceError::Usage(format!("{} has no file name", path.display())))?;
+        let data = fs::read(path).map_err(PieceError::host_io(format!("Could not read {}", path.display())))?;
+        Ok((name.to_string_lossy().into_owned(), data))
+    }).collect::<Result<_>>()?;
+    pffs::create(&mut flash, &geometry, pffs_top, &files)?;
+    fs::write(output, &flash).map_err(PieceError::host_io(format!("Could not write image {}", output.display())))?;
+    for (name, data) in &files {
+        println!("{}\t{}", name, data.len());
+    }
+    println!("PFFS at {:#x}, {} files", pffs_top, files.len());
+    Ok(())
+}
+
+/// Report every problem in `image`'s filesystem. Returns the exit code: 1 if
+/// there were any.
+pub fn fsck(image: &Image) -> i32 {
+    println!("PFFS at {:#x}", image.pffs_top);
+    fsck::report(&image.check(), image.ls().len())
+}
@@ -0,0 +1,255 @@
+use crate::crc32::crc32;
+
+/// Apply an IPS or BPS patch (told apart by their signatures) to `source`.
+pub fn apply(source: &[u8], patch: &[u8]) -> Result<Vec<u8>, String> {
+    if patch.starts_with(b"PATCH") {
+        ips(source, patch)
+    } else if patch.starts_with(b"BPS1") {
+        bps(source, patch)
+    } else {
+        Err("not an IPS or BPS patch".to_string())
+    }
+}
+
+fn ips(source: &[u8], patch: &[u8]) -> Result<Vec<u8>, String> {
+    let truncated = || "IPS patch is truncated".to_string();
+    let mut out = source.to_vec();
+    let mut pos = 5;
+    loop {
+        let record = patch.get(pos..pos + 3).ok_or_else(truncated)?;
+        if record == b"EOF" {
+            // An optional 3-byte length after EOF truncates the output.
+            if let Some(len) = patch.get(pos + 3..pos + 6) {
+                out.truncate(u32::from_be_bytes([0, len[0], len[1], len[2]]) as usize);
+            }
+            return Ok(out);
+        }
+        let offset = u32::from_be_bytes([0, record[0], record[1], record[2]]) as usize;
+        let size = patch.get(pos + 3..pos + 5).ok_or_else(truncated)?;
+        let size = u16::from_be_bytes([size[0], size[1]]) as usize;
+        pos += 5;
+        let bytes = match size {
+            0 => {
+                let rle = patch.get(pos..pos + 3).ok_or_else(truncated)?;
+                pos += 3;
+                vec![rle[2]; u16::from_be_bytes([rle[0], rle[1]]) as usize]
+            }
+            _ => {
+                let bytes = patch.get(pos..pos + size).ok_or_else(truncated)?.to_vec();
+                pos += size;
+                bytes
+            }
+        };
+        if out.len() < offset + bytes.len() {
+            out.resize(offset + bytes.len(), 0);
+        }
+        out[offset..offset + bytes.len()].copy_from_slice(&bytes);
+    }
+}
+
+fn bps(source: &[u8], patch: &[u8]) -> Result<Vec<u8>, String> {
+    if patch.len() < 16 {
+        return Err("BPS patch is truncated".to_string());
+    }
+    let footer = &patch[patch.len() - 12..];
+    let checksum = |i: usize| u32::from_le_bytes(footer[i..i + 4].try_into().unwrap());
+    if crc32(&patch[..patch.len() - 4]) != checksum(8) {
+        return Err("BPS patch is corrupt".to_string());
+    }
+    if crc32(source) != checksum(0) {
+        return Err("BPS patch is for a different file".to_string());
+    }
+    let body = &patch[..patch.len() - 12];
+    let mut reader = Reader { data: body, pos: 4 };
+    let source_size = reader.number()?;
+    let target_size = reader.number()? as usize;
+    let metadata_size = reader.number()? as usize;
+    if source_size != source.len() as u64 {
+        return Err("BPS patch is for a different file".to_string());
+    }
+    reader.pos = reader.pos.checked_add(metadata_size).ok_or("BPS patch is truncated")?;
+    // Not trusting the size for more than the patch could plausibly make.
+    let mut out = Vec::with_capacity(target_size.min(source.len() + body.len()));
+    let (mut source_offset, mut target_offset) = (0i64, 0i64);
+    let bad = || "BPS patch reads out of range".to_string();
+    while reader.pos < body.len() {
+        let command = reader.number()?;
+        let len = (command >> 2) as usize + 1;
+        match command & 3 {
+            0 => out.extend_from_slice(source.get(out.len()..).and_then(|rest| rest.get(..len)).ok_or_else(bad)?),
+            1 => {
+                out.extend_from_slice(body.get(reader.pos..).and_then(|rest| rest.get(..len)).ok_or_else(bad)?);
+                reader.pos += len;
+            }
+            2 => {
+                source_offset = source_offset.checked_add(reader.relative()?).ok_or_else(bad)?;
+                let start = usize::try_from(source_offset).map_err(|_| bad())?;
+                out.extend_from_slice(source.get(start..).and_then(|rest| rest.get(..len)).ok_or_else(bad)?);
+                source_offset += len as i64;
+            }
+            _ => {
+                target_offset = target_offset.checked_add(reader.relative()?).ok_or_else(bad)?;
+                let start = usize::try_from(target_offset).map_err(|_| bad())?;
+                if start >= out.len() {
+                    return Err(bad());
+                }
+                // Copies may overlap their own output, so go byte by byte.
+                for i in 0..len {
+                    out.push(out[start + i]);
+                }
+                target_offset += len as i64;
+            }
+        }
+    }
+    if out.len() != target_size || crc32(&out) != checksum(4) {
+        return Err("BPS patch produced the wrong result".to_string());
+    }
+    Ok(out)
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl Reader<'_> {
+    fn number(&mut self) -> Result<u64, String> {
+        let mut value = 0u64;
+        let mut shift = 1u64;
+        loop {
+            let byte = *self.data.get(self.pos).ok_or("BPS patch is truncated")? as u64;
+            self.pos += 1;
+            value = value.checked_add((byte & 0x7f) * shift).ok_or("BPS number overflows")?;
+            if byte & 0x80 != 0 {
+                return Ok(value);
+            }
+            shift <<= 7;
+            value = value.checked_add(shift).ok_or("BPS number overflows")?;
+        }
+    }
+    /// A signed offset: the low bit is the sign.
+    fn relative(&mut self) -> Result<i64, String> {
+        let n = self.number()?;
+        Ok(if n & 1 != 0 { -((n >> 1) as i64) } else { (n >> 1) as i64 })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A BPS number, as [`Reader::number`] reads them.
+    fn number(mut n: u64) -> Vec<u8> {
+        let mut out = Vec::new();
+        loop {
+            let low = (n & 0x7f) as u8;
+            n >>= 7;
+            if n == 0 {
+                out.push(0x80 | low);
+                return out;
+            }
+            out.push(low);
+            n -= 1;
+        }
+    }
+
+    /// A BPS command: `kind` 0 to 3 acting on `len` bytes.
+    fn command(kind: u64, len: u64) -> Vec<u8> {
+        number((len - 1) << 2 | kind)
+    }
+
+    /// A BPS patch turning `source` into `target` with `commands`.
+    fn bps_patch(source: &[u8], target: &[u8], commands: &[Vec<u8>]) -> Vec<u8> {
+        let mut patch = b"BPS1".to_vec();
+        patch.extend(number(source.len() as u64));
+        patch.extend(number(target.len() as u64));
+        patch.extend(number(0));
+        patch.extend(commands.concat());
+        patch.extend(crc32(source).to_le_bytes());
+        patch.extend(crc32(target).to_le_bytes());
+        patch.extend(crc32(&patch).to_le_bytes());
+        patch
+    }
+
+    #[test]
+    fn ips_applies() {
+        let mut patch = b"PATCH".to_vec();
+        patch.extend([0, 0, 0, 0, 5]);
+        patch.extend(b"HELLO");
+        // Run-length record: five '!' at 6.
+        patch.extend([0, 0, 6, 0, 0, 0, 5, b'!']);
+        // Past the end, which grows the file.
+        patch.extend([0, 0, 13, 0, 1, b'?']);
+        patch.extend(b"EOF");
+        assert_eq!(apply(b"hello world", &patch).unwrap(), b"HELLO !!!!!\0\0?");
+        // A length after EOF truncates.
+        patch.extend([0, 0, 5]);
+        assert_eq!(apply(b"hello world", &patch).unwrap(), b"HELLO");
+    }
+
+    #[test]
+    fn ips_truncated() {
+        assert!(apply(b"hello", b"PATCH").is_err());
+        assert!(apply(b"hello", b"PATCH\0\0\0\0\x05ab").is_err());
+        assert!(apply(b"hello", b"PATCH\0\0\0\0").is_err());
+        assert!(apply(b"hello", b"PATCH\0\0\0\0\0\0").is_err());
+        // Every record applied, but no EOF.
+        assert!(apply(b"hello", b"PATCH\0\0\0\0\x01a").is_err());
+    }
+
+    #[test]
+    fn bps_applies() {
+        let source = b"hello world";
+        let target = b"hello hello world!";
+        let mut source_copy = command(2, 5);
+        source_copy.extend(number(6 << 1));
+        let mut target_copy = command(3, 6);
+        target_copy.extend(number(0));
+        let mut target_read = command(1, 1);
+        target_read.push(b'!');
+        let patch = bps_patch(source, target, &[command(0, 6), target_copy, source_copy, target_read]);
+        assert_eq!(apply(source, &patch).unwrap(), target);
+        assert_eq!(bps(b"hello there", &patch).unwrap_err(), "BPS patch is for a different file");
+    }
+
+    #[test]
+    fn bps_malformed() {
+        let source = b"hello world";
+        let mut patch = bps_patch(source, b"hello", &[command(0, 5)]);
+        patch[6] ^= 1;
+        assert_eq!(apply(source, &patch).unwrap_err(), "BPS patch is corrupt");
+        assert!(apply(source, b"BPS1").is_err());
+        // Reads past the end of the source, absolutely and relatively.
+        let patch = bps_patch(source, b"hello world!", &[command(0, 12)]);
+        assert_eq!(apply(source, &patch).unwrap_err(), "BPS patch reads out of range");
+        let mut past = command(2, 4);
+        past.extend(number(9 << 1));
+        assert_eq!(apply(source, &bps_patch(source, b"ld!!", &[past])).unwrap_err(), "BPS patch reads out of range");
+        let mut before = command(2, 1);
+        before.extend(number(1 << 1 | 1));
+        assert_eq!(apply(source, &bps_patch(source, b"h", &[before])).unwrap_err(), "BPS patch reads out of range");
+        // Copies from output that isn't there yet.
+        let mut ahead = command(3, 1);
+        ahead.extend(number(0));
+        assert_eq!(apply(source, &bps_patch(source, b"h", &[ahead])).unwrap_err(), "BPS patch reads out of range");
+        // More literal bytes than the patch has.
+        assert_eq!(apply(source, &bps_patch(source, b"hi", &[command(1, 40)])).unwrap_err(), "BPS patch reads out of range");
+        // Sizes and offsets too big for anything.
+        let mut huge = b"BPS1".to_vec();
+        huge.extend(number(source.len() as u64));
+        huge.extend(number(u64::MAX >> 8));
+        huge.extend(number(u64::MAX));
+        huge.extend(crc32(source).to_le_bytes());
+        huge.extend([0; 4]);
+        huge.extend(crc32(&huge).to_le_bytes());
+        assert!(apply(source, &huge).is_err());
+        let mut far = command(2, 1);
+        far.extend(number(u64::MAX));
+        assert_eq!(apply(source, &bps_patch(source, b"x", &[far])).unwrap_err(), "BPS patch reads out of range");
+    }
+
+    #[test]
+    fn unknown_format() {
+        assert_eq!(apply(b"hello", b"UPS1").unwrap_err(), "not an IPS or BPS patch");
+    }
+}
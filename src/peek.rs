@@ -0,0 +1,74 @@
+use crate::hexfile::{Encoder, Format};
+use crate::{Piece, Result};
+use piecer::wire;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::Path;
+
+/// Read `len` bytes at `addr`, printed as a hex dump, or in `format` to
+/// `output` or stdout. A file is written raw unless `format` says otherwise.
+pub fn peek(piece: &mut Piece, addr: u32, len: u32, output: Option<&Path>, format: Option<Format>) -> Result<()> {
+    let data = piece.read_memory(addr, len)?;
+    let out: Box<dyn Write> = match (output, format) {
+        (None, None) => {
+            print!("{}", wire::hexdump(addr, &data));
+            return Ok(());
+        }
+        (Some(path), _) => Box::new(File::create(path).expect("Could not write output file")),
+        (None, _) => Box::new(io::stdout().lock()),
+    };
+    let mut encoder = Encoder::new(format.unwrap_or_default(), addr, len, out).expect("Could not write output");
+    encoder.write_all(&data).expect("Could not write output");
+    encoder.finish().expect("Could not write output");
+    Ok(())
+}
+
+/// Print `len` bytes at `addr` as a hex dump of `width` bytes to a line,
+/// offsets counted from the start with `relative` or else addresses.
+pub fn hexdump(piece: &mut Piece, addr: u32, len: u32, width: usize, relative: bool) -> Result<()> {
+    let data = piece.read_memory(addr, len)?;
+    print!("{}", wire::hexdump_width(if relative { 0 } else { addr }, &data, width));
+    Ok(())
+}
+
+/// The bytes `arg` stands for: the contents of the file it names, or else hex
+/// digits, optionally 0x-prefixed and separated by spaces or commas.
+pub fn parse_bytes(arg: &str) -> Vec<u8> {
+    if Path::new(arg).is_file() {
+        return fs::read(arg).expect("Could not read file to poke");
+    }
+    let digits: String = arg.split([' ', ',']).filter(|group| !group.is_empty())
+        .map(|group| group.strip_prefix("0x").or_else(|| group.strip_prefix("0X")).unwrap_or(group))
+        .map(|group| match group.len() % 2 {
+            0 => group.to_string(),
+            _ => format!("0{}", group),
+        })
+        .collect();
+    (0..digits.len()).step_by(2)
+        .map(|i| u8::from_str_radix(&digits[i..i + 2], 16)
+            .unwrap_or_else(|_| panic!("{:?} is neither a file nor hex bytes", arg)))
+        .collect()
+}
+
+/// Send the command `cmd` and then `payload`, each as `parse_bytes` reads
+/// them, and print a hex dump of up to `len` bytes of answer.
+pub fn raw(piece: &mut Piece, cmd: &str, payload: Option<&str>, len: usize) -> Result<()> {
+    let command = parse_bytes(cmd);
+    assert!(!command.is_empty(), "Nothing to send");
+    let payload = payload.map(parse_bytes);
+    let answer = piece.raw(&command, payload.as_deref(), len)?;
+    print!("{}", wire::hexdump(0, &answer));
+    if answer.len() < len {
+        eprintln!("warning: the kernel answered {} of {} bytes", answer.len(), len);
+    }
+    Ok(())
+}
+
+/// Write the bytes `arg` stands for at `addr`; see `parse_bytes`.
+pub fn poke(piece: &mut Piece, addr: u32, arg: &str) -> Result<()> {
+    let data = parse_bytes(arg);
+    assert!(!data.is_empty(), "Nothing to write");
+    piece.set_memory(addr, &data)?;
+    println!("Wrote {} bytes at {:#x}", data.len(), addr);
+    Ok(())
+}
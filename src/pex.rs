@@ -0,0 +1,73 @@
+//! Headers of P/ECE executables (.pex).
+//!
+//! A .pex starts with a fixed header, all fields little-endian:
+//!
+//! | offset | size | field                                      |
+//! |--------|------|--------------------------------------------|
+//! | 0x00   | 4    | `pCeX`                                     |
+//! | 0x04   | 4    | length of the image after the header       |
+//! | 0x08   | 4    | load address                               |
+//! | 0x0c   | 4    | entry point                                |
+//! | 0x10   | 2    | version in BCD, 0x0102 for 1.02            |
+//! | 0x12   | 2    | number of resources appended to the image  |
+//! | 0x14   | 32   | title, NUL-padded                          |
+
+use crate::filetype::PEX_MAGIC;
+use crate::kernel;
+use crate::{DirEnt, Piece, Result};
+
+/// Bytes needed to parse a header.
+pub const HEADER_LEN: u32 = 0x34;
+
+/// The fields of a .pex header, as laid out above.
+pub struct Header {
+    /// Length of the image after the header.
+    pub image_len: u32,
+    /// Where the image is loaded.
+    pub load_addr: u32,
+    /// Where execution starts.
+    pub entry: u32,
+    /// Version in BCD.
+    pub version: u16,
+    /// Number of resources appended to the image.
+    pub resources: u16,
+    /// Title, up to the first NUL.
+    pub title: String,
+}
+
+impl Header {
+    /// The header at the start of `data`, if it is a .pex.
+    pub fn parse(data: &[u8]) -> Option<Header> {
+        if !data.starts_with(PEX_MAGIC) || data.len() < HEADER_LEN as usize {
+            return None;
+        }
+        let u32_at = |offset: usize| u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap());
+        let u16_at = |offset: usize| u16::from_le_bytes(data[offset..offset + 2].try_into().unwrap());
+        let title = &data[0x14..0x34];
+        let title = &title[..title.iter().position(|&b| b == 0).unwrap_or(title.len())];
+        Some(Header {
+            image_len: u32_at(0x04),
+            load_addr: u32_at(0x08),
+            entry: u32_at(0x0c),
+            version: u16_at(0x10),
+            resources: u16_at(0x12),
+            title: String::from_utf8_lossy(title).into_owned(),
+        })
+    }
+    /// The version as it's usually written, e.g. `1.02`.
+    pub fn version_string(&self) -> String {
+        kernel::version_string(self.version)
+    }
+}
+
+impl Piece {
+    /// The .pex header of `dirent`, or None if it isn't an executable.
+    pub fn pex_header(&mut self, dirent: &DirEnt) -> Result<Option<Header>> {
+        if dirent.problem.is_some() || dirent.len < HEADER_LEN {
+            return Ok(None);
+        }
+        let mut head = [0; HEADER_LEN as usize];
+        self.get_memory(self.cluster_addr(dirent.cluster), HEADER_LEN, &mut head)?;
+        Ok(Header::parse(&head))
+    }
+}
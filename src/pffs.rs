@@ -0,0 +1,1162 @@
+//! PFFS, the P/ECE's flash filesystem.
+//!
+//! PFFS starts with the metadata: the directory and, after it, the cluster
+//! table. Cluster n lives at pffs_top + n * cluster size, so the first
+//! clusters are the metadata's own. How many directory slots and clusters
+//! there are, and how big a cluster is, is described by a [`PffsGeometry`].
+
+use crate::audit;
+use crate::cancel;
+use crate::error::{PieceError, Result};
+use crate::filetype;
+use crate::config;
+use crate::flash::{self, FLASH_BASE, SECTOR_SIZE};
+use crate::i18n;
+use crate::json;
+use crate::names;
+use crate::pex;
+use crate::progress;
+use crate::trace;
+use crate::Piece;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Seek, SeekFrom, Write};
+use std::path::Path;
+use std::str;
+
+/// How a filesystem is laid out. Kernels don't record this anywhere, so it
+/// comes from the config or is inferred by [`PffsGeometry::detect`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PffsGeometry {
+    /// Directory slots, including the reserved first one.
+    pub dir_entries: usize,
+    /// Cluster table entries, including those of the metadata clusters.
+    pub clusters: usize,
+    /// Bytes per cluster, a whole number of flash sectors.
+    pub cluster_size: u32,
+}
+
+impl PffsGeometry {
+    /// The layout of every stock kernel: 96 directory slots and a table of
+    /// 496 clusters of 4 KiB, which together fill one flash sector.
+    pub const STOCK: PffsGeometry = PffsGeometry { dir_entries: 96, clusters: 496, cluster_size: 4096 };
+
+    /// The layout set by `pffs.dir-entries`, `pffs.clusters` and
+    /// `pffs.cluster-size` in the config, with the stock value for any not
+    /// given, or None if none are.
+    pub fn from_config(config: &config::Config) -> Option<PffsGeometry> {
+        let keys = ["pffs.dir-entries", "pffs.clusters", "pffs.cluster-size"];
+        if keys.iter().all(|key| config.get(key).is_none()) {
+            return None;
+        }
+        let stock = PffsGeometry::STOCK;
+        let number = |key, default| config.get(key).map_or(default, |value: &str| {
+            value.parse().unwrap_or_else(|_| panic!("{} in config must be a number", key))
+        });
+        let geometry = PffsGeometry {
+            dir_entries: number(keys[0], stock.dir_entries),
+            clusters: number(keys[1], stock.clusters),
+            cluster_size: number(keys[2], stock.cluster_size as usize) as u32,
+        };
+        assert!(geometry.cluster_size > 0 && geometry.cluster_size.is_multiple_of(SECTOR_SIZE),
+                "pffs.cluster-size in config must be a multiple of {}", SECTOR_SIZE);
+        assert!((2..0x8000).contains(&geometry.clusters), "pffs.clusters in config must be below 32768");
+        assert!(geometry.dir_entries >= 2, "pffs.dir-entries in config must be at least 2");
+        Some(geometry)
+    }
+
+    /// Offset of the cluster table in the metadata. A 32-byte slot is left
+    /// unused after the directory.
+    pub fn fat_offset(&self) -> usize {
+        (self.dir_entries + 1) * 32
+    }
+
+    /// Length of the metadata, a whole number of flash sectors.
+    pub fn meta_len(&self) -> usize {
+        (self.fat_offset() + self.clusters * 2).next_multiple_of(SECTOR_SIZE as usize)
+    }
+
+    /// The first cluster that holds file data; the ones before it hold the
+    /// metadata.
+    pub fn first_cluster(&self) -> usize {
+        self.meta_len().div_ceil(self.cluster_size as usize)
+    }
+
+    /// Clusters files can be stored in.
+    pub fn data_clusters(&self) -> usize {
+        self.clusters - self.first_cluster()
+    }
+
+    /// Directory slots files can use.
+    pub fn file_slots(&self) -> usize {
+        self.dir_entries - 1
+    }
+
+    /// Layouts a filesystem of `span` bytes could have, most likely first:
+    /// flash bigger than stock is covered either with more 4 KiB clusters
+    /// or with 496 bigger ones.
+    fn candidates(span: u32) -> Vec<PffsGeometry> {
+        let stock = PffsGeometry::STOCK;
+        if span as u64 <= stock.clusters as u64 * stock.cluster_size as u64 {
+            return vec![stock];
+        }
+        let more = PffsGeometry { clusters: (span / stock.cluster_size).min(0x7fff) as usize, ..stock };
+        let mut candidates = vec![more];
+        for cluster_size in [8192, 16384, 32768] {
+            candidates.push(PffsGeometry { cluster_size, ..stock });
+            if stock.clusters as u64 * cluster_size as u64 >= span as u64 {
+                break;
+            }
+        }
+        candidates.push(stock);
+        candidates
+    }
+
+    /// The layout of a filesystem of `span` bytes, given `read_meta` to
+    /// fetch that many bytes of its metadata. Stock-sized filesystems are
+    /// taken to be stock without reading anything. Otherwise each plausible
+    /// layout is tried, and the one whose metadata parses and has the fewest
+    /// problems wins; the stock layout is the fallback.
+    pub fn detect(span: u32, read_meta: impl FnOnce(usize) -> Result<Vec<u8>>) -> Result<PffsGeometry> {
+        let candidates = PffsGeometry::candidates(span);
+        if candidates.len() == 1 {
+            return Ok(candidates[0]);
+        }
+        let meta = read_meta(candidates.iter().map(PffsGeometry::meta_len).max().unwrap())?;
+        Ok(candidates.iter()
+            .filter(|geometry| looks_like_meta(geometry, &meta[..geometry.meta_len()]))
+            .min_by_key(|geometry| check(geometry, &meta[..geometry.meta_len()]).len())
+            .copied()
+            .unwrap_or(PffsGeometry::STOCK))
+    }
+}
+
+/// A directory entry: 24 bytes of name, two bytes no kernel is known to
+/// use, then the first cluster and the length in bytes.
+pub struct DirEnt {
+    /// Slot in the directory table.
+    pub index: usize,
+    /// The name, decoded with the configured encoding.
+    pub name: String,
+    /// The name as stored, before decoding.
+    pub raw_name: Vec<u8>,
+    /// Bytes 24..26, little-endian. They are zero in entries piecer
+    /// writes, and kept when a file is replaced in case a kernel or
+    /// application stores a time or flags there.
+    pub attrs: u16,
+    /// First cluster of the file's chain.
+    pub cluster: u16,
+    /// Length in bytes.
+    pub len: u32,
+    /// The whole slot as stored, for working out what else it holds.
+    pub slot: Vec<u8>,
+    /// Why this entry looks corrupt, if it does.
+    pub problem: Option<&'static str>,
+}
+
+impl DirEnt {
+    /// Parse the 32-byte entry `raw` from directory slot `index` of a
+    /// filesystem laid out as `geometry`.
+    pub fn parse(geometry: &PffsGeometry, index: usize, raw: &[u8]) -> DirEnt {
+        let name_raw = &raw[0..24];
+        let name_raw = &name_raw[..name_raw.iter().position(|&b| b == 0).unwrap_or(24)];
+        let (name, valid) = names::decode(name_raw);
+        let attrs = u16::from_le_bytes(raw[24..26].try_into().unwrap());
+        let cluster = u16::from_le_bytes(raw[26..28].try_into().unwrap());
+        let len = u32::from_le_bytes(raw[28..32].try_into().unwrap());
+        let problem = if !valid {
+            Some("name is not valid in the configured encoding")
+        } else if name.chars().any(char::is_control) {
+            Some("name contains control characters")
+        } else if (cluster as usize) < geometry.first_cluster() || cluster as usize >= geometry.clusters {
+            Some("start cluster is out of range")
+        } else if len as u64 > geometry.data_clusters() as u64 * geometry.cluster_size as u64 {
+            Some("length is larger than the filesystem")
+        } else {
+            None
+        };
+        DirEnt { index, name, raw_name: name_raw.to_vec(), attrs, cluster, len, slot: raw[..32].to_vec(), problem }
+    }
+}
+
+/// Cluster table value for an unused cluster.
+pub const FAT_FREE: u16 = 0xFFFF;
+/// Cluster table value for the last cluster of a chain.
+pub const FAT_END: u16 = 0xFFFE;
+
+fn fat_entry(geometry: &PffsGeometry, meta: &[u8], cluster: usize) -> u16 {
+    let offset = geometry.fat_offset() + cluster * 2;
+    u16::from_le_bytes([meta[offset], meta[offset + 1]])
+}
+
+fn set_fat_entry(geometry: &PffsGeometry, meta: &mut [u8], cluster: usize, value: u16) {
+    let offset = geometry.fat_offset() + cluster * 2;
+    meta[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+}
+
+/// Whether directory slot `index` in `meta` is free.
+fn slot_free(meta: &[u8], index: usize) -> bool {
+    meta[index * 32] == 0x00 || meta[index * 32] == 0xFF
+}
+
+/// Clear `filename`'s directory entry in the metadata sector `meta` and free
+/// its clusters. Returns whether there was such a file. A damaged directory
+/// can hold the name twice; only the first entry goes, as that is the one
+/// reads find.
+fn remove_entry(geometry: &PffsGeometry, meta: &mut [u8], filename: &str) -> bool {
+    let Some(i) = (1..geometry.dir_entries)
+        .find(|&i| !slot_free(meta, i) && DirEnt::parse(geometry, i, &meta[i * 32..i * 32 + 32]).name == filename) else {
+        return false;
+    };
+    let mut cluster = DirEnt::parse(geometry, i, &meta[i * 32..i * 32 + 32]).cluster as usize;
+    // Bounded, so a looping chain can't hang us.
+    for _ in 0..geometry.clusters {
+        if cluster < geometry.first_cluster() || cluster >= geometry.clusters {
+            break;
+        }
+        let next = fat_entry(geometry, meta, cluster);
+        set_fat_entry(geometry, meta, cluster, FAT_FREE);
+        if next > 0x8000 {
+            break;
+        }
+        cluster = next as usize;
+    }
+    meta[i * 32..i * 32 + 32].fill(0xFF);
+    true
+}
+
+/// Clusters of the chain starting at `start`, stopping at the end marker or
+/// at the first out-of-range or repeated link.
+pub fn chain(fat: &[u16], start: u16) -> Vec<u16> {
+    let mut clusters = Vec::new();
+    let mut cluster = start;
+    while cluster != 0 && (cluster as usize) < fat.len() && !clusters.contains(&cluster) {
+        clusters.push(cluster);
+        cluster = fat[cluster as usize];
+    }
+    clusters
+}
+
+/// The used entries of the directory in the metadata `meta`.
+pub fn parse_directory(geometry: &PffsGeometry, meta: &[u8]) -> Vec<DirEnt> {
+    (1..geometry.dir_entries)
+        .filter(|&i| !slot_free(meta, i))
+        .map(|i| DirEnt::parse(geometry, i, &meta[i * 32..i * 32 + 32]))
+        .collect()
+}
+
+/// The cluster table in the metadata `meta`, one link per cluster.
+pub fn parse_fat(geometry: &PffsGeometry, meta: &[u8]) -> Vec<u16> {
+    (0..geometry.clusters).map(|cluster| fat_entry(geometry, meta, cluster)).collect()
+}
+
+fn find(directory: Vec<DirEnt>, filename: &str) -> Result<DirEnt> {
+    let dirent = directory.into_iter().find(|dirent| {
+        dirent.name == filename
+    }).ok_or_else(|| PieceError::FileNotFound(filename.to_string()))?;
+    match dirent.problem {
+        Some(problem) => Err(PieceError::CorruptEntry { index: dirent.index, problem }),
+        None => Ok(dirent),
+    }
+}
+
+/// Follow the chain in `fat` from `cluster` for `len` bytes, or to the end of
+/// the chain when the length is unknown, fetching clusters of `cluster_size`
+/// bytes with `read_cluster`.
+fn follow_chain(label: &str, fat: &[u16], cluster_size: u32, mut cluster: u16, len: Option<u32>,
+                mut read_cluster: impl FnMut(u16, &mut [u8]) -> Result<()>) -> Result<Vec<u8>> {
+    let mut contents = Vec::with_capacity(len.unwrap_or(0) as usize);
+    let mut data_left = len.map_or(usize::MAX, |len| len as usize);
+    let mut data = vec![0; cluster_size as usize];
+    for _ in 0..fat.len() {
+        if cluster == 0 || cluster as usize >= fat.len() {
+            break;
+        }
+        read_cluster(cluster, &mut data)?;
+        contents.extend_from_slice(&data[..data_left.min(data.len())]);
+        data_left -= data_left.min(data.len());
+        progress::update(Some(label), contents.len() as u64, len.unwrap_or(0) as u64);
+        cluster = fat[cluster as usize];
+        if cluster > 0x8000 {
+            break;
+        }
+    }
+    if let Some(len) = len.filter(|&len| contents.len() < len as usize) {
+        eprintln!("{}", i18n::trf("warning: {}: broken cluster chain, only {} of {} bytes read",
+                                  &[&format!("{:?}", label), &contents.len(), &len]));
+    }
+    Ok(contents)
+}
+
+/// Something wrong with the directory or cluster table, found by [`check`].
+pub enum Problem {
+    /// Directory entry `index` can't be trusted at all.
+    BadEntry {
+        /// Slot in the directory table.
+        index: usize,
+        /// The entry's name, as far as it decodes.
+        name: String,
+        /// What is wrong with it.
+        problem: &'static str,
+    },
+    /// The chain of entry `index` leaves the table, reaches a free cluster
+    /// or loops back on itself at `cluster`.
+    BrokenChain {
+        /// Slot in the directory table.
+        index: usize,
+        /// The file's name.
+        name: String,
+        /// Where the chain goes wrong.
+        cluster: u16,
+        /// Which of those it does.
+        problem: &'static str,
+    },
+    /// `cluster` is in the chains of more than one file.
+    CrossLinked {
+        /// The shared cluster.
+        cluster: u16,
+        /// The files whose chains run through it.
+        names: Vec<String>,
+    },
+    /// The chain of entry `index` has `clusters` clusters, but its length
+    /// needs `expected`.
+    LengthMismatch {
+        /// Slot in the directory table.
+        index: usize,
+        /// The file's name.
+        name: String,
+        /// Clusters in the chain.
+        clusters: usize,
+        /// Clusters the length in the entry needs.
+        expected: usize,
+    },
+    /// Allocated clusters that no file's chain reaches.
+    Orphaned {
+        /// The clusters, in order.
+        clusters: Vec<u16>,
+    },
+}
+
+impl std::fmt::Display for Problem {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Problem::BadEntry { index, name, problem } => {
+                write!(f, "entry {} {:?}: {}", index, name, i18n::tr(problem))
+            }
+            Problem::BrokenChain { index, name, cluster, problem } => {
+                write!(f, "entry {} {:?}: chain {} at cluster {}", index, name, problem, cluster)
+            }
+            Problem::CrossLinked { cluster, names } => {
+                write!(f, "cluster {} is shared by {}", cluster, names.join(", "))
+            }
+            Problem::LengthMismatch { index, name, clusters, expected } => {
+                write!(f, "entry {} {:?}: chain has {} clusters, length needs {}", index, name, clusters, expected)
+            }
+            Problem::Orphaned { clusters } => {
+                write!(f, "{} allocated clusters belong to no file: {:?}", clusters.len(), clusters)
+            }
+        }
+    }
+}
+
+/// The chain from `start` as far as it can be trusted, and why it stops early
+/// if it doesn't reach an end marker.
+fn walk(fat: &[u16], start: u16) -> (Vec<u16>, Option<(u16, &'static str)>) {
+    let mut clusters = Vec::new();
+    let mut cluster = start;
+    loop {
+        if cluster == 0 || cluster as usize >= fat.len() {
+            return (clusters, Some((cluster, "leaves the table")));
+        }
+        if fat[cluster as usize] == FAT_FREE {
+            return (clusters, Some((cluster, "reaches a free cluster")));
+        }
+        if clusters.contains(&cluster) {
+            return (clusters, Some((cluster, "loops")));
+        }
+        clusters.push(cluster);
+        match fat[cluster as usize] {
+            next if next > 0x8000 => return (clusters, None),
+            next => cluster = next,
+        }
+    }
+}
+
+/// Walk every directory entry and cluster chain in the metadata `meta`,
+/// without trusting any of it.
+pub fn check(geometry: &PffsGeometry, meta: &[u8]) -> Vec<Problem> {
+    let fat = parse_fat(geometry, meta);
+    let mut problems = Vec::new();
+    // Which files' chains reach each cluster.
+    let mut owners: Vec<Vec<String>> = vec![Vec::new(); fat.len()];
+    for dirent in parse_directory(geometry, meta) {
+        if let Some(problem) = dirent.problem {
+            problems.push(Problem::BadEntry { index: dirent.index, name: dirent.name, problem });
+            continue;
+        }
+        let (clusters, broken) = walk(&fat, dirent.cluster);
+        for &cluster in &clusters {
+            owners[cluster as usize].push(dirent.name.clone());
+        }
+        if let Some((cluster, problem)) = broken {
+            problems.push(Problem::BrokenChain { index: dirent.index, name: dirent.name, cluster, problem });
+            continue;
+        }
+        let expected = (dirent.len as usize).div_ceil(geometry.cluster_size as usize).max(1);
+        if clusters.len() != expected {
+            problems.push(Problem::LengthMismatch { index: dirent.index, name: dirent.name, clusters: clusters.len(), expected });
+        }
+    }
+    for (cluster, names) in owners.iter().enumerate() {
+        if names.len() > 1 {
+            problems.push(Problem::CrossLinked { cluster: cluster as u16, names: names.clone() });
+        }
+    }
+    let orphaned: Vec<u16> = (geometry.first_cluster()..fat.len())
+        .filter(|&cluster| fat[cluster] != FAT_FREE && owners[cluster].is_empty())
+        .map(|cluster| cluster as u16)
+        .collect();
+    if !orphaned.is_empty() {
+        problems.push(Problem::Orphaned { clusters: orphaned });
+    }
+    problems
+}
+
+/// Fix what [`check`] finds in `meta` without touching file data: unusable
+/// entries are removed, broken chains end at their last good cluster, chains
+/// and lengths are cut to agree, and orphaned clusters are freed. Returns the
+/// problems left, which are the cross-links.
+pub fn repair(geometry: &PffsGeometry, meta: &mut [u8]) -> Vec<Problem> {
+    // Each pass can orphan clusters for the next to free.
+    for _ in 0..3 {
+        let problems = check(geometry, meta);
+        if problems.iter().all(|problem| matches!(problem, Problem::CrossLinked { .. })) {
+            return problems;
+        }
+        let fat = parse_fat(geometry, meta);
+        for problem in problems {
+            match problem {
+                Problem::BadEntry { index, .. } => meta[index * 32..index * 32 + 32].fill(0xFF),
+                Problem::BrokenChain { index, .. } => {
+                    let dirent = DirEnt::parse(geometry, index, &meta[index * 32..index * 32 + 32]);
+                    let (clusters, _) = walk(&fat, dirent.cluster);
+                    match clusters.last() {
+                        Some(&last) => {
+                            set_fat_entry(geometry, meta, last as usize, FAT_END);
+                            let len = dirent.len.min(clusters.len() as u32 * geometry.cluster_size);
+                            meta[index * 32 + 28..index * 32 + 32].copy_from_slice(&len.to_le_bytes());
+                        }
+                        None => meta[index * 32..index * 32 + 32].fill(0xFF),
+                    }
+                }
+                Problem::LengthMismatch { index, clusters, expected, .. } => {
+                    let dirent = DirEnt::parse(geometry, index, &meta[index * 32..index * 32 + 32]);
+                    let (chain, _) = walk(&fat, dirent.cluster);
+                    if clusters > expected {
+                        set_fat_entry(geometry, meta, chain[expected - 1] as usize, FAT_END);
+                        for &cluster in &chain[expected..] {
+                            set_fat_entry(geometry, meta, cluster as usize, FAT_FREE);
+                        }
+                    } else {
+                        let len = clusters as u32 * geometry.cluster_size;
+                        meta[index * 32 + 28..index * 32 + 32].copy_from_slice(&len.to_le_bytes());
+                    }
+                }
+                Problem::Orphaned { clusters } => {
+                    for cluster in clusters {
+                        set_fat_entry(geometry, meta, cluster as usize, FAT_FREE);
+                    }
+                }
+                Problem::CrossLinked { .. } => {}
+            }
+        }
+    }
+    check(geometry, meta)
+}
+
+/// Print the directory entries and cluster links that differ between the
+/// metadata `old` and `new`, for a dry run.
+fn print_changes(geometry: &PffsGeometry, old: &[u8], new: &[u8]) {
+    let entry = |meta: &[u8], index| match slot_free(meta, index) {
+        true => "free".to_string(),
+        false => {
+            let dirent = DirEnt::parse(geometry, index, &meta[index * 32..index * 32 + 32]);
+            format!("{:?} from cluster {}, {} bytes", dirent.name, dirent.cluster, dirent.len)
+        }
+    };
+    for index in 1..geometry.dir_entries {
+        if old[index * 32..index * 32 + 32] != new[index * 32..index * 32 + 32] {
+            println!("would change directory slot {}: {} -> {}", index, entry(old, index), entry(new, index));
+        }
+    }
+    let link = |link| match link {
+        FAT_FREE => "free".to_string(),
+        FAT_END => "end of chain".to_string(),
+        next => format!("next {}", next),
+    };
+    for (cluster, (was, now)) in parse_fat(geometry, old).into_iter().zip(parse_fat(geometry, new)).enumerate() {
+        if was != now {
+            println!("would change cluster {}: {} -> {}", cluster, link(was), link(now));
+        }
+    }
+}
+
+/// Make whatever links to cluster `from` in `meta`, the directory entry its
+/// chain starts at or the cluster before it, link to `to` instead, and move
+/// `from`'s own link to `to`, freeing `from`.
+fn relink(geometry: &PffsGeometry, meta: &mut [u8], from: u16, to: u16) {
+    let start = parse_directory(geometry, meta).into_iter().find(|dirent| dirent.cluster == from);
+    match start {
+        Some(dirent) => meta[dirent.index * 32 + 26..dirent.index * 32 + 28].copy_from_slice(&to.to_le_bytes()),
+        None => {
+            let fat = parse_fat(geometry, meta);
+            if let Some(previous) = (geometry.first_cluster()..fat.len()).find(|&cluster| fat[cluster] == from) {
+                set_fat_entry(geometry, meta, previous, to);
+            }
+        }
+    }
+    let next = fat_entry(geometry, meta, from as usize);
+    set_fat_entry(geometry, meta, to as usize, next);
+    set_fat_entry(geometry, meta, from as usize, FAT_FREE);
+}
+
+/// Make every chain in `meta` contiguous, in the order the files start, by
+/// calling `move_cluster` to move one cluster at a time into a free one.
+/// Returns how many moves it took.
+fn pack(geometry: &PffsGeometry, meta: &mut [u8],
+        mut move_cluster: impl FnMut(&mut [u8], u16, u16) -> Result<()>) -> Result<usize> {
+    let mut files = parse_directory(geometry, meta);
+    files.sort_by_key(|dirent| dirent.cluster);
+    let total: usize = files.iter().map(|dirent| walk(&parse_fat(geometry, meta), dirent.cluster).0.len()).sum();
+    let (mut target, mut moved, mut placed) = (geometry.first_cluster() as u16, 0, 0);
+    for file in files {
+        // Where the chain currently starts, which moves along with its first cluster.
+        let start = |meta: &[u8]| DirEnt::parse(geometry, file.index, &meta[file.index * 32..file.index * 32 + 32]).cluster;
+        let len = walk(&parse_fat(geometry, meta), start(meta)).0.len();
+        for k in 0..len {
+            let fat = parse_fat(geometry, meta);
+            if walk(&fat, start(meta)).0[k] != target {
+                // Whatever is in the way goes to the last free cluster, out of the way of the next files.
+                if fat[target as usize] != FAT_FREE {
+                    let spare = (target as usize + 1..fat.len()).rev().find(|&cluster| fat[cluster] == FAT_FREE)
+                        .ok_or(PieceError::NoSpace)?;
+                    move_cluster(meta, target, spare as u16)?;
+                    moved += 1;
+                }
+                let current = walk(&parse_fat(geometry, meta), start(meta)).0[k];
+                move_cluster(meta, current, target)?;
+                moved += 1;
+            }
+            target += 1;
+            placed += 1;
+            progress::update(Some(&file.name), placed, total as u64);
+        }
+    }
+    Ok(moved)
+}
+
+/// Turn the metadata `meta` into an empty filesystem whose clusters run to
+/// `pffs_end`. The reserved first directory slot and the links of clusters
+/// past the end of flash are kept as they were; metadata clusters after the
+/// first are marked in use.
+pub fn format(geometry: &PffsGeometry, meta: &mut [u8], pffs_top: u32, pffs_end: u32) {
+    meta[32..geometry.dir_entries * 32].fill(0xFF);
+    for cluster in 1..geometry.first_cluster() {
+        set_fat_entry(geometry, meta, cluster, FAT_END);
+    }
+    for cluster in geometry.first_cluster()..geometry.clusters {
+        if pffs_top as u64 + (cluster as u64 + 1) * geometry.cluster_size as u64 <= pffs_end as u64 {
+            set_fat_entry(geometry, meta, cluster, FAT_FREE);
+        }
+    }
+}
+
+/// Write a new filesystem holding `files` into `flash`, an image of flash
+/// from `FLASH_BASE` with PFFS at `pffs_top`. The files go in the order
+/// given, each in consecutive clusters. The metadata already in `flash` is
+/// formatted rather than replaced, so a dump's reserved slot and the links
+/// of clusters past the end of flash carry over.
+pub fn create(flash: &mut [u8], geometry: &PffsGeometry, pffs_top: u32, files: &[(String, Vec<u8>)]) -> Result<()> {
+    let offset = (pffs_top - FLASH_BASE) as usize;
+    let pffs_end = FLASH_BASE + flash.len() as u32;
+    let cluster_size = geometry.cluster_size as usize;
+    let mut meta = flash[offset..offset + geometry.meta_len()].to_vec();
+    format(geometry, &mut meta, pffs_top, pffs_end);
+    let usable = geometry.clusters.min((flash.len() - offset) / cluster_size);
+    let mut next = geometry.first_cluster();
+    for (slot, (name, data)) in (1..).zip(files) {
+        let raw_name = names::encode(name).ok_or_else(|| PieceError::UnencodableName(name.clone()))?;
+        if raw_name.len() > 24 {
+            return Err(PieceError::NameTooLong(name.clone()));
+        }
+        if files[..slot - 1].iter().any(|(other, _)| other == name) {
+            return Err(PieceError::FileExists(name.clone()));
+        }
+        if slot >= geometry.dir_entries {
+            return Err(PieceError::DirectoryFull);
+        }
+        let clusters: Vec<usize> = (next..next + data.len().div_ceil(cluster_size).max(1)).collect();
+        if clusters.iter().any(|&cluster| cluster >= usable || fat_entry(geometry, &meta, cluster) != FAT_FREE) {
+            return Err(PieceError::NoSpace);
+        }
+        for (i, &cluster) in clusters.iter().enumerate() {
+            let chunk = &data[(i * cluster_size).min(data.len())..((i + 1) * cluster_size).min(data.len())];
+            let at = offset + cluster * cluster_size;
+            flash[at..at + cluster_size].fill(0xFF);
+            flash[at..at + chunk.len()].copy_from_slice(chunk);
+            set_fat_entry(geometry, &mut meta, cluster, clusters.get(i + 1).map_or(FAT_END, |&next| next as u16));
+        }
+        let dirent = &mut meta[slot * 32..slot * 32 + 32];
+        dirent.fill(0);
+        dirent[..raw_name.len()].copy_from_slice(&raw_name);
+        dirent[26..28].copy_from_slice(&(clusters[0] as u16).to_le_bytes());
+        dirent[28..32].copy_from_slice(&(data.len() as u32).to_le_bytes());
+        next += clusters.len();
+    }
+    flash[offset..offset + meta.len()].copy_from_slice(&meta);
+    Ok(())
+}
+
+/// Whether `meta` could be PFFS metadata laid out as `geometry`: not erased,
+/// every cluster link in range and every used directory entry sane.
+fn looks_like_meta(geometry: &PffsGeometry, meta: &[u8]) -> bool {
+    let in_range = |link: u16| link == FAT_FREE || link == FAT_END || (1..geometry.clusters).contains(&(link as usize));
+    meta.iter().any(|&b| b != 0xFF)
+        && parse_fat(geometry, meta)[1..].iter().all(|&link| in_range(link))
+        && parse_directory(geometry, meta).iter().all(|dirent| dirent.problem.is_none())
+}
+
+/// A deleted file put back together by [`recover`].
+pub struct Recovered {
+    /// The old name with its first letter lost, or a made-up one.
+    pub name: String,
+    /// The clusters it was gathered from, in order.
+    pub clusters: Vec<u16>,
+    /// The recovered contents.
+    pub data: Vec<u8>,
+    /// Whether it was found through a deleted directory entry rather than by
+    /// scanning.
+    pub from_entry: bool,
+}
+
+/// A file being gathered from free clusters by [`recover`].
+struct Gathering {
+    start: u16,
+    kind: filetype::Kind,
+    clusters: Vec<u16>,
+    data: Vec<u8>,
+    /// Its length, if the header says.
+    len: Option<usize>,
+}
+
+impl Gathering {
+    fn finish(mut self) -> Recovered {
+        match self.len {
+            Some(len) => self.data.truncate(len),
+            // The last cluster of a file is padded with erased bytes.
+            None => self.data.truncate(self.data.iter().rposition(|&b| b != 0xFF).map_or(0, |last| last + 1)),
+        }
+        let extension = match self.kind {
+            filetype::Kind::Data | filetype::Kind::Empty => "bin",
+            kind => kind.label(),
+        };
+        Recovered { name: format!("recovered-{:04}.{}", self.start, extension), clusters: self.clusters,
+                    data: self.data, from_entry: false }
+    }
+}
+
+/// Files deleted from the filesystem `meta` whose data is still in free
+/// clusters, read with `read_cluster`.
+///
+/// A directory slot that was only marked free at its first byte still has
+/// the rest of the name, the start and the length; the file is taken to
+/// continue through the following free clusters, the order uploads give
+/// them out. With `scan`, the free clusters nothing else claimed are then
+/// searched: each that starts with a known header begins a file, which
+/// takes the clusters after it up to an erased one or the next header.
+/// Only the length of a .pex without resources is known that way; other
+/// files lose any trailing 0xFF bytes. What comes back is a best guess,
+/// and clusters reused since can make it wrong.
+pub fn recover(geometry: &PffsGeometry, meta: &[u8], scan: bool,
+               mut read_cluster: impl FnMut(u16, &mut [u8]) -> Result<()>) -> Result<Vec<Recovered>> {
+    let fat = parse_fat(geometry, meta);
+    let cluster_size = geometry.cluster_size as usize;
+    let mut taken: Vec<bool> = fat.iter().map(|&link| link != FAT_FREE).collect();
+    for dirent in parse_directory(geometry, meta) {
+        for cluster in chain(&fat, dirent.cluster) {
+            taken[cluster as usize] = true;
+        }
+    }
+    taken[..geometry.first_cluster()].fill(true);
+    let mut found = Vec::new();
+    let mut data = vec![0; cluster_size];
+    for index in (1..geometry.dir_entries).filter(|&i| slot_free(meta, i)) {
+        let mut raw = meta[index * 32..index * 32 + 32].to_vec();
+        if raw[1..24].iter().all(|&b| b == raw[0]) {
+            continue;
+        }
+        raw[0] = b'_';
+        let dirent = DirEnt::parse(geometry, index, &raw);
+        if dirent.problem.is_some() || dirent.len == 0 {
+            continue;
+        }
+        let clusters: Vec<u16> = (dirent.cluster as usize..geometry.clusters).filter(|&c| !taken[c])
+            .take((dirent.len as usize).div_ceil(cluster_size)).map(|c| c as u16).collect();
+        if clusters.first() != Some(&dirent.cluster) {
+            continue;
+        }
+        let mut contents = Vec::with_capacity(clusters.len() * cluster_size);
+        for &cluster in &clusters {
+            read_cluster(cluster, &mut data)?;
+            contents.extend_from_slice(&data);
+            taken[cluster as usize] = true;
+        }
+        contents.truncate(dirent.len as usize);
+        found.push(Recovered { name: dirent.name, clusters, data: contents, from_entry: true });
+    }
+    if !scan {
+        return Ok(found);
+    }
+    let mut gathering: Option<Gathering> = None;
+    for cluster in (0..geometry.clusters).filter(|&c| !taken[c]).map(|c| c as u16) {
+        read_cluster(cluster, &mut data)?;
+        let kind = filetype::Kind::detect(&data[..filetype::HEAD_LEN as usize]);
+        let erased = data.iter().all(|&b| b == 0xFF);
+        let full = gathering.as_ref().is_some_and(|file| file.len.is_some_and(|len| file.data.len() >= len));
+        if erased || kind != filetype::Kind::Data || full {
+            found.extend(gathering.take().map(Gathering::finish));
+        }
+        if erased {
+            continue;
+        }
+        let file = gathering.get_or_insert_with(|| Gathering {
+            start: cluster,
+            kind,
+            clusters: Vec::new(),
+            data: Vec::new(),
+            len: pex::Header::parse(&data).filter(|header| header.resources == 0)
+                .map(|header| (pex::HEADER_LEN + header.image_len) as usize),
+        });
+        file.clusters.push(cluster);
+        file.data.extend_from_slice(&data);
+    }
+    found.extend(gathering.map(Gathering::finish));
+    Ok(found)
+}
+
+/// A flash image made by `dump`, read with the same parsing as the device,
+/// for when the device isn't at hand.
+pub struct Image {
+    data: Vec<u8>,
+    /// Flash address of the PFFS metadata.
+    pub pffs_top: u32,
+    /// The filesystem layout the image is read with.
+    pub geometry: PffsGeometry,
+}
+
+impl Image {
+    /// The dump `data` of flash from `FLASH_BASE`, with PFFS at `pffs_top`, or
+    /// at the first sector that looks like PFFS metadata if that's not known.
+    /// The layout is detected as on a device, taking PFFS to run to the end
+    /// of the dump, unless `geometry` is given.
+    pub fn new(data: Vec<u8>, pffs_top: Option<u32>, geometry: Option<PffsGeometry>) -> Option<Image> {
+        let pffs_top = match pffs_top {
+            Some(pffs_top) => pffs_top,
+            None => {
+                let stock = PffsGeometry::STOCK;
+                let sector = data.chunks_exact(SECTOR_SIZE as usize).skip(1)
+                    .position(|sector| looks_like_meta(&stock, sector))?;
+                FLASH_BASE + (sector as u32 + 1) * SECTOR_SIZE
+            }
+        };
+        let offset = pffs_top.checked_sub(FLASH_BASE)? as usize;
+        let span = data.len().checked_sub(offset)?;
+        let geometry = match geometry {
+            Some(geometry) => geometry,
+            None => PffsGeometry::detect(span as u32, |len| {
+                let mut meta = data[offset..(offset + len).min(data.len())].to_vec();
+                meta.resize(len, 0xFF);
+                Ok(meta)
+            }).ok()?,
+        };
+        (offset + geometry.meta_len() <= data.len()).then_some(Image { data, pffs_top, geometry })
+    }
+    /// The dump the image was made from.
+    pub fn into_data(self) -> Vec<u8> {
+        self.data
+    }
+    fn meta(&self) -> &[u8] {
+        let offset = (self.pffs_top - FLASH_BASE) as usize;
+        &self.data[offset..offset + self.geometry.meta_len()]
+    }
+    fn read_cluster(&self, cluster: u16, data: &mut [u8]) -> Result<()> {
+        let len = self.geometry.cluster_size;
+        let addr = self.pffs_top + cluster as u32 * len;
+        let offset = (addr - FLASH_BASE) as usize;
+        let cluster = self.data.get(offset..offset + len as usize)
+            .ok_or(PieceError::ShortRead { addr, read: 0, len })?;
+        data.copy_from_slice(cluster);
+        Ok(())
+    }
+    /// The directory, skipping unused slots.
+    pub fn ls(&self) -> Vec<DirEnt> {
+        parse_directory(&self.geometry, self.meta())
+    }
+    /// The type of `dirent`'s contents, from the start of its first cluster.
+    pub fn file_kind(&self, dirent: &DirEnt) -> filetype::Kind {
+        let mut data = vec![0; self.geometry.cluster_size as usize];
+        match dirent.problem.is_none() && self.read_cluster(dirent.cluster, &mut data).is_ok() {
+            true => filetype::Kind::detect(&data[..dirent.len.min(filetype::HEAD_LEN) as usize]),
+            false => filetype::Kind::Data,
+        }
+    }
+    /// The contents of `filename`.
+    pub fn read_file(&self, filename: &str) -> Result<Vec<u8>> {
+        let dirent = find(self.ls(), filename)?;
+        let fat = parse_fat(&self.geometry, self.meta());
+        follow_chain(filename, &fat, self.geometry.cluster_size, dirent.cluster, Some(dirent.len),
+                     |cluster, data| self.read_cluster(cluster, data))
+    }
+    /// Problems with the image's directory and cluster table; see [`check`].
+    pub fn check(&self) -> Vec<Problem> {
+        check(&self.geometry, self.meta())
+    }
+    /// Deleted files still in the image; see [`recover`].
+    pub fn recover_deleted(&self, scan: bool) -> Result<Vec<Recovered>> {
+        recover(&self.geometry, self.meta(), scan, |cluster, data| self.read_cluster(cluster, data))
+    }
+}
+
+impl Piece {
+    /// The layout of the filesystem running from `pffs_top` to `pffs_end`;
+    /// see [`PffsGeometry::detect`].
+    pub(crate) fn detect_pffs(&mut self, pffs_end: u32) -> Result<PffsGeometry> {
+        let pffs_top = self.pffs_top;
+        PffsGeometry::detect(pffs_end.saturating_sub(pffs_top), |len| {
+            let mut meta = vec![0; len];
+            self.get_memory(pffs_top, len as u32, &mut meta)?;
+            Ok(meta)
+        })
+    }
+    /// Keep the directory and cluster table once read, until something is
+    /// written or started, so a run of commands over one connection reads
+    /// them once. Files an application writes while it runs aren't
+    /// noticed.
+    pub fn keep_meta(&mut self, keep: bool) {
+        self.keep_meta = keep;
+        self.cached_meta = None;
+    }
+    /// The directory and cluster table.
+    fn read_meta(&mut self) -> Result<Vec<u8>> {
+        if let Some(meta) = &self.cached_meta {
+            return Ok(meta.clone());
+        }
+        let mut meta = vec![0; self.pffs.meta_len()];
+        self.get_memory(self.pffs_top, meta.len() as u32, &mut meta)?;
+        if self.keep_meta {
+            self.cached_meta = Some(meta.clone());
+        }
+        Ok(meta)
+    }
+    /// Write `meta` back over the directory and cluster table. A dry run
+    /// prints the entries and links that would change first.
+    fn write_meta(&mut self, meta: &[u8]) -> Result<()> {
+        if self.options.dry_run {
+            let old = self.read_meta()?;
+            print_changes(&self.pffs, &old, meta);
+        }
+        for (i, sector) in meta.chunks(SECTOR_SIZE as usize).enumerate() {
+            self.write_flash_sector(self.pffs_top + i as u32 * SECTOR_SIZE, sector)?;
+        }
+        Ok(())
+    }
+    /// The directory, skipping unused slots.
+    pub fn ls(&mut self) -> Result<Vec<DirEnt>> {
+        let _span = trace::span("pffs_ls");
+        if self.keep_meta {
+            let meta = self.read_meta()?;
+            return Ok(parse_directory(&self.pffs, &meta));
+        }
+        let mut directory_raw = vec![0; self.pffs.fat_offset()];
+        self.get_memory(self.pffs_top, directory_raw.len() as u32, &mut directory_raw)?;
+        Ok(parse_directory(&self.pffs, &directory_raw))
+    }
+    /// Flash address of the data in `cluster`.
+    pub fn cluster_addr(&self, cluster: u16) -> u32 {
+        self.pffs_top + cluster as u32 * self.pffs.cluster_size
+    }
+    /// Read `cluster` into `data`, which holds a whole cluster.
+    pub fn read_cluster(&mut self, cluster: u16, data: &mut [u8]) -> Result<()> {
+        self.read_stable(self.cluster_addr(cluster), self.pffs.cluster_size, data)
+    }
+    /// Write `data`, a whole cluster, over `cluster` and check it reads back
+    /// the same. Only the data is written; nothing in the cluster table
+    /// changes.
+    pub fn write_cluster(&mut self, cluster: u16, data: &[u8]) -> Result<()> {
+        let _span = trace::span("pffs_write_cluster").arg("cluster", cluster);
+        if data.len() as u32 != self.pffs.cluster_size {
+            return Err(PieceError::BadRequest(format!("{} bytes for a cluster of {}", data.len(), self.pffs.cluster_size)));
+        }
+        let addr = self.cluster_addr(cluster);
+        for (i, sector) in data.chunks(SECTOR_SIZE as usize).enumerate() {
+            self.write_flash_sector(addr + i as u32 * SECTOR_SIZE, sector)?;
+        }
+        let mut written = vec![0; data.len()];
+        self.read_cluster(cluster, &mut written)?;
+        if written != data {
+            return Err(PieceError::WriteMismatch { addr });
+        }
+        Ok(())
+    }
+    /// The type of `dirent`'s contents, from the start of its first cluster.
+    pub fn file_kind(&mut self, dirent: &DirEnt) -> Result<filetype::Kind> {
+        if dirent.problem.is_some() {
+            return Ok(filetype::Kind::Data);
+        }
+        let mut head = vec![0; dirent.len.min(filetype::HEAD_LEN) as usize];
+        self.get_memory(self.cluster_addr(dirent.cluster), head.len() as u32, &mut head)?;
+        Ok(filetype::Kind::detect(&head))
+    }
+    /// The cluster table, one link per cluster.
+    pub fn read_fat(&mut self) -> Result<Vec<u16>> {
+        let meta = self.read_meta()?;
+        Ok(parse_fat(&self.pffs, &meta))
+    }
+    /// Save `filename` in the current directory under its host name.
+    pub fn download(&mut self, filename: &str) -> Result<()> {
+        self.download_to(filename, Path::new(&names::host(filename)))
+    }
+    /// Save `filename` to `path`.
+    pub fn download_to(&mut self, filename: &str, path: &Path) -> Result<()> {
+        let data = self.read_file(filename)?;
+        let mut file = File::create(path)?;
+        file.write_all(&data)?;
+        Ok(())
+    }
+    /// Save the file `dirent` describes to `path` a cluster at a time, using
+    /// `fat` from [`Piece::read_fat`]. The file is written as `path.part`
+    /// and renamed when complete; with `resume`, a `.part` left by an
+    /// interrupted download is continued from its last whole cluster.
+    pub fn download_entry(&mut self, dirent: &DirEnt, fat: &[u16], path: &Path, resume: bool) -> Result<()> {
+        if let Some(problem) = dirent.problem {
+            return Err(PieceError::CorruptEntry { index: dirent.index, problem });
+        }
+        let _span = trace::span("pffs_download").arg("file", &dirent.name);
+        let cluster_size = self.pffs.cluster_size as u64;
+        let mut part = path.as_os_str().to_owned();
+        part.push(".part");
+        let part = Path::new(&part);
+        let kept = match resume {
+            true => fs::metadata(part).map_or(0, |metadata| metadata.len()),
+            false => 0,
+        };
+        // A partial file longer than the one on the device is from something else.
+        let kept = match kept <= dirent.len as u64 {
+            true => kept - kept % cluster_size,
+            false => 0,
+        };
+        let mut file = OpenOptions::new().create(true).write(true).truncate(false).open(part)?;
+        file.set_len(kept)?;
+        file.seek(SeekFrom::End(0))?;
+        let mut written = kept;
+        let mut data = vec![0; cluster_size as usize];
+        for &cluster in chain(fat, dirent.cluster).iter().skip((kept / cluster_size) as usize) {
+            if written >= dirent.len as u64 {
+                break;
+            }
+            self.read_cluster(cluster, &mut data)?;
+            let len = (dirent.len as u64 - written).min(cluster_size) as usize;
+            file.write_all(&data[..len])?;
+            written += len as u64;
+            progress::update(Some(&dirent.name), written, dirent.len as u64);
+        }
+        file.sync_data()?;
+        if written < dirent.len as u64 {
+            eprintln!("{}", i18n::trf("warning: {}: broken cluster chain, only {} of {} bytes read",
+                                      &[&format!("{:?}", dirent.name), &written, &dirent.len]));
+        }
+        fs::rename(part, path)?;
+        Ok(())
+    }
+    /// The contents of `filename`.
+    pub fn read_file(&mut self, filename: &str) -> Result<Vec<u8>> {
+        let dirent = find(self.ls()?, filename)?;
+        self.read_chain(filename, dirent.cluster, Some(dirent.len))
+    }
+    /// The contents of the file `dirent` describes, following its chain in
+    /// `fat` from [`Piece::read_fat`]. Saves re-reading the metadata sector
+    /// when fetching many files.
+    pub fn read_entry(&mut self, dirent: &DirEnt, fat: &[u16]) -> Result<Vec<u8>> {
+        if let Some(problem) = dirent.problem {
+            return Err(PieceError::CorruptEntry { index: dirent.index, problem });
+        }
+        let _span = trace::span("pffs_read").arg("file", &dirent.name);
+        follow_chain(&dirent.name, fat, self.pffs.cluster_size, dirent.cluster, Some(dirent.len),
+                     |cluster, data| self.read_cluster(cluster, data))
+    }
+    /// Follow the cluster chain from `cluster` for `len` bytes, or to the end
+    /// of the chain when the length is unknown.
+    pub fn read_chain(&mut self, label: &str, cluster: u16, len: Option<u32>) -> Result<Vec<u8>> {
+        let _span = trace::span("pffs_read").arg("file", label);
+        let fat = self.read_fat()?;
+        follow_chain(label, &fat, self.pffs.cluster_size, cluster, len, |cluster, data| self.read_cluster(cluster, data))
+    }
+    /// Problems in the directory and cluster table; see [`check`].
+    pub fn check(&mut self) -> Result<Vec<Problem>> {
+        let meta = self.read_meta()?;
+        Ok(check(&self.pffs, &meta))
+    }
+    /// Deleted files still on the device; see [`recover`]. Nothing is
+    /// written.
+    pub fn recover_deleted(&mut self, scan: bool) -> Result<Vec<Recovered>> {
+        let _span = trace::span("pffs_recover");
+        let geometry = self.pffs;
+        let meta = self.read_meta()?;
+        recover(&geometry, &meta, scan, |cluster, data| self.read_cluster(cluster, data))
+    }
+    /// Fix what [`check`] finds, writing the metadata sector back if anything
+    /// changed. Returns the problems that couldn't be fixed.
+    pub fn repair(&mut self) -> Result<Vec<Problem>> {
+        let _span = trace::span("pffs_repair");
+        self.require_writable("repair the filesystem")?;
+        let mut meta = self.read_meta()?;
+        let original = meta.clone();
+        let left = repair(&self.pffs, &mut meta);
+        if meta != original {
+            audit::record(self, "repair", "")?;
+            self.write_meta(&meta)?;
+        }
+        Ok(left)
+    }
+    /// Empty the filesystem. Only the metadata is rewritten; the old
+    /// clusters are left as they are until reused.
+    pub fn format(&mut self) -> Result<()> {
+        let _span = trace::span("pffs_format");
+        self.require_writable("format the filesystem")?;
+        let pffs_end = flash::geometry(self)?.pffs_end;
+        audit::record(self, "format", "")?;
+        let mut meta = self.read_meta()?;
+        format(&self.pffs, &mut meta, self.pffs_top, pffs_end);
+        self.write_meta(&meta)
+    }
+    /// Delete `filename` from PFFS, freeing its clusters.
+    pub fn remove(&mut self, filename: &str) -> Result<()> {
+        let _span = trace::span("pffs_remove").arg("file", filename);
+        self.require_writable("delete files")?;
+        audit::record(self, "remove", &format!("file={}", json::string(filename)))?;
+        let mut meta = self.read_meta()?;
+        if !remove_entry(&self.pffs, &mut meta, filename) {
+            return Err(PieceError::FileNotFound(filename.to_string()));
+        }
+        self.write_meta(&meta)
+    }
+    /// Copy cluster `from` to the free cluster `to`, check the copy, and then
+    /// commit the metadata with the chain through `to` instead. Until the
+    /// metadata is written the copy is in a free cluster, so an interruption
+    /// at any point leaves a consistent filesystem.
+    fn move_cluster(&mut self, meta: &mut [u8], from: u16, to: u16) -> Result<()> {
+        let mut data = vec![0; self.pffs.cluster_size as usize];
+        self.read_cluster(from, &mut data)?;
+        self.write_cluster(to, &data)?;
+        relink(&self.pffs, meta, from, to);
+        self.write_meta(meta)
+    }
+    /// Move clusters so every file's chain is contiguous, packed from the
+    /// start of the filesystem in the order the files start now. Each move
+    /// is verified and committed on its own; see `move_cluster`.
+    /// Returns how many clusters were moved.
+    ///
+    /// The filesystem has to pass [`check`] first, and needs a free cluster
+    /// to shuffle through.
+    pub fn defrag(&mut self) -> Result<usize> {
+        let _span = trace::span("pffs_defrag");
+        self.require_writable("defragment the filesystem")?;
+        let geometry = self.pffs;
+        let mut meta = self.read_meta()?;
+        let problems = check(&geometry, &meta);
+        if !problems.is_empty() {
+            return Err(PieceError::NeedsRepair(problems.iter().map(Problem::to_string).collect()));
+        }
+        audit::record(self, "defrag", "")?;
+        pack(&geometry, &mut meta, |meta, from, to| self.move_cluster(meta, from, to))
+    }
+    /// Rename `old` to `new` by rewriting the name in its directory entry,
+    /// leaving the data where it is. Fails if another file is called `new`.
+    pub fn rename(&mut self, old: &str, new: &str) -> Result<()> {
+        let _span = trace::span("pffs_rename").arg("file", old).arg("to", new);
+        let raw_name = names::encode(new).ok_or_else(|| PieceError::UnencodableName(new.to_string()))?;
+        if raw_name.len() > 24 {
+            return Err(PieceError::NameTooLong(new.to_string()));
+        }
+        self.require_writable("rename files")?;
+        let mut meta = self.read_meta()?;
+        let index = find(parse_directory(&self.pffs, &meta), old)?.index;
+        if parse_directory(&self.pffs, &meta).iter().any(|dirent| dirent.name == new && dirent.index != index) {
+            return Err(PieceError::FileExists(new.to_string()));
+        }
+        audit::record(self, "rename", &format!("file={} to={}", json::string(old), json::string(new)))?;
+        let name = &mut meta[index * 32..index * 32 + 24];
+        name.fill(0);
+        name[..raw_name.len()].copy_from_slice(&raw_name);
+        self.write_meta(&meta)
+    }
+    /// Write a file to PFFS, replacing any existing file with the same name.
+    /// Writes that would leave the device nearly full are warned about, or
+    /// refused unless `force` if the config asks for that.
+    pub fn upload(&mut self, filename: &str, data: &[u8], force: bool) -> Result<()> {
+        let _span = trace::span("pffs_write").arg("file", filename).arg("len", data.len());
+        let raw_name = names::encode(filename).ok_or_else(|| PieceError::UnencodableName(filename.to_string()))?;
+        if raw_name.len() > 24 {
+            return Err(PieceError::NameTooLong(filename.to_string()));
+        }
+        self.require_writable("upload")?;
+        let geometry = self.pffs;
+        let cluster_size = geometry.cluster_size as usize;
+        let mut meta = self.read_meta()?;
+        let attrs = parse_directory(&geometry, &meta).into_iter().find(|dirent| dirent.name == filename)
+            .map_or(0, |dirent| dirent.attrs);
+        // Only clusters free before the old file goes, so it is intact until
+        // the new directory is written.
+        let free: Vec<usize> = (geometry.first_cluster()..geometry.clusters)
+            .filter(|&c| fat_entry(&geometry, &meta, c) == FAT_FREE).collect();
+        remove_entry(&geometry, &mut meta, filename);
+        let slot = (1..geometry.dir_entries).find(|&i| slot_free(&meta, i)).ok_or(PieceError::DirectoryFull)?;
+        let clusters_needed = data.len().div_ceil(cluster_size).max(1);
+        if free.len() < clusters_needed {
+            return Err(PieceError::NoSpace);
+        }
+        let clusters = &free[..clusters_needed];
+        let free_clusters = (geometry.first_cluster()..geometry.clusters)
+            .filter(|&c| fat_entry(&geometry, &meta, c) == FAT_FREE).count() - clusters_needed;
+        let free_slots = (1..geometry.dir_entries).filter(|&i| slot_free(&meta, i)).count() - 1;
+        let low_space = &self.options.low_space;
+        if free_clusters < low_space.min_free_clusters || free_slots < low_space.min_free_slots {
+            let message = i18n::trf("writing {} leaves only {} free clusters and {} free directory slots",
+                                    &[&format!("{:?}", filename), &free_clusters, &free_slots]);
+            if low_space.refuse && !force {
+                return Err(PieceError::LowSpace(message));
+            }
+            eprintln!("{}", i18n::trf("warning: {}", &[&message]));
+        }
+        audit::record(self, "upload", &format!("file={} len={}", json::string(filename), data.len()))?;
+        for (i, &cluster) in clusters.iter().enumerate() {
+            // Safe to stop: only free clusters are written until the directory.
+            cancel::check()?;
+            let mut contents = vec![0xFF; cluster_size];
+            let chunk = &data[(i * cluster_size).min(data.len())..((i + 1) * cluster_size).min(data.len())];
+            contents[..chunk.len()].copy_from_slice(chunk);
+            let addr = self.cluster_addr(cluster as u16);
+            for (j, sector) in contents.chunks(SECTOR_SIZE as usize).enumerate() {
+                self.write_flash_sector(addr + j as u32 * SECTOR_SIZE, sector)?;
+            }
+            progress::update(Some(filename), (i * cluster_size + chunk.len()) as u64, data.len() as u64);
+            set_fat_entry(&geometry, &mut meta, cluster, clusters.get(i + 1).map_or(FAT_END, |&next| next as u16));
+        }
+        let dirent = &mut meta[slot * 32..slot * 32 + 32];
+        dirent.fill(0);
+        dirent[..raw_name.len()].copy_from_slice(&raw_name);
+        dirent[24..26].copy_from_slice(&attrs.to_le_bytes());
+        dirent[26..28].copy_from_slice(&(clusters[0] as u16).to_le_bytes());
+        dirent[28..32].copy_from_slice(&(data.len() as u32).to_le_bytes());
+        self.write_meta(&meta)
+    }
+}
@@ -0,0 +1,45 @@
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::process::Command;
+
+const PREFIX: &str = "piecer-";
+
+/// Run `piecer-<name>` from PATH with the remaining arguments, returning its
+/// exit code.
+///
+/// Plugins find the piecer binary in PIECER and the USB IDs of the device to
+/// talk to in PIECER_VID and PIECER_PID.
+pub fn run(args: &[OsString]) -> i32 {
+    let name = args[0].to_string_lossy();
+    let program = format!("{}{}", PREFIX, name);
+    let status = Command::new(&program)
+        .args(&args[1..])
+        .env("PIECER", env::current_exe().unwrap_or_default())
+        .env("PIECER_VID", format!("{:04x}", crate::VID))
+        .env("PIECER_PID", format!("{:04x}", crate::PID))
+        .status();
+    match status {
+        Ok(status) => status.code().unwrap_or(1),
+        Err(_) => {
+            eprintln!("error: unrecognized subcommand '{}' (no {} found on PATH)", name, program);
+            2
+        }
+    }
+}
+
+/// Names of plugins available on PATH.
+pub fn list() -> Vec<String> {
+    let mut names: Vec<String> = env::split_paths(&env::var_os("PATH").unwrap_or_default())
+        .filter_map(|dir| fs::read_dir(dir).ok())
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.ok()?.file_name().into_string().ok()?;
+            let name = name.strip_prefix(PREFIX)?;
+            Some(name.strip_suffix(".exe").unwrap_or(name).to_string())
+        })
+        .collect();
+    names.sort();
+    names.dedup();
+    names
+}
@@ -0,0 +1,229 @@
+use crate::crc32::crc32;
+use crate::deflate;
+
+/// A decoded image as 8-bit grayscale, row by row.
+pub struct Gray {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+
+/// Encode 8-bit grayscale `pixels`, row by row, as a PNG.
+pub fn encode_gray(width: u32, height: u32, pixels: &[u8]) -> Vec<u8> {
+    let mut raw = Vec::with_capacity(pixels.len() + height as usize);
+    for row in pixels.chunks(width as usize) {
+        raw.push(0);
+        raw.extend_from_slice(row);
+    }
+    let mut header = Vec::new();
+    header.extend(width.to_be_bytes());
+    header.extend(height.to_be_bytes());
+    header.extend([8, 0, 0, 0, 0]);
+    let mut out = b"\x89PNG\r\n\x1a\n".to_vec();
+    chunk(&mut out, b"IHDR", &header);
+    chunk(&mut out, b"IDAT", &deflate::zlib(&raw));
+    chunk(&mut out, b"IEND", &[]);
+    out
+}
+
+fn chunk(out: &mut Vec<u8>, kind: &[u8; 4], body: &[u8]) {
+    out.extend((body.len() as u32).to_be_bytes());
+    let start = out.len();
+    out.extend_from_slice(kind);
+    out.extend_from_slice(body);
+    let crc = crc32(&out[start..]);
+    out.extend(crc.to_be_bytes());
+}
+
+/// Decode a non-interlaced PNG of any color type and bit depth to grayscale.
+/// Transparency is ignored. Returns `None` on malformed or unsupported input.
+pub fn decode_gray(data: &[u8]) -> Option<Gray> {
+    if !data.starts_with(b"\x89PNG\r\n\x1a\n") {
+        return None;
+    }
+    let mut pos = 8;
+    let (mut width, mut height, mut depth, mut color) = (0, 0, 0, 0);
+    let mut palette: &[u8] = &[];
+    let mut compressed = Vec::new();
+    while pos + 8 <= data.len() {
+        let len = u32::from_be_bytes(data[pos..pos + 4].try_into().unwrap()) as usize;
+        let kind = &data[pos + 4..pos + 8];
+        let body = data.get(pos + 8..pos + 8 + len)?;
+        match kind {
+            b"IHDR" => {
+                if len < 13 || body[10] != 0 || body[12] != 0 {
+                    return None;
+                }
+                width = u32::from_be_bytes(body[0..4].try_into().unwrap());
+                height = u32::from_be_bytes(body[4..8].try_into().unwrap());
+                depth = body[8] as usize;
+                color = body[9];
+            }
+            b"PLTE" => palette = body,
+            b"IDAT" => compressed.extend_from_slice(body),
+            b"IEND" => break,
+            _ => {}
+        }
+        pos += 12 + len;
+    }
+    let channels = match color {
+        0 | 3 => 1,
+        2 => 3,
+        4 => 2,
+        6 => 4,
+        _ => return None,
+    };
+    let raw = deflate::unzlib(&compressed)?;
+    let bits_per_pixel = channels * depth;
+    let stride = (width as usize * bits_per_pixel).div_ceil(8);
+    let filter_step = bits_per_pixel.div_ceil(8);
+    let mut rows = vec![0u8; stride * height as usize];
+    for y in 0..height as usize {
+        let line = raw.get(y * (stride + 1)..(y + 1) * (stride + 1))?;
+        let (filter, line) = (line[0], &line[1..]);
+        let (done, row) = rows.split_at_mut(y * stride);
+        let above = if y > 0 { &done[(y - 1) * stride..] } else { &[][..] };
+        let row = &mut row[..stride];
+        for x in 0..stride {
+            let a = if x >= filter_step { row[x - filter_step] } else { 0 };
+            let b = above.get(x).copied().unwrap_or(0);
+            let c = if x >= filter_step { above.get(x - filter_step).copied().unwrap_or(0) } else { 0 };
+            row[x] = line[x].wrapping_add(match filter {
+                0 => 0,
+                1 => a,
+                2 => b,
+                3 => ((a as u16 + b as u16) / 2) as u8,
+                4 => paeth(a, b, c),
+                _ => return None,
+            });
+        }
+    }
+    let sample = |row: &[u8], index: usize| -> u16 {
+        match depth {
+            16 => u16::from_be_bytes([row[index * 2], row[index * 2 + 1]]) >> 8,
+            8 => row[index] as u16,
+            1 | 2 | 4 => {
+                let bit = index * depth;
+                (row[bit / 8] >> (8 - depth - bit % 8)) as u16 & ((1 << depth) - 1)
+            }
+            _ => 0,
+        }
+    };
+    let scale = |value: u16| if depth < 8 && color == 0 { value * 255 / ((1 << depth) - 1) } else { value };
+    let mut pixels = Vec::with_capacity((width * height) as usize);
+    for row in rows.chunks(stride) {
+        for x in 0..width as usize {
+            let (r, g, b) = match color {
+                3 => {
+                    let entry = palette.get(sample(row, x) as usize * 3..sample(row, x) as usize * 3 + 3)?;
+                    (entry[0] as u16, entry[1] as u16, entry[2] as u16)
+                }
+                0 | 4 => {
+                    let v = scale(sample(row, x * channels));
+                    (v, v, v)
+                }
+                _ => (sample(row, x * channels), sample(row, x * channels + 1), sample(row, x * channels + 2)),
+            };
+            pixels.push(((r as u32 * 299 + g as u32 * 587 + b as u32 * 114) / 1000) as u8);
+        }
+    }
+    Some(Gray { width, height, pixels })
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let (pa, pb, pc) = ((p - a as i16).abs(), (p - b as i16).abs(), (p - c as i16).abs());
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The chunks of `png` as (kind, body), checking each one's CRC.
+    fn chunks(png: &[u8]) -> Vec<(&[u8], &[u8])> {
+        let mut chunks = Vec::new();
+        let mut pos = 8;
+        while pos < png.len() {
+            let len = u32::from_be_bytes(png[pos..pos + 4].try_into().unwrap()) as usize;
+            let crc = u32::from_be_bytes(png[pos + 8 + len..pos + 12 + len].try_into().unwrap());
+            assert_eq!(crc32(&png[pos + 4..pos + 8 + len]), crc);
+            chunks.push((&png[pos + 4..pos + 8], &png[pos + 8..pos + 8 + len]));
+            pos += 12 + len;
+        }
+        chunks
+    }
+
+    fn adler32(data: &[u8]) -> u32 {
+        let (a, b) = data.iter().fold((1u32, 0u32), |(a, b), &byte| {
+            let a = (a + byte as u32) % 65521;
+            (a, (b + a) % 65521)
+        });
+        b << 16 | a
+    }
+
+    #[test]
+    fn encoded_layout() {
+        let pixels: Vec<u8> = (0..12).map(|i| i * 20).collect();
+        let png = encode_gray(4, 3, &pixels);
+        assert!(png.starts_with(b"\x89PNG\r\n\x1a\n"));
+        let chunks = chunks(&png);
+        let kinds: Vec<&[u8]> = chunks.iter().map(|&(kind, _)| kind).collect();
+        assert_eq!(kinds, [&b"IHDR"[..], b"IDAT", b"IEND"]);
+        assert_eq!(chunks[0].1, [0, 0, 0, 4, 0, 0, 0, 3, 8, 0, 0, 0, 0]);
+        assert!(chunks[2].1.is_empty());
+        // Every PNG ends with the same IEND chunk.
+        assert!(png.ends_with(&[0, 0, 0, 0, b'I', b'E', b'N', b'D', 0xAE, 0x42, 0x60, 0x82]));
+    }
+
+    #[test]
+    fn image_data_is_zlib_with_adler32() {
+        assert_eq!(adler32(b"Wikipedia"), 0x11E60398);
+        let pixels: Vec<u8> = (0..=255).collect();
+        let png = encode_gray(16, 16, &pixels);
+        let idat = chunks(&png)[1].1;
+        // Deflate with a 32K window, and a check value the header passes.
+        assert_eq!(idat[0], 0x78);
+        assert_eq!(u16::from_be_bytes([idat[0], idat[1]]) % 31, 0);
+        let raw: Vec<u8> = pixels.chunks(16).flat_map(|row| [&[0][..], row].concat()).collect();
+        assert_eq!(idat[idat.len() - 4..], adler32(&raw).to_be_bytes());
+        assert_eq!(deflate::unzlib(idat).unwrap(), raw);
+    }
+
+    #[test]
+    fn round_trip() {
+        let pixels: Vec<u8> = (0..128u32 * 88).map(|i| (i * 7 % 251) as u8).collect();
+        let gray = decode_gray(&encode_gray(128, 88, &pixels)).unwrap();
+        assert_eq!((gray.width, gray.height), (128, 88));
+        assert_eq!(gray.pixels, pixels);
+    }
+
+    #[test]
+    fn filters_and_low_bit_depths_decode() {
+        // 2-bit gray, 4x2: row 0 with the Sub filter, row 1 with Up.
+        let mut header = Vec::new();
+        header.extend(4u32.to_be_bytes());
+        header.extend(2u32.to_be_bytes());
+        header.extend([2, 0, 0, 0, 0]);
+        let raw = [1, 0b00_01_10_11, 2, 0b00_00_00_00];
+        let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+        chunk(&mut png, b"IHDR", &header);
+        chunk(&mut png, b"IDAT", &deflate::zlib(&raw));
+        chunk(&mut png, b"IEND", &[]);
+        let gray = decode_gray(&png).unwrap();
+        assert_eq!(gray.pixels, [0, 85, 170, 255, 0, 85, 170, 255]);
+    }
+
+    #[test]
+    fn damage_is_refused() {
+        let png = encode_gray(2, 2, &[0, 1, 2, 3]);
+        assert!(decode_gray(&png[1..]).is_none());
+        assert!(decode_gray(&png[..30]).is_none());
+    }
+}
@@ -0,0 +1,40 @@
+//! Keeping the device's USB port awake during long transfers.
+
+use rusb::{Device, GlobalContext};
+use std::fs;
+use std::path::PathBuf;
+
+/// Keeps the host from autosuspending the device's USB port while held, by
+/// setting its runtime power control to "on". The previous setting is put
+/// back on drop.
+pub struct NoSuspend {
+    control: PathBuf,
+    saved: String,
+}
+
+/// Disable autosuspend for `device`, if the platform allows it and we have
+/// permission; otherwise `None`, and the port keeps its normal policy.
+#[cfg(target_os = "linux")]
+pub fn prevent_suspend(device: &Device<GlobalContext>) -> Option<NoSuspend> {
+    let ports: Vec<String> = device.port_numbers().ok()?.iter().map(u8::to_string).collect();
+    let name = format!("{}-{}", device.bus_number(), ports.join("."));
+    let control = PathBuf::from("/sys/bus/usb/devices").join(name).join("power/control");
+    let saved = fs::read_to_string(&control).ok()?.trim().to_string();
+    if saved != "on" {
+        fs::write(&control, "on").ok()?;
+    }
+    Some(NoSuspend { control, saved })
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn prevent_suspend(_device: &Device<GlobalContext>) -> Option<NoSuspend> {
+    None
+}
+
+impl Drop for NoSuspend {
+    fn drop(&mut self) {
+        if self.saved != "on" {
+            let _ = fs::write(&self.control, &self.saved);
+        }
+    }
+}
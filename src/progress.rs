@@ -0,0 +1,164 @@
+//! Progress of long transfers, as a bar on the terminal, JSON events for
+//! other programs, or callbacks for library users.
+
+use crate::json;
+use clap::ValueEnum;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::panic;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Copy, ValueEnum)]
+/// How `--progress` reports.
+pub enum Format {
+    /// Newline-delimited JSON events
+    Json,
+}
+
+/// What happened in the operation passed to [`set_callback`] hooks.
+pub enum Event<'a> {
+    /// The operation has begun.
+    Start,
+    /// `bytes` of `total` are done, for `file` if the operation works on
+    /// files, at `speed` bytes per second since the file started.
+    Progress {
+        /// The file being transferred.
+        file: Option<&'a str>,
+        /// Bytes done so far.
+        bytes: u64,
+        /// Bytes in all.
+        total: u64,
+        /// Bytes per second.
+        speed: f64,
+    },
+    /// The operation failed with this message.
+    Error(&'a str),
+    /// The operation finished.
+    Done,
+}
+
+type Callback = Box<dyn FnMut(&str, &Event) + Send>;
+
+// Progress events for the whole process go to one sink, so transfers deep in
+// Piece can report without threading a reporter through every call.
+struct Sink {
+    out: Option<Box<dyn Write + Send>>,
+    callback: Option<Callback>,
+    operation: String,
+    file: Option<String>,
+    started: Option<Instant>,
+}
+
+static SINK: Mutex<Sink> = Mutex::new(Sink { out: None, callback: None, operation: String::new(), file: None, started: None });
+
+/// Start emitting events to `path` (e.g. a named pipe), or stderr.
+///
+/// Panics are reported as error events before the usual message.
+pub fn init(_format: Format, path: Option<&Path>) {
+    let out: Box<dyn Write + Send> = match path {
+        Some(path) => Box::new(OpenOptions::new().create(true).append(true).open(path).expect("Could not open progress output")),
+        None => Box::new(io::stderr()),
+    };
+    SINK.lock().unwrap().out = Some(out);
+    report_panics();
+}
+
+fn report_panics() {
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        let message = crate::panic_message(info.payload());
+        error(&message);
+        default_hook(info);
+    }));
+}
+
+/// Call `callback` with the operation name for every event, alongside any
+/// event output set up by [`init`]. Replaces an earlier callback.
+pub fn set_callback(callback: impl FnMut(&str, &Event) + Send + 'static) {
+    SINK.lock().unwrap().callback = Some(Box::new(callback));
+}
+
+/// Draw a progress bar with throughput and time left on stderr.
+pub fn bar() {
+    let mut drawn: Option<Instant> = None;
+    set_callback(move |operation, event| match *event {
+        Event::Progress { file, bytes, total, speed } => {
+            // Redraw a few times a second, but always show completion.
+            if drawn.is_some_and(|drawn| drawn.elapsed() < Duration::from_millis(100)) && bytes < total {
+                return;
+            }
+            drawn = Some(Instant::now());
+            let fraction = match total {
+                0 => 0.0,
+                _ => (bytes as f64 / total as f64).min(1.0),
+            };
+            let filled = (fraction * 30.0) as usize;
+            let eta = match speed > 0.0 {
+                true => format!("{:.0}s", total.saturating_sub(bytes) as f64 / speed),
+                false => "?".to_string(),
+            };
+            eprint!("\r\x1b[K{} [{}{}] {:3.0}% {}/{} KB {:.1} KB/s ETA {}",
+                    file.unwrap_or(operation), "#".repeat(filled), " ".repeat(30 - filled), fraction * 100.0,
+                    bytes / 1024, total / 1024, speed / 1024.0, eta);
+        }
+        Event::Error(_) | Event::Done => {
+            if drawn.take().is_some() {
+                eprintln!();
+            }
+        }
+        Event::Start => {}
+    });
+    report_panics();
+}
+
+fn emit(sink: &mut Sink, event: &str, fields: &str, callback_event: Event) {
+    if let Some(out) = sink.out.as_mut() {
+        writeln!(out, "{{\"event\":\"{}\",\"operation\":{}{}}}", event, json::string(&sink.operation), fields).unwrap();
+        out.flush().unwrap();
+    }
+    if let Some(callback) = sink.callback.as_mut() {
+        callback(&sink.operation, &callback_event);
+    }
+}
+
+/// Start reporting `operation`, e.g. "download".
+pub fn begin(operation: &str) {
+    let mut sink = SINK.lock().unwrap();
+    sink.operation = operation.to_string();
+    sink.file = None;
+    sink.started = Some(Instant::now());
+    emit(&mut sink, "start", "", Event::Start);
+}
+
+/// `bytes` of `total` have been transferred, for `file` if the operation works
+/// on files.
+pub fn update(file: Option<&str>, bytes: u64, total: u64) {
+    let mut sink = SINK.lock().unwrap();
+    if sink.out.is_none() && sink.callback.is_none() {
+        return;
+    }
+    if sink.file.as_deref() != file || sink.started.is_none() {
+        sink.file = file.map(String::from);
+        sink.started = Some(Instant::now());
+    }
+    let elapsed = sink.started.map_or(Duration::ZERO, |started| started.elapsed());
+    let speed = bytes as f64 / elapsed.as_secs_f64().max(1e-3);
+    let fields = format!(",\"file\":{},\"bytes\":{},\"total\":{},\"speed\":{:.0}",
+                         file.map_or("null".to_string(), json::string), bytes, total, speed);
+    emit(&mut sink, "progress", &fields, Event::Progress { file, bytes, total, speed });
+}
+
+/// Report that the operation failed with `message`.
+pub fn error(message: &str) {
+    // try_lock, since a panic while the sink is held lands here too.
+    if let Ok(mut sink) = SINK.try_lock() {
+        emit(&mut sink, "error", &format!(",\"message\":{}", json::string(message)), Event::Error(message));
+    }
+}
+
+/// Report that the operation finished.
+pub fn end() {
+    emit(&mut SINK.lock().unwrap(), "done", "", Event::Done);
+}
@@ -0,0 +1,88 @@
+use crate::{Piece, Result};
+use piecer::memmap::{self, Address};
+use std::fs;
+use std::path::Path;
+
+/// Unchanged bytes between two changes that still count as one run, so a
+/// counter and its neighbours print as a single line.
+const GAP: usize = 3;
+/// Bytes of a run shown before it is cut short.
+const SHOWN: usize = 16;
+
+/// How many bytes to capture at `addr`: `len`, or else as many as the
+/// baseline has, or else the rest of the region `addr` is in.
+fn capture_len(piece: &mut Piece, addr: Address, len: Option<u32>, baseline: Option<&[u8]>) -> Result<u32> {
+    if let Some(len) = len.or(baseline.map(|baseline| baseline.len() as u32)) {
+        return Ok(len);
+    }
+    let Address::Symbolic { region, offset } = addr else {
+        panic!("Give --len with an address that isn't a region");
+    };
+    let region = memmap::regions(piece)?.into_iter().find(|found| found.name == region)
+        .unwrap_or_else(|| panic!("This device has no {} region", region));
+    Ok((region.size as i64 - offset).max(0) as u32)
+}
+
+/// Stretches of `after` that differ from `before`, as (offset, length).
+fn runs(before: &[u8], after: &[u8]) -> Vec<(usize, usize)> {
+    let mut runs: Vec<(usize, usize)> = Vec::new();
+    for offset in (0..before.len().min(after.len())).filter(|&i| before[i] != after[i]) {
+        match runs.last_mut() {
+            Some((start, len)) if offset - (*start + *len) <= GAP => *len = offset - *start + 1,
+            _ => runs.push((offset, 1)),
+        }
+    }
+    runs
+}
+
+fn hex(bytes: &[u8]) -> String {
+    let shown: Vec<String> = bytes.iter().take(SHOWN).map(|byte| format!("{:02x}", byte)).collect();
+    match bytes.len() > SHOWN {
+        true => format!("{} ...", shown.join(" ")),
+        false => shown.join(" "),
+    }
+}
+
+/// A run's bytes read as a little-endian number, when it is as wide as one.
+fn number(bytes: &[u8]) -> Option<u32> {
+    match bytes.len() {
+        1 | 2 | 4 => Some(bytes.iter().rev().fold(0, |value, &byte| value << 8 | byte as u32)),
+        _ => None,
+    }
+}
+
+/// Capture memory at `addr` and print what changed since the snapshot in
+/// `baseline`. The snapshot is saved first if there isn't one, and
+/// replaced by this capture with `update`.
+pub fn run(piece: &mut Piece, baseline: &Path, addr: Address, len: Option<u32>, update: bool) -> Result<()> {
+    let before = fs::read(baseline).ok();
+    let len = capture_len(piece, addr, len, before.as_deref())?;
+    let start = addr.resolve(piece)?;
+    let after = piece.read_memory(start, len)?;
+    let Some(before) = before else {
+        fs::write(baseline, &after).expect("Could not write baseline");
+        println!("Saved {} bytes at {:#x} to {}; run again to see what changes", len, start, baseline.display());
+        return Ok(());
+    };
+    if before.len() != after.len() {
+        eprintln!("Baseline is {} bytes but {} were read; comparing the first {}",
+                  before.len(), after.len(), before.len().min(after.len()));
+    }
+    let runs = runs(&before, &after);
+    for &(offset, len) in &runs {
+        let (old, new) = (&before[offset..offset + len], &after[offset..offset + len]);
+        let values = match (number(old), number(new)) {
+            (Some(old), Some(new)) => format!("  ({} -> {})", old, new),
+            _ => String::new(),
+        };
+        println!("{:#010x} {:>5}  {} -> {}{}", start as usize + offset, len, hex(old), hex(new), values);
+    }
+    let changed: usize = runs.iter()
+        .map(|&(offset, len)| (offset..offset + len).filter(|&i| before[i] != after[i]).count())
+        .sum();
+    println!("{} bytes changed in {} places", changed, runs.len());
+    if update {
+        fs::write(baseline, &after).expect("Could not write baseline");
+    }
+    Ok(())
+}
@@ -0,0 +1,94 @@
+//! `piecer fs`: the filesystem a cluster at a time, for recovering deleted
+//! files and checking what a write did.
+
+use crate::{chain, Piece, PieceError, Result, FAT_FREE};
+use piecer::pffs::FAT_END;
+use piecer::wire;
+use std::fs;
+use std::path::Path;
+
+/// Stop unless `cluster` is in the filesystem.
+fn check_range(piece: &Piece, cluster: u16) {
+    assert!((cluster as usize) < piece.pffs.clusters,
+            "There are only {} clusters, numbered from 0", piece.pffs.clusters);
+}
+
+/// A cluster table link as written in listings.
+fn link(next: u16) -> String {
+    match next {
+        FAT_FREE => "free".to_string(),
+        FAT_END => "end".to_string(),
+        next => next.to_string(),
+    }
+}
+
+/// Which file's chain `cluster` is in, if any.
+fn owner(piece: &mut Piece, cluster: u16) -> Result<Option<String>> {
+    let fat = piece.read_fat()?;
+    Ok(piece.ls()?.into_iter().find(|dirent| chain(&fat, dirent.cluster).contains(&cluster)).map(|dirent| dirent.name))
+}
+
+/// Print `cluster` as a hex dump at its flash address, or save it raw to
+/// `output`.
+pub fn read_cluster(piece: &mut Piece, cluster: u16, output: Option<&Path>) -> Result<()> {
+    check_range(piece, cluster);
+    let mut data = vec![0; piece.pffs.cluster_size as usize];
+    piece.read_cluster(cluster, &mut data)?;
+    match output {
+        Some(path) => fs::write(path, &data).expect("Could not write output file"),
+        None => print!("{}", wire::hexdump(piece.cluster_addr(cluster), &data)),
+    }
+    Ok(())
+}
+
+/// What overwriting `cluster` would hit, for the confirmation question.
+pub fn describe(piece: &mut Piece, cluster: u16) -> Result<String> {
+    check_range(piece, cluster);
+    assert!(cluster as usize >= piece.pffs.first_cluster(),
+            "Cluster {} holds the directory and cluster table; use fsck or restore to change them", cluster);
+    let fat = piece.read_fat()?;
+    Ok(match owner(piece, cluster)? {
+        Some(name) => format!("cluster {}, part of {}", cluster, name),
+        None if fat[cluster as usize] == FAT_FREE => format!("free cluster {}", cluster),
+        None => format!("cluster {}, linked from no file", cluster),
+    })
+}
+
+/// Write the contents of `file` over `cluster`, padded with erased bytes
+/// to a whole cluster. The cluster table is left alone.
+pub fn write_cluster(piece: &mut Piece, cluster: u16, file: &Path) -> Result<()> {
+    describe(piece, cluster)?;
+    let contents = fs::read(file).expect("Could not read cluster contents");
+    let cluster_size = piece.pffs.cluster_size as usize;
+    assert!(contents.len() <= cluster_size, "{} is {} bytes, more than a cluster of {}",
+            file.display(), contents.len(), cluster_size);
+    let mut data = vec![0xFF; cluster_size];
+    data[..contents.len()].copy_from_slice(&contents);
+    piece.write_cluster(cluster, &data)?;
+    println!("Wrote {} bytes to cluster {} at {:#x}", contents.len(), cluster, piece.cluster_addr(cluster));
+    Ok(())
+}
+
+/// Print the clusters of `filename` in order, with where each is in flash,
+/// how much of the file it holds and its link in the cluster table.
+pub fn print_chain(piece: &mut Piece, filename: &str) -> Result<()> {
+    let dirent = piece.ls()?.into_iter().find(|dirent| dirent.name == filename)
+        .ok_or_else(|| PieceError::FileNotFound(filename.to_string()))?;
+    let fat = piece.read_fat()?;
+    let clusters = chain(&fat, dirent.cluster);
+    let cluster_size = piece.pffs.cluster_size;
+    println!("CLUSTER\tADDRESS\tBYTES\tNEXT");
+    let mut left = dirent.len;
+    for &cluster in &clusters {
+        let bytes = left.min(cluster_size);
+        left -= bytes;
+        println!("{}\t{:#x}\t{}\t{}", cluster, piece.cluster_addr(cluster), bytes, link(fat[cluster as usize]));
+    }
+    let last = clusters.last().map(|&cluster| fat[cluster as usize]);
+    if left > 0 || last != Some(FAT_END) {
+        eprintln!("warning: the chain doesn't end cleanly; {} of {} bytes are in it",
+                  dirent.len - left, dirent.len);
+    }
+    println!("{} clusters, {} bytes, starting at cluster {}", clusters.len(), dirent.len, dirent.cluster);
+    Ok(())
+}
@@ -0,0 +1,45 @@
+use crate::names;
+use piecer::filetype::Kind;
+use piecer::pffs::Recovered;
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::Path;
+
+/// List what [`piecer::pffs::recover`] found and, unless `list`, write each
+/// to `dest` under its host name. Files already in `dest` are left alone.
+/// Returns the exit code: 1 if nothing was found.
+pub fn save(found: &[Recovered], dest: &Path, list: bool) -> i32 {
+    if found.is_empty() {
+        eprintln!("Found nothing to recover");
+        return 1;
+    }
+    if !list {
+        fs::create_dir_all(dest).expect("Could not create destination directory");
+    }
+    println!("FILE\tBYTES\tTYPE\tCLUSTERS\tFROM");
+    let mut written = 0;
+    for file in found {
+        let clusters: Vec<String> = file.clusters.iter().map(u16::to_string).collect();
+        println!("{}\t{}\t{}\t{}\t{}", file.name, file.data.len(), Kind::detect(&file.data).label(),
+                 clusters.join(","), if file.from_entry { "deleted entry" } else { "free clusters" });
+        if list {
+            continue;
+        }
+        let path = dest.join(names::host(&file.name));
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(mut out) => {
+                out.write_all(&file.data).expect("Could not write recovered file");
+                written += 1;
+            }
+            Err(error) if error.kind() == ErrorKind::AlreadyExists => {
+                eprintln!("warning: {} already exists; not replaced", path.display());
+            }
+            Err(error) => panic!("Could not write {}: {}", path.display(), error),
+        }
+    }
+    match list {
+        true => println!("{} files could be recovered", found.len()),
+        false => println!("Recovered {} files to {}", written, dest.display()),
+    }
+    0
+}
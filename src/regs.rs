@@ -0,0 +1,47 @@
+use crate::halt;
+use crate::kernel;
+use crate::{Piece, Result};
+use piecer::flash::FLASH_BASE;
+use piecer::memmap::{IRAM_BASE, IRAM_SIZE};
+
+/// What a word on the stack points into, if it could be a return address:
+/// the running app's code or the kernel's.
+fn place(word: u32, app: Option<(&str, u32, u32)>, kernel_end: u32) -> Option<String> {
+    // S1C33 instructions are 16 bits, so return addresses are even.
+    if !word.is_multiple_of(2) {
+        return None;
+    }
+    if let Some((name, base, _)) = app.filter(|&(_, base, len)| (base..base + len).contains(&word)) {
+        return Some(format!("{}+{:#x}", name, word - base));
+    }
+    (FLASH_BASE..kernel_end).contains(&word).then(|| format!("kernel+{:#x}", word - FLASH_BASE))
+}
+
+/// Print what can be seen of the CPU while the app is stopped. The kernel
+/// has no command to read registers, so that is said plainly, and the
+/// stack in internal RAM is scanned from the top for words pointing into
+/// code: a best-effort backtrace, which can include stale frames and
+/// misses frames that kept nothing on the stack. At most `depth` frames
+/// are shown.
+pub fn show(piece: &mut Piece, depth: usize) -> Result<()> {
+    piece.pause()?;
+    let app = halt::running_app(piece)?;
+    let stack = piece.read_memory(IRAM_BASE, IRAM_SIZE)?;
+    piece.resume_unless_halted()?;
+    println!("registers\tnot readable over USB with kernel {}", kernel::version_string(piece.kernel_version));
+    let app = app.as_ref().map(|(name, header)| (name.as_str(), header.load_addr, header.image_len));
+    let frames: Vec<(u32, u32, String)> = stack.chunks_exact(4).enumerate().rev()
+        .filter_map(|(i, word)| {
+            let word = u32::from_le_bytes(word.try_into().unwrap());
+            Some((IRAM_BASE + i as u32 * 4, word, place(word, app, piece.pffs_top)?))
+        })
+        .take(depth)
+        .collect();
+    if frames.is_empty() {
+        println!("No return addresses found on the stack");
+    }
+    for (i, (addr, word, place)) in frames.iter().enumerate() {
+        println!("#{:<3} {:#010x} in {}\t(stack {:#06x})", i, word, place, addr);
+    }
+    Ok(())
+}
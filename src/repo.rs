@@ -0,0 +1,110 @@
+use crate::date;
+use crate::deflate;
+use crate::names;
+use crate::sha256;
+use crate::{Piece, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// A repository holds content-addressed 4096-byte chunks under objects/ and
+// one manifest per snapshot under snapshots/. Each manifest line is
+// `name<TAB>len<TAB>chunk,chunk,...`. Chunks may be stored deflated, with a
+// `.z` suffix; the hash is always of the uncompressed data.
+const CHUNK_SIZE: usize = 4096;
+
+fn object_path(repo: &Path, hash: &str) -> PathBuf {
+    repo.join("objects").join(&hash[..2]).join(hash)
+}
+
+pub fn backup(piece: &mut Piece, repo: &Path, compress: bool) -> Result<()> {
+    let (y, mo, d, h, mi, s) = date::civil(date::now_local());
+    let snapshot = format!("{:04}-{:02}-{:02}T{:02}{:02}{:02}", y, mo, d, h, mi, s);
+    fs::create_dir_all(repo.join("snapshots")).expect("Could not create repository");
+    let mut manifest = String::new();
+    let mut new_chunks = 0;
+    let directory = piece.ls()?;
+    for dirent in &directory {
+        println!("{}", dirent.name);
+        let data = piece.read_file(&dirent.name)?;
+        let mut hashes = Vec::new();
+        for chunk in data.chunks(CHUNK_SIZE) {
+            let hash = sha256::hex(chunk);
+            let path = object_path(repo, &hash);
+            let compressed_path = path.with_extension("z");
+            if !path.exists() && !compressed_path.exists() {
+                fs::create_dir_all(path.parent().unwrap()).unwrap();
+                let deflated = compress.then(|| deflate::compress(chunk)).filter(|d| d.len() < chunk.len());
+                match deflated {
+                    Some(deflated) => fs::write(&compressed_path, deflated),
+                    None => fs::write(&path, chunk),
+                }.expect("Could not write chunk");
+                new_chunks += 1;
+            }
+            hashes.push(hash);
+        }
+        manifest += &format!("{}\t{}\t{}\n", dirent.name, data.len(), hashes.join(","));
+    }
+    fs::write(repo.join("snapshots").join(&snapshot), manifest).expect("Could not write snapshot");
+    println!("Snapshot {}: {} files, {} new chunks", snapshot, directory.len(), new_chunks);
+    Ok(())
+}
+
+struct Entry {
+    name: String,
+    len: usize,
+    chunks: Vec<String>,
+}
+
+fn read_snapshot(repo: &Path, snapshot: &str) -> Vec<Entry> {
+    let manifest = fs::read_to_string(repo.join("snapshots").join(snapshot)).expect("Could not read snapshot");
+    manifest.lines().map(|line| {
+        let mut fields = line.split('\t');
+        let name = fields.next().unwrap().to_string();
+        let len = fields.next().and_then(|len| len.parse().ok()).expect("Corrupt snapshot manifest");
+        let chunks = fields.next().unwrap_or("").split(',').filter(|c| !c.is_empty()).map(String::from).collect();
+        Entry { name, len, chunks }
+    }).collect()
+}
+
+pub fn list(repo: &Path) {
+    let mut snapshots: Vec<String> = fs::read_dir(repo.join("snapshots")).expect("Could not read repository")
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned()).collect();
+    snapshots.sort();
+    for snapshot in snapshots {
+        let entries = read_snapshot(repo, &snapshot);
+        let bytes: usize = entries.iter().map(|e| e.len).sum();
+        println!("{}\t{} files\t{} bytes", snapshot, entries.len(), bytes);
+    }
+}
+
+fn entry_data(repo: &Path, entry: &Entry) -> Vec<u8> {
+    let mut data = Vec::with_capacity(entry.len);
+    for hash in &entry.chunks {
+        let path = object_path(repo, hash);
+        let chunk = match fs::read(path.with_extension("z")) {
+            Ok(deflated) => deflate::decompress(&deflated).expect("Corrupt chunk in repository"),
+            Err(_) => fs::read(path).expect("Missing chunk in repository"),
+        };
+        assert_eq!(sha256::hex(&chunk), *hash, "Corrupt chunk in repository");
+        data.extend(chunk);
+    }
+    data
+}
+
+pub fn checkout(repo: &Path, snapshot: &str, dest: &Path) {
+    fs::create_dir_all(dest).expect("Could not create destination directory");
+    let entries = read_snapshot(repo, snapshot);
+    let hosts = names::hosts(entries.iter().map(|entry| entry.name.as_str()));
+    for (entry, host) in entries.iter().zip(hosts) {
+        println!("{}", entry.name);
+        fs::write(dest.join(host), entry_data(repo, entry)).unwrap();
+    }
+}
+
+/// Contents of one file from the snapshot whose manifest is at `manifest`.
+pub fn read_file(manifest: &Path, name: &str) -> Option<Vec<u8>> {
+    let repo = manifest.parent()?.parent()?;
+    let snapshot = manifest.file_name()?.to_str()?;
+    let entry = read_snapshot(repo, snapshot).into_iter().find(|entry| entry.name == name)?;
+    Some(entry_data(repo, &entry))
+}
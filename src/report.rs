@@ -0,0 +1,70 @@
+use crate::base64;
+use crate::date;
+use crate::kernel;
+use crate::png;
+use crate::screen;
+use crate::sha256;
+use crate::{Piece, Result, LCD_HEIGHT, LCD_WIDTH};
+use std::fs;
+use std::path::Path;
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+/// Write a self-contained HTML page cataloguing the device: its details, a
+/// screenshot, and every file with its size, type and SHA-256.
+pub fn write(piece: &mut Piece, path: &Path) -> Result<()> {
+    let frame = piece.capture()?;
+    let gray = screen::gray(&frame);
+    let screenshot = base64::encode(&png::encode_gray(LCD_WIDTH as u32, LCD_HEIGHT as u32, &gray));
+    let battery = piece.battery_mv()?;
+    let directory = piece.ls()?;
+    let mut rows = String::new();
+    for dirent in &directory {
+        let kind = piece.file_kind(dirent)?;
+        let hash = match dirent.problem {
+            Some(problem) => escape(problem),
+            None => sha256::hex(&piece.read_file(&dirent.name)?),
+        };
+        rows += &format!("<tr><td>{}</td><td class=n>{}</td><td>{}</td><td><code>{}</code></td></tr>\n",
+                         escape(&dirent.name), dirent.len, kind.label(), hash);
+    }
+    let html = format!(r#"<!DOCTYPE html>
+<html><head><meta charset="utf-8"><title>P/ECE report</title>
+<style>
+body {{ font-family: sans-serif; margin: 2em; }}
+table {{ border-collapse: collapse; }}
+td, th {{ border: 1px solid #ccc; padding: 0.2em 0.6em; text-align: left; }}
+td.n {{ text-align: right; }}
+img {{ image-rendering: pixelated; width: 384px; border: 1px solid #888; }}
+</style></head><body>
+<h1>P/ECE report</h1>
+<p>Generated {generated} by piecer {version}</p>
+<h2>Device</h2>
+<table>
+<tr><th>Kernel</th><td>{kernel}</td></tr>
+<tr><th>Battery</th><td>{battery_v}.{battery_mv:03} V</td></tr>
+<tr><th>Application RAM</th><td>from {sram_top:#x}</td></tr>
+<tr><th>Filesystem</th><td>at {pffs_top:#x}, {files} files</td></tr>
+</table>
+<h2>Screen</h2>
+<img alt="screenshot" src="data:image/png;base64,{screenshot}">
+<h2>Files</h2>
+<table>
+<tr><th>Name</th><th>Size</th><th>Type</th><th>SHA-256</th></tr>
+{rows}</table>
+</body></html>
+"#,
+        generated = date::format(date::now_local()),
+        version = env!("CARGO_PKG_VERSION"),
+        kernel = kernel::version_string(piece.kernel_version),
+        battery_v = battery / 1000,
+        battery_mv = battery % 1000,
+        sram_top = piece.sram_top,
+        pffs_top = piece.pffs_top,
+        files = directory.len(),
+    );
+    fs::write(path, html).expect("Could not write report");
+    Ok(())
+}
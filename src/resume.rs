@@ -0,0 +1,42 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Record of completed steps of a long operation, so a rerun with `--resume`
+/// can skip them. Steps are appended one per line as they finish, so the file
+/// stays valid if piecer is interrupted at any point.
+pub struct State {
+    path: PathBuf,
+    done: Vec<String>,
+    file: File,
+}
+
+impl State {
+    /// Open the state file at `path`, discarding earlier progress unless `resume`.
+    pub fn open(path: &Path, resume: bool) -> State {
+        let done = match resume {
+            true => fs::read_to_string(path).unwrap_or_default().lines().map(String::from).collect(),
+            false => Vec::new(),
+        };
+        let file = OpenOptions::new().create(true).append(true).open(path).expect("Could not open resume state file");
+        if !resume {
+            file.set_len(0).unwrap();
+        }
+        State { path: path.to_path_buf(), done, file }
+    }
+    pub fn is_done(&self, step: &str) -> bool {
+        self.done.iter().any(|done| done == step)
+    }
+    pub fn done(&self) -> impl Iterator<Item = &str> {
+        self.done.iter().map(String::as_str)
+    }
+    pub fn mark_done(&mut self, step: &str) {
+        writeln!(self.file, "{}", step).unwrap();
+        self.file.sync_data().unwrap();
+        self.done.push(step.to_string());
+    }
+    /// The operation completed; the state file is no longer needed.
+    pub fn finish(self) {
+        fs::remove_file(&self.path).unwrap();
+    }
+}
@@ -0,0 +1,111 @@
+use crate::date;
+use crate::dirs;
+use crate::{Piece, PieceError, Result};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+
+// S1C33209 clock timer: run/stop at TCRUN, then seconds, minutes, hours and
+// a 16-bit day counter starting at TCMD.
+const TCRUN: u32 = 0x40151;
+const TCMD: u32 = 0x40154;
+
+// The kernel counts days from 2000-01-01.
+const EPOCH_DAYS: i64 = 10957;
+
+/// The raw clock registers: seconds, minutes, hours and the day counter.
+pub fn registers(piece: &mut Piece) -> Result<[u8; 5]> {
+    let mut regs = [0; 5];
+    piece.get_memory(TCMD, 5, &mut regs)?;
+    Ok(regs)
+}
+
+/// Device wall-clock time, in seconds since the Unix epoch.
+pub fn get(piece: &mut Piece) -> Result<i64> {
+    let regs = registers(piece)?;
+    let days = u16::from_le_bytes([regs[3], regs[4]]) as i64;
+    Ok((EPOCH_DAYS + days) * 86400 + regs[2] as i64 * 3600 + regs[1] as i64 * 60 + regs[0] as i64)
+}
+
+/// Set the device clock to `time`, in seconds since the Unix epoch.
+pub fn set(piece: &mut Piece, time: i64) -> Result<()> {
+    let days = u16::try_from(time.div_euclid(86400) - EPOCH_DAYS).map_err(|_| PieceError::ClockRange(time))?;
+    let secs = time.rem_euclid(86400);
+    piece.set_memory(TCRUN, &[0b10])?;
+    piece.set_memory(TCMD, &[(secs % 60) as u8, (secs / 60 % 60) as u8, (secs / 3600) as u8,
+                             days as u8, (days >> 8) as u8])?;
+    piece.set_memory(TCRUN, &[0b01])?;
+    Ok(())
+}
+
+/// Print the device time next to the host's.
+pub fn show(piece: &mut Piece) -> Result<()> {
+    let device = get(piece)?;
+    let host = date::now_local();
+    println!("device  {}", date::format(device));
+    println!("host    {}", date::format(host));
+    println!("offset  {:+} s", device - host);
+    Ok(())
+}
+
+/// Set the device clock from the host and report drift since the last sync.
+///
+/// Each sync appends `<host time> <offset>` to a log in the state directory,
+/// one per device serial number, so drift is measured against the device's
+/// previous entry.
+pub fn sync(piece: &mut Piece) -> Result<()> {
+    let host = date::now_local();
+    let offset = get(piece)? - host;
+    println!("Clock offset: {:+} s", offset);
+    let log_path = dirs::state_dir().join(log_name(piece.serial.as_deref()));
+    let last_sync = fs::read_to_string(&log_path).ok().and_then(|log| {
+        log.lines().last()?.split_whitespace().next()?.parse::<i64>().ok()
+    });
+    if let Some(last_sync) = last_sync {
+        let elapsed = host - last_sync;
+        if elapsed > 0 {
+            println!("Drift: {:+.2} s/day over {:.1} days",
+                     offset as f64 * 86400.0 / elapsed as f64, elapsed as f64 / 86400.0);
+        }
+    }
+    set(piece, host)?;
+    // The clock wasn't set, so this isn't a sync to measure drift from.
+    if piece.dry_run() {
+        return Ok(());
+    }
+    let state_dir = dirs::state_dir();
+    fs::create_dir_all(&state_dir).map_err(PieceError::host_io(format!("Could not create {}", state_dir.display())))?;
+    let unwritten = || PieceError::host_io(format!("Could not write {}", log_path.display()));
+    let mut log = OpenOptions::new().create(true).append(true).open(&log_path).map_err(unwritten())?;
+    writeln!(log, "{} {}", host, offset).map_err(unwritten())
+}
+
+/// The drift log for the device with `serial`. Devices without one share a
+/// log, as they can't be told apart.
+fn log_name(serial: Option<&str>) -> String {
+    match serial {
+        Some(serial) => {
+            let serial: String = serial.chars().map(|c| if c.is_ascii_alphanumeric() || "-_.".contains(c) { c } else { '_' }).collect();
+            format!("clock-sync-{}.log", serial)
+        }
+        None => "clock-sync.log".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn day_counter_range() {
+        assert_eq!(date::parse("2000-01-01 00:00"), Some(EPOCH_DAYS * 86400));
+        // What the ClockRange message gives as the last day.
+        assert_eq!(date::format_date((EPOCH_DAYS + u16::MAX as i64) * 86400), "2179-06-06");
+    }
+
+    #[test]
+    fn drift_logs_are_per_device() {
+        assert_eq!(log_name(Some("1234")), "clock-sync-1234.log");
+        assert_eq!(log_name(Some("../a b")), "clock-sync-.._a_b.log");
+        assert_eq!(log_name(None), "clock-sync.log");
+    }
+}
@@ -0,0 +1,51 @@
+use crate::backup;
+use crate::glob;
+use crate::names;
+use crate::{Piece, PieceError, Result};
+use piecer::config;
+use std::fs;
+use std::path::Path;
+
+/// What save files are called unless `patterns` in the [saves] section of
+/// the config says otherwise, as a comma-separated list.
+const DEFAULT_PATTERNS: &str = "*.sav,*.sv,save*,*save.dat";
+
+/// The wildcard patterns that pick out save files: `given`, or else those
+/// from the config or the defaults.
+fn patterns(given: &[String]) -> Result<Vec<String>> {
+    if !given.is_empty() {
+        return Ok(given.to_vec());
+    }
+    let config = config::load()?;
+    Ok(config.get("saves.patterns").unwrap_or(DEFAULT_PATTERNS)
+        .split(',').map(|pattern| pattern.trim().to_string()).filter(|pattern| !pattern.is_empty())
+        .collect())
+}
+
+/// Download every file matching the save patterns, ignoring case, into
+/// `dir`.
+pub fn backup(piece: &mut Piece, dir: &Path, given: &[String]) -> Result<()> {
+    let patterns: Vec<String> = patterns(given)?.iter().map(|pattern| pattern.to_lowercase()).collect();
+    let directory = piece.ls()?;
+    let saves: Vec<_> = directory.iter()
+        .filter(|dirent| patterns.iter().any(|pattern| glob::matches(pattern, &dirent.name.to_lowercase())))
+        .collect();
+    if saves.is_empty() {
+        println!("No save files on the device (looked for {})", patterns.join(", "));
+        return Ok(());
+    }
+    fs::create_dir_all(dir).map_err(PieceError::host_io(format!("Could not create {}", dir.display())))?;
+    let fat = piece.read_fat()?;
+    let hosts = names::hosts(saves.iter().map(|dirent| dirent.name.as_str()));
+    for (dirent, host) in saves.into_iter().zip(hosts) {
+        piece.download_entry(dirent, &fat, &dir.join(host), false)?;
+        println!("{}\t{}", dirent.name, dirent.len);
+    }
+    Ok(())
+}
+
+/// Upload the saves in `dir` that differ from the device's. Returns the exit
+/// code, as [`backup::restore_dir`] does.
+pub fn restore(piece: &mut Piece, dir: &Path, force: bool) -> Result<i32> {
+    backup::restore_dir(piece, dir, force, false)
+}
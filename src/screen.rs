@@ -0,0 +1,467 @@
+use crate::base64;
+use crate::bmp;
+use crate::crc32::crc32;
+use crate::gif;
+use crate::png;
+use crate::term::{Key, Screen};
+use clap::ValueEnum;
+use crate::{Piece, PieceError, Result, LCD_HEIGHT, LCD_WIDTH};
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Copy, Default, ValueEnum)]
+pub enum Format {
+    /// In the terminal, drawn as --render says
+    #[default]
+    Terminal,
+    /// C source with the pixels packed four to a byte
+    CArray,
+    /// Rust source with the pixels packed four to a byte
+    RsArray,
+}
+
+/// How the display is drawn in a terminal.
+#[derive(Clone, Copy, Default, ValueEnum)]
+pub enum Render {
+    /// Shaded block characters, one per pixel
+    #[default]
+    Blocks,
+    /// Half blocks in 24-bit color, two pixels to a character
+    HalfBlocks,
+    /// Braille dots, eight pixels to a character, dithered to black and white
+    Braille,
+    /// Sixel graphics (xterm -ti vt340, foot, WezTerm, mlterm)
+    Sixel,
+    /// The kitty graphics protocol (kitty, WezTerm, Konsole, Ghostty)
+    Kitty,
+}
+
+impl Render {
+    /// The renderer, drawing with the palette from [`set_palette`].
+    pub fn renderer(self) -> Box<dyn Renderer> {
+        let palette = palette().clone();
+        match self {
+            Render::Blocks => Box::new(Blocks { palette }),
+            Render::HalfBlocks => Box::new(HalfBlocks { palette }),
+            Render::Braille => Box::new(Braille { palette }),
+            Render::Sixel => Box::new(Sixel { scale: SIXEL_SCALE, palette }),
+            Render::Kitty => Box::new(Kitty { columns: LCD_WIDTH / 2, palette }),
+        }
+    }
+}
+
+/// How the four levels are drawn in the terminal.
+#[derive(Clone)]
+pub struct Palette {
+    /// Characters for `blocks`, from black to white.
+    pub chars: [char; 4],
+    /// Colors for the other renderers, from black to white.
+    pub colors: [[u8; 3]; 4],
+    /// Swap black with white and dark with light gray, for light terminals.
+    pub invert: bool,
+    /// Times over each pixel is drawn, across and down.
+    pub scale: usize,
+}
+
+impl Default for Palette {
+    fn default() -> Palette {
+        Palette { chars: ['▓', '▒', '░', ' '], colors: [[0; 3], [85; 3], [170; 3], [255; 3]], invert: false, scale: 1 }
+    }
+}
+
+impl Palette {
+    /// The level `p` is drawn as.
+    fn level(&self, p: u8) -> u8 {
+        match self.invert {
+            true => 3 - p.min(3),
+            false => p.min(3),
+        }
+    }
+    fn rgb(&self, p: u8) -> [u8; 3] {
+        self.colors[self.level(p) as usize]
+    }
+    /// `frame` as the levels drawn, each pixel repeated `scale` times in
+    /// both directions, with its width and height.
+    fn apply(&self, frame: &[u8]) -> (Vec<u8>, usize, usize) {
+        let (width, height) = (LCD_WIDTH * self.scale, LCD_HEIGHT * self.scale);
+        let levels = (0..width * height)
+            .map(|i| self.level(frame[i / width / self.scale * LCD_WIDTH + i % width / self.scale]))
+            .collect();
+        (levels, width, height)
+    }
+}
+
+/// Parse `--palette-chars`: four characters, from black to white.
+pub fn parse_chars(s: &str) -> std::result::Result<[char; 4], String> {
+    let chars: Vec<char> = s.chars().collect();
+    chars.try_into().map_err(|_| format!("expected four characters from black to white, got {:?}", s))
+}
+
+/// Parse `--palette-colors`: four comma-separated hex colors such as
+/// `#1d2b1f`, from black to white.
+pub fn parse_colors(s: &str) -> std::result::Result<[[u8; 3]; 4], String> {
+    let color = |color: &str| {
+        let hex = color.trim().trim_start_matches('#');
+        let value = u32::from_str_radix(hex, 16).ok().filter(|_| hex.len() == 6)?;
+        Some([(value >> 16) as u8, (value >> 8) as u8, value as u8])
+    };
+    let colors: Option<Vec<[u8; 3]>> = s.split(',').map(color).collect();
+    colors.and_then(|colors| colors.try_into().ok())
+        .ok_or_else(|| format!("expected four colors like #000000,#555555,#aaaaaa,#ffffff, got {:?}", s))
+}
+
+static PALETTE: OnceLock<Palette> = OnceLock::new();
+
+/// Draw with `palette` instead of the default grays.
+pub fn set_palette(palette: Palette) {
+    PALETTE.set(palette).ok();
+}
+
+fn palette() -> &'static Palette {
+    PALETTE.get_or_init(Palette::default)
+}
+
+/// Turns a frame, one byte per pixel from 0 (black) to 3 (white), into
+/// text for the terminal.
+pub trait Renderer {
+    fn render(&self, frame: &[u8]) -> String;
+}
+
+pub struct Blocks {
+    pub palette: Palette,
+}
+
+impl Renderer for Blocks {
+    fn render(&self, frame: &[u8]) -> String {
+        let (levels, width, _) = self.palette.apply(frame);
+        let mut out = String::new();
+        for line in levels.chunks(width) {
+            out.extend(line.iter().map(|&level| self.palette.chars[level as usize]));
+            out.push('\n');
+        }
+        out
+    }
+}
+
+/// Half-block characters in 24-bit color, two pixel rows per line, so the
+/// whole display fits in 44 lines.
+pub struct HalfBlocks {
+    pub palette: Palette,
+}
+
+impl Renderer for HalfBlocks {
+    fn render(&self, frame: &[u8]) -> String {
+        let (levels, width, _) = self.palette.apply(frame);
+        let colors = self.palette.colors;
+        let mut out = String::new();
+        for rows in levels.chunks(width * 2) {
+            let (upper, lower) = rows.split_at(width);
+            for (&top, &bottom) in upper.iter().zip(lower) {
+                let ([r, g, b], [br, bg, bb]) = (colors[top as usize], colors[bottom as usize]);
+                out += &format!("\x1b[38;2;{};{};{}m\x1b[48;2;{};{};{}m▀", r, g, b, br, bg, bb);
+            }
+            out += "\x1b[0m\n";
+        }
+        out
+    }
+}
+
+/// Braille cells of 2x4 pixels, a dot for each dark one, so the display
+/// takes 64x22 characters. The two middle levels are dithered.
+pub struct Braille {
+    pub palette: Palette,
+}
+
+impl Renderer for Braille {
+    fn render(&self, frame: &[u8]) -> String {
+        // Dot bits of each cell position, by row then column.
+        const DOTS: [[u32; 2]; 4] = [[0x01, 0x08], [0x02, 0x10], [0x04, 0x20], [0x40, 0x80]];
+        // A pixel gets a dot where its level is under the threshold for its
+        // position: always for black, 3 in 4 for dark gray, 1 in 4 for light.
+        const THRESHOLDS: [[u8; 2]; 2] = [[1, 3], [2, 2]];
+        let (levels, width, height) = self.palette.apply(frame);
+        let mut out = String::new();
+        for y in (0..height).step_by(4) {
+            for x in (0..width).step_by(2) {
+                let mut cell = 0;
+                for (dy, row) in DOTS.iter().enumerate() {
+                    for (dx, bit) in row.iter().enumerate() {
+                        let (px, py) = (x + dx, y + dy);
+                        if py < height && levels[py * width + px] < THRESHOLDS[py % 2][px % 2] {
+                            cell |= bit;
+                        }
+                    }
+                }
+                out.push(char::from_u32(0x2800 + cell).unwrap());
+            }
+            out.push('\n');
+        }
+        out
+    }
+}
+
+/// Magnification of sixel output, which is drawn in screen pixels.
+const SIXEL_SCALE: usize = 3;
+
+/// Sixel graphics with the four levels as palette entries, each pixel
+/// drawn `scale` screen pixels square, times the palette's scale.
+pub struct Sixel {
+    pub scale: usize,
+    pub palette: Palette,
+}
+
+impl Renderer for Sixel {
+    fn render(&self, frame: &[u8]) -> String {
+        let (levels, columns, rows) = self.palette.apply(frame);
+        let (width, height) = (columns * self.scale, rows * self.scale);
+        let level = |x: usize, y: usize| levels[y / self.scale * columns + x / self.scale];
+        let mut out = format!("\x1bPq\"1;1;{};{}", width, height);
+        for (color, rgb) in self.palette.colors.iter().enumerate() {
+            let [r, g, b] = rgb.map(|channel| channel as u32 * 100 / 255);
+            out += &format!("#{};2;{};{};{}", color, r, g, b);
+        }
+        for band in (0..height).step_by(6) {
+            for color in 0..4 {
+                let sixels: Vec<u8> = (0..width)
+                    .map(|x| (0..6).filter(|&dy| band + dy < height && level(x, band + dy) == color)
+                        .fold(0, |bits, dy| bits | 1 << dy))
+                    .collect();
+                if sixels.iter().all(|&bits| bits == 0) {
+                    continue;
+                }
+                out += &format!("#{}", color);
+                for run in sixels.chunk_by(|a, b| a == b) {
+                    let c = (63 + run[0]) as char;
+                    match run.len() {
+                        1..=3 => out.extend(std::iter::repeat_n(c, run.len())),
+                        n => out += &format!("!{}{}", n, c),
+                    }
+                }
+                out.push('$');
+            }
+            out.push('-');
+        }
+        out + "\x1b\\\n"
+    }
+}
+
+/// Bytes of base64 sent per kitty graphics escape, the protocol's limit.
+const KITTY_CHUNK: usize = 4096;
+
+/// An RGB image in kitty graphics escapes, scaled by the terminal to
+/// `columns` cells wide, times the palette's scale. Each frame replaces the
+/// last, so `watch` doesn't pile up images.
+pub struct Kitty {
+    pub columns: usize,
+    pub palette: Palette,
+}
+
+impl Renderer for Kitty {
+    fn render(&self, frame: &[u8]) -> String {
+        let rgb: Vec<u8> = frame.iter().flat_map(|&p| self.palette.rgb(p)).collect();
+        let data = base64::encode(&rgb);
+        let chunks: Vec<&[u8]> = data.as_bytes().chunks(KITTY_CHUNK).collect();
+        let mut out = String::new();
+        for (i, chunk) in chunks.iter().enumerate() {
+            let more = (i + 1 < chunks.len()) as u8;
+            let chunk = std::str::from_utf8(chunk).unwrap();
+            match i {
+                0 => out += &format!("\x1b_Ga=T,i=1,q=2,f=24,s={},v={},c={},m={};{}\x1b\\",
+                                     LCD_WIDTH, LCD_HEIGHT, self.columns * self.palette.scale, more, chunk),
+                _ => out += &format!("\x1b_Gm={};{}\x1b\\", more, chunk),
+            }
+        }
+        out + "\n"
+    }
+}
+
+/// `frame` (one byte per pixel) in `format`, drawn in `style` for the
+/// terminal. The arrays keep the device's levels, 0 black to 3 white, with
+/// the leftmost pixel in the high bits.
+pub fn render(frame: &[u8], format: Format, style: Render) -> String {
+    let mut out = String::new();
+    if let Format::Terminal = format {
+        return style.renderer().render(frame);
+    }
+    let packed: Vec<u8> = frame.chunks(4)
+        .map(|group| group.iter().enumerate().fold(0, |byte, (i, &p)| byte | (p & 3) << (6 - 2 * i)))
+        .collect();
+    match format {
+        Format::CArray => {
+            out += &format!("#define SCREENSHOT_WIDTH {}\n#define SCREENSHOT_HEIGHT {}\n\n", LCD_WIDTH, LCD_HEIGHT);
+            out += &format!("const unsigned char screenshot[{}] = {{\n", packed.len());
+        }
+        _ => {
+            out += &format!("pub const SCREENSHOT_WIDTH: usize = {};\npub const SCREENSHOT_HEIGHT: usize = {};\n\n", LCD_WIDTH, LCD_HEIGHT);
+            out += &format!("pub static SCREENSHOT: [u8; {}] = [\n", packed.len());
+        }
+    }
+    for row in packed.chunks(LCD_WIDTH / 4) {
+        out += "   ";
+        for byte in row {
+            out += &format!(" 0x{:02x},", byte);
+        }
+        out.push('\n');
+    }
+    out += match format {
+        Format::CArray => "};\n",
+        _ => "];\n",
+    };
+    out
+}
+
+/// `frame` as 8-bit grayscale, spreading the four levels evenly.
+pub fn gray(frame: &[u8]) -> Vec<u8> {
+    frame.iter().map(|&level| level.min(3) * 85).collect()
+}
+
+/// Write `frame` to `path` as a PNG, or a BMP if the name ends in `.bmp`.
+pub fn save(frame: &[u8], path: &Path) {
+    let gray = gray(frame);
+    let image = match path.extension().and_then(|extension| extension.to_str()) {
+        Some(extension) if extension.eq_ignore_ascii_case("bmp") => {
+            bmp::encode_gray(LCD_WIDTH as u32, LCD_HEIGHT as u32, &gray)
+        }
+        _ => png::encode_gray(LCD_WIDTH as u32, LCD_HEIGHT as u32, &gray),
+    };
+    fs::write(path, image).expect("Could not write screenshot");
+}
+
+/// Mirror the display in the terminal at up to `fps` frames per second until
+/// q is pressed. Frames are read without pausing the app, so a frame drawn
+/// mid-update can tear.
+pub fn mirror(piece: &mut Piece, fps: f64, renderer: &dyn Renderer) -> Result<()> {
+    assert!(fps > 0.0, "--fps must be positive");
+    let screen = Screen::new();
+    let interval = Duration::from_secs_f64(1.0 / fps);
+    let mut frame = vec![0; LCD_WIDTH * LCD_HEIGHT];
+    loop {
+        let start = Instant::now();
+        let addr = piece.framebuffer_addr()?;
+        piece.get_memory(addr, frame.len() as u32, &mut frame)?;
+        screen.draw(&(renderer.render(&frame) + "q quit"));
+        let wait = interval.saturating_sub(start.elapsed()).max(Duration::from_millis(1));
+        if let Some(Key::Char('q') | Key::Esc) = screen.key(wait) {
+            return Ok(());
+        }
+    }
+}
+
+/// Record the display for `duration` as a looping GIF at `output`. Frames
+/// are read back to back without pausing the app, and a frame is only
+/// stored when the display changed, shown until the next change.
+pub fn record(piece: &mut Piece, duration: Duration, output: &Path) -> Result<()> {
+    let palette = [[0, 0, 0], [85, 85, 85], [170, 170, 170], [255, 255, 255]];
+    let mut animation = gif::Animation::new(LCD_WIDTH as u16, LCD_HEIGHT as u16, &palette);
+    let centiseconds = |elapsed: Duration| (elapsed.as_millis() / 10) as u64;
+    // The frame on screen since `shown`, in hundredths of a second from the
+    // start, so rounding doesn't accumulate across frames.
+    let mut current: Option<(Vec<u8>, u64)> = None;
+    let mut frame = vec![0; LCD_WIDTH * LCD_HEIGHT];
+    let (mut captured, mut stored) = (0, 0);
+    let start = Instant::now();
+    while start.elapsed() < duration {
+        let addr = piece.framebuffer_addr()?;
+        piece.get_memory(addr, frame.len() as u32, &mut frame)?;
+        captured += 1;
+        let now = centiseconds(start.elapsed());
+        match &current {
+            Some((previous, _)) if *previous == frame => continue,
+            // Viewers stretch delays under 2cs, so fold faster changes into
+            // the frame already on screen.
+            Some((_, shown)) if now < shown + 2 => continue,
+            Some((previous, shown)) => {
+                animation.frame(previous, (now - shown).min(u16::MAX as u64) as u16);
+                stored += 1;
+            }
+            None => {}
+        }
+        current = Some((frame.clone(), now));
+    }
+    if let Some((last, shown)) = current {
+        let end = centiseconds(duration).max(shown + 2);
+        animation.frame(&last, (end - shown).min(u16::MAX as u64) as u16);
+        stored += 1;
+    }
+    fs::write(output, animation.finish()).expect("Could not write recording");
+    println!("Recorded {} frames ({} captured) to {}", stored, captured, output.display());
+    Ok(())
+}
+
+/// Save each new screen the app shows as a numbered PNG in `dir`, checking
+/// every `interval` until Ctrl-C or `count` have been saved. Frames are read
+/// without pausing the app, and one is saved once it reads the same twice
+/// running, so those drawn mid-update and passing animation steps are
+/// skipped. A screen already saved isn't saved again.
+pub fn shots(piece: &mut Piece, dir: &Path, interval: Duration, count: Option<usize>) -> Result<()> {
+    fs::create_dir_all(dir).expect("Could not create screenshot directory");
+    let mut saved = HashSet::new();
+    let mut previous = None;
+    let mut number = 0;
+    let mut frame = vec![0; LCD_WIDTH * LCD_HEIGHT];
+    println!("Saving new screens to {}; press Ctrl-C to stop", dir.display());
+    while count.is_none_or(|count| saved.len() < count) {
+        let read = piece.framebuffer_addr().and_then(|addr| piece.get_memory(addr, frame.len() as u32, &mut frame));
+        match read {
+            Err(PieceError::Cancelled) => break,
+            result => result?,
+        }
+        let hash = crc32(&frame);
+        if previous == Some(hash) && saved.insert(hash) {
+            // Numbered past any shots already there.
+            let path = loop {
+                number += 1;
+                let path = dir.join(format!("shot-{:04}.png", number));
+                if !path.exists() {
+                    break path;
+                }
+            };
+            save(&frame, &path);
+            println!("{}", path.display());
+        }
+        previous = Some(hash);
+        match piece.idle(interval) {
+            Err(PieceError::Cancelled) => break,
+            result => result?,
+        }
+    }
+    println!("Saved {} screens", saved.len());
+    Ok(())
+}
+
+/// How far apart two gray levels may be and still count as the same pixel.
+/// The LCD only has four levels, 85 apart.
+const LEVEL_SLACK: u8 = 42;
+
+/// Capture the display until it matches the PNG at `reference`, allowing
+/// `tolerance` percent of pixels to differ. Returns the exit code: 0 on a
+/// match, 1 on timeout.
+pub fn assert(piece: &mut Piece, reference: &Path, timeout: Duration, tolerance: f64) -> Result<i32> {
+    let data = fs::read(reference).expect("Could not read reference image");
+    let expected = png::decode_gray(&data).expect("Reference is not a supported PNG");
+    assert!(expected.width as usize == LCD_WIDTH && expected.height as usize == LCD_HEIGHT,
+            "Reference is {}x{}, but the display is {}x{}", expected.width, expected.height, LCD_WIDTH, LCD_HEIGHT);
+    let allowed = (tolerance / 100.0 * expected.pixels.len() as f64) as usize;
+    let start = Instant::now();
+    let mut best = usize::MAX;
+    loop {
+        let frame = piece.capture()?;
+        let differing = frame.iter().zip(&expected.pixels)
+            .filter(|&(&level, &gray)| (level * 85).abs_diff(gray) > LEVEL_SLACK)
+            .count();
+        if differing <= allowed {
+            println!("Display matches {} after {:.1}s", reference.display(), start.elapsed().as_secs_f64());
+            return Ok(0);
+        }
+        best = best.min(differing);
+        if start.elapsed() >= timeout {
+            eprintln!("Display did not match {} within {:?}; closest frame had {} differing pixels ({} allowed)",
+                      reference.display(), timeout, best, allowed);
+            return Ok(1);
+        }
+        piece.idle(Duration::from_millis(100))?;
+    }
+}
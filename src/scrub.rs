@@ -0,0 +1,55 @@
+use crate::dirs;
+use crate::sha256;
+use crate::{Piece, Result};
+use std::fs;
+
+/// Read every file, compare its SHA-256 with the previous scrub of this
+/// device, and record the new results. A file whose contents changed while
+/// its size stayed the same is reported as suspect, since apps rewriting a
+/// file usually also change its size, but decaying flash doesn't.
+///
+/// Returns the exit code: 1 if any file is suspect.
+pub fn run(piece: &mut Piece) -> Result<i32> {
+    let dir = dirs::state_dir().join("scrub");
+    fs::create_dir_all(&dir).expect("Could not create state directory");
+    let device: String = piece.serial.as_deref().unwrap_or("device").chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' { c } else { '_' })
+        .collect();
+    let path = dir.join(format!("{}.txt", device));
+    let previous = fs::read_to_string(&path).unwrap_or_default();
+    let previous: Vec<(&str, &str, &str)> = previous.lines()
+        .filter_map(|line| {
+            let mut fields = line.splitn(3, '\t');
+            Some((fields.next()?, fields.next()?, fields.next()?))
+        })
+        .collect();
+    let mut manifest = String::new();
+    let (mut changed, mut suspect) = (0, 0);
+    for dirent in piece.ls()? {
+        if let Some(problem) = dirent.problem {
+            println!("skipped {:?}: {}", dirent.name, problem);
+            continue;
+        }
+        let hash = sha256::hex(&sha256::digest(&piece.read_file(&dirent.name)?));
+        let len = dirent.len.to_string();
+        match previous.iter().find(|&&(_, _, name)| name == dirent.name) {
+            Some(&(old_hash, old_len, _)) if old_hash != hash && old_len == len => {
+                println!("SUSPECT {}: contents changed but size did not", dirent.name);
+                suspect += 1;
+            }
+            Some(&(old_hash, old_len, _)) if old_hash != hash => {
+                println!("changed {}: {} -> {} bytes", dirent.name, old_len, len);
+                changed += 1;
+            }
+            Some(_) => {}
+            None => println!("new {}", dirent.name),
+        }
+        manifest += &format!("{}\t{}\t{}\n", hash, len, dirent.name);
+    }
+    fs::write(&path, manifest).expect("Could not write scrub manifest");
+    match previous.is_empty() {
+        true => println!("Recorded checksums for the next scrub"),
+        false => println!("{} changed, {} suspect", changed, suspect),
+    }
+    Ok((suspect > 0) as i32)
+}
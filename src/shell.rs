@@ -0,0 +1,203 @@
+//! `piecer shell` and `piecer script`: commands read a line at a time, all
+//! run over one connection so each doesn't handshake again, and reading the
+//! directory once.
+
+use crate::{run, Commands, Options, Piece, Result};
+use clap::Parser;
+use piecer::{cancel, i18n, progress};
+use std::cell::{Cell, RefCell};
+use std::fs;
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::ops::{Deref, DerefMut};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+
+thread_local! {
+    /// Whether commands are being run from a shell or script.
+    static ACTIVE: Cell<bool> = const { Cell::new(false) };
+    /// The connection kept between commands while [`ACTIVE`].
+    static SHARED: RefCell<Option<Piece>> = const { RefCell::new(None) };
+}
+
+/// A connection to the device, handed back to the shell for the next
+/// command when dropped.
+pub struct Connection(Option<Piece>);
+
+impl Deref for Connection {
+    type Target = Piece;
+    fn deref(&self) -> &Piece {
+        self.0.as_ref().unwrap()
+    }
+}
+
+impl DerefMut for Connection {
+    fn deref_mut(&mut self) -> &mut Piece {
+        self.0.as_mut().unwrap()
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        if ACTIVE.get() {
+            SHARED.set(self.0.take());
+        }
+    }
+}
+
+/// Connect to the device, or in a shell reuse the connection the last
+/// command had.
+pub fn connect(options: &Options) -> Result<Connection> {
+    if let Some(piece) = SHARED.take() {
+        return Ok(Connection(Some(piece)));
+    }
+    let mut piece = Piece::new(options)?;
+    piece.keep_meta(ACTIVE.get());
+    Ok(Connection(Some(piece)))
+}
+
+/// Close the shared connection, if there is one, so that a command can
+/// open devices itself or the next one starts afresh.
+pub fn disconnect() {
+    drop(SHARED.take());
+}
+
+/// Commands run over one connection. `refresh` reconnects, and `exit`
+/// leaves.
+#[derive(Parser)]
+#[command(name = "", no_binary_name = true, disable_version_flag = true)]
+struct Line {
+    #[command(subcommand)]
+    command: Commands,
+}
+
+/// Split `line` into words as a POSIX shell would for simple commands:
+/// on whitespace, with quotes and backslashes, and `#` starting a comment.
+fn split(line: &str) -> std::result::Result<Vec<String>, String> {
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => words.extend(word.take()),
+            '#' if word.is_none() => break,
+            '\\' => word.get_or_insert_default().push(chars.next().ok_or("trailing backslash")?),
+            '\'' => {
+                let word = word.get_or_insert_default();
+                loop {
+                    match chars.next().ok_or("unterminated '")? {
+                        '\'' => break,
+                        c => word.push(c),
+                    }
+                }
+            }
+            '"' => {
+                let word = word.get_or_insert_default();
+                loop {
+                    match chars.next().ok_or("unterminated \"")? {
+                        '"' => break,
+                        '\\' => word.push(chars.next().ok_or("unterminated \"")?),
+                        c => word.push(c),
+                    }
+                }
+            }
+            c => word.get_or_insert_default().push(c),
+        }
+    }
+    words.extend(word);
+    Ok(words)
+}
+
+/// Run one line. Returns its exit code, or `None` for `exit`.
+fn run_line(line: &str, options: &Options, as_json: bool) -> Option<i32> {
+    let words = match split(line) {
+        Ok(words) => words,
+        Err(error) => {
+            eprintln!("{}", error);
+            return Some(2);
+        }
+    };
+    match words.first().map(String::as_str) {
+        None => return Some(0),
+        Some("exit" | "quit") => return None,
+        Some("refresh") => {
+            disconnect();
+            return Some(0);
+        }
+        Some(_) => {}
+    }
+    let command = match Line::try_parse_from(&words) {
+        Ok(line) => line.command,
+        Err(error) => {
+            let _ = error.print();
+            return Some(error.exit_code());
+        }
+    };
+    if matches!(command, Commands::Shell | Commands::Script { .. }) {
+        eprintln!("Already running commands from a shell");
+        return Some(2);
+    }
+    // Ctrl-C stops the command, not the shell.
+    cancel::reset();
+    // A panic has already been reported by the hook; the shell carries on.
+    let result = panic::catch_unwind(AssertUnwindSafe(|| run(command, options, as_json)));
+    Some(match result {
+        Ok(Ok(code)) => code,
+        Ok(Err(error)) => {
+            // The device may have gone away, so the next command connects again.
+            disconnect();
+            let message = error.to_string();
+            progress::error(&message);
+            eprintln!("{}", i18n::trf("error: {}", &[&message]));
+            error.exit_code()
+        }
+        Err(_) => 101,
+    })
+}
+
+/// Read commands from the terminal until `exit` or end of input.
+pub fn interactive(options: &Options, as_json: bool) -> i32 {
+    ACTIVE.set(true);
+    let prompt = io::stdin().is_terminal();
+    let mut code = 0;
+    loop {
+        if prompt {
+            print!("piecer> ");
+            io::stdout().flush().unwrap();
+        }
+        let mut line = String::new();
+        if io::stdin().lock().read_line(&mut line).expect("Could not read command") == 0 {
+            break;
+        }
+        match run_line(&line, options, as_json) {
+            Some(status) => code = status,
+            None => break,
+        }
+    }
+    ACTIVE.set(false);
+    disconnect();
+    code
+}
+
+/// Run the commands in `path`, stopping at the first that fails unless
+/// `keep_going`. Returns the exit code of the last command run.
+pub fn script(path: &Path, options: &Options, as_json: bool, keep_going: bool) -> i32 {
+    let text = fs::read_to_string(path).expect("Could not read script");
+    ACTIVE.set(true);
+    let mut code = 0;
+    for (number, line) in text.lines().enumerate() {
+        match run_line(line, options, as_json) {
+            Some(0) => code = 0,
+            Some(status) => {
+                code = status;
+                if !keep_going || cancel::cancelled() {
+                    eprintln!("{}:{}: stopping after a failed command", path.display(), number + 1);
+                    break;
+                }
+            }
+            None => break,
+        }
+    }
+    ACTIVE.set(false);
+    disconnect();
+    code
+}
@@ -0,0 +1,75 @@
+use crate::date;
+use crate::dump;
+use crate::flash::{FLASH_BASE, FLASH_SIZE};
+use crate::json;
+use crate::kernel;
+use crate::png;
+use crate::progress;
+use crate::rtc;
+use crate::screen;
+use crate::tar;
+use crate::{Piece, Result, LCD_HEIGHT, LCD_WIDTH};
+use piecer::memmap::{IRAM_BASE, IRAM_SIZE, SRAM_BASE};
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+/// Capture the paused device into a tar archive at `path` for reproducing
+/// its state in an emulator. Members:
+///
+/// - `manifest.json`: format version, capture time, kernel version, and the
+///   device address and length of each memory image
+/// - `flash.bin`, `iram.bin`, `sram.bin`: raw memory images
+/// - `systeminfo.bin`: the kernel's 32-byte SYSTEMINFO block
+/// - `rtc.bin`: the clock timer registers (seconds, minutes, hours, days)
+/// - `lcd.bin`: the framebuffer, one byte per pixel from 0 (black) to 3 (white)
+/// - `lcd.png`: the same as an image
+///
+/// CPU registers can't be read over USB and aren't included.
+pub fn export(piece: &mut Piece, path: &Path) -> Result<()> {
+    piece.pause()?;
+    let info = piece.system_info()?;
+    let sram_end = info.sram_end;
+    assert!(sram_end > SRAM_BASE, "SYSTEMINFO reports an unexpected SRAM end of {:#x}", sram_end);
+    let lcd_addr = piece.framebuffer_addr()?;
+    let mut lcd = vec![0; LCD_WIDTH * LCD_HEIGHT];
+    piece.get_memory(lcd_addr, lcd.len() as u32, &mut lcd)?;
+    let rtc = rtc::registers(piece)?;
+    let regions = [("iram.bin", IRAM_BASE, IRAM_SIZE), ("sram.bin", SRAM_BASE, sram_end - SRAM_BASE)];
+    let mut images = Vec::new();
+    let total = (IRAM_SIZE + sram_end - SRAM_BASE + FLASH_SIZE) as u64;
+    let mut done = 0;
+    for &(name, base, len) in &regions {
+        let mut data = vec![0; len as usize];
+        piece.get_memory(base, len, &mut data)?;
+        done += len as u64;
+        progress::update(Some(name), done, total);
+        images.push(data);
+    }
+    let mut flash = Vec::with_capacity(FLASH_SIZE as usize);
+    dump::to_writer(piece, FLASH_BASE, FLASH_SIZE, &mut flash)?;
+    piece.resume_unless_halted()?;
+
+    let mut members = String::new();
+    for &(name, base, len) in regions.iter().chain(&[("flash.bin", FLASH_BASE, FLASH_SIZE)]) {
+        members += &format!("    {{\"file\": {}, \"addr\": {}, \"len\": {}}},\n", json::string(name), base, len);
+    }
+    let manifest = format!("{{\n  \"format\": \"piecer-state\",\n  \"version\": 1,\n  \"captured\": {},\n  \"kernel\": {},\n  \"lcd_addr\": {},\n  \"memory\": [\n{}\n  ]\n}}\n",
+                           json::string(&date::format(date::now_local())),
+                           json::string(&kernel::version_string(piece.kernel_version)),
+                           lcd_addr, members.trim_end_matches(",\n"));
+    let gray = screen::gray(&lcd);
+    let file = File::create(path).expect("Could not create state bundle");
+    let mut archive = tar::Writer::new(BufWriter::new(file), date::now_local() as u64);
+    let write = |archive: &mut tar::Writer<_>, name: &str, data: &[u8]| archive.append(name, data).expect("Could not write state bundle");
+    write(&mut archive, "manifest.json", manifest.as_bytes());
+    write(&mut archive, "systeminfo.bin", &info.raw);
+    write(&mut archive, "rtc.bin", &rtc);
+    write(&mut archive, "lcd.bin", &lcd);
+    write(&mut archive, "lcd.png", &png::encode_gray(LCD_WIDTH as u32, LCD_HEIGHT as u32, &gray));
+    write(&mut archive, "iram.bin", &images[0]);
+    write(&mut archive, "sram.bin", &images[1]);
+    write(&mut archive, "flash.bin", &flash);
+    archive.finish().expect("Could not write state bundle");
+    Ok(())
+}
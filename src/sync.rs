@@ -0,0 +1,148 @@
+use crate::launch;
+use crate::{Piece, PieceError, Result};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+/// How long to let a build finish writing after the first change before
+/// uploading, so a file isn't sent half-written.
+const SETTLE: Duration = Duration::from_millis(300);
+/// Longest wait for an inotify event before the link is kept alive.
+const WAKE: Duration = Duration::from_millis(500);
+
+/// Size and modification time of each file in `dir`, skipping hidden ones.
+fn scan(dir: &Path) -> HashMap<String, (u64, SystemTime)> {
+    fs::read_dir(dir).expect("Could not read sync directory")
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok().filter(|name| !name.starts_with('.'))?;
+            let metadata = entry.metadata().ok().filter(|metadata| metadata.is_file())?;
+            Some((name, (metadata.len(), metadata.modified().ok()?)))
+        })
+        .collect()
+}
+
+/// Upload `name` from `dir`, reporting errors that a later change could fix
+/// instead of stopping. Returns whether it was written.
+fn upload(piece: &mut Piece, dir: &Path, name: &str) -> Result<bool> {
+    let Ok(data) = fs::read(dir.join(name)) else {
+        return Ok(false);
+    };
+    match piece.upload(name, &data, false) {
+        Ok(()) => {
+            println!("uploaded {} ({} bytes)", name, data.len());
+            Ok(true)
+        }
+        Err(error @ (PieceError::NameTooLong(_) | PieceError::UnencodableName(_) | PieceError::DirectoryFull
+                     | PieceError::NoSpace | PieceError::LowSpace(_))) => {
+            eprintln!("{}: {}", name, error);
+            Ok(false)
+        }
+        Err(error) => Err(error),
+    }
+}
+
+/// Upload the files in `dir` that the device lacks or has with different
+/// contents. With `watch`, keep uploading files as they change until
+/// interrupted; with `run`, start that executable after each round.
+pub fn run(piece: &mut Piece, dir: &Path, watch: bool, interval: Duration, run: Option<&str>) -> Result<()> {
+    let mut known = scan(dir);
+    let directory = piece.ls()?;
+    let mut names: Vec<&String> = known.keys().collect();
+    names.sort();
+    let mut uploaded = false;
+    for name in names {
+        let same_len = directory.iter().any(|dirent| dirent.name == *name && dirent.len as u64 == known[name].0);
+        if same_len && fs::read(dir.join(name)).ok() == Some(piece.read_file(name)?) {
+            continue;
+        }
+        uploaded |= upload(piece, dir, name)?;
+    }
+    if let Some(name) = run.filter(|_| uploaded) {
+        launch::run(piece, name)?;
+    }
+    if !watch {
+        return Ok(());
+    }
+    let watcher = Watcher::new(dir);
+    match watcher {
+        Some(_) => println!("Watching {} for changes", dir.display()),
+        None => println!("Checking {} for changes every {:?}", dir.display(), interval),
+    }
+    loop {
+        match &watcher {
+            Some(watcher) => {
+                while !watcher.wait(WAKE) {
+                    piece.idle(Duration::from_millis(1))?;
+                }
+                thread::sleep(SETTLE);
+                watcher.wait(Duration::ZERO);
+            }
+            None => piece.idle(interval)?,
+        }
+        let current = scan(dir);
+        let mut changed: Vec<&String> = current.iter().filter(|&(name, state)| known.get(name) != Some(state))
+            .map(|(name, _)| name).collect();
+        changed.sort();
+        let mut uploaded = false;
+        for name in changed {
+            uploaded |= upload(piece, dir, name)?;
+        }
+        if let Some(name) = run.filter(|_| uploaded) {
+            launch::run(piece, name)?;
+        }
+        known = current;
+    }
+}
+
+/// Reports files being written in a directory, using inotify.
+#[cfg(target_os = "linux")]
+struct Watcher(std::os::fd::OwnedFd);
+
+#[cfg(target_os = "linux")]
+impl Watcher {
+    fn new(dir: &Path) -> Option<Watcher> {
+        use std::ffi::CString;
+        use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+        use std::os::unix::ffi::OsStrExt;
+        let fd = unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) };
+        if fd < 0 {
+            return None;
+        }
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+        let path = CString::new(dir.as_os_str().as_bytes()).ok()?;
+        let mask = libc::IN_CLOSE_WRITE | libc::IN_MOVED_TO;
+        (unsafe { libc::inotify_add_watch(fd.as_raw_fd(), path.as_ptr(), mask) } >= 0).then_some(Watcher(fd))
+    }
+
+    /// Whether a file was written within `timeout`. Pending events are
+    /// discarded, since the directory is rescanned anyway.
+    fn wait(&self, timeout: Duration) -> bool {
+        use std::os::fd::AsRawFd;
+        let fd = self.0.as_raw_fd();
+        let mut poll = libc::pollfd { fd, events: libc::POLLIN, revents: 0 };
+        if unsafe { libc::poll(&mut poll, 1, timeout.as_millis() as libc::c_int) } <= 0 {
+            return false;
+        }
+        let mut events = [0u8; 4096];
+        while unsafe { libc::read(fd, events.as_mut_ptr().cast(), events.len()) } > 0 {}
+        true
+    }
+}
+
+/// Elsewhere the directory is polled instead.
+#[cfg(not(target_os = "linux"))]
+struct Watcher;
+
+#[cfg(not(target_os = "linux"))]
+impl Watcher {
+    fn new(_dir: &Path) -> Option<Watcher> {
+        None
+    }
+
+    fn wait(&self, _timeout: Duration) -> bool {
+        false
+    }
+}
@@ -0,0 +1,55 @@
+use piecer::{flash, json, kernel};
+use crate::{Piece, Result};
+
+/// Print what the kernel and the USB descriptor say about the device.
+pub fn show(piece: &mut Piece, as_json: bool) -> Result<()> {
+    let info = piece.system_info()?;
+    let usb = piece.usb_info()?;
+    let (year, month, day) = info.kernel_date;
+    let flash = flash::geometry(piece)?;
+    if as_json {
+        let text = |value: &Option<String>| value.as_deref().map_or("null".to_string(), json::string);
+        let usb = json::object(&[
+            ("vendor_id", usb.vendor_id.to_string()),
+            ("product_id", usb.product_id.to_string()),
+            ("bus", usb.bus.to_string()),
+            ("address", usb.address.to_string()),
+            ("usb_version", json::string(&usb.usb_version)),
+            ("device_version", json::string(&usb.device_version)),
+            ("speed", json::string(&format!("{:?}", usb.speed))),
+            ("manufacturer", text(&usb.manufacturer)),
+            ("product", text(&usb.product)),
+            ("serial", text(&usb.serial)),
+        ]);
+        println!("{}", json::object(&[
+            ("kernel", json::string(&kernel::version_string(info.kernel_version))),
+            ("kernel_date", json::string(&format!("{:04}-{:02}-{:02}", year, month, day))),
+            ("hardware", json::string(&kernel::version_string(info.hardware_version))),
+            ("clock_hz", info.clock_hz.to_string()),
+            ("battery_mv", info.vdde_mv.to_string()),
+            ("sram_top", info.sram_top.to_string()),
+            ("sram_end", info.sram_end.to_string()),
+            ("flash_size", flash.size.to_string()),
+            ("pffs_top", info.pffs_top.to_string()),
+            ("pffs_end", info.pffs_end.to_string()),
+            ("usb", usb),
+        ]));
+        return Ok(());
+    }
+    println!("kernel      {} ({:04}-{:02}-{:02})", kernel::version_string(info.kernel_version), year, month, day);
+    println!("hardware    {}", kernel::version_string(info.hardware_version));
+    println!("clock       {:.3} MHz", info.clock_hz as f64 / 1e6);
+    println!("battery     {}.{:03} V", info.vdde_mv / 1000, info.vdde_mv % 1000);
+    println!("sram        {:#x}-{:#x} ({} KiB for applications)", info.sram_top, info.sram_end,
+             info.sram_end.saturating_sub(info.sram_top) / 1024);
+    println!("flash       {} KiB", flash.size / 1024);
+    println!("filesystem  {:#x}-{:#x}", info.pffs_top, info.pffs_end);
+    println!("usb         {:04x}:{:04x} at bus {} address {}, USB {}, {:?} speed",
+             usb.vendor_id, usb.product_id, usb.bus, usb.address, usb.usb_version, usb.speed);
+    println!("release     {}", usb.device_version);
+    let missing = || "(none)".to_string();
+    println!("maker       {}", usb.manufacturer.unwrap_or_else(missing));
+    println!("product     {}", usb.product.unwrap_or_else(missing));
+    println!("serial      {}", usb.serial.unwrap_or_else(missing));
+    Ok(())
+}
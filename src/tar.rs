@@ -0,0 +1,163 @@
+use std::io::{self, Write};
+
+/// Streams a POSIX ustar archive of regular files to `out`.
+pub struct Writer<W: Write> {
+    out: W,
+    mtime: u64,
+}
+
+impl<W: Write> Writer<W> {
+    /// Files are stamped with `mtime`, in seconds since the Unix epoch.
+    pub fn new(out: W, mtime: u64) -> Writer<W> {
+        Writer { out, mtime }
+    }
+
+    pub fn append(&mut self, name: &str, data: &[u8]) -> io::Result<()> {
+        let mut header = [0u8; 512];
+        let (prefix, name) = split_name(name);
+        header[..name.len()].copy_from_slice(name.as_bytes());
+        octal(&mut header[100..108], 0o644);
+        octal(&mut header[108..116], 0);
+        octal(&mut header[116..124], 0);
+        octal(&mut header[124..136], data.len() as u64);
+        octal(&mut header[136..148], self.mtime);
+        header[156] = b'0';
+        header[257..265].copy_from_slice(b"ustar\x0000");
+        header[345..345 + prefix.len()].copy_from_slice(prefix.as_bytes());
+        // The checksum is computed with its own field as spaces.
+        header[148..156].fill(b' ');
+        let sum: u64 = header.iter().map(|&b| b as u64).sum();
+        octal(&mut header[148..155], sum);
+        self.out.write_all(&header)?;
+        self.out.write_all(data)?;
+        self.out.write_all(&vec![0; data.len().next_multiple_of(512) - data.len()])
+    }
+
+    /// Write the end-of-archive marker and return the underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.out.write_all(&[0; 1024])?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+/// Split a path into ustar's 155-byte prefix and 100-byte name fields.
+fn split_name(path: &str) -> (&str, &str) {
+    if path.len() <= 100 {
+        return ("", path);
+    }
+    let split = path.char_indices()
+        .filter(|&(i, c)| c == '/' && i <= 155 && path.len() - i - 1 <= 100)
+        .map(|(i, _)| i)
+        .next()
+        .unwrap_or_else(|| panic!("Path is too long for a tar archive: {}", path));
+    (&path[..split], &path[split + 1..])
+}
+
+/// Zero-padded octal filling all but the last byte of `field`, which is NUL.
+fn octal(field: &mut [u8], value: u64) {
+    let width = field.len() - 1;
+    let digits = format!("{:0width$o}", value, width = width);
+    field[..width].copy_from_slice(digits.as_bytes());
+    // The checksum field is all spaces when its digits go in.
+    field[width] = 0;
+}
+
+/// The regular files in a ustar archive as (path, contents). Returns `None`
+/// on a truncated archive or a bad header checksum.
+pub fn read(data: &[u8]) -> Option<Vec<(String, Vec<u8>)>> {
+    let mut members = Vec::new();
+    let mut pos = 0;
+    while let Some(header) = data.get(pos..pos + 512) {
+        if header.iter().all(|&b| b == 0) {
+            break;
+        }
+        let sum: u64 = header.iter().enumerate().map(|(i, &b)| if (148..156).contains(&i) { b' ' as u64 } else { b as u64 }).sum();
+        if parse_octal(&header[148..156])? != sum {
+            return None;
+        }
+        let len = parse_octal(&header[124..136])? as usize;
+        let field = |range: std::ops::Range<usize>| {
+            let field = &header[range];
+            String::from_utf8_lossy(&field[..field.iter().position(|&b| b == 0).unwrap_or(field.len())]).into_owned()
+        };
+        let (prefix, name) = (field(345..500), field(0..100));
+        let contents = data.get(pos + 512..pos + 512 + len)?;
+        if matches!(header[156], b'0' | 0) {
+            members.push((if prefix.is_empty() { name } else { format!("{}/{}", prefix, name) }, contents.to_vec()));
+        }
+        pos += 512 + len.next_multiple_of(512);
+    }
+    Some(members)
+}
+
+fn parse_octal(field: &[u8]) -> Option<u64> {
+    let digits = std::str::from_utf8(field).ok()?.trim_matches(|c| c == ' ' || c == '\0');
+    u64::from_str_radix(digits, 8).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn archive(members: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut writer = Writer::new(Vec::new(), 0o1234);
+        for (name, data) in members {
+            writer.append(name, data).unwrap();
+        }
+        writer.finish().unwrap()
+    }
+
+    #[test]
+    fn round_trip() {
+        let big = vec![7; 1300];
+        let data = archive(&[("SAVE.DAT", b"level 3"), ("GAME.PEX", &big), ("EMPTY", b"")]);
+        let members = read(&data).unwrap();
+        let members: Vec<(&str, &[u8])> = members.iter().map(|(name, data)| (name.as_str(), data.as_slice())).collect();
+        assert_eq!(members, [("SAVE.DAT", &b"level 3"[..]), ("GAME.PEX", &big), ("EMPTY", b"")]);
+    }
+
+    #[test]
+    fn header_layout() {
+        let data = archive(&[("SAVE.DAT", b"level 3")]);
+        // Header, one block of data, and the two-block end marker.
+        assert_eq!(data.len(), 4 * 512);
+        let header = &data[..512];
+        assert_eq!(&header[..9], b"SAVE.DAT\0");
+        assert_eq!(&header[100..108], b"0000644\0");
+        assert_eq!(&header[124..136], b"00000000007\0");
+        assert_eq!(&header[136..148], b"00000001234\0");
+        assert_eq!(header[156], b'0');
+        assert_eq!(&header[257..265], b"ustar\x0000");
+        // Six octal digits, NUL, space; summed with the field as spaces.
+        assert_eq!(&header[154..156], b"\0 ");
+        let sum: u64 = header.iter().enumerate()
+            .map(|(i, &b)| if (148..156).contains(&i) { b' ' as u64 } else { b as u64 }).sum();
+        assert_eq!(&header[148..154], format!("{:06o}", sum).as_bytes());
+        assert_eq!(&data[512..519], b"level 3");
+        assert!(data[519..].iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn long_names_use_the_prefix() {
+        let exact = "n".repeat(100);
+        let data = archive(&[(&exact, b"x")]);
+        assert_eq!(&data[..100], exact.as_bytes());
+        assert_eq!(data[345], 0);
+
+        let long = format!("{}/{}", "d".repeat(60), "f".repeat(60));
+        let data = archive(&[(&long, b"x")]);
+        assert_eq!(&data[..61], format!("{}\0", "f".repeat(60)).as_bytes());
+        assert_eq!(&data[345..406], format!("{}\0", "d".repeat(60)).as_bytes());
+        assert_eq!(read(&data).unwrap()[0].0, long);
+    }
+
+    #[test]
+    fn damage_is_refused() {
+        let mut data = archive(&[("SAVE.DAT", b"level 3")]);
+        data[0] = b'X';
+        assert!(read(&data).is_none());
+        let data = archive(&[("SAVE.DAT", &[1; 600])]);
+        assert!(read(&data[..700]).is_none());
+    }
+}
@@ -0,0 +1,116 @@
+use std::io::{self, Write};
+use std::time::Duration;
+
+/// A key read by `Screen::key`.
+pub enum Key {
+    Char(char),
+    Up,
+    Down,
+    Left,
+    Right,
+    PageUp,
+    PageDown,
+    Enter,
+    Backspace,
+    Tab,
+    Esc,
+}
+
+/// Full-screen terminal session: switches to the alternate screen with the
+/// cursor hidden and, on unix, puts the terminal in raw mode so keys arrive
+/// one at a time. Everything is restored on drop, including when unwinding
+/// from a panic.
+pub struct Screen {
+    #[cfg(unix)]
+    saved: Option<libc::termios>,
+}
+
+impl Screen {
+    pub fn new() -> Screen {
+        #[cfg(unix)]
+        let saved = unsafe {
+            let mut termios: libc::termios = std::mem::zeroed();
+            if libc::tcgetattr(0, &mut termios) == 0 {
+                let saved = termios;
+                // Ctrl-C comes through as a key, so the terminal is restored on quit.
+                termios.c_lflag &= !(libc::ICANON | libc::ECHO | libc::ISIG);
+                libc::tcsetattr(0, libc::TCSANOW, &termios);
+                Some(saved)
+            } else {
+                None
+            }
+        };
+        print!("\x1b[?1049h\x1b[?25l");
+        io::stdout().flush().unwrap();
+        Screen {
+            #[cfg(unix)]
+            saved,
+        }
+    }
+
+    /// Replace the screen contents with `text`.
+    pub fn draw(&self, text: &str) {
+        let mut out = io::stdout().lock();
+        write!(out, "\x1b[H\x1b[2J").unwrap();
+        for line in text.lines() {
+            write!(out, "{}\r\n", line).unwrap();
+        }
+        out.flush().unwrap();
+    }
+
+    /// Wait up to `timeout` for a key press.
+    #[cfg(unix)]
+    pub fn key(&self, timeout: Duration) -> Option<Key> {
+        let byte = read_byte(timeout)?;
+        Some(match byte {
+            b'\r' | b'\n' => Key::Enter,
+            0x7f | 0x08 => Key::Backspace,
+            b'\t' => Key::Tab,
+            0x1b => match (read_byte(Duration::from_millis(20)), read_byte(Duration::from_millis(20))) {
+                (Some(b'['), Some(b'A')) => Key::Up,
+                (Some(b'['), Some(b'B')) => Key::Down,
+                (Some(b'['), Some(b'C')) => Key::Right,
+                (Some(b'['), Some(b'D')) => Key::Left,
+                (Some(b'['), Some(code @ (b'5' | b'6'))) => {
+                    read_byte(Duration::from_millis(20));
+                    if code == b'5' { Key::PageUp } else { Key::PageDown }
+                }
+                _ => Key::Esc,
+            },
+            // Ctrl-C
+            0x03 => Key::Char('q'),
+            byte => Key::Char(byte as char),
+        })
+    }
+
+    #[cfg(not(unix))]
+    pub fn key(&self, timeout: Duration) -> Option<Key> {
+        std::thread::sleep(timeout);
+        None
+    }
+}
+
+#[cfg(unix)]
+fn read_byte(timeout: Duration) -> Option<u8> {
+    let mut poll = libc::pollfd { fd: 0, events: libc::POLLIN, revents: 0 };
+    let ready = unsafe { libc::poll(&mut poll, 1, timeout.as_millis() as libc::c_int) };
+    if ready <= 0 {
+        return None;
+    }
+    let mut byte = 0u8;
+    match unsafe { libc::read(0, &mut byte as *mut u8 as *mut libc::c_void, 1) } {
+        1 => Some(byte),
+        _ => None,
+    }
+}
+
+impl Drop for Screen {
+    fn drop(&mut self) {
+        print!("\x1b[?25h\x1b[?1049l");
+        io::stdout().flush().unwrap();
+        #[cfg(unix)]
+        if let Some(saved) = self.saved {
+            unsafe { libc::tcsetattr(0, libc::TCSANOW, &saved) };
+        }
+    }
+}
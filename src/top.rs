@@ -0,0 +1,38 @@
+use crate::date;
+use crate::kernel;
+use crate::rtc;
+use crate::term::{Key, Screen};
+use crate::{Piece, Result, FAT_FREE};
+use std::time::Duration;
+
+/// Continuously refreshed view of device state, until `q` is pressed.
+pub fn run(piece: &mut Piece, interval: Duration) -> Result<()> {
+    let screen = Screen::new();
+    loop {
+        let battery = piece.battery_mv()?;
+        let clock = rtc::get(piece)?;
+        let fat = piece.read_fat()?;
+        let directory = piece.ls()?;
+        let geometry = piece.pffs;
+        let free = fat[geometry.first_cluster()..].iter().filter(|&&entry| entry == FAT_FREE).count();
+        let used: u64 = directory.iter().map(|dirent| dirent.len as u64).sum();
+        let mut text = String::new();
+        text += &format!("piecer top - {}    kernel {}\n\n", date::format(date::now_local()), kernel::version_string(piece.kernel_version));
+        text += &format!("device clock  {}\n", date::format(clock));
+        text += &format!("battery       {}.{:03} V\n", battery / 1000, battery % 1000);
+        text += &format!("flash         {} of {} clusters free ({} KiB)\n", free, geometry.data_clusters(),
+                         free * geometry.cluster_size as usize / 1024);
+        text += &format!("files         {} of {} slots, {} bytes\n\n", directory.len(), geometry.file_slots(), used);
+        let mut largest: Vec<_> = directory.iter().collect();
+        largest.sort_by_key(|dirent| std::cmp::Reverse(dirent.len));
+        text += "largest files\n";
+        for dirent in largest.iter().take(10) {
+            text += &format!("  {:<24} {:>8}\n", dirent.name, dirent.len);
+        }
+        text += "\nq quit";
+        screen.draw(&text);
+        if let Some(Key::Char('q') | Key::Esc) = screen.key(interval) {
+            return Ok(());
+        }
+    }
+}
@@ -0,0 +1,72 @@
+//! Timing traces of device operations, for `--trace-output`.
+
+use crate::json;
+use std::fmt::Display;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::Instant;
+
+// Spans are written as Chrome trace "complete" events (load the file in
+// chrome://tracing or Perfetto). The array format tolerates a missing closing
+// bracket, so events are streamed and a crashed run still leaves a usable trace.
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static OUT: Mutex<Option<BufWriter<File>>> = Mutex::new(None);
+static EPOCH: OnceLock<Instant> = OnceLock::new();
+
+/// Start writing spans to `path`. Until this is called, spans cost next to
+/// nothing.
+pub fn init(path: &Path) {
+    let mut out = BufWriter::new(File::create(path).expect("Could not create trace file"));
+    writeln!(out, "[").unwrap();
+    *OUT.lock().unwrap() = Some(out);
+    EPOCH.get_or_init(Instant::now);
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+/// Write out buffered events, e.g. before exiting.
+pub fn flush() {
+    if let Some(out) = OUT.lock().unwrap().as_mut() {
+        out.flush().unwrap();
+    }
+}
+
+/// A timed region, recorded when dropped. Arguments are only formatted when
+/// tracing is enabled.
+pub struct Span {
+    name: &'static str,
+    args: Vec<String>,
+    start: Option<Instant>,
+}
+
+/// Start a span called `name`.
+pub fn span(name: &'static str) -> Span {
+    let start = ENABLED.load(Ordering::Relaxed).then(Instant::now);
+    Span { name, args: Vec::new(), start }
+}
+
+impl Span {
+    /// Attach `key` = `value` to the span, shown with it in the viewer.
+    pub fn arg(mut self, key: &str, value: impl Display) -> Span {
+        if self.start.is_some() {
+            self.args.push(format!("{}:{}", json::string(key), json::string(&value.to_string())));
+        }
+        self
+    }
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        let Some(start) = self.start else {
+            return;
+        };
+        let ts = start.duration_since(*EPOCH.get().unwrap()).as_micros();
+        let dur = start.elapsed().as_micros();
+        if let Some(out) = OUT.lock().unwrap().as_mut() {
+            writeln!(out, "{{\"name\":{},\"cat\":\"piecer\",\"ph\":\"X\",\"ts\":{},\"dur\":{},\"pid\":1,\"tid\":1,\"args\":{{{}}}}},",
+                     json::string(self.name), ts, dur, self.args.join(",")).unwrap();
+        }
+    }
+}
@@ -0,0 +1,271 @@
+//! The link under a [`Piece`](crate::Piece): bulk transfers to a real
+//! device over USB, or to a [`FakeDevice`] that answers the kernel's
+//! commands from memory, so everything above the link can be tested
+//! without hardware.
+
+use crate::flash::{self, FLASH_BASE, FLASH_SIZE};
+use crate::memmap::{IRAM_BASE, IRAM_SIZE, SRAM_BASE};
+use crate::pffs::{self, PffsGeometry};
+use crate::Result;
+use rusb::{DeviceHandle, GlobalContext};
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+const OUT: u8 = 0x02;
+const IN: u8 = 0x82;
+
+/// Bulk transfers to and from the kernel, with the USB-level control the
+/// retry logic needs.
+pub trait Transport: Send + Sync {
+    /// Send `data` as one bulk OUT transfer.
+    fn write(&self, data: &[u8], timeout: Duration) -> rusb::Result<usize>;
+    /// Receive up to `buf.len()` bytes in one bulk IN transfer.
+    fn read(&self, buf: &mut [u8], timeout: Duration) -> rusb::Result<usize>;
+    /// Clear a stall on both endpoints.
+    fn clear_halt(&self) -> rusb::Result<()>;
+    /// Reset the USB port, after which the device may enumerate afresh.
+    fn reset(&self) -> rusb::Result<()>;
+    /// The USB handle, if the link is over USB.
+    fn usb(&self) -> Option<&DeviceHandle<GlobalContext>>;
+}
+
+impl Transport for DeviceHandle<GlobalContext> {
+    fn write(&self, data: &[u8], timeout: Duration) -> rusb::Result<usize> {
+        self.write_bulk(OUT, data, timeout)
+    }
+    fn read(&self, buf: &mut [u8], timeout: Duration) -> rusb::Result<usize> {
+        self.read_bulk(IN, buf, timeout)
+    }
+    fn clear_halt(&self) -> rusb::Result<()> {
+        DeviceHandle::clear_halt(self, OUT)?;
+        DeviceHandle::clear_halt(self, IN)
+    }
+    fn reset(&self) -> rusb::Result<()> {
+        DeviceHandle::reset(self)
+    }
+    fn usb(&self) -> Option<&DeviceHandle<GlobalContext>> {
+        Some(self)
+    }
+}
+
+/// Where the fake device's filesystem starts, leaving the first 64 KiB of
+/// flash to the kernel so a stock filesystem fills the rest.
+pub const FAKE_PFFS_TOP: u32 = FLASH_BASE + 0x10000;
+/// End of the fake device's SRAM.
+const FAKE_SRAM_END: u32 = SRAM_BASE + 0x40000;
+/// Where the fake device's display buffer is.
+pub const FAKE_FRAMEBUFFER: u32 = SRAM_BASE + 0x100;
+
+/// What the fake device holds and is in the middle of.
+struct FakeState {
+    iram: Vec<u8>,
+    sram: Vec<u8>,
+    flash: Vec<u8>,
+    kernel_version: u16,
+    /// Bytes of answers not yet read. Answers queue up, as they do when
+    /// requests are overlapped.
+    reply: VecDeque<u8>,
+    /// A write or flash command whose data comes in the next transfer.
+    payload: Option<(u8, u32, u32)>,
+    /// The most of a read request the kernel answers; see
+    /// [`FakeDevice::limit_reads`].
+    read_limit: Option<u32>,
+    /// Sector writes that still succeed; see
+    /// [`FakeDevice::fail_flash_writes_after`].
+    flash_writes_left: Option<u32>,
+    paused: bool,
+    keys: u8,
+    /// Commands received, by their first byte.
+    commands: Vec<u8>,
+}
+
+impl FakeState {
+    /// The memory behind `addr`, and the offset of `addr` in it.
+    fn memory(&mut self, addr: u32) -> Option<(&mut Vec<u8>, usize)> {
+        let (memory, base) = match addr {
+            _ if (IRAM_BASE..IRAM_BASE + IRAM_SIZE).contains(&addr) => (&mut self.iram, IRAM_BASE),
+            _ if (SRAM_BASE..FAKE_SRAM_END).contains(&addr) => (&mut self.sram, SRAM_BASE),
+            _ if (FLASH_BASE..FLASH_BASE + FLASH_SIZE).contains(&addr) => (&mut self.flash, FLASH_BASE),
+            _ => return None,
+        };
+        Some((memory, (addr - base) as usize))
+    }
+    fn peek(&mut self, addr: u32) -> u8 {
+        self.memory(addr).map_or(0, |(memory, offset)| memory[offset])
+    }
+    fn poke(&mut self, addr: u32, byte: u8) {
+        if let Some((memory, offset)) = self.memory(addr) {
+            memory[offset] = byte;
+        }
+    }
+    fn info(&self) -> [u8; 32] {
+        let mut info = [0; 32];
+        info[2..4].copy_from_slice(&0x0100u16.to_le_bytes());
+        info[4..6].copy_from_slice(&self.kernel_version.to_le_bytes());
+        // 2003-01-01, packed as in DeviceInfo::parse.
+        info[6..8].copy_from_slice(&(3u16 << 9 | 1 << 5 | 1).to_le_bytes());
+        info[8..12].copy_from_slice(&24_000_000u32.to_le_bytes());
+        info[12..14].copy_from_slice(&3000u16.to_le_bytes());
+        info[16..20].copy_from_slice(&(SRAM_BASE + 0x1000).to_le_bytes());
+        info[20..24].copy_from_slice(&FAKE_SRAM_END.to_le_bytes());
+        info[24..28].copy_from_slice(&FAKE_PFFS_TOP.to_le_bytes());
+        info[28..32].copy_from_slice(&(FLASH_BASE + FLASH_SIZE).to_le_bytes());
+        info
+    }
+    /// Act on one bulk OUT transfer as the kernel would.
+    fn receive(&mut self, data: &[u8]) -> rusb::Result<()> {
+        let u32_at = |offset: usize| data.get(offset..offset + 4).map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()));
+        if let Some((command, addr, len)) = self.payload.take() {
+            if data.len() != len as usize {
+                return Err(rusb::Error::Io);
+            }
+            if command == 5 {
+                match &mut self.flash_writes_left {
+                    Some(0) => return Err(rusb::Error::Io),
+                    Some(left) => *left -= 1,
+                    None => {}
+                }
+                let start = addr.checked_sub(FLASH_BASE).filter(|offset| offset.is_multiple_of(flash::SECTOR_SIZE))
+                    .ok_or(rusb::Error::Io)? as usize;
+                self.flash.get_mut(start..start + data.len()).ok_or(rusb::Error::Io)?.copy_from_slice(data);
+            } else {
+                for (i, &byte) in data.iter().enumerate() {
+                    self.poke(addr + i as u32, byte);
+                }
+            }
+            return Ok(());
+        }
+        let &command = data.first().ok_or(rusb::Error::Io)?;
+        self.commands.push(command);
+        match (command, u32_at(1), u32_at(5)) {
+            (0, _, _) => self.reply.extend(self.info()),
+            (2, Some(addr), Some(len)) => {
+                let len = self.read_limit.map_or(len, |limit| len.min(limit));
+                let bytes: Vec<u8> = (0..len).map(|i| self.peek(addr + i)).collect();
+                self.reply.extend(bytes);
+            }
+            (3 | 5, Some(addr), Some(len)) => self.payload = Some((command, addr, len)),
+            (4, Some(_), _) => {}
+            (16, _, _) => self.paused = data.get(1) == Some(&1),
+            (17, _, _) => {
+                let mut lcd = [0; 12];
+                lcd[2] = crate::device::LCD_WIDTH as u8;
+                lcd[4] = crate::device::LCD_HEIGHT as u8;
+                lcd[8..12].copy_from_slice(&FAKE_FRAMEBUFFER.to_le_bytes());
+                self.reply.extend(lcd);
+            }
+            (18, _, _) => self.keys = data.get(1).copied().unwrap_or(0),
+            _ => return Err(rusb::Error::Io),
+        }
+        Ok(())
+    }
+}
+
+/// A device that lives in memory: flash, SRAM and internal RAM, and a
+/// kernel that answers the commands piecer sends. Clones share the one
+/// device, so a test can look at it while a [`Piece`](crate::Piece) uses
+/// it.
+#[derive(Clone)]
+pub struct FakeDevice(Arc<Mutex<FakeState>>);
+
+impl FakeDevice {
+    /// A device whose flash is `flash`, a dump from `FLASH_BASE`, with its
+    /// filesystem at [`FAKE_PFFS_TOP`].
+    pub fn new(mut flash: Vec<u8>) -> FakeDevice {
+        flash.resize(FLASH_SIZE as usize, 0xFF);
+        FakeDevice(Arc::new(Mutex::new(FakeState {
+            iram: vec![0; IRAM_SIZE as usize],
+            sram: vec![0; (FAKE_SRAM_END - SRAM_BASE) as usize],
+            flash,
+            kernel_version: 0x0130,
+            reply: VecDeque::new(),
+            payload: None,
+            read_limit: None,
+            flash_writes_left: None,
+            paused: false,
+            keys: 0,
+            commands: Vec::new(),
+        })))
+    }
+    /// A device with a stock filesystem holding `files`.
+    pub fn with_files(files: &[(&str, &[u8])]) -> Result<FakeDevice> {
+        let mut flash = vec![0xFF; FLASH_SIZE as usize];
+        let files: Vec<(String, Vec<u8>)> = files.iter().map(|&(name, data)| (name.to_string(), data.to_vec())).collect();
+        pffs::create(&mut flash, &PffsGeometry::STOCK, FAKE_PFFS_TOP, &files)?;
+        Ok(FakeDevice::new(flash))
+    }
+    /// Report kernel `version` in the handshake instead of 1.30.
+    pub fn set_kernel_version(&self, version: u16) {
+        self.0.lock().unwrap().kernel_version = version;
+    }
+    /// Answer at most `limit` bytes of each read request, as kernels that
+    /// can't send large blocks do, or again in full with `None`.
+    pub fn limit_reads(&self, limit: Option<u32>) {
+        self.0.lock().unwrap().read_limit = limit;
+    }
+    /// Fail every sector write after the next `writes`, as if the cable
+    /// came out, or none again with `None`.
+    pub fn fail_flash_writes_after(&self, writes: Option<u32>) {
+        self.0.lock().unwrap().flash_writes_left = writes;
+    }
+    /// A copy of all of flash, from `FLASH_BASE`.
+    pub fn flash(&self) -> Vec<u8> {
+        self.0.lock().unwrap().flash.clone()
+    }
+    /// `len` bytes of memory at `addr`, with zeros where nothing is mapped.
+    pub fn memory(&self, addr: u32, len: u32) -> Vec<u8> {
+        let mut state = self.0.lock().unwrap();
+        (0..len).map(|i| state.peek(addr + i)).collect()
+    }
+    /// Write `data` into memory at `addr`, as the running application might.
+    pub fn set_memory(&self, addr: u32, data: &[u8]) {
+        let mut state = self.0.lock().unwrap();
+        for (i, &byte) in data.iter().enumerate() {
+            state.poke(addr + i as u32, byte);
+        }
+    }
+    /// Whether the application is paused.
+    pub fn paused(&self) -> bool {
+        self.0.lock().unwrap().paused
+    }
+    /// The keys last held down over USB.
+    pub fn keys(&self) -> u8 {
+        self.0.lock().unwrap().keys
+    }
+    /// The first byte of every command received so far.
+    pub fn commands(&self) -> Vec<u8> {
+        self.0.lock().unwrap().commands.clone()
+    }
+}
+
+impl Transport for FakeDevice {
+    fn write(&self, data: &[u8], _timeout: Duration) -> rusb::Result<usize> {
+        self.0.lock().unwrap().receive(data)?;
+        Ok(data.len())
+    }
+    /// Nothing to read looks like the kernel not answering.
+    fn read(&self, buf: &mut [u8], _timeout: Duration) -> rusb::Result<usize> {
+        let mut state = self.0.lock().unwrap();
+        if state.reply.is_empty() {
+            return Err(rusb::Error::Timeout);
+        }
+        let len = buf.len().min(state.reply.len());
+        for (byte, reply) in buf.iter_mut().zip(state.reply.drain(..len)) {
+            *byte = reply;
+        }
+        Ok(len)
+    }
+    fn clear_halt(&self) -> rusb::Result<()> {
+        Ok(())
+    }
+    fn reset(&self) -> rusb::Result<()> {
+        let mut state = self.0.lock().unwrap();
+        state.reply.clear();
+        state.payload = None;
+        Ok(())
+    }
+    fn usb(&self) -> Option<&DeviceHandle<GlobalContext>> {
+        None
+    }
+}
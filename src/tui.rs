@@ -0,0 +1,220 @@
+use crate::names;
+use crate::term::{Key, Screen};
+use crate::{Piece, PieceError, Result, LCD_WIDTH};
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+/// Rows of each file list shown at once.
+const ROWS: usize = 20;
+/// Width of a pane, border included.
+const WIDTH: usize = 40;
+
+struct Pane {
+    title: &'static str,
+    /// Names and sizes.
+    files: Vec<(String, u64)>,
+    selected: usize,
+    top: usize,
+}
+
+impl Pane {
+    fn new(title: &'static str, files: Vec<(String, u64)>) -> Pane {
+        Pane { title, files, selected: 0, top: 0 }
+    }
+
+    fn set(&mut self, files: Vec<(String, u64)>) {
+        self.files = files;
+        self.selected = self.selected.min(self.files.len().saturating_sub(1));
+    }
+
+    fn step(&mut self, by: isize) {
+        let last = self.files.len().saturating_sub(1) as isize;
+        self.selected = (self.selected as isize + by).clamp(0, last) as usize;
+        if self.selected < self.top {
+            self.top = self.selected;
+        } else if self.selected >= self.top + ROWS {
+            self.top = self.selected + 1 - ROWS;
+        }
+    }
+
+    fn current(&self) -> Option<&str> {
+        self.files.get(self.selected).map(|(name, _)| name.as_str())
+    }
+
+    /// Line `row` of the pane, highlighted where selected in the active pane.
+    fn line(&self, row: usize, active: bool) -> String {
+        let Some((name, size)) = self.files.get(self.top + row) else {
+            return " ".repeat(WIDTH);
+        };
+        let name: String = name.chars().take(WIDTH - 12).collect();
+        let text = format!(" {:<width$} {:>9} ", name, size, width = WIDTH - 12);
+        match active && self.top + row == self.selected {
+            true => format!("\x1b[7m{}\x1b[0m", text),
+            false => text,
+        }
+    }
+}
+
+/// What `y` confirms, when a question is showing.
+enum Pending {
+    Delete(String),
+    Overwrite(String),
+}
+
+fn device_files(piece: &mut Piece) -> Result<Vec<(String, u64)>> {
+    Ok(piece.ls()?.into_iter().map(|dirent| (dirent.name, dirent.len as u64)).collect())
+}
+
+fn local_files() -> Vec<(String, u64)> {
+    let mut files: Vec<(String, u64)> = fs::read_dir(".").expect("Could not read the current directory")
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| Some((entry.file_name().into_string().ok()?, entry.metadata().ok().filter(|m| m.is_file())?.len())))
+        .collect();
+    files.sort();
+    files
+}
+
+/// The message for errors worth reporting in the status line rather than
+/// ending the session.
+fn recoverable(error: PieceError) -> Result<String> {
+    match error {
+        PieceError::NameTooLong(_) | PieceError::UnencodableName(_) | PieceError::DirectoryFull | PieceError::NoSpace
+        | PieceError::LowSpace(_) | PieceError::ReadOnly(_) | PieceError::Unsupported { .. }
+        | PieceError::FileNotFound(_) | PieceError::CorruptEntry { .. } => Ok(error.to_string()),
+        error => Err(error),
+    }
+}
+
+/// Two-pane file manager: the device's files on the left, the current
+/// directory's on the right.
+pub fn run(piece: &mut Piece) -> Result<()> {
+    let screen = Screen::new();
+    let mut panes = [Pane::new("device", device_files(piece)?), Pane::new("local", local_files())];
+    let mut active = 0;
+    let mut pending: Option<Pending> = None;
+    let mut status = String::from("tab switch, c copy, d delete, s screenshot, r refresh, q quit");
+    loop {
+        screen.draw(&render(&panes, active, &status));
+        let Some(key) = screen.key(Duration::from_secs(3600)) else {
+            continue;
+        };
+        if let Some(question) = pending.take() {
+            status = match (key, question) {
+                (Key::Char('y'), Pending::Delete(name)) if active == 0 => match piece.remove(&name) {
+                    Ok(()) => format!("deleted {} from the device", name),
+                    Err(error) => recoverable(error)?,
+                },
+                (Key::Char('y'), Pending::Delete(name)) => match fs::remove_file(&name) {
+                    Ok(()) => format!("deleted {}", name),
+                    Err(error) => format!("{}: {}", name, error),
+                },
+                (Key::Char('y'), Pending::Overwrite(name)) => download(piece, &name)?,
+                _ => "cancelled".to_string(),
+            };
+            panes[0].set(device_files(piece)?);
+            panes[1].set(local_files());
+            continue;
+        }
+        let pane = &mut panes[active];
+        match key {
+            Key::Tab | Key::Left | Key::Right => active = 1 - active,
+            Key::Up => pane.step(-1),
+            Key::Down => pane.step(1),
+            Key::PageUp => pane.step(-(ROWS as isize)),
+            Key::PageDown => pane.step(ROWS as isize),
+            Key::Char('c') | Key::Enter => {
+                let Some(name) = pane.current().map(str::to_string) else {
+                    continue;
+                };
+                status = match active {
+                    0 if Path::new(&names::host(&name)).exists() => {
+                        pending = Some(Pending::Overwrite(name.clone()));
+                        format!("{} exists here; overwrite it? (y/n)", names::host(&name))
+                    }
+                    0 => download(piece, &name)?,
+                    _ => {
+                        let data = fs::read(&name).unwrap_or_default();
+                        match piece.upload(&name, &data, false) {
+                            Ok(()) => format!("uploaded {}", name),
+                            Err(error) => recoverable(error)?,
+                        }
+                    }
+                };
+                panes[0].set(device_files(piece)?);
+                panes[1].set(local_files());
+            }
+            Key::Char('d') => {
+                if let Some(name) = pane.current() {
+                    status = format!("delete {} from the {}? (y/n)", name, pane.title);
+                    pending = Some(Pending::Delete(name.to_string()));
+                }
+            }
+            Key::Char('s') => {
+                let frame = piece.capture()?;
+                screen.draw(&format!("{}\npress any key", preview(&frame)));
+                screen.key(Duration::from_secs(3600));
+            }
+            Key::Char('r') => {
+                panes[0].set(device_files(piece)?);
+                panes[1].set(local_files());
+                status = "refreshed".to_string();
+            }
+            Key::Char('q') | Key::Esc => return Ok(()),
+            _ => {}
+        }
+    }
+}
+
+fn download(piece: &mut Piece, name: &str) -> Result<String> {
+    match piece.read_file(name) {
+        Ok(data) => {
+            let path = names::host(name);
+            Ok(match fs::write(&path, data) {
+                Ok(()) => format!("downloaded {}", path),
+                Err(error) => format!("{}: {}", path, error),
+            })
+        }
+        Err(error) => recoverable(error),
+    }
+}
+
+fn render(panes: &[Pane; 2], active: usize, status: &str) -> String {
+    let mut out = String::new();
+    for (i, pane) in panes.iter().enumerate() {
+        let title = format!(" {} ({}) ", pane.title, pane.files.len());
+        let title = match i == active {
+            true => format!("\x1b[1m{:─^width$}\x1b[0m", title, width = WIDTH),
+            false => format!("{:─^width$}", title, width = WIDTH),
+        };
+        out += &format!("┌{}┐", title);
+    }
+    out.push('\n');
+    for row in 0..ROWS {
+        for (i, pane) in panes.iter().enumerate() {
+            out += &format!("│{}│", pane.line(row, i == active));
+        }
+        out.push('\n');
+    }
+    for _ in panes {
+        out += &format!("└{}┘", "─".repeat(WIDTH));
+    }
+    out += &format!("\n{}", status);
+    out
+}
+
+/// `frame` in half the lines, two pixel rows to a character cell coloured
+/// from the terminal's grey ramp.
+fn preview(frame: &[u8]) -> String {
+    const GREYS: [u8; 4] = [16, 240, 248, 231];
+    let mut out = String::new();
+    for rows in frame.chunks(LCD_WIDTH * 2) {
+        let (upper, lower) = rows.split_at(LCD_WIDTH.min(rows.len()));
+        for (x, &top) in upper.iter().enumerate() {
+            let bottom = lower.get(x).copied().unwrap_or(top);
+            out += &format!("\x1b[38;5;{}m\x1b[48;5;{}m▀", GREYS[top.min(3) as usize], GREYS[bottom.min(3) as usize]);
+        }
+        out += "\x1b[0m\n";
+    }
+    out
+}
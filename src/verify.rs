@@ -0,0 +1,135 @@
+use crate::backup;
+use crate::crc32::crc32;
+use crate::crypto;
+use crate::json;
+use crate::names;
+use crate::sha256;
+use crate::{DirEnt, Piece, PieceError, Result};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Print the CRC32 and SHA-256 of a device file, read cluster by cluster,
+/// and with `local` compare it against that file. Returns the exit code: 1
+/// if they differ.
+pub fn run(piece: &mut Piece, dirent: &DirEnt, local: Option<&Path>, as_json: bool) -> Result<i32> {
+    let fat = piece.read_fat()?;
+    let data = piece.read_entry(dirent, &fat)?;
+    let crc = format!("{:08x}", crc32(&data));
+    let hash = sha256::hex(&data);
+    let expected = local.map(|local| fs::read(local).map_err(PieceError::host_io(format!("Could not read {}", local.display())))).transpose()?;
+    let matches = expected.as_ref().map(|expected| *expected == data);
+    if as_json {
+        println!("{}", json::object(&[
+            ("name", json::string(&dirent.name)),
+            ("len", data.len().to_string()),
+            ("crc32", json::string(&crc)),
+            ("sha256", json::string(&hash)),
+            ("local", local.map_or("null".to_string(), |local| json::string(&local.to_string_lossy()))),
+            ("matches", matches.map_or("null".to_string(), |matches| matches.to_string())),
+        ]));
+        return Ok((matches == Some(false)) as i32);
+    }
+    println!("crc32   {}", crc);
+    println!("sha256  {}", hash);
+    let (Some(local), Some(expected)) = (local, expected) else {
+        return Ok(0);
+    };
+    if matches == Some(true) {
+        println!("{} matches {}", dirent.name, local.display());
+        return Ok(0);
+    }
+    println!("local   {} ({} bytes, device has {})", sha256::hex(&expected), expected.len(), data.len());
+    println!("{} differs from {}", dirent.name, local.display());
+    Ok(1)
+}
+
+/// Read `dirent` a second time and check it against `data`, which was just
+/// downloaded, to catch corruption on the way over USB.
+pub fn reread(piece: &mut Piece, dirent: &DirEnt, fat: &[u16], data: &[u8]) -> Result<()> {
+    let again = piece.read_entry(dirent, fat)?;
+    if again != data {
+        return Err(PieceError::Protocol(format!("{} read differently the second time; the transfer is unreliable", dirent.name)));
+    }
+    Ok(())
+}
+
+/// Compare the device's files with those in `dir`, as saved by `download`
+/// or `backup`, compressed or encrypted files included. Lists files only on
+/// the device, only in `dir`, and those whose size or contents differ;
+/// with `size_only` contents aren't read. Returns the exit code: 1 if
+/// anything differs.
+pub fn dir(piece: &mut Piece, dir: &Path, size_only: bool, as_json: bool) -> Result<i32> {
+    let unreadable = || PieceError::host_io(format!("Could not read directory {}", dir.display()));
+    let mut local: Vec<String> = fs::read_dir(dir).map_err(unreadable())?
+        .collect::<io::Result<Vec<_>>>().map_err(unreadable())?
+        .into_iter()
+        .filter(|entry| entry.file_type().is_ok_and(|kind| kind.is_file()))
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .filter(|name| !name.starts_with('.') && name != backup::MANIFEST)
+        .collect();
+    local.sort();
+    // Ask once, not for every encrypted file.
+    let passphrase = local.iter().any(|name| name.ends_with(".enc")).then(crypto::passphrase).transpose()?;
+    let directory = piece.ls()?;
+    let hosts = names::hosts(directory.iter().map(|dirent| dirent.name.as_str()));
+    let fat = piece.read_fat()?;
+    let (mut device_only, mut differs, mut same) = (Vec::new(), Vec::new(), 0);
+    for (dirent, host) in directory.iter().zip(&hosts) {
+        if let Some(problem) = dirent.problem {
+            eprintln!("warning: skipping entry {} {:?}: {}", dirent.index, dirent.name, problem);
+            continue;
+        }
+        let Some(expected) = backup::find_host(dir, host, || Ok(passphrase.clone().unwrap_or_default()))? else {
+            device_only.push(dirent);
+            continue;
+        };
+        if expected.len() != dirent.len as usize {
+            differs.push((dirent, host, expected.len()));
+            continue;
+        }
+        if size_only {
+            same += 1;
+            continue;
+        }
+        let data = piece.read_entry(dirent, &fat)?;
+        match data == expected {
+            true => same += 1,
+            false => differs.push((dirent, host, expected.len())),
+        }
+    }
+    let host_only: Vec<&String> = local.iter()
+        .filter(|name| !hosts.iter().any(|host| host == *name || host == backup::saved_name(name).0))
+        .collect();
+    let changed = !device_only.is_empty() || !host_only.is_empty() || !differs.is_empty();
+    if as_json {
+        let differs: Vec<String> = differs.iter().map(|(dirent, host, len)| json::object(&[
+            ("name", json::string(&dirent.name)),
+            ("host", json::string(host)),
+            ("len", dirent.len.to_string()),
+            ("host_len", len.to_string()),
+        ])).collect();
+        println!("{}", json::object(&[
+            ("device_only", json::array(&device_only.iter().map(|dirent| json::string(&dirent.name)).collect::<Vec<_>>())),
+            ("host_only", json::array(&host_only.iter().map(|name| json::string(name)).collect::<Vec<_>>())),
+            ("differs", json::array(&differs)),
+            ("same", same.to_string()),
+        ]));
+        return Ok(changed as i32);
+    }
+    for dirent in &device_only {
+        println!("device only\t{}", dirent.name);
+    }
+    for name in &host_only {
+        println!("host only\t{}", name);
+    }
+    for (dirent, host, len) in &differs {
+        match *len == dirent.len as usize {
+            true => println!("differs\t{}\tcontents differ from {}", dirent.name, host),
+            false => println!("differs\t{}\t{} bytes, {} has {}", dirent.name, dirent.len, host, len),
+        }
+    }
+    println!("{} the same, {} only on the device, {} only in {}, {} different",
+             same, device_only.len(), host_only.len(), dir.display(), differs.len());
+    Ok(changed as i32)
+}
@@ -0,0 +1,86 @@
+use crate::date;
+use crate::glob;
+use crate::names;
+use crate::{DirEnt, Piece, Result};
+use std::fs;
+use std::io::{self, IsTerminal};
+use std::path::Path;
+use std::time::Duration;
+
+/// Print the directory, then re-read it every `interval` and print the
+/// entries that were added (`+`), removed (`-`) or changed (`~`) since.
+/// Runs until interrupted.
+pub fn directory(piece: &mut Piece, pattern: Option<&str>, interval: Duration) -> Result<()> {
+    let color = io::stdout().is_terminal();
+    let list = |piece: &mut Piece| -> Result<Vec<DirEnt>> {
+        let mut directory = piece.ls()?;
+        directory.retain(|dirent| pattern.is_none_or(|pattern| glob::matches(pattern, &dirent.name)));
+        Ok(directory)
+    };
+    let mut previous = list(piece)?;
+    for dirent in &previous {
+        println!("  {}\t{}", dirent.name, dirent.len);
+    }
+    loop {
+        piece.idle(interval)?;
+        let current = list(piece)?;
+        let find = |directory: &[DirEnt], name: &str| directory.iter().position(|dirent| dirent.name == name);
+        let mut changes = Vec::new();
+        for dirent in &current {
+            match find(&previous, &dirent.name).map(|i| &previous[i]) {
+                None => changes.push(('+', dirent)),
+                Some(old) if old.len != dirent.len || old.cluster != dirent.cluster => changes.push(('~', dirent)),
+                Some(_) => {}
+            }
+        }
+        for dirent in &previous {
+            if find(&current, &dirent.name).is_none() {
+                changes.push(('-', dirent));
+            }
+        }
+        if !changes.is_empty() {
+            println!("{}", date::format(date::now_local()));
+            for (mark, dirent) in changes {
+                let line = format!("{} {}\t{}", mark, dirent.name, dirent.len);
+                match (color, mark) {
+                    (false, _) => println!("{}", line),
+                    (true, '+') => println!("\x1b[32m{}\x1b[0m", line),
+                    (true, '-') => println!("\x1b[31m{}\x1b[0m", line),
+                    (true, _) => println!("\x1b[33m{}\x1b[0m", line),
+                }
+            }
+        }
+        previous = current;
+    }
+}
+
+/// Poll the device every `interval` and download files that appear or grow
+/// into `dir`. Files already there when it starts are only fetched with `all`.
+/// Runs until interrupted.
+pub fn autopull(piece: &mut Piece, dir: &Path, interval: Duration, all: bool) -> Result<()> {
+    fs::create_dir_all(dir).expect("Could not create output directory");
+    let mut seen: Vec<(String, u32)> = match all {
+        true => Vec::new(),
+        false => piece.ls()?.into_iter().map(|dirent| (dirent.name, dirent.len)).collect(),
+    };
+    println!("Watching for new files, {} already on the device", seen.len());
+    loop {
+        for dirent in piece.ls()? {
+            if dirent.problem.is_some() {
+                continue;
+            }
+            let known = seen.iter().position(|(name, _)| *name == dirent.name);
+            if known.is_some_and(|i| seen[i].1 >= dirent.len) {
+                continue;
+            }
+            let path = dir.join(names::host(&dirent.name));
+            piece.download_to(&dirent.name, &path)?;
+            println!("{} {}\t{} bytes", date::format(date::now_local()), dirent.name, dirent.len);
+            match known {
+                Some(i) => seen[i].1 = dirent.len,
+                None => seen.push((dirent.name, dirent.len)),
+            }
+        }
+        piece.idle(interval)?;
+    }
+}
@@ -0,0 +1,118 @@
+//! Logging of the raw bulk transfers behind every device command, for
+//! working out more of the kernel's protocol and for bug reports.
+
+use crate::transport::Transport;
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+/// 0 logs nothing, 1 a line per transfer, 2 adds the first bytes of each
+/// payload and 3 all of them.
+static LEVEL: AtomicU8 = AtomicU8::new(0);
+/// Whether the last write was a command whose data follows in the next one.
+static PAYLOAD_NEXT: AtomicBool = AtomicBool::new(false);
+static EPOCH: OnceLock<Instant> = OnceLock::new();
+/// Bytes of a payload dumped at level 2.
+const SHORT_DUMP: usize = 64;
+
+/// Log transfers at `level`, from 0 for none to 3.
+pub fn set_level(level: u8) {
+    EPOCH.get_or_init(Instant::now);
+    LEVEL.store(level, Ordering::Relaxed);
+}
+
+/// `data` read from `addr` as hex and ASCII, 16 bytes to a line.
+pub fn hexdump(addr: u32, data: &[u8]) -> String {
+    hexdump_width(addr, data, 16)
+}
+
+/// `hexdump` with `width` bytes to a line, split in two halves.
+pub fn hexdump_width(addr: u32, data: &[u8], width: usize) -> String {
+    let mut out = String::new();
+    for (i, row) in data.chunks(width).enumerate() {
+        out += &format!("{:08x} ", addr as usize + i * width);
+        for column in 0..width {
+            match row.get(column) {
+                Some(byte) => out += &format!(" {:02x}", byte),
+                None => out += "   ",
+            }
+            if column + 1 == width / 2 {
+                out.push(' ');
+            }
+        }
+        out += "  |";
+        out.extend(row.iter().map(|&byte| match byte {
+            0x20..=0x7e => byte as char,
+            _ => '.',
+        }));
+        out += "|\n";
+    }
+    out
+}
+
+/// What a command written to the kernel asks for.
+fn describe(command: &[u8]) -> String {
+    let word = |at: usize| command.get(at..at + 4).map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()));
+    let hex = |value: Option<u32>| value.map_or("?".to_string(), |value| format!("{:#010x}", value));
+    let dec = |value: Option<u32>| value.map_or("?".to_string(), |value| value.to_string());
+    match command {
+        [0, len, ..] => format!("system info len={}", len),
+        [2, ..] => format!("get memory addr={} len={}", hex(word(1)), dec(word(5))),
+        [3, ..] => format!("set memory addr={} len={}", hex(word(1)), dec(word(5))),
+        [4, ..] => format!("exec addr={}", hex(word(1))),
+        [5, ..] => format!("write flash addr={} len={}", hex(word(1)), dec(word(5))),
+        [16, 1] => "pause".to_string(),
+        [16, 0] => "resume".to_string(),
+        [17] => "lcd info".to_string(),
+        [18, mask] => format!("set keys mask={:#04x}", mask),
+        _ => "unknown command".to_string(),
+    }
+}
+
+fn log(level: u8, line: &str, data: &[u8]) {
+    let elapsed = EPOCH.get().map_or(Duration::ZERO, |epoch| epoch.elapsed());
+    eprintln!("usb {:>10.3} {}", elapsed.as_secs_f64(), line);
+    if level >= 2 && !data.is_empty() {
+        let shown = if level >= 3 { data.len() } else { data.len().min(SHORT_DUMP) };
+        eprint!("{}", hexdump(0, &data[..shown]));
+        if shown < data.len() {
+            eprintln!("         ... {} more bytes", data.len() - shown);
+        }
+    }
+}
+
+/// Send `data` on the bulk OUT endpoint.
+pub fn write(transport: &dyn Transport, data: &[u8], timeout: Duration) -> rusb::Result<usize> {
+    let result = transport.write(data, timeout);
+    let level = LEVEL.load(Ordering::Relaxed);
+    if level > 0 {
+        let payload = PAYLOAD_NEXT.swap(false, Ordering::Relaxed);
+        let what = match payload {
+            true => "payload".to_string(),
+            false => match data.first() {
+                Some(&command) => format!("cmd {:#04x} {}", command, describe(data)),
+                None => "empty".to_string(),
+            },
+        };
+        let line = match result {
+            Ok(n) => format!("OUT {} ({} of {} bytes)", what, n, data.len()),
+            Err(error) => format!("OUT {} ({} bytes) failed: {}", what, data.len(), error),
+        };
+        log(level, &line, data);
+        PAYLOAD_NEXT.store(!payload && result.is_ok() && matches!(data.first(), Some(3 | 5)), Ordering::Relaxed);
+    }
+    result
+}
+
+/// Receive up to `buf.len()` bytes on the bulk IN endpoint.
+pub fn read(transport: &dyn Transport, buf: &mut [u8], timeout: Duration) -> rusb::Result<usize> {
+    let result = transport.read(buf, timeout);
+    let level = LEVEL.load(Ordering::Relaxed);
+    if level > 0 {
+        match result {
+            Ok(n) => log(level, &format!("IN  {} of {} bytes", n, buf.len()), &buf[..n]),
+            Err(error) => log(level, &format!("IN  {} bytes failed: {}", buf.len(), error), &[]),
+        }
+    }
+    result
+}
@@ -0,0 +1,197 @@
+use crate::crc32::crc32;
+use crate::date;
+use crate::deflate;
+use std::io::{self, Write};
+
+/// Streams a zip archive to `out`, deflating members that shrink.
+pub struct Writer<W: Write> {
+    out: W,
+    /// MS-DOS time and date fields every member is stamped with.
+    dos_time: [u8; 4],
+    offset: u32,
+    central: Vec<u8>,
+    count: u16,
+}
+
+impl<W: Write> Writer<W> {
+    /// Files are stamped with `mtime`, in local seconds since the Unix epoch.
+    pub fn new(out: W, mtime: i64) -> Writer<W> {
+        let (year, month, day, hour, minute, second) = date::civil(mtime);
+        let time = (hour << 11 | minute << 5 | (second / 2)) as u16;
+        let date = (((year - 1980).max(0) as u32) << 9 | month << 5 | day) as u16;
+        let mut dos_time = [0; 4];
+        dos_time[..2].copy_from_slice(&time.to_le_bytes());
+        dos_time[2..].copy_from_slice(&date.to_le_bytes());
+        Writer { out, dos_time, offset: 0, central: Vec::new(), count: 0 }
+    }
+
+    pub fn append(&mut self, name: &str, data: &[u8]) -> io::Result<()> {
+        let compressed = deflate::compress(data);
+        let (method, stored): (u16, &[u8]) = match compressed.len() < data.len() {
+            true => (8, &compressed),
+            false => (0, data),
+        };
+        // Version needed, flags (bit 11: UTF-8 name), method, time and date,
+        // CRC and sizes: shared by the local and central headers.
+        let mut common = Vec::new();
+        common.extend(20u16.to_le_bytes());
+        common.extend(0x0800u16.to_le_bytes());
+        common.extend(method.to_le_bytes());
+        common.extend(self.dos_time);
+        common.extend(crc32(data).to_le_bytes());
+        common.extend((stored.len() as u32).to_le_bytes());
+        common.extend((data.len() as u32).to_le_bytes());
+        common.extend((name.len() as u16).to_le_bytes());
+        common.extend(0u16.to_le_bytes());
+        self.out.write_all(b"PK\x03\x04")?;
+        self.out.write_all(&common)?;
+        self.out.write_all(name.as_bytes())?;
+        self.out.write_all(stored)?;
+
+        self.central.extend(b"PK\x01\x02");
+        self.central.extend(20u16.to_le_bytes());
+        self.central.extend(&common);
+        // Comment length, disk, internal and external attributes.
+        self.central.extend([0; 10]);
+        self.central.extend(self.offset.to_le_bytes());
+        self.central.extend(name.as_bytes());
+        self.offset += (30 + name.len() + stored.len()) as u32;
+        self.count += 1;
+        Ok(())
+    }
+
+    /// Write the central directory and return the underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.out.write_all(&self.central)?;
+        self.out.write_all(b"PK\x05\x06\0\0\0\0")?;
+        self.out.write_all(&self.count.to_le_bytes())?;
+        self.out.write_all(&self.count.to_le_bytes())?;
+        self.out.write_all(&(self.central.len() as u32).to_le_bytes())?;
+        self.out.write_all(&self.offset.to_le_bytes())?;
+        self.out.write_all(&[0, 0])?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+fn u16_at(data: &[u8], offset: usize) -> Option<usize> {
+    Some(u16::from_le_bytes(data.get(offset..offset + 2)?.try_into().unwrap()) as usize)
+}
+
+fn u32_at(data: &[u8], offset: usize) -> Option<usize> {
+    Some(u32::from_le_bytes(data.get(offset..offset + 4)?.try_into().unwrap()) as usize)
+}
+
+/// The members of a zip archive as (name, contents), for stored and deflated
+/// members only. Returns `None` on malformed input or a CRC mismatch.
+pub fn read(data: &[u8]) -> Option<Vec<(String, Vec<u8>)>> {
+    let end = (0..data.len().saturating_sub(21)).rev().find(|&i| data[i..].starts_with(b"PK\x05\x06"))?;
+    let count = u16_at(data, end + 10)?;
+    let mut entry = u32_at(data, end + 16)?;
+    let mut members = Vec::new();
+    for _ in 0..count {
+        if !data.get(entry..)?.starts_with(b"PK\x01\x02") {
+            return None;
+        }
+        let method = u16_at(data, entry + 10)?;
+        let crc = u32_at(data, entry + 16)? as u32;
+        let stored_len = u32_at(data, entry + 20)?;
+        let name_len = u16_at(data, entry + 28)?;
+        let extra_len = u16_at(data, entry + 30)?;
+        let comment_len = u16_at(data, entry + 32)?;
+        let local = u32_at(data, entry + 42)?;
+        let name = String::from_utf8_lossy(data.get(entry + 46..entry + 46 + name_len)?).into_owned();
+        entry += 46 + name_len + extra_len + comment_len;
+
+        if !data.get(local..)?.starts_with(b"PK\x03\x04") {
+            return None;
+        }
+        let start = local + 30 + u16_at(data, local + 26)? + u16_at(data, local + 28)?;
+        let stored = data.get(start..start + stored_len)?;
+        let contents = match method {
+            0 => stored.to_vec(),
+            8 => deflate::decompress(stored)?,
+            _ => return None,
+        };
+        if crc32(&contents) != crc {
+            return None;
+        }
+        members.push((name, contents));
+    }
+    Some(members)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn archive(members: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut writer = Writer::new(Vec::new(), 0);
+        for (name, data) in members {
+            writer.append(name, data).unwrap();
+        }
+        writer.finish().unwrap()
+    }
+
+    #[test]
+    fn round_trip() {
+        let big = vec![b'a'; 5000];
+        let data = archive(&[("SAVE.DAT", b"level 3"), ("GAME.PEX", &big), ("EMPTY", b"")]);
+        let members = read(&data).unwrap();
+        let members: Vec<(&str, &[u8])> = members.iter().map(|(name, data)| (name.as_str(), data.as_slice())).collect();
+        assert_eq!(members, [("SAVE.DAT", &b"level 3"[..]), ("GAME.PEX", &big), ("EMPTY", b"")]);
+    }
+
+    #[test]
+    fn headers_are_where_the_directory_says() {
+        let big = vec![b'a'; 5000];
+        let data = archive(&[("SAVE.DAT", b"level 3"), ("GAME.PEX", &big)]);
+        // SAVE.DAT doesn't shrink, so is stored; GAME.PEX is deflated.
+        assert!(data.starts_with(b"PK\x03\x04"));
+        assert_eq!(u16_at(&data, 8), Some(0));
+        assert_eq!(u32_at(&data, 14), Some(crc32(b"level 3") as usize));
+        assert_eq!(u32_at(&data, 18), Some(7));
+        assert_eq!(&data[30..38], b"SAVE.DAT");
+        assert_eq!(&data[38..45], b"level 3");
+        let second = 45;
+        assert!(data[second..].starts_with(b"PK\x03\x04"));
+        assert_eq!(u16_at(&data, second + 8), Some(8));
+        assert_eq!(u32_at(&data, second + 22), Some(5000));
+        let stored = u32_at(&data, second + 18).unwrap();
+        assert!(stored < 5000);
+
+        let central = second + 30 + 8 + stored;
+        let end = data.len() - 22;
+        assert!(data[end..].starts_with(b"PK\x05\x06"));
+        assert_eq!(u16_at(&data, end + 10), Some(2));
+        assert_eq!(u32_at(&data, end + 12), Some(end - central));
+        assert_eq!(u32_at(&data, end + 16), Some(central));
+        assert!(data[central..].starts_with(b"PK\x01\x02"));
+        assert_eq!(u32_at(&data, central + 42), Some(0));
+        let next = central + 46 + 8;
+        assert!(data[next..].starts_with(b"PK\x01\x02"));
+        assert_eq!(u32_at(&data, next + 42), Some(second));
+        assert_eq!(&data[next + 46..next + 54], b"GAME.PEX");
+    }
+
+    #[test]
+    fn dos_time_is_stamped() {
+        // 2001-02-03 04:05:06.
+        let data = Writer::new(Vec::new(), 981173106).finish().unwrap();
+        assert_eq!(data.len(), 22);
+        let mut writer = Writer::new(Vec::new(), 981173106);
+        writer.append("A", b"").unwrap();
+        let data = writer.finish().unwrap();
+        assert_eq!(u16_at(&data, 10), Some(4 << 11 | 5 << 5 | 3));
+        assert_eq!(u16_at(&data, 12), Some(21 << 9 | 2 << 5 | 3));
+    }
+
+    #[test]
+    fn damage_is_refused() {
+        let mut data = archive(&[("SAVE.DAT", b"level 3")]);
+        data[38] ^= 1;
+        assert!(read(&data).is_none());
+        assert!(read(&data[..data.len() - 1]).is_none());
+        assert!(read(b"not a zip").is_none());
+    }
+}
@@ -0,0 +1,24 @@
+//! Cancelling against an in-memory device. Apart from the other tests, as
+//! cancelling stops everything in the process.
+
+mod common;
+
+use common::connect;
+use piecer::transport::FakeDevice;
+use piecer::{cancel, Options, PieceError};
+
+#[test]
+fn cancelled_commands_leave_the_device_as_it_was() {
+    let device = FakeDevice::with_files(&[("SAVE.DAT", &[1; 300])]).unwrap();
+    let mut piece = connect(&device, &Options::default());
+    let before = device.flash();
+    cancel::cancel();
+    assert!(matches!(piece.capture(), Err(PieceError::Cancelled)));
+    assert!(matches!(piece.upload("NEW.BIN", &[2; 10000], false), Err(PieceError::Cancelled)));
+    drop(piece);
+    assert!(!device.paused());
+    assert!(device.flash() == before);
+    cancel::reset();
+    let mut piece = connect(&device, &Options::default());
+    assert_eq!(piece.read_file("SAVE.DAT").unwrap(), [1; 300]);
+}
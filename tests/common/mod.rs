@@ -0,0 +1,23 @@
+//! Fixtures shared by the integration tests.
+
+use piecer::transport::FakeDevice;
+use piecer::{Options, Piece};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Once;
+
+/// A fresh directory for one test's files, under the temporary directory.
+pub fn scratch(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("piecer-test-{}-{}", std::process::id(), name));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// A connection to `device`. The audit log and other state go to a scratch
+/// directory rather than the user's.
+pub fn connect(device: &FakeDevice, options: &Options) -> Piece {
+    static STATE: Once = Once::new();
+    STATE.call_once(|| std::env::set_var("XDG_STATE_HOME", scratch("state")));
+    Piece::with_transport(device.clone(), options).unwrap()
+}
@@ -0,0 +1,280 @@
+//! The library against an in-memory device, so the protocol and filesystem
+//! code run end to end without hardware.
+
+mod common;
+
+use common::{connect, scratch};
+use piecer::flash::{FLASH_BASE, SECTOR_SIZE};
+use piecer::memmap::SRAM_BASE;
+use piecer::pffs::Problem;
+use piecer::transport::{FakeDevice, FAKE_FRAMEBUFFER, FAKE_PFFS_TOP};
+use piecer::{Options, PieceError};
+use std::fs;
+
+/// Contents that differ from cluster to cluster, so misplaced data shows.
+fn pattern(len: usize, seed: u8) -> Vec<u8> {
+    (0..len).map(|i| (i * 7 + i / 4096) as u8 ^ seed).collect()
+}
+
+fn sample() -> FakeDevice {
+    FakeDevice::with_files(&[
+        ("GAME.PEX", &pattern(10000, 1)),
+        ("SAVE.DAT", &pattern(300, 2)),
+        ("EMPTY", &[]),
+    ]).unwrap()
+}
+
+#[test]
+fn handshake_reads_systeminfo() {
+    let device = sample();
+    let mut piece = connect(&device, &Options::default());
+    assert_eq!(piece.kernel_version, 0x0130);
+    assert_eq!(piece.pffs_top, FAKE_PFFS_TOP);
+    assert_eq!(piece.system_info().unwrap().vdde_mv, 3000);
+    assert!(matches!(piece.usb_info(), Err(PieceError::Usb(rusb::Error::NotSupported))));
+}
+
+#[test]
+fn ls_lists_files() {
+    let device = sample();
+    let mut piece = connect(&device, &Options::default());
+    let directory = piece.ls().unwrap();
+    let names: Vec<(&str, u32)> = directory.iter().map(|dirent| (dirent.name.as_str(), dirent.len)).collect();
+    assert_eq!(names, [("GAME.PEX", 10000), ("SAVE.DAT", 300), ("EMPTY", 0)]);
+    assert!(directory.iter().all(|dirent| dirent.problem.is_none()));
+    assert!(piece.check().unwrap().is_empty());
+}
+
+#[test]
+fn download_matches_contents() {
+    let device = sample();
+    let mut piece = connect(&device, &Options::default());
+    assert_eq!(piece.read_file("GAME.PEX").unwrap(), pattern(10000, 1));
+    let dir = scratch("download");
+    piece.download_to("SAVE.DAT", &dir.join("save.dat")).unwrap();
+    assert_eq!(fs::read(dir.join("save.dat")).unwrap(), pattern(300, 2));
+    let fat = piece.read_fat().unwrap();
+    let game = piece.ls().unwrap().into_iter().next().unwrap();
+    piece.download_entry(&game, &fat, &dir.join("game.pex"), false).unwrap();
+    assert_eq!(fs::read(dir.join("game.pex")).unwrap(), pattern(10000, 1));
+    assert!(matches!(piece.read_file("MISSING"), Err(PieceError::FileNotFound(_))));
+}
+
+#[test]
+fn upload_replaces_file() {
+    let device = sample();
+    let mut piece = connect(&device, &Options::default());
+    piece.upload("SAVE.DAT", &pattern(9000, 3), false).unwrap();
+    piece.upload("NEW.BIN", b"new", false).unwrap();
+    assert_eq!(piece.read_file("SAVE.DAT").unwrap(), pattern(9000, 3));
+    assert_eq!(piece.read_file("NEW.BIN").unwrap(), b"new");
+    assert_eq!(piece.read_file("GAME.PEX").unwrap(), pattern(10000, 1));
+    assert_eq!(piece.ls().unwrap().len(), 4);
+    assert!(piece.check().unwrap().is_empty());
+    // A new connection sees what the last one wrote.
+    let mut again = connect(&device, &Options::default());
+    assert_eq!(again.read_file("SAVE.DAT").unwrap(), pattern(9000, 3));
+}
+
+#[test]
+fn failed_replace_keeps_the_old_file() {
+    let device = sample();
+    let mut piece = connect(&device, &Options::default());
+    device.fail_flash_writes_after(Some(1));
+    assert!(piece.upload("SAVE.DAT", &pattern(9000, 3), false).is_err());
+    device.fail_flash_writes_after(None);
+    let mut again = connect(&device, &Options::default());
+    assert_eq!(again.read_file("SAVE.DAT").unwrap(), pattern(300, 2));
+    assert!(again.check().unwrap().is_empty());
+}
+
+#[test]
+fn remove_rename_and_format() {
+    let device = sample();
+    let mut piece = connect(&device, &Options::default());
+    piece.remove("GAME.PEX").unwrap();
+    piece.rename("SAVE.DAT", "OLD.DAT").unwrap();
+    let names: Vec<String> = piece.ls().unwrap().into_iter().map(|dirent| dirent.name).collect();
+    assert_eq!(names, ["OLD.DAT", "EMPTY"]);
+    assert_eq!(piece.read_file("OLD.DAT").unwrap(), pattern(300, 2));
+    assert!(matches!(piece.remove("GAME.PEX"), Err(PieceError::FileNotFound(_))));
+    piece.format().unwrap();
+    assert!(piece.ls().unwrap().is_empty());
+    assert!(piece.check().unwrap().is_empty());
+}
+
+#[test]
+fn remove_only_takes_one_of_two_same_named_entries() {
+    let device = sample();
+    let mut flash = device.flash();
+    // Give SAVE.DAT, in slot 2, the name of GAME.PEX.
+    let save = connect(&device, &Options::default()).ls().unwrap().remove(1);
+    let name = (FAKE_PFFS_TOP - FLASH_BASE) as usize + save.index * 32;
+    flash[name..name + 24].fill(0);
+    flash[name..name + 8].copy_from_slice(b"GAME.PEX");
+    let device = FakeDevice::new(flash);
+    let mut piece = connect(&device, &Options::default());
+    piece.remove("GAME.PEX").unwrap();
+    let directory = piece.ls().unwrap();
+    let left: Vec<(usize, &str)> = directory.iter().map(|dirent| (dirent.index, dirent.name.as_str())).collect();
+    assert_eq!(left, [(save.index, "GAME.PEX"), (save.index + 1, "EMPTY")]);
+    assert_eq!(piece.read_file("GAME.PEX").unwrap(), pattern(300, 2));
+}
+
+#[test]
+fn defrag_keeps_contents() {
+    let device = sample();
+    let mut piece = connect(&device, &Options::default());
+    // Fill the gap SAVE.DAT leaves with a file that has to be split.
+    piece.remove("SAVE.DAT").unwrap();
+    piece.upload("BIG.BIN", &pattern(20000, 4), false).unwrap();
+    piece.defrag().unwrap();
+    assert!(piece.check().unwrap().is_empty());
+    assert_eq!(piece.read_file("BIG.BIN").unwrap(), pattern(20000, 4));
+    assert_eq!(piece.read_file("GAME.PEX").unwrap(), pattern(10000, 1));
+    let fat = piece.read_fat().unwrap();
+    for dirent in piece.ls().unwrap() {
+        let chain = piecer::pffs::chain(&fat, dirent.cluster);
+        assert!(chain.windows(2).all(|pair| pair[1] == pair[0] + 1), "{} is fragmented", dirent.name);
+    }
+}
+
+#[test]
+fn dry_run_leaves_flash_alone() {
+    let device = sample();
+    let before = device.flash();
+    let options = Options { dry_run: true, ..Options::default() };
+    let mut piece = connect(&device, &options);
+    piece.upload("NEW.BIN", &pattern(5000, 5), false).unwrap();
+    // Later steps see what would have been written.
+    assert_eq!(piece.read_file("NEW.BIN").unwrap(), pattern(5000, 5));
+    assert!(device.flash() == before);
+}
+
+#[test]
+fn read_only_refuses_writes() {
+    let device = sample();
+    let options = Options { read_only: true, ..Options::default() };
+    let mut piece = connect(&device, &options);
+    assert!(matches!(piece.upload("NEW.BIN", b"x", false), Err(PieceError::ReadOnly(_))));
+    assert!(matches!(piece.remove("GAME.PEX"), Err(PieceError::ReadOnly(_))));
+}
+
+#[test]
+fn corrupt_chain_is_found() {
+    let device = sample();
+    let mut flash = device.flash();
+    let geometry = piecer::pffs::PffsGeometry::STOCK;
+    // Point GAME.PEX's first cluster at a free one.
+    let game = connect(&device, &Options::default()).ls().unwrap().remove(0);
+    let link = (FAKE_PFFS_TOP - FLASH_BASE) as usize + geometry.fat_offset() + game.cluster as usize * 2;
+    flash[link..link + 2].copy_from_slice(&400u16.to_le_bytes());
+    let device = FakeDevice::new(flash);
+    let mut piece = connect(&device, &Options::default());
+    assert!(piece.check().unwrap().iter().any(|problem| matches!(problem, Problem::BrokenChain { .. })));
+}
+
+#[test]
+fn defrag_refuses_a_corrupt_filesystem() {
+    let device = sample();
+    let mut flash = device.flash();
+    let geometry = piecer::pffs::PffsGeometry::STOCK;
+    let game = connect(&device, &Options::default()).ls().unwrap().remove(0);
+    let link = (FAKE_PFFS_TOP - FLASH_BASE) as usize + geometry.fat_offset() + game.cluster as usize * 2;
+    flash[link..link + 2].copy_from_slice(&400u16.to_le_bytes());
+    let device = FakeDevice::new(flash);
+    let before = device.flash();
+    let mut piece = connect(&device, &Options::default());
+    assert!(matches!(piece.defrag(), Err(PieceError::NeedsRepair(problems)) if !problems.is_empty()));
+    assert!(device.flash() == before);
+}
+
+#[test]
+fn short_reads_shrink_the_block() {
+    let device = sample();
+    device.limit_reads(Some(512));
+    let mut piece = connect(&device, &Options::default());
+    assert_eq!(piece.read_file("GAME.PEX").unwrap(), pattern(10000, 1));
+    assert!(piece.read_block() <= 512);
+}
+
+#[test]
+fn overlapped_reads_match() {
+    let device = sample();
+    let mut piece = connect(&device, &Options::default());
+    piece.set_overlap(true);
+    piece.set_read_block(1024);
+    let mut data = vec![0; 3 * SECTOR_SIZE as usize + 100];
+    piece.get_memory(FAKE_PFFS_TOP, data.len() as u32, &mut data).unwrap();
+    assert!(data == device.memory(FAKE_PFFS_TOP, data.len() as u32));
+}
+
+#[test]
+fn memory_pause_and_screen() {
+    let device = sample();
+    let mut piece = connect(&device, &Options::default());
+    piece.set_memory(SRAM_BASE + 0x2000, b"hello").unwrap();
+    assert_eq!(device.memory(SRAM_BASE + 0x2000, 5), b"hello");
+    let frame: Vec<u8> = (0..128 * 88).map(|i| (i % 4) as u8).collect();
+    device.set_memory(FAKE_FRAMEBUFFER, &frame);
+    assert_eq!(piece.capture().unwrap(), frame);
+    piece.pause().unwrap();
+    assert!(device.paused());
+    piece.resume().unwrap();
+    assert!(!device.paused());
+    piece.set_keys(0x11).unwrap();
+    assert_eq!(device.keys(), 0x11);
+}
+
+#[test]
+fn old_kernels_lack_features() {
+    let device = sample();
+    device.set_kernel_version(0x0110);
+    let mut piece = connect(&device, &Options::default());
+    assert!(matches!(piece.upload("NEW.BIN", b"x", false), Err(PieceError::Unsupported { .. })));
+    assert!(!device.commands().contains(&5));
+}
+
+#[test]
+fn partial_answers_are_kept() {
+    let device = sample();
+    // Not a power of two, so blocks never line up with what arrives.
+    device.limit_reads(Some(700));
+    let mut piece = connect(&device, &Options::default());
+    piece.set_read_block(4096);
+    let data = piece.read_memory(FAKE_PFFS_TOP + 5, 9000).unwrap();
+    assert!(data == device.memory(FAKE_PFFS_TOP + 5, 9000));
+}
+
+#[test]
+fn small_buffer_is_refused() {
+    let device = sample();
+    let mut piece = connect(&device, &Options::default());
+    let mut data = [0; 10];
+    let error = piece.get_memory(FAKE_PFFS_TOP, 20, &mut data).unwrap_err();
+    assert!(matches!(&error, PieceError::BadRequest(message) if message.starts_with("Buffer of 10 bytes")), "{}", error);
+}
+
+#[test]
+fn raw_commands_leave_no_answer_behind() {
+    let device = sample();
+    let mut piece = connect(&device, &Options::default());
+    let info = piece.raw(&[0, 32], None, 8).unwrap();
+    assert_eq!(info[4..6], 0x0130u16.to_le_bytes());
+    // The rest of the answer was read off, so the next command gets its own.
+    assert_eq!(piece.read_file("SAVE.DAT").unwrap(), pattern(300, 2));
+    let options = Options { read_only: true, ..Options::default() };
+    assert!(matches!(connect(&device, &options).raw(&[17], None, 12), Err(PieceError::ReadOnly(_))));
+}
+
+#[test]
+fn symbolic_addresses_resolve() {
+    let device = sample();
+    let mut piece = connect(&device, &Options::default());
+    let resolve = |piece: &mut piecer::Piece, text| piecer::memmap::parse(text).unwrap().resolve(piece);
+    assert_eq!(resolve(&mut piece, "flash+0x1000").unwrap(), FLASH_BASE + 0x1000);
+    assert_eq!(resolve(&mut piece, "pffs").unwrap(), FAKE_PFFS_TOP);
+    assert_eq!(resolve(&mut piece, "lcd+1").unwrap(), FAKE_FRAMEBUFFER + 1);
+    assert_eq!(resolve(&mut piece, "sram").unwrap(), SRAM_BASE);
+    assert!(matches!(resolve(&mut piece, "iram-1"), Err(PieceError::Usage(_))));
+}